@@ -6,7 +6,14 @@ const AUDIO_WORKLET_URL = "/audio-processor.js";
 
 type VoiceRecorderStatus = "idle" | "recording" | "stopping";
 
-export function useVoiceRecorder(onAudioData: (data: ArrayBuffer) => void) {
+/**
+ * Records the microphone as mono PCM16 at the audio context's native rate.
+ * `onStart` receives that rate before the first chunk is delivered.
+ */
+export function useVoiceRecorder(
+  onAudioData: (data: ArrayBuffer) => void,
+  onStart?: (sampleRate: number) => void
+) {
   const [status, setStatus] = useState<VoiceRecorderStatus>("idle");
   // Use a ref to hold the current status for use in stale closures.
   const statusRef = useRef(status);
@@ -32,6 +39,7 @@ export function useVoiceRecorder(onAudioData: (data: ArrayBuffer) => void) {
 
       const context = new AudioContext();
       audioContextRef.current = context;
+      onStart?.(context.sampleRate);
 
       await context.audioWorklet.addModule(AUDIO_WORKLET_URL);
       const source = context.createMediaStreamSource(stream);
@@ -52,7 +60,7 @@ export function useVoiceRecorder(onAudioData: (data: ArrayBuffer) => void) {
       toast.error("Could not access microphone.");
       setStatus("idle"); // Rollback status on error
     }
-  }, [onAudioData]);
+  }, [onAudioData, onStart]);
 
   const stop = useCallback(() => {
     if (statusRef.current !== "recording") return;
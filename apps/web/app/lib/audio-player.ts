@@ -1,27 +1,14 @@
 // The backend sends PCM16 data at a specific sample rate.
 // OpenAI uses 24000, which we will standardize on for the frontend player.
+// Chunks at other rates are resampled by the AudioContext.
 const SAMPLE_RATE = 24000;
 
-/**
- * A helper function to decode a base64 string into an ArrayBuffer using browser-native APIs.
- * @param base64 The base64 string to decode.
- * @returns An ArrayBuffer containing the raw binary data.
- */
-function base64ToArrayBuffer(base64: string): ArrayBuffer {
-  const binaryString = atob(base64); // Decode base64 to a raw binary string
-  const len = binaryString.length;
-  const bytes = new Uint8Array(len);
-  for (let i = 0; i < len; i++) {
-    bytes[i] = binaryString.charCodeAt(i); // Convert each character to a byte
-  }
-  return bytes.buffer;
-}
-
 export class AudioPlayer {
   private audioContext: AudioContext;
   private audioQueue: AudioBuffer[] = [];
   private isPlaying = false;
   private nextStartTime = 0;
+  private currentSource: AudioBufferSourceNode | null = null;
 
   constructor() {
     // Lazily create AudioContext on first interaction if needed, or create it here.
@@ -29,28 +16,24 @@ export class AudioPlayer {
     this.audioContext = new AudioContext({ sampleRate: SAMPLE_RATE });
   }
 
-  public addChunk = async (base64Chunk: string) => {
+  public addChunk = (pcm16: Int16Array, sampleRate: number = SAMPLE_RATE) => {
     try {
-      // 1. Decode base64 using the browser-native function.
-      const chunkBuffer = base64ToArrayBuffer(base64Chunk);
-
-      // 2. Create a Float32Array from the 16-bit PCM data.
-      const pcm16 = new Int16Array(chunkBuffer);
+      // 1. Create a Float32Array from the 16-bit PCM data.
       const pcm32 = new Float32Array(pcm16.length);
       for (let i = 0; i < pcm16.length; i++) {
         pcm32[i] = pcm16[i] / 32768.0; // Normalize to [-1.0, 1.0]
       }
 
-      // 3. Create an AudioBuffer.
+      // 2. Create an AudioBuffer; the context resamples other rates on playback.
       if (pcm32.length === 0) return;
       const audioBuffer = this.audioContext.createBuffer(
         1, // 1 channel (mono)
         pcm32.length,
-        this.audioContext.sampleRate
+        sampleRate
       );
       audioBuffer.copyToChannel(pcm32, 0);
 
-      // 4. Add to queue and start playback if not already playing.
+      // 3. Add to queue and start playback if not already playing.
       this.audioQueue.push(audioBuffer);
       if (!this.isPlaying) {
         this.playQueue();
@@ -63,6 +46,7 @@ export class AudioPlayer {
   private playQueue = () => {
     if (this.audioQueue.length === 0) {
       this.isPlaying = false;
+      this.currentSource = null;
       return;
     }
 
@@ -77,6 +61,7 @@ export class AudioPlayer {
     const startTime = Math.max(currentTime, this.nextStartTime);
 
     source.start(startTime);
+    this.currentSource = source;
 
     // Schedule the next chunk to play right after this one ends.
     this.nextStartTime = startTime + bufferToPlay.duration;
@@ -84,6 +69,21 @@ export class AudioPlayer {
     source.onended = this.playQueue;
   };
 
+  /**
+   * Discards queued audio and silences the chunk that is playing, keeping the
+   * context open for the next response (used when the user interrupts the AI).
+   */
+  public flush() {
+    this.audioQueue = [];
+    this.isPlaying = false;
+    this.nextStartTime = 0;
+    if (this.currentSource) {
+      this.currentSource.onended = null;
+      this.currentSource.stop();
+      this.currentSource = null;
+    }
+  }
+
   public stop() {
     this.audioQueue = [];
     this.isPlaying = false;
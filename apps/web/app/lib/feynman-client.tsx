@@ -22,12 +22,76 @@ export interface ChatMessage {
 
 // --- WebSocket Protocol ---
 
+// Per-session overrides for the realtime voice. Omitted fields keep their values.
+export interface VoiceSettingsUpdate {
+  voice?: string;
+  speaking_rate?: number;
+  vad_threshold?: number;
+  vad_prefix_padding_ms?: number;
+  vad_silence_duration_ms?: number;
+  turn_detection?: TurnDetection;
+}
+
+// Who ends a user turn: the realtime provider's VAD, the server's own, or the
+// client via push-to-talk.
+export type TurnDetection = "provider" | "server" | "push_to_talk";
+
+// How the server delivers AI audio: base64 inside JSON, or binary frames.
+export type AudioTransport = "json" | "binary";
+
+// The codec of voice audio in both directions: raw PCM16 or Opus packets.
+export type AudioCodec = "pcm16" | "opus";
+
+// The format of raw microphone audio, declared when enabling voice.
+// The server downmixes and resamples it for the voice provider.
+export interface CaptureFormat {
+  sample_rate: number;
+  channels: number;
+  sample_format: "pcm16" | "f32";
+}
+
+// Binary audio frames start with a 12-byte little-endian header:
+// version (u8), codec (u8), reserved (u16), sequence (u32), sample rate (u32).
+const AUDIO_FRAME_HEADER_LEN = 12;
+const AUDIO_FRAME_VERSION = 1;
+const AUDIO_CODEC_PCM16 = 0;
+const AUDIO_CODEC_OPUS = 1;
+const JSON_AUDIO_SAMPLE_RATE = 24000;
+// Opus audio is exchanged at 24kHz mono in 20ms packets.
+const OPUS_SAMPLE_RATE = 24000;
+const OPUS_FRAME_US = 20000;
+
+function base64ToBytes(base64: string): Uint8Array {
+  const binaryString = atob(base64);
+  const bytes = new Uint8Array(binaryString.length);
+  for (let i = 0; i < binaryString.length; i++) {
+    bytes[i] = binaryString.charCodeAt(i);
+  }
+  return bytes;
+}
+
+function float32ToInt16(samples: Float32Array): Int16Array {
+  const pcm = new Int16Array(samples.length);
+  for (let i = 0; i < samples.length; i++) {
+    const s = Math.max(-1, Math.min(1, samples[i]));
+    pcm[i] = s < 0 ? s * 0x8000 : s * 0x7fff;
+  }
+  return pcm;
+}
+
+function base64ToInt16Array(base64: string): Int16Array {
+  const bytes = base64ToBytes(base64);
+  return new Int16Array(bytes.buffer, 0, bytes.length >> 1);
+}
+
 // Messages sent FROM the browser client TO the server
 type ClientToServerMessage =
   | {
       type: "init";
       topic: string;
       session_id?: string;
+      audio_transport?: AudioTransport;
+      audio_codec?: AudioCodec;
     }
   | {
       type: "user_message";
@@ -36,7 +100,20 @@ type ClientToServerMessage =
   | {
       type: "set_voice_enabled";
       enabled: boolean;
-    };
+      capture?: CaptureFormat;
+      playback_sample_rate?: number;
+    }
+  | ({ type: "update_voice_settings" } & VoiceSettingsUpdate)
+  | { type: "begin_utterance" }
+  | { type: "end_utterance" }
+  | { type: "cancel_utterance" }
+  | { type: "cancel_turn"; turn_id?: number };
+
+/** How the agent's work on a user message ended. */
+export type TurnOutcome = "completed" | "cancelled" | "failed";
+
+/** Connectivity of the server's realtime voice provider connection. */
+export type VoiceConnectionStatus = "connected" | "reconnecting" | "disconnected";
 
 type ServerToClientMessage =
   | {
@@ -53,7 +130,13 @@ type ServerToClientMessage =
   | { type: "transcription_update"; text: string; is_final: boolean }
   | { type: "audio_chunk"; data: string }
   | { type: "ai_speaking_start" }
-  | { type: "ai_speaking_end" };
+  | { type: "ai_speaking_end" }
+  | { type: "user_speaking_start" }
+  | { type: "user_speaking_end" }
+  | { type: "interrupted" }
+  | { type: "voice_status"; status: VoiceConnectionStatus; attempt: number }
+  | { type: "turn_started"; turn_id: number }
+  | { type: "turn_finished"; turn_id: number; outcome: TurnOutcome };
 
 // --- Client-Side Events ---
 interface FeynmanClientEvents {
@@ -71,9 +154,15 @@ interface FeynmanClientEvents {
   stateUpdate: (data: { state: FeynmanAgentState }) => void;
   serverError: (data: { message: string }) => void;
   transcriptionUpdate: (data: { text: string; isFinal: boolean }) => void;
-  audioChunk: (data: { data: string }) => void;
+  audioChunk: (data: { pcm: Int16Array; sampleRate: number }) => void;
   aiSpeakingStart: () => void;
   aiSpeakingEnd: () => void;
+  userSpeakingStart: () => void;
+  userSpeakingEnd: () => void;
+  interrupted: () => void;
+  voiceStatus: (data: { status: VoiceConnectionStatus; attempt: number }) => void;
+  turnStarted: (data: { turnId: number }) => void;
+  turnFinished: (data: { turnId: number; outcome: TurnOutcome }) => void;
 }
 
 export class FeynmanClient {
@@ -81,8 +170,17 @@ export class FeynmanClient {
   private listeners: {
     [K in keyof FeynmanClientEvents]?: Array<FeynmanClientEvents[K]>;
   } = {};
+  private nextAudioSequence = 0;
+  private opusDecoder: AudioDecoder | null = null;
+  private opusEncoder: AudioEncoder | null = null;
+  private opusTimestamp = 0;
+  private captureSampleRate = OPUS_SAMPLE_RATE;
 
-  constructor(private url: string) {}
+  constructor(
+    private url: string,
+    private audioTransport: AudioTransport = "binary",
+    private audioCodec: AudioCodec = "pcm16"
+  ) {}
 
   public on<K extends keyof FeynmanClientEvents>(
     event: K,
@@ -129,7 +227,17 @@ export class FeynmanClient {
       console.log(
         "FeynmanClient: WebSocket connection opened successfully (onopen event)."
       );
-      this.sendMessageToServer({ type: "init", topic, session_id: sessionId });
+      this.nextAudioSequence = 0;
+      if (this.audioCodec === "opus") {
+        this.setUpOpus();
+      }
+      this.sendMessageToServer({
+        type: "init",
+        topic,
+        session_id: sessionId,
+        audio_transport: this.audioTransport,
+        audio_codec: this.audioCodec,
+      });
       this.emit("open");
     };
 
@@ -142,6 +250,8 @@ export class FeynmanClient {
         if (typeof event.data === "string") {
           const message: ServerToClientMessage = JSON.parse(event.data);
           this.handleServerMessage(message);
+        } else if (event.data instanceof ArrayBuffer) {
+          this.handleAudioFrame(event.data);
         }
       } catch (error) {
         console.error("FeynmanClient: Failed to parse server message.", error);
@@ -194,7 +304,14 @@ export class FeynmanClient {
         });
         break;
       case "audio_chunk":
-        this.emit("audioChunk", { data: message.data });
+        if (this.audioCodec === "opus") {
+          this.decodeOpus(base64ToBytes(message.data));
+        } else {
+          this.emit("audioChunk", {
+            pcm: base64ToInt16Array(message.data),
+            sampleRate: JSON_AUDIO_SAMPLE_RATE,
+          });
+        }
         break;
       case "ai_speaking_start":
         this.emit("aiSpeakingStart");
@@ -202,6 +319,30 @@ export class FeynmanClient {
       case "ai_speaking_end":
         this.emit("aiSpeakingEnd");
         break;
+      case "user_speaking_start":
+        this.emit("userSpeakingStart");
+        break;
+      case "user_speaking_end":
+        this.emit("userSpeakingEnd");
+        break;
+      case "interrupted":
+        this.emit("interrupted");
+        break;
+      case "voice_status":
+        this.emit("voiceStatus", {
+          status: message.status,
+          attempt: message.attempt,
+        });
+        break;
+      case "turn_started":
+        this.emit("turnStarted", { turnId: message.turn_id });
+        break;
+      case "turn_finished":
+        this.emit("turnFinished", {
+          turnId: message.turn_id,
+          outcome: message.outcome,
+        });
+        break;
       default:
         console.warn(
           "FeynmanClient: Received unknown message type from server:",
@@ -210,24 +351,176 @@ export class FeynmanClient {
     }
   }
 
+  private handleAudioFrame(frame: ArrayBuffer): void {
+    if (frame.byteLength < AUDIO_FRAME_HEADER_LEN) {
+      console.warn("FeynmanClient: Ignoring truncated audio frame.");
+      return;
+    }
+    const header = new DataView(frame, 0, AUDIO_FRAME_HEADER_LEN);
+    const version = header.getUint8(0);
+    const codec = header.getUint8(1);
+    if (
+      version !== AUDIO_FRAME_VERSION ||
+      (codec !== AUDIO_CODEC_PCM16 && codec !== AUDIO_CODEC_OPUS)
+    ) {
+      console.warn(
+        `FeynmanClient: Unsupported audio frame (version ${version}, codec ${codec}).`
+      );
+      return;
+    }
+    const sequence = header.getUint32(4, true);
+    if (sequence !== this.nextAudioSequence) {
+      console.warn(
+        `FeynmanClient: Audio frame ${sequence} arrived, expected ${this.nextAudioSequence}.`
+      );
+    }
+    this.nextAudioSequence = (sequence + 1) >>> 0;
+    if (codec === AUDIO_CODEC_OPUS) {
+      this.decodeOpus(new Uint8Array(frame, AUDIO_FRAME_HEADER_LEN));
+      return;
+    }
+    this.emit("audioChunk", {
+      pcm: new Int16Array(
+        frame,
+        AUDIO_FRAME_HEADER_LEN,
+        (frame.byteLength - AUDIO_FRAME_HEADER_LEN) >> 1
+      ),
+      sampleRate: header.getUint32(8, true),
+    });
+  }
+
   public sendUserMessage(text: string): void {
     this.sendMessageToServer({ type: "user_message", text });
   }
 
-  public setVoiceEnabled(enabled: boolean): void {
-    this.sendMessageToServer({ type: "set_voice_enabled", enabled });
+  public setVoiceEnabled(
+    enabled: boolean,
+    capture?: CaptureFormat,
+    playbackSampleRate?: number
+  ): void {
+    if (capture) {
+      this.captureSampleRate = capture.sample_rate;
+    }
+    this.sendMessageToServer({
+      type: "set_voice_enabled",
+      enabled,
+      capture,
+      playback_sample_rate: playbackSampleRate,
+    });
+  }
+
+  public updateVoiceSettings(settings: VoiceSettingsUpdate): void {
+    this.sendMessageToServer({ type: "update_voice_settings", ...settings });
+  }
+
+  /**
+   * Push-to-talk markers, used with `turn_detection: "push_to_talk"`. Audio is
+   * only forwarded between `beginUtterance` and `endUtterance`; `cancelUtterance`
+   * discards it instead.
+   */
+  public beginUtterance(): void {
+    this.sendMessageToServer({ type: "begin_utterance" });
+  }
+
+  public endUtterance(): void {
+    this.sendMessageToServer({ type: "end_utterance" });
   }
 
-  public sendAudioChunk(chunk: ArrayBuffer): void {
-    if (this.ws?.readyState === WebSocket.OPEN) {
+  public cancelUtterance(): void {
+    this.sendMessageToServer({ type: "cancel_utterance" });
+  }
+
+  /**
+   * Stops the agent working on the current message. Pass the ID from
+   * `turnStarted` to avoid cancelling a later turn by mistake.
+   */
+  public cancelTurn(turnId?: number): void {
+    this.sendMessageToServer({ type: "cancel_turn", turn_id: turnId });
+  }
+
+  /**
+   * Sends captured PCM16 audio. With the Opus codec the audio is encoded first
+   * and each 20ms packet is sent as its own binary message.
+   */
+  public sendAudioChunk(
+    chunk: ArrayBuffer,
+    sampleRate: number = this.captureSampleRate
+  ): void {
+    if (this.ws?.readyState !== WebSocket.OPEN) return;
+    if (!this.opusEncoder) {
       this.ws.send(chunk);
+      return;
     }
+    const frames = chunk.byteLength >> 1;
+    this.opusEncoder.encode(
+      new AudioData({
+        format: "s16",
+        sampleRate,
+        numberOfFrames: frames,
+        numberOfChannels: 1,
+        timestamp: this.opusTimestamp,
+        data: chunk,
+      })
+    );
+    this.opusTimestamp += (frames * 1_000_000) / sampleRate;
   }
 
   public close(): void {
     if (this.ws) {
       this.ws.close();
     }
+    this.tearDownOpus();
+  }
+
+  private setUpOpus(): void {
+    this.tearDownOpus();
+    this.opusDecoder = new AudioDecoder({
+      output: (data) => {
+        const samples = new Float32Array(data.numberOfFrames);
+        data.copyTo(samples, { planeIndex: 0, format: "f32-planar" });
+        this.emit("audioChunk", {
+          pcm: float32ToInt16(samples),
+          sampleRate: data.sampleRate,
+        });
+        data.close();
+      },
+      error: (error) => console.error("FeynmanClient: Opus decode failed.", error),
+    });
+    this.opusDecoder.configure({
+      codec: "opus",
+      sampleRate: OPUS_SAMPLE_RATE,
+      numberOfChannels: 1,
+    });
+    this.opusEncoder = new AudioEncoder({
+      output: (packet) => {
+        const bytes = new ArrayBuffer(packet.byteLength);
+        packet.copyTo(bytes);
+        if (this.ws?.readyState === WebSocket.OPEN) {
+          this.ws.send(bytes);
+        }
+      },
+      error: (error) => console.error("FeynmanClient: Opus encode failed.", error),
+    });
+    this.opusEncoder.configure({
+      codec: "opus",
+      sampleRate: OPUS_SAMPLE_RATE,
+      numberOfChannels: 1,
+      opus: { frameDuration: OPUS_FRAME_US },
+    });
+    this.opusTimestamp = 0;
+  }
+
+  private tearDownOpus(): void {
+    if (this.opusDecoder?.state !== "closed") this.opusDecoder?.close();
+    if (this.opusEncoder?.state !== "closed") this.opusEncoder?.close();
+    this.opusDecoder = null;
+    this.opusEncoder = null;
+  }
+
+  private decodeOpus(packet: Uint8Array): void {
+    this.opusDecoder?.decode(
+      new EncodedAudioChunk({ type: "key", timestamp: 0, data: packet })
+    );
   }
 
   private sendMessageToServer(message: ClientToServerMessage): void {
@@ -17,6 +17,7 @@ import {
   FeynmanClient,
   type FeynmanAgentState,
   type ChatMessage,
+  type VoiceConnectionStatus,
 } from "~/lib/feynman-client";
 import { toast } from "@revlentless/ui/components/sonner";
 import { axios } from "~/lib/axios";
@@ -32,6 +33,7 @@ type FeynmanContextType = {
   agentState: FeynmanAgentState | null;
   messages: ChatMessage[];
   liveTranscript: string;
+  voiceStatus: VoiceConnectionStatus | null;
   connect: (topic: string, sessionId?: string) => void;
   disconnect: () => void;
   sendUserMessage: (text: string) => void;
@@ -59,7 +61,11 @@ export function FeynmanProvider({ children }: { children: ReactNode }) {
   const [agentState, setAgentState] = useState<FeynmanAgentState | null>(null);
   const [messages, setMessages] = useState<ChatMessage[]>([]);
   const [liveTranscript, setLiveTranscript] = useState("");
+  const [voiceStatus, setVoiceStatus] = useState<VoiceConnectionStatus | null>(
+    null
+  );
   const clientRef = useRef<FeynmanClient | null>(null);
+  const voiceStatusRef = useRef<VoiceConnectionStatus | null>(null);
   const audioPlayerRef = useRef<AudioPlayer | null>(null);
 
   const isConnectedRef = useRef(isConnected);
@@ -94,11 +100,20 @@ export function FeynmanProvider({ children }: { children: ReactNode }) {
     clientRef.current?.sendAudioChunk(data);
   }, []);
 
+  // Voice is enabled once the recorder knows the rate it captures at.
+  const enableVoice = useCallback((sampleRate: number) => {
+    clientRef.current?.setVoiceEnabled(true, {
+      sample_rate: sampleRate,
+      channels: 1,
+      sample_format: "pcm16",
+    });
+  }, []);
+
   const {
     start: startRecorderHook,
     stop: stopRecorderHook,
     isRecording,
-  } = useVoiceRecorder(sendAudioData);
+  } = useVoiceRecorder(sendAudioData, enableVoice);
 
   const isRecordingRef = useRef(isRecording);
   useEffect(() => {
@@ -180,9 +195,24 @@ export function FeynmanProvider({ children }: { children: ReactNode }) {
     client.on("agentResponseEnd", () => setAiStatus("listening"));
     client.on("aiSpeakingStart", () => setAiStatus("speaking"));
     client.on("aiSpeakingEnd", () => setAiStatus("listening"));
-    client.on("audioChunk", ({ data }) =>
-      audioPlayerRef.current?.addChunk(data)
+    client.on("audioChunk", ({ pcm, sampleRate }) =>
+      audioPlayerRef.current?.addChunk(pcm, sampleRate)
     );
+    client.on("userSpeakingStart", () => setAiStatus("listening"));
+    client.on("userSpeakingEnd", () => setAiStatus("thinking"));
+    client.on("interrupted", () => audioPlayerRef.current?.flush());
+    client.on("voiceStatus", ({ status, attempt }) => {
+      if (status === "reconnecting" && attempt === 1) {
+        toast.warning("Voice connection lost. Reconnecting...");
+      } else if (
+        status === "connected" &&
+        voiceStatusRef.current === "reconnecting"
+      ) {
+        toast.success("Voice reconnected.");
+      }
+      voiceStatusRef.current = status;
+      setVoiceStatus(status);
+    });
     client.on("transcriptionUpdate", ({ text, isFinal }) => {
       setLiveTranscript(text);
       if (isFinal) setLiveTranscript("");
@@ -200,6 +230,8 @@ export function FeynmanProvider({ children }: { children: ReactNode }) {
       setSessionId(null);
       setAgentState(null);
       setMessages([]);
+      setVoiceStatus(null);
+      voiceStatusRef.current = null;
       audioPlayerRef.current?.stop();
       toast.warning("Disconnected from agent.");
     });
@@ -231,7 +263,6 @@ export function FeynmanProvider({ children }: { children: ReactNode }) {
       toast.error("Not connected to agent.");
       return;
     }
-    clientRef.current?.setVoiceEnabled(true);
     startRecorderHook();
   }, [startRecorderHook]);
 
@@ -250,6 +281,7 @@ export function FeynmanProvider({ children }: { children: ReactNode }) {
       agentState,
       messages,
       liveTranscript,
+      voiceStatus,
       connect,
       disconnect,
       sendUserMessage,
@@ -266,6 +298,7 @@ export function FeynmanProvider({ children }: { children: ReactNode }) {
       agentState,
       messages,
       liveTranscript,
+      voiceStatus,
       connect,
       disconnect,
       sendUserMessage,
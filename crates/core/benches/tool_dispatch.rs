@@ -0,0 +1,73 @@
+//! Compares calling the agent's tools in-process with calling them over MCP.
+//!
+//! Run with `cargo bench -p feynman-core --bench tool_dispatch`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use feynman_core::{
+    agent::{FeynmanAgent, FeynmanService},
+    tools::{McpTools, ToolRegistry},
+    topic::SubTopic,
+};
+use rmcp::model::JsonObject;
+use std::sync::Arc;
+use tokio::{runtime::Runtime, sync::Mutex};
+
+/// A service for an agent with a realistic number of subtopics.
+fn service() -> FeynmanService {
+    let subtopics = (0..12)
+        .map(|i| SubTopic::new(format!("Subtopic {}", i)))
+        .collect();
+    let agent = FeynmanAgent::new("Data Structures".to_string(), subtopics);
+    FeynmanService::new(Arc::new(Mutex::new(agent)), None)
+}
+
+fn update_args() -> JsonObject {
+    serde_json::json!({
+        "subtopic_name": "Subtopic 3",
+        "criterion": "definition",
+        "is_covered": true,
+    })
+    .as_object()
+    .cloned()
+    .unwrap()
+}
+
+fn bench_tool_dispatch(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let registries: Vec<(&str, Arc<dyn ToolRegistry>)> = vec![
+        ("direct", Arc::new(service())),
+        (
+            "mcp",
+            Arc::new(
+                runtime
+                    .block_on(McpTools::serve_in_process(service()))
+                    .unwrap(),
+            ),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("get_session_status");
+    for (name, registry) in &registries {
+        group.bench_function(*name, |b| {
+            b.to_async(&runtime)
+                .iter(|| async { registry.call("get_session_status", None).await.unwrap() })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("update_subtopic_status");
+    for (name, registry) in &registries {
+        group.bench_function(*name, |b| {
+            b.to_async(&runtime).iter(|| async {
+                registry
+                    .call("update_subtopic_status", Some(update_args()))
+                    .await
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tool_dispatch);
+criterion_main!(benches);
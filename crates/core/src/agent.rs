@@ -3,18 +3,37 @@
 //! This module implements the core Feynman learning agent that tracks educational progress
 //! through subtopics using the Model Context Protocol (MCP). The agent follows the Feynman
 //! technique principle of breaking down complex topics into understandable components.
+//! Its tools can be served over MCP or called directly through `ToolRegistry`, and
+//! over MCP it can also offer the session's resources and prompt templates.
 
-use crate::topic::SubTopic;
+use crate::{
+    prompts::{self, PromptTemplate},
+    resources::{ResourceSubscribers, SessionResources},
+    tools::ToolRegistry,
+    topic::SubTopic,
+};
+use anyhow::{Context, bail};
+use async_trait::async_trait;
 use rmcp::{
-    ServerHandler,
+    ErrorData as McpError, RoleServer, ServerHandler,
     handler::server::{router::tool::ToolRouter, tool::Parameters},
-    model::{ServerCapabilities, ServerInfo},
+    model::{
+        GetPromptRequestParam, GetPromptResult, Implementation, JsonObject, ListPromptsResult,
+        ListResourceTemplatesResult, ListResourcesResult, PaginatedRequestParam, Prompt,
+        PromptArgument, PromptMessage, PromptMessageRole, PromptsCapability,
+        ReadResourceRequestParam, ReadResourceResult, ResourcesCapability, ServerCapabilities,
+        ServerInfo, SubscribeRequestParam, Tool, UnsubscribeRequestParam,
+    },
+    service::RequestContext,
     tool, tool_handler, tool_router,
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{
+    Arc, LazyLock,
+    atomic::{AtomicU64, Ordering},
+};
 use tokio::sync::mpsc;
 use tracing::info;
 
@@ -52,6 +71,16 @@ impl FeynmanAgent {
     }
 }
 
+/// Where a session's agent state is kept when others may change it too, e.g.
+/// a database shared with other processes serving the session.
+#[async_trait]
+pub trait AgentStateStore: Send + Sync {
+    /// The latest stored state.
+    async fn load(&self) -> anyhow::Result<FeynmanAgent>;
+    /// Stores a new version of the state.
+    async fn save(&self, state: &FeynmanAgent) -> anyhow::Result<()>;
+}
+
 // --- Data Structures for Tools ---
 
 /// Arguments for updating the learning status of a specific subtopic criterion.
@@ -72,6 +101,17 @@ pub struct UpdateSubtopicStatusArgs {
 
 // --- Service and Handler Implementation ---
 
+/// The declarations of the service's tools, generated once per process and
+/// sorted so prompts list them in a stable order.
+static TOOLS: LazyLock<Vec<Tool>> = LazyLock::new(|| {
+    let mut tools = FeynmanService::tool_router().list_all();
+    tools.sort_by(|a, b| a.name.cmp(&b.name));
+    tools
+});
+
+/// Numbers service instances, which each serve one MCP connection.
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
 /// The main service implementation for the Feynman learning agent.
 ///
 /// This service provides MCP (Model Context Protocol) tools that allow external
@@ -81,19 +121,180 @@ pub struct FeynmanService {
     pub agent_state: Arc<tokio::sync::Mutex<FeynmanAgent>>,
     /// Optional channel for broadcasting state changes to subscribers.
     pub state_tx: Option<mpsc::Sender<FeynmanAgent>>,
+    /// Where the state is reloaded from before each tool call and saved to
+    /// after each change, if others may change it.
+    store: Option<Arc<dyn AgentStateStore>>,
     /// MCP tool router for handling incoming tool calls.
     tool_router: ToolRouter<Self>,
+    /// The session's resources, offered when serving a stored session.
+    resources: Option<SessionResources>,
+    /// Prompt templates offered to MCP clients.
+    prompts: Vec<PromptTemplate>,
+    /// The clients subscribed to the session's resources, across connections.
+    subscribers: ResourceSubscribers,
+    /// Identifies this instance's connection among the subscribers.
+    connection: u64,
 }
 
 #[tool_handler]
 impl ServerHandler for FeynmanService {
-    /// Returns server information and capabilities, advertising tool support.
+    /// Returns server information and capabilities, advertising tool support,
+    /// and resource and prompt support when they are configured.
     fn get_info(&self) -> ServerInfo {
+        let mut capabilities = ServerCapabilities::builder().enable_tools().build();
+        if self.resources.is_some() {
+            capabilities.resources = Some(ResourcesCapability {
+                subscribe: Some(true),
+                list_changed: None,
+            });
+        }
+        if !self.prompts.is_empty() {
+            capabilities.prompts = Some(PromptsCapability::default());
+        }
         ServerInfo {
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities,
+            server_info: Implementation::from_build_env(),
+            instructions: Some(
+                "You are a curious student learning a topic through the Feynman technique. \
+                 Check the session status, mark each subtopic's definition, mechanism and \
+                 example as covered once the user has explained it, and conclude the \
+                 session when every subtopic is covered."
+                    .to_string(),
+            ),
             ..Default::default()
         }
     }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let resources = match &self.resources {
+            Some(resources) => resources.list(&*self.agent_state.lock().await),
+            None => Vec::new(),
+        };
+        Ok(ListResourcesResult {
+            resources,
+            next_cursor: None,
+        })
+    }
+
+    async fn list_resource_templates(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourceTemplatesResult, McpError> {
+        Ok(ListResourceTemplatesResult {
+            resource_templates: self
+                .resources
+                .as_ref()
+                .map(SessionResources::templates)
+                .unwrap_or_default(),
+            next_cursor: None,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        let resources = self
+            .resources
+            .as_ref()
+            .ok_or_else(|| McpError::resource_not_found("No resources are offered", None))?;
+        // Clone the state so a slow transcript read doesn't hold up tool calls.
+        let agent = self.agent_state.lock().await.clone();
+        let contents = resources
+            .read(&request.uri, &agent)
+            .await
+            .map_err(|e| McpError::resource_not_found(e.to_string(), None))?;
+        Ok(ReadResourceResult {
+            contents: vec![contents],
+        })
+    }
+
+    async fn subscribe(
+        &self,
+        request: SubscribeRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.subscribers
+            .subscribe(self.connection, context.peer, request.uri);
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &self,
+        request: UnsubscribeRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.subscribers.unsubscribe(self.connection, &request.uri);
+        Ok(())
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        // Arguments the session can fill in itself are optional.
+        let session_arguments = prompts::session_arguments(&*self.agent_state.lock().await);
+        let prompts = self
+            .prompts
+            .iter()
+            .map(|template| {
+                let arguments = template
+                    .arguments()
+                    .into_iter()
+                    .map(|name| PromptArgument {
+                        name: name.to_string(),
+                        description: None,
+                        required: Some(!session_arguments.contains_key(name)),
+                    })
+                    .collect();
+                Prompt::new(
+                    &template.name,
+                    Some(template.description()),
+                    Some(arguments),
+                )
+            })
+            .collect();
+        Ok(ListPromptsResult {
+            prompts,
+            next_cursor: None,
+        })
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        let template = self
+            .prompts
+            .iter()
+            .find(|template| template.name == request.name)
+            .ok_or_else(|| {
+                McpError::invalid_params(format!("Unknown prompt: '{}'", request.name), None)
+            })?;
+        let mut arguments = prompts::session_arguments(&*self.agent_state.lock().await);
+        for (name, value) in request.arguments.unwrap_or_default() {
+            let value = match value {
+                serde_json::Value::String(text) => text,
+                other => other.to_string(),
+            };
+            arguments.insert(name, value);
+        }
+        let text = template
+            .render(&arguments)
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+        Ok(GetPromptResult {
+            description: Some(template.description().to_string()),
+            messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+        })
+    }
 }
 
 #[tool_router]
@@ -106,10 +307,53 @@ impl FeynmanService {
         Self {
             agent_state,
             state_tx,
+            store: None,
             tool_router: Self::tool_router(),
+            resources: None,
+            prompts: Vec::new(),
+            subscribers: ResourceSubscribers::default(),
+            connection: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
         }
     }
 
+    /// Reloads the state from `store` before each tool call and saves each
+    /// change to it before the next call, so tools never act on a stale state.
+    pub fn with_store(mut self, store: Arc<dyn AgentStateStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Locks the agent state, first reloading it from the store if there is one.
+    async fn lock_latest(&self) -> Result<tokio::sync::MutexGuard<'_, FeynmanAgent>, String> {
+        let mut agent = self.agent_state.lock().await;
+        if let Some(store) = &self.store {
+            *agent = store
+                .load()
+                .await
+                .map_err(|e| format!("Failed to load the session state: {}", e))?;
+        }
+        Ok(agent)
+    }
+
+    /// Offers the session's resources to MCP clients.
+    pub fn with_resources(mut self, resources: SessionResources) -> Self {
+        self.resources = Some(resources);
+        self
+    }
+
+    /// Shares the clients subscribed to the session's resources with other
+    /// connections, so whoever watches the session can notify all of them.
+    pub fn with_subscribers(mut self, subscribers: ResourceSubscribers) -> Self {
+        self.subscribers = subscribers;
+        self
+    }
+
+    /// Offers prompt templates to MCP clients.
+    pub fn with_prompts(mut self, prompts: Vec<PromptTemplate>) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
     /// Retrieves the current status of the entire learning session.
     ///
     /// This tool provides a complete snapshot of the agent's state, including
@@ -119,7 +363,7 @@ impl FeynmanService {
     )]
     pub async fn get_session_status(&self) -> Result<String, String> {
         info!("Executing tool 'get_session_status'");
-        let agent = self.agent_state.lock().await;
+        let agent = self.lock_latest().await?;
         serde_json::to_string(&*agent)
             .map_err(|e| format!("Failed to serialize agent state: {}", e))
     }
@@ -138,7 +382,7 @@ impl FeynmanService {
         args: Parameters<UpdateSubtopicStatusArgs>,
     ) -> Result<String, String> {
         info!(args = ?args.0, "Executing tool 'update_subtopic_status'");
-        let mut agent = self.agent_state.lock().await;
+        let mut agent = self.lock_latest().await?;
         let subtopic_name = &args.0.subtopic_name;
 
         let result = if let Some(subtopic) = agent.incomplete_subtopics.get_mut(subtopic_name) {
@@ -178,12 +422,20 @@ impl FeynmanService {
             Err(format!("Subtopic '{}' not found.", subtopic_name))
         };
 
-        if let Some(tx) = &self.state_tx {
-            if tx.send(agent.clone()).await.is_err() {
-                tracing::warn!("Failed to broadcast state update: receiver dropped.");
-            }
+        if let Some(store) = &self.store
+            && result.is_ok()
+        {
+            store
+                .save(&agent)
+                .await
+                .map_err(|e| format!("Failed to save the session state: {}", e))?;
         }
 
+        if let Some(tx) = &self.state_tx
+            && tx.send(agent.clone()).await.is_err()
+        {
+            tracing::warn!("Failed to broadcast state update: receiver dropped.");
+        }
         result
     }
 
@@ -202,4 +454,95 @@ impl FeynmanService {
         // handled by a separate mechanism (e.g., a REST API call).
         Ok("OK. Session will be concluded.".to_string())
     }
-}
\ No newline at end of file
+}
+
+impl Drop for FeynmanService {
+    fn drop(&mut self) {
+        self.subscribers.remove(self.connection);
+    }
+}
+
+/// Calls the service's tools in-process, without going through MCP.
+#[async_trait]
+impl ToolRegistry for FeynmanService {
+    fn tools(&self) -> &[Tool] {
+        &TOOLS
+    }
+
+    async fn call(&self, name: &str, arguments: Option<JsonObject>) -> anyhow::Result<String> {
+        let output = match name {
+            "get_session_status" => self.get_session_status().await,
+            "update_subtopic_status" => {
+                let args = serde_json::from_value(serde_json::Value::Object(
+                    arguments.unwrap_or_default(),
+                ))
+                .context("Invalid arguments for 'update_subtopic_status'")?;
+                self.update_subtopic_status(Parameters(args)).await
+            }
+            "conclude_session" => self.conclude_session().await,
+            _ => bail!("Unknown tool: '{}'", name),
+        };
+        // Tool errors are returned as text, as they would be over MCP.
+        Ok(output.unwrap_or_else(|e| e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store another session writes to as well.
+    struct SharedStore(std::sync::Mutex<FeynmanAgent>);
+
+    #[async_trait]
+    impl AgentStateStore for SharedStore {
+        async fn load(&self) -> anyhow::Result<FeynmanAgent> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        async fn save(&self, state: &FeynmanAgent) -> anyhow::Result<()> {
+            *self.0.lock().unwrap() = state.clone();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tools_start_from_the_stored_state() {
+        let agent = FeynmanAgent::new(
+            "Graphs".to_string(),
+            vec![
+                SubTopic::new("Nodes".to_string()),
+                SubTopic::new("Edges".to_string()),
+            ],
+        );
+        let store = Arc::new(SharedStore(std::sync::Mutex::new(agent.clone())));
+        let service = FeynmanService::new(Arc::new(tokio::sync::Mutex::new(agent)), None)
+            .with_store(store.clone());
+
+        // Another session covers a criterion the service hasn't seen.
+        store
+            .0
+            .lock()
+            .unwrap()
+            .incomplete_subtopics
+            .get_mut("Edges")
+            .unwrap()
+            .has_example = true;
+
+        let update = UpdateSubtopicStatusArgs {
+            subtopic_name: "Nodes".to_string(),
+            criterion: "definition".to_string(),
+            is_covered: true,
+        };
+        service
+            .update_subtopic_status(Parameters(update))
+            .await
+            .unwrap();
+
+        let stored = store.0.lock().unwrap().clone();
+        assert!(stored.incomplete_subtopics["Edges"].has_example);
+        assert!(stored.incomplete_subtopics["Nodes"].has_definition);
+        let status = service.get_session_status().await.unwrap();
+        assert!(status.contains("\"has_example\":true"));
+    }
+}
@@ -4,17 +4,18 @@
 //! topics into manageable subtopics. It serves as the foundation for initializing
 //! learning sessions in the Feynman agent system.
 
-use anyhow::{Context, Result};
-use async_openai::{
-    Client,
-    config::OpenAIConfig,
-    types::{
-        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-        CreateChatCompletionRequestArgs,
-    },
-};
+use crate::llm_client::{ChatMessage, LLMAction, LLMClient, Usage};
+use anyhow::{Context, Result, bail};
 use async_trait::async_trait;
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
+
+/// The subtopics generated for a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Curriculum {
+    pub subtopics: Vec<String>,
+    /// The tokens used to generate them, if an LLM did.
+    pub usage: Option<Usage>,
+}
 
 /// Defines the contract for any service that can generate a curriculum.
 ///
@@ -34,18 +35,17 @@ pub trait CurriculumService: Send + Sync {
     ///
     /// # Returns
     ///
-    /// A `Result` containing a vector of subtopic names or an error.
-    async fn generate_subtopics(&self, topic: &str) -> Result<Vec<String>>;
+    /// A `Result` containing the subtopic names, with the usage that produced them, or an error.
+    async fn generate_subtopics(&self, topic: &str) -> Result<Curriculum>;
 }
 
-/// An implementation of `CurriculumService` that uses an OpenAI-compatible API.
+/// An implementation of `CurriculumService` that asks an LLM.
 ///
 /// This service leverages Large Language Models to generate contextually
 /// appropriate subtopics for any given topic, providing dynamic and intelligent
 /// curriculum generation.
 pub struct LLMCurriculumService {
-    client: Client<OpenAIConfig>,
-    model: String,
+    llm_client: Arc<dyn LLMClient>,
     prompts: HashMap<String, String>,
 }
 
@@ -54,14 +54,12 @@ impl LLMCurriculumService {
     ///
     /// # Arguments
     ///
-    /// * `config` - OpenAI API configuration (API key, base URL, etc.).
-    /// * `model` - Model identifier to use for generation (e.g., "gpt-4o").
+    /// * `llm_client` - The LLM to generate with, from any provider.
     /// * `prompts` - A map of template strings, which must include a key
     ///   for `"generate_subtopics"`.
-    pub fn new(config: OpenAIConfig, model: String, prompts: HashMap<String, String>) -> Self {
+    pub fn new(llm_client: Arc<dyn LLMClient>, prompts: HashMap<String, String>) -> Self {
         Self {
-            client: Client::with_config(config),
-            model,
+            llm_client,
             prompts,
         }
     }
@@ -69,44 +67,31 @@ impl LLMCurriculumService {
 
 #[async_trait]
 impl CurriculumService for LLMCurriculumService {
-    async fn generate_subtopics(&self, topic: &str) -> Result<Vec<String>> {
+    async fn generate_subtopics(&self, topic: &str) -> Result<Curriculum> {
         let prompt_template = self
             .prompts
             .get("generate_subtopics")
             .context("Missing prompt template: 'generate_subtopics'")?;
         let prompt = prompt_template.replace("{topic}", topic);
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(&self.model)
-            .messages(vec![
-                ChatCompletionRequestSystemMessageArgs::default()
-                    .content("You are a helpful assistant that generates curriculum.")
-                    .build()?
-                    .into(),
-                ChatCompletionRequestUserMessageArgs::default()
-                    .content(prompt)
-                    .build()?
-                    .into(),
-            ])
-            .build()?;
-
-        let response = self.client.chat().create(request).await?;
-
-        let answer = response
-            .choices
-            .get(0)
-            .context("No response choice from LLM")?
-            .message
-            .content
-            .as_ref()
-            .context("No content in LLM response")?;
+        let messages = vec![
+            ChatMessage::System(
+                "You are a helpful assistant that generates curriculum.".to_string(),
+            ),
+            ChatMessage::User(prompt),
+        ];
+        let decision = self.llm_client.decide_action(messages, vec![]).await?;
+        let answer = match decision.action {
+            LLMAction::TextResponse(answer) => answer,
+            LLMAction::ToolCall(_) => bail!("LLM called a tool instead of listing subtopics"),
+        };
 
         // Parse structured subtopics from the response by looking for list items.
         let subtopics: Vec<String> = answer
             .lines()
             .filter_map(|line| {
                 let line = line.trim();
-                if let Some(idx) = line.find(|c: char| c == '.' || c == ')') {
+                if let Some(idx) = line.find(['.', ')']) {
                     let name = line[idx + 1..].trim().to_string();
                     if !name.is_empty() {
                         return Some(name);
@@ -116,7 +101,10 @@ impl CurriculumService for LLMCurriculumService {
             })
             .collect();
 
-        Ok(subtopics)
+        Ok(Curriculum {
+            subtopics,
+            usage: decision.usage,
+        })
     }
 }
 
@@ -132,12 +120,15 @@ impl CurriculumService for MockCurriculumService {
     ///
     /// This implementation provides a consistent curriculum structure that
     /// follows a logical progression from introductory to advanced concepts.
-    async fn generate_subtopics(&self, topic: &str) -> Result<Vec<String>> {
-        Ok(vec![
-            format!("Introduction to {}", topic),
-            "Core Concepts".to_string(),
-            "Practical Applications".to_string(),
-            "Advanced Topics".to_string(),
-        ])
+    async fn generate_subtopics(&self, topic: &str) -> Result<Curriculum> {
+        Ok(Curriculum {
+            subtopics: vec![
+                format!("Introduction to {}", topic),
+                "Core Concepts".to_string(),
+                "Practical Applications".to_string(),
+                "Advanced Topics".to_string(),
+            ],
+            usage: None,
+        })
     }
 }
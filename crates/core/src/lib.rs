@@ -2,7 +2,10 @@ pub mod agent;
 pub mod curriculum;
 pub mod generic_types;
 pub mod llm_client;
+pub mod prompts;
 pub mod realtime_api;
+pub mod resources;
+pub mod tools;
 pub mod topic;
 
 /// Represents commands that the core logic issues to an external runtime.
@@ -0,0 +1,379 @@
+//! A client for the Anthropic Messages API.
+
+use super::{
+    ChatMessage, LLMAction, LLMClient, LLMDecision, LLMStream, LLMStreamEvent, ToolCall,
+    ToolDefinition, Usage, body_lines, check_status,
+};
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::{Value, json};
+
+/// The default Anthropic API endpoint.
+pub const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com";
+/// The version of the Messages API the requests are written for.
+const API_VERSION: &str = "2023-06-01";
+/// The Messages API requires a limit; replies in a session are far shorter.
+const MAX_TOKENS: u32 = 4096;
+
+/// An implementation of `LLMClient` for the Anthropic Messages API.
+pub struct AnthropicClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl AnthropicClient {
+    /// Creates a new client.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The API endpoint, e.g. `DEFAULT_ANTHROPIC_BASE_URL` or a local stand-in.
+    /// * `api_key` - The key sent in the `x-api-key` header.
+    /// * `model` - The model identifier (e.g., "claude-sonnet-4-5").
+    pub fn new(base_url: String, api_key: String, model: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+        }
+    }
+
+    /// Builds a request body. Tools are declared whenever given, but only
+    /// offered for use when `offer_tools` is set.
+    fn request(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+        offer_tools: bool,
+        stream: bool,
+    ) -> Value {
+        let (system, messages) = to_anthropic_messages(messages);
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": MAX_TOKENS,
+            "messages": messages,
+            "stream": stream,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system);
+        }
+        if !tools.is_empty() {
+            body["tools"] = tools
+                .into_iter()
+                .map(|tool| {
+                    json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.parameters,
+                    })
+                })
+                .collect();
+            body["tool_choice"] = json!({ "type": if offer_tools { "auto" } else { "none" } });
+        }
+        body
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response> {
+        let response = self
+            .http
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(body)
+            .send()
+            .await?;
+        check_status(response).await
+    }
+}
+
+/// Stands in for the user when the conversation opens with the agent's greeting.
+const OPENING_USER_TEXT: &str = "(The session has started.)";
+
+/// Splits off the system prompt and converts the rest to content blocks.
+///
+/// Tool results are sent as user messages, and consecutive messages from the
+/// same side are merged, since the API expects the two sides to alternate,
+/// starting with the user.
+fn to_anthropic_messages(messages: Vec<ChatMessage>) -> (String, Vec<Value>) {
+    let mut system = Vec::new();
+    let mut converted: Vec<(&str, Vec<Value>)> = Vec::new();
+    for message in messages {
+        let (role, blocks) = match message {
+            ChatMessage::System(content) => {
+                system.push(content);
+                continue;
+            }
+            ChatMessage::User(content) => ("user", vec![json!({"type": "text", "text": content})]),
+            ChatMessage::Assistant {
+                content,
+                tool_calls,
+            } => {
+                let mut blocks = Vec::new();
+                if !content.is_empty() {
+                    blocks.push(json!({"type": "text", "text": content}));
+                }
+                blocks.extend(tool_calls.into_iter().map(|call| {
+                    json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.name,
+                        "input": call.arguments,
+                    })
+                }));
+                ("assistant", blocks)
+            }
+            ChatMessage::Tool {
+                call_id, content, ..
+            } => (
+                "user",
+                vec![json!({"type": "tool_result", "tool_use_id": call_id, "content": content})],
+            ),
+        };
+        match converted.last_mut() {
+            Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+            None if role == "assistant" => converted.extend([
+                (
+                    "user",
+                    vec![json!({"type": "text", "text": OPENING_USER_TEXT})],
+                ),
+                (role, blocks),
+            ]),
+            _ => converted.push((role, blocks)),
+        }
+    }
+    let messages = converted
+        .into_iter()
+        .map(|(role, content)| json!({"role": role, "content": content}))
+        .collect();
+    (system.join("\n\n"), messages)
+}
+
+#[async_trait]
+impl LLMClient for AnthropicClient {
+    async fn decide_action(
+        &self,
+        history_with_user_message: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMDecision> {
+        let body = self.request(history_with_user_message, tools, true, false);
+        let response: Value = self.send(&body).await?.json().await?;
+        let blocks = response["content"]
+            .as_array()
+            .context("Anthropic response had no content")?;
+
+        let mut text = String::new();
+        let mut tool_calls = Vec::new();
+        for block in blocks {
+            match block["type"].as_str() {
+                Some("text") => text.push_str(block["text"].as_str().unwrap_or_default()),
+                Some("tool_use") => tool_calls.push(ToolCall {
+                    id: block["id"].as_str().unwrap_or_default().to_string(),
+                    name: block["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: block["input"].as_object().cloned().unwrap_or_default(),
+                }),
+                _ => {}
+            }
+        }
+
+        let action = if !tool_calls.is_empty() {
+            LLMAction::ToolCall(tool_calls)
+        } else if !text.is_empty() {
+            LLMAction::TextResponse(text)
+        } else {
+            return Err(anyhow!(
+                "LLM response had neither text content nor tool calls."
+            ));
+        };
+        let usage = &response["usage"];
+        Ok(LLMDecision {
+            action,
+            usage: usage.is_object().then(|| Usage {
+                model: self.model.clone(),
+                prompt_tokens: token_count(&usage["input_tokens"]),
+                completion_tokens: token_count(&usage["output_tokens"]),
+            }),
+        })
+    }
+
+    async fn stream_after_tools(
+        &self,
+        history_with_tool_results: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMStream> {
+        let body = self.request(history_with_tool_results, tools, false, true);
+        let response = self.send(&body).await?;
+
+        let model = self.model.clone();
+        let events = body_lines(response).scan(0, move |prompt_tokens, line| {
+            let event = line.and_then(|line| stream_event(&line, &model, prompt_tokens));
+            futures::future::ready(Some(event))
+        });
+        Ok(Box::pin(
+            events.filter_map(|event| async { event.transpose() }),
+        ))
+    }
+}
+
+/// Reads one line of the event stream, if it carries an event of interest.
+///
+/// The prompt's tokens are reported when the message starts and kept in
+/// `prompt_tokens` until the reply's are reported at its end.
+fn stream_event(
+    line: &str,
+    model: &str,
+    prompt_tokens: &mut u32,
+) -> Result<Option<LLMStreamEvent>> {
+    let Some(event) = line
+        .strip_prefix("data:")
+        .and_then(|data| serde_json::from_str::<Value>(data.trim()).ok())
+    else {
+        return Ok(None);
+    };
+    Ok(match event["type"].as_str() {
+        Some("message_start") => {
+            *prompt_tokens = token_count(&event["message"]["usage"]["input_tokens"]);
+            None
+        }
+        Some("message_delta") if event["usage"].is_object() => Some(LLMStreamEvent::Usage(Usage {
+            model: model.to_string(),
+            prompt_tokens: *prompt_tokens,
+            completion_tokens: token_count(&event["usage"]["output_tokens"]),
+        })),
+        Some("content_block_delta") if event["delta"]["type"] == "text_delta" => {
+            event["delta"]["text"]
+                .as_str()
+                .map(|text| LLMStreamEvent::TextChunk(text.to_string()))
+        }
+        Some("error") => {
+            return Err(anyhow!(
+                "Anthropic stream failed: {}",
+                event["error"]["message"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+            ));
+        }
+        _ => None,
+    })
+}
+
+/// Reads a token count, treating a missing one as zero.
+fn token_count(value: &Value) -> u32 {
+    value.as_u64().unwrap_or_default() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_client::stub::StubServer;
+
+    fn tools() -> Vec<ToolDefinition> {
+        vec![ToolDefinition {
+            name: "get_session_status".to_string(),
+            description: "Gets the status.".to_string(),
+            parameters: json!({"type": "object", "properties": {}}),
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_tool_call_and_stream() -> Result<()> {
+        let mut stub = StubServer::start(
+            "/v1/messages",
+            vec![
+                (
+                    "application/json",
+                    json!({"content": [
+                        {"type": "text", "text": "Let me check."},
+                        {"type": "tool_use", "id": "toolu_1", "name": "get_session_status", "input": {}},
+                    ], "usage": {"input_tokens": 120, "output_tokens": 15}})
+                    .to_string(),
+                ),
+                (
+                    "text/event-stream",
+                    [
+                        r#"event: message_start"#,
+                        r#"data: {"type": "message_start", "message": {"usage": {"input_tokens": 150, "output_tokens": 1}}}"#,
+                        "",
+                        r#"data: {"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "What is "}}"#,
+                        "",
+                        r#"data: {"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "a node?"}}"#,
+                        "",
+                        r#"data: {"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 6}}"#,
+                        "",
+                        r#"data: {"type": "message_stop"}"#,
+                        "",
+                    ]
+                    .join("\n"),
+                ),
+            ],
+        )
+        .await;
+        let client = AnthropicClient::new(stub.base_url(), "key".into(), "claude-test".into());
+
+        let mut history = vec![
+            ChatMessage::System("You are a student.".to_string()),
+            ChatMessage::assistant("Hello!"),
+            ChatMessage::User("Nodes hold values.".to_string()),
+        ];
+        let decision = client.decide_action(history.clone(), tools()).await?;
+        let LLMAction::ToolCall(calls) = decision.action else {
+            panic!("expected a tool call");
+        };
+        assert_eq!(
+            decision.usage,
+            Some(Usage {
+                model: "claude-test".to_string(),
+                prompt_tokens: 120,
+                completion_tokens: 15,
+            })
+        );
+        assert_eq!(calls[0].id, "toolu_1");
+        assert_eq!(calls[0].name, "get_session_status");
+
+        let (headers, request) = stub.next_request().await;
+        assert_eq!(headers["x-api-key"], "key");
+        assert_eq!(headers["anthropic-version"], API_VERSION);
+        assert_eq!(request["system"], "You are a student.");
+        assert_eq!(
+            request["messages"][0]["content"][0]["text"],
+            OPENING_USER_TEXT
+        );
+        assert_eq!(request["messages"][1]["role"], "assistant");
+        assert_eq!(request["tools"][0]["input_schema"]["type"], "object");
+        assert_eq!(request["tool_choice"]["type"], "auto");
+
+        history.push(ChatMessage::Assistant {
+            content: String::new(),
+            tool_calls: calls,
+        });
+        history.push(ChatMessage::Tool {
+            call_id: "toolu_1".to_string(),
+            name: "get_session_status".to_string(),
+            content: "{}".to_string(),
+        });
+        let mut stream = client.stream_after_tools(history, tools()).await?;
+        let mut reply = String::new();
+        let mut usage = None;
+        while let Some(event) = stream.next().await.transpose()? {
+            match event {
+                LLMStreamEvent::TextChunk(chunk) => reply.push_str(&chunk),
+                LLMStreamEvent::Usage(reported) => usage = Some(reported),
+            }
+        }
+        assert_eq!(reply, "What is a node?");
+        let usage = usage.expect("no usage reported");
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (150, 6));
+
+        let (_, request) = stub.next_request().await;
+        assert_eq!(request["stream"], true);
+        assert_eq!(request["tool_choice"]["type"], "none");
+        let messages = request["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[3]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[4]["role"], "user");
+        assert_eq!(messages[4]["content"][0]["tool_use_id"], "toolu_1");
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! LLM Clients
+//!
+//! The ReAct cycle and curriculum generation talk to an LLM through the
+//! `LLMClient` trait, using the provider-neutral message and tool types
+//! defined here. Each submodule implements the trait for one kind of API:
+//!
+//! - `openai`: any OpenAI-compatible Chat Completions API, including Gemini's.
+//! - `anthropic`: the Anthropic Messages API.
+//! - `ollama`: a local Ollama server's chat API.
+//!
+//! `resilient` wraps any of them with timeouts, retries and fallbacks.
+
+pub mod anthropic;
+pub mod ollama;
+pub mod openai;
+pub mod resilient;
+#[cfg(test)]
+mod stub;
+
+pub use anthropic::AnthropicClient;
+pub use ollama::OllamaClient;
+pub use openai::OpenAICompatibleClient;
+pub use resilient::{CircuitBreaker, ResilienceSettings, ResilientClient, Route};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use serde_json::{Map, Value};
+use std::pin::Pin;
+
+/// A JSON object, as used for tool arguments.
+pub type JsonObject = Map<String, Value>;
+
+/// One message of a conversation with an LLM.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatMessage {
+    /// Instructions for the LLM. Providers without a system role receive these separately.
+    System(String),
+    User(String),
+    /// A reply from the LLM, with any tools it called.
+    Assistant {
+        content: String,
+        tool_calls: Vec<ToolCall>,
+    },
+    /// The result of a tool call, answering the call with the same ID.
+    Tool {
+        call_id: String,
+        name: String,
+        content: String,
+    },
+}
+
+impl ChatMessage {
+    /// A plain text reply from the LLM.
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::Assistant {
+            content: content.into(),
+            tool_calls: vec![],
+        }
+    }
+}
+
+/// A tool call requested by the LLM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    /// Identifies the call, so its result can be matched to it.
+    pub id: String,
+    pub name: String,
+    pub arguments: JsonObject,
+}
+
+/// A tool the LLM may call, described by a JSON schema of its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+impl From<&rmcp::model::Tool> for ToolDefinition {
+    fn from(tool: &rmcp::model::Tool) -> Self {
+        Self {
+            name: tool.name.to_string(),
+            description: tool.description.clone().unwrap_or_default().to_string(),
+            parameters: Value::Object((*tool.input_schema).clone()),
+        }
+    }
+}
+
+/// The tokens one call to an LLM consumed, as its provider reported them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// The model that answered.
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+/// Represents the events that can be yielded from a streaming text response.
+#[derive(Debug, Clone)]
+pub enum LLMStreamEvent {
+    TextChunk(String),
+    /// The tokens the whole reply used, sent after its last chunk when the provider reports them.
+    Usage(Usage),
+}
+
+/// A stream of text chunks from the LLM.
+pub type LLMStream = Pin<Box<dyn Stream<Item = Result<LLMStreamEvent>> + Send>>;
+
+/// Represents the two possible outcomes of the LLM's initial decision-making turn.
+#[derive(Debug, Clone)]
+pub enum LLMAction {
+    /// The LLM decided to respond directly with text.
+    TextResponse(String),
+    /// The LLM decided to call one or more tools.
+    ToolCall(Vec<ToolCall>),
+}
+
+/// The action the LLM decided on, with the tokens the decision used.
+#[derive(Debug, Clone)]
+pub struct LLMDecision {
+    pub action: LLMAction,
+    /// `None` when the provider didn't report usage.
+    pub usage: Option<Usage>,
+}
+
+/// A generic client for interacting with an LLM.
+#[async_trait]
+pub trait LLMClient: Send + Sync {
+    /// Makes a single, non-streaming call to the LLM to decide on the next action.
+    async fn decide_action(
+        &self,
+        history_with_user_message: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMDecision>;
+
+    /// Makes a streaming call to the LLM after tools have been executed.
+    ///
+    /// `tools` are the tools declared to `decide_action`, which some providers
+    /// need in order to read the earlier calls. They are not offered again.
+    async fn stream_after_tools(
+        &self,
+        history_with_tool_results: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMStream>;
+}
+
+/// A provider answered with an error status.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LLM API returned {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Fails with the response body if a provider answered with an error status.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(StatusError { status, body }.into())
+}
+
+/// Whether a status may succeed when the request is repeated: timeouts,
+/// rate limits and server errors.
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// Whether a failed call may succeed when repeated, as opposed to one the
+/// provider rejected, such as a bad request or key.
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    use async_openai::error::OpenAIError;
+
+    let transient_transport = |e: &reqwest::Error| {
+        e.is_timeout() || e.is_connect() || e.status().is_some_and(is_retryable_status)
+    };
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<StatusError>() {
+            is_retryable_status(e.status)
+        } else if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            transient_transport(e)
+        } else if let Some(e) = cause.downcast_ref::<OpenAIError>() {
+            match e {
+                OpenAIError::Reqwest(e) => transient_transport(e),
+                // Server errors arrive as a bare message, without a type or code.
+                OpenAIError::ApiError(api) => {
+                    (api.r#type.is_none() && api.code.is_none())
+                        || api.code.as_deref() == Some("rate_limit_exceeded")
+                }
+                OpenAIError::StreamError(_) => true,
+                _ => false,
+            }
+        } else {
+            false
+        }
+    })
+}
+
+/// Splits a streamed response body into lines, as sent by both server-sent
+/// events and newline-delimited JSON.
+fn body_lines(response: reqwest::Response) -> impl Stream<Item = Result<String>> + Send {
+    let bytes = Box::pin(response.bytes_stream());
+    futures::stream::unfold(
+        (bytes, Vec::new(), false),
+        |(mut bytes, mut buffer, mut done)| async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                    return Some((Ok(line), (bytes, buffer, done)));
+                }
+                if done {
+                    if buffer.is_empty() {
+                        return None;
+                    }
+                    let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+                    buffer.clear();
+                    return Some((Ok(line), (bytes, buffer, done)));
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(e)) => {
+                        buffer.clear();
+                        return Some((Err(e.into()), (bytes, buffer, true)));
+                    }
+                    None => done = true,
+                }
+            }
+        },
+    )
+}
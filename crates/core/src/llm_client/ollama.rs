@@ -0,0 +1,287 @@
+//! A client for the chat API of a local Ollama server.
+
+use super::{
+    ChatMessage, LLMAction, LLMClient, LLMDecision, LLMStream, LLMStreamEvent, ToolCall,
+    ToolDefinition, Usage, body_lines, check_status,
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::{Value, json};
+
+/// Where Ollama listens by default.
+pub const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+/// An implementation of `LLMClient` for Ollama.
+pub struct OllamaClient {
+    http: reqwest::Client,
+    base_url: String,
+    model: String,
+}
+
+impl OllamaClient {
+    /// Creates a new client.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The server, e.g. `DEFAULT_OLLAMA_BASE_URL` or a local stand-in.
+    /// * `model` - A model the server has pulled (e.g., "llama3.1").
+    pub fn new(base_url: String, model: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model,
+        }
+    }
+
+    async fn send(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+        stream: bool,
+    ) -> Result<reqwest::Response> {
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages.into_iter().map(to_ollama_message).collect::<Vec<_>>(),
+            "stream": stream,
+        });
+        if !tools.is_empty() {
+            body["tools"] = tools
+                .into_iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters,
+                        },
+                    })
+                })
+                .collect();
+        }
+        let response = self
+            .http
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&body)
+            .send()
+            .await?;
+        check_status(response).await
+    }
+}
+
+/// Reads the token counts Ollama adds to its final response.
+fn ollama_usage(model: &str, response: &Value) -> Option<Usage> {
+    let (prompt, completion) = (&response["prompt_eval_count"], &response["eval_count"]);
+    (prompt.is_u64() || completion.is_u64()).then(|| Usage {
+        model: model.to_string(),
+        prompt_tokens: prompt.as_u64().unwrap_or_default() as u32,
+        completion_tokens: completion.as_u64().unwrap_or_default() as u32,
+    })
+}
+
+fn to_ollama_message(message: ChatMessage) -> Value {
+    match message {
+        ChatMessage::System(content) => json!({"role": "system", "content": content}),
+        ChatMessage::User(content) => json!({"role": "user", "content": content}),
+        ChatMessage::Assistant {
+            content,
+            tool_calls,
+        } => {
+            let mut message = json!({"role": "assistant", "content": content});
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = tool_calls
+                    .into_iter()
+                    .map(|call| json!({"function": {"name": call.name, "arguments": call.arguments}}))
+                    .collect();
+            }
+            message
+        }
+        ChatMessage::Tool { name, content, .. } => {
+            json!({"role": "tool", "tool_name": name, "content": content})
+        }
+    }
+}
+
+#[async_trait]
+impl LLMClient for OllamaClient {
+    async fn decide_action(
+        &self,
+        history_with_user_message: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMDecision> {
+        let response: Value = self
+            .send(history_with_user_message, tools, false)
+            .await?
+            .json()
+            .await?;
+        let message = &response["message"];
+
+        // Ollama doesn't identify tool calls, so they are numbered in order.
+        let tool_calls: Vec<ToolCall> = message["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, call)| ToolCall {
+                id: format!("call_{}", i),
+                name: call["function"]["name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                arguments: call["function"]["arguments"]
+                    .as_object()
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        let action = match message["content"].as_str() {
+            _ if !tool_calls.is_empty() => LLMAction::ToolCall(tool_calls),
+            Some(content) if !content.is_empty() => LLMAction::TextResponse(content.into()),
+            _ => {
+                return Err(anyhow!(
+                    "LLM response had neither text content nor tool calls."
+                ));
+            }
+        };
+        Ok(LLMDecision {
+            action,
+            usage: ollama_usage(&self.model, &response),
+        })
+    }
+
+    async fn stream_after_tools(
+        &self,
+        history_with_tool_results: Vec<ChatMessage>,
+        _tools: Vec<ToolDefinition>,
+    ) -> Result<LLMStream> {
+        let response = self.send(history_with_tool_results, vec![], true).await?;
+
+        let model = self.model.clone();
+        Ok(Box::pin(body_lines(response).filter_map(move |line| {
+            let model = model.clone();
+            async move {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => return Some(Err(e)),
+                };
+                let chunk: Value = serde_json::from_str(&line).ok()?;
+                if let Some(error) = chunk["error"].as_str() {
+                    return Some(Err(anyhow!("Ollama stream failed: {}", error)));
+                }
+                match chunk["message"]["content"].as_str() {
+                    Some(text) if !text.is_empty() => {
+                        Some(Ok(LLMStreamEvent::TextChunk(text.to_string())))
+                    }
+                    // The last chunk is empty, carrying the counts for the whole reply.
+                    _ if chunk["done"] == true => {
+                        ollama_usage(&model, &chunk).map(|usage| Ok(LLMStreamEvent::Usage(usage)))
+                    }
+                    _ => None,
+                }
+            }
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_client::stub::StubServer;
+
+    #[tokio::test]
+    async fn test_ollama_tool_call_and_stream() -> Result<()> {
+        let mut stub = StubServer::start(
+            "/api/chat",
+            vec![
+                (
+                    "application/json",
+                    json!({"message": {"role": "assistant", "content": "", "tool_calls": [
+                        {"function": {"name": "update_subtopic_status", "arguments": {"subtopic_name": "Nodes"}}},
+                    ]}, "done": true, "prompt_eval_count": 80, "eval_count": 12})
+                    .to_string(),
+                ),
+                (
+                    "application/x-ndjson",
+                    [
+                        r#"{"message": {"role": "assistant", "content": "Got "}, "done": false}"#,
+                        r#"{"message": {"role": "assistant", "content": "it!"}, "done": false}"#,
+                        r#"{"message": {"role": "assistant", "content": ""}, "done": true, "prompt_eval_count": 95, "eval_count": 4}"#,
+                    ]
+                    .join("\n"),
+                ),
+            ],
+        )
+        .await;
+        let client = OllamaClient::new(stub.base_url(), "llama-test".into());
+        let tools = vec![ToolDefinition {
+            name: "update_subtopic_status".to_string(),
+            description: "Updates a subtopic.".to_string(),
+            parameters: json!({"type": "object"}),
+        }];
+
+        let mut history = vec![
+            ChatMessage::System("You are a student.".to_string()),
+            ChatMessage::User("Nodes hold values.".to_string()),
+        ];
+        let decision = client.decide_action(history.clone(), tools.clone()).await?;
+        let LLMAction::ToolCall(calls) = decision.action else {
+            panic!("expected a tool call");
+        };
+        assert_eq!(
+            decision.usage,
+            Some(Usage {
+                model: "llama-test".to_string(),
+                prompt_tokens: 80,
+                completion_tokens: 12,
+            })
+        );
+        assert_eq!(calls[0].id, "call_0");
+        assert_eq!(calls[0].arguments["subtopic_name"], "Nodes");
+
+        let (_, request) = stub.next_request().await;
+        assert_eq!(request["model"], "llama-test");
+        assert_eq!(request["stream"], false);
+        assert_eq!(request["messages"][0]["role"], "system");
+        assert_eq!(
+            request["tools"][0]["function"]["name"],
+            "update_subtopic_status"
+        );
+
+        history.push(ChatMessage::Assistant {
+            content: String::new(),
+            tool_calls: calls,
+        });
+        history.push(ChatMessage::Tool {
+            call_id: "call_0".to_string(),
+            name: "update_subtopic_status".to_string(),
+            content: "Updated.".to_string(),
+        });
+        let mut stream = client.stream_after_tools(history, tools).await?;
+        let mut reply = String::new();
+        let mut usage = None;
+        while let Some(event) = stream.next().await.transpose()? {
+            match event {
+                LLMStreamEvent::TextChunk(chunk) => reply.push_str(&chunk),
+                LLMStreamEvent::Usage(reported) => usage = Some(reported),
+            }
+        }
+        assert_eq!(reply, "Got it!");
+        let usage = usage.expect("no usage reported");
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (95, 4));
+
+        let (_, request) = stub.next_request().await;
+        assert_eq!(request["stream"], true);
+        assert_eq!(request["messages"][3]["role"], "tool");
+        assert_eq!(
+            request["messages"][3]["tool_name"],
+            "update_subtopic_status"
+        );
+        assert_eq!(
+            request["messages"][2]["tool_calls"][0]["function"]["arguments"]["subtopic_name"],
+            "Nodes"
+        );
+        Ok(())
+    }
+}
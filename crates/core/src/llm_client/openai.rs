@@ -0,0 +1,235 @@
+//! A client for any OpenAI-compatible Chat Completions API.
+
+use super::{
+    ChatMessage, LLMAction, LLMClient, LLMDecision, LLMStream, LLMStreamEvent, ToolCall,
+    ToolDefinition, Usage,
+};
+use anyhow::{Context, Result, anyhow};
+use async_openai::{
+    Client,
+    config::OpenAIConfig,
+    types::{
+        ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
+        ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
+        ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs,
+        ChatCompletionStreamOptions, ChatCompletionTool, ChatCompletionToolArgs,
+        ChatCompletionToolType, CompletionUsage, CreateChatCompletionRequestArgs,
+        CreateChatCompletionResponse, FunctionCall, FunctionObjectArgs,
+    },
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::time::Duration;
+
+/// An implementation of `LLMClient` for any OpenAI-compatible API.
+pub struct OpenAICompatibleClient {
+    client: Client<OpenAIConfig>,
+    model: String,
+}
+
+impl OpenAICompatibleClient {
+    /// Creates a new client for an OpenAI-compatible service.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration for the OpenAI client, including API key and base URL.
+    /// * `model` - The specific model identifier to use for chat completions (e.g., "gpt-4o").
+    pub fn new(config: OpenAIConfig, model: String) -> Self {
+        // Rate limits and server errors are returned rather than retried here,
+        // leaving retries to `ResilientClient` and its timeout.
+        let no_retries = backoff::ExponentialBackoff {
+            max_elapsed_time: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        Self {
+            client: Client::with_config(config).with_backoff(no_retries),
+            model,
+        }
+    }
+}
+
+/// Converts the conversation to Chat Completions messages.
+fn to_openai_messages(messages: Vec<ChatMessage>) -> Result<Vec<ChatCompletionRequestMessage>> {
+    messages
+        .into_iter()
+        .map(|message| {
+            Ok(match message {
+                ChatMessage::System(content) => ChatCompletionRequestSystemMessageArgs::default()
+                    .content(content)
+                    .build()?
+                    .into(),
+                ChatMessage::User(content) => ChatCompletionRequestUserMessageArgs::default()
+                    .content(content)
+                    .build()?
+                    .into(),
+                ChatMessage::Assistant {
+                    content,
+                    tool_calls,
+                } => {
+                    let mut args = ChatCompletionRequestAssistantMessageArgs::default();
+                    if !content.is_empty() {
+                        args.content(content);
+                    }
+                    if !tool_calls.is_empty() {
+                        args.tool_calls(
+                            tool_calls
+                                .into_iter()
+                                .map(|call| ChatCompletionMessageToolCall {
+                                    id: call.id,
+                                    r#type: ChatCompletionToolType::Function,
+                                    function: FunctionCall {
+                                        name: call.name,
+                                        arguments: serde_json::Value::Object(call.arguments)
+                                            .to_string(),
+                                    },
+                                })
+                                .collect::<Vec<_>>(),
+                        );
+                    }
+                    args.build()?.into()
+                }
+                ChatMessage::Tool {
+                    call_id, content, ..
+                } => ChatCompletionRequestToolMessageArgs::default()
+                    .tool_call_id(call_id)
+                    .content(content)
+                    .build()?
+                    .into(),
+            })
+        })
+        .collect()
+}
+
+/// Converts tool definitions to Chat Completions function tools.
+fn to_openai_tools(tools: Vec<ToolDefinition>) -> Result<Vec<ChatCompletionTool>> {
+    tools
+        .into_iter()
+        .map(|tool| {
+            Ok(ChatCompletionToolArgs::default()
+                .function(
+                    FunctionObjectArgs::default()
+                        .name(tool.name)
+                        .description(tool.description)
+                        .parameters(tool.parameters)
+                        .build()?,
+                )
+                .build()?)
+        })
+        .collect()
+}
+
+/// Reads a tool call, whose arguments arrive as a JSON string.
+fn from_openai_tool_call(call: &ChatCompletionMessageToolCall) -> Result<ToolCall> {
+    let arguments = if call.function.arguments.trim().is_empty() {
+        Default::default()
+    } else {
+        serde_json::from_str(&call.function.arguments).with_context(|| {
+            format!(
+                "Tool call '{}' had malformed arguments: {}",
+                call.function.name, call.function.arguments
+            )
+        })?
+    };
+    Ok(ToolCall {
+        id: call.id.clone(),
+        name: call.function.name.clone(),
+        arguments,
+    })
+}
+
+/// Reads the token counts of a completion.
+fn from_openai_usage(model: &str, usage: &CompletionUsage) -> Usage {
+    Usage {
+        model: model.to_string(),
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+    }
+}
+
+#[async_trait]
+impl LLMClient for OpenAICompatibleClient {
+    async fn decide_action(
+        &self,
+        history_with_user_message: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMDecision> {
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request
+            .model(&self.model)
+            .messages(to_openai_messages(history_with_user_message)?);
+        if !tools.is_empty() {
+            request.tools(to_openai_tools(tools)?).tool_choice("auto");
+        }
+
+        let response: CreateChatCompletionResponse =
+            self.client.chat().create(request.build()?).await?;
+        let choice = response
+            .choices
+            .first()
+            .context("No response choice from LLM")?;
+
+        let action = if let Some(tool_calls) = &choice.message.tool_calls {
+            LLMAction::ToolCall(
+                tool_calls
+                    .iter()
+                    .map(from_openai_tool_call)
+                    .collect::<Result<_>>()?,
+            )
+        } else if let Some(content) = &choice.message.content {
+            LLMAction::TextResponse(content.clone())
+        } else {
+            return Err(anyhow!(
+                "LLM response had neither text content nor tool calls."
+            ));
+        };
+        Ok(LLMDecision {
+            action,
+            usage: response
+                .usage
+                .as_ref()
+                .map(|usage| from_openai_usage(&self.model, usage)),
+        })
+    }
+
+    async fn stream_after_tools(
+        &self,
+        history_with_tool_results: Vec<ChatMessage>,
+        _tools: Vec<ToolDefinition>,
+    ) -> Result<LLMStream> {
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.model)
+            .messages(to_openai_messages(history_with_tool_results)?)
+            .stream(true)
+            .stream_options(ChatCompletionStreamOptions {
+                include_usage: true,
+            })
+            .build()?;
+
+        let stream = self.client.chat().create_stream(request).await?;
+
+        let model = self.model.clone();
+        Ok(Box::pin(stream.filter_map(move |result| {
+            let model = model.clone();
+            async move {
+                match result {
+                    Ok(response) => {
+                        // The usage arrives in a final chunk without choices.
+                        if let Some(usage) = &response.usage {
+                            return Some(Ok(LLMStreamEvent::Usage(from_openai_usage(
+                                &model, usage,
+                            ))));
+                        }
+                        let choice = response.choices.first()?;
+                        if let Some(content) = &choice.delta.content
+                            && !content.is_empty()
+                        {
+                            return Some(Ok(LLMStreamEvent::TextChunk(content.clone())));
+                        }
+                        None
+                    }
+                    Err(e) => Some(Err(e.into())),
+                }
+            }
+        })))
+    }
+}
@@ -0,0 +1,624 @@
+//! An `LLMClient` that survives a misbehaving provider.
+//!
+//! `ResilientClient` tries an ordered list of routes, each a provider and
+//! model. Every call is bounded by a timeout, and errors that may pass, such
+//! as rate limits and server errors, are retried with jittered backoff before
+//! the next route is tried. A circuit breaker per provider skips one that keeps
+//! failing until it has had time to recover. Each attempt runs in an
+//! `llm_attempt` tracing span recording the route and its outcome.
+
+use super::{ChatMessage, LLMClient, LLMDecision, LLMStream, ToolDefinition, is_retryable};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::Instrument;
+
+/// How long to wait for a provider, how often to retry, and when to give up on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResilienceSettings {
+    /// The longest a call may take. For a stream, this covers the first chunk
+    /// and then each wait for the next one.
+    pub timeout: Duration,
+    /// How many times a route is retried after a retryable error.
+    pub max_retries: u32,
+    /// The wait before the first retry, doubled for each one after it.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Consecutive failures after which a provider's circuit opens.
+    pub breaker_threshold: u32,
+    /// How long an open circuit skips the provider before trying it again.
+    pub breaker_cooldown: Duration,
+}
+
+impl Default for ResilienceSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ResilienceSettings {
+    /// The wait before retry number `retry` (from 1), between half and all of
+    /// the exponential backoff so that clients don't retry in lockstep.
+    fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+        let millis = exponential.as_millis() as u64;
+        Duration::from_millis(rand::random_range(millis / 2..=millis))
+    }
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// The cooldown has passed and one trial call is in flight. A trial that
+    /// never reports back is given up on after another cooldown.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// Stops calls to a provider after repeated failures, then lets a single
+/// trial call through once the cooldown has passed.
+///
+/// Share one breaker between every route to the same provider.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a call may go to the provider now.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if now >= until => {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            BreakerState::HalfOpen { since } if now.duration_since(since) >= self.cooldown => {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    /// The provider answered, so its circuit closes.
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { failures: 0 };
+    }
+
+    /// The provider failed or timed out.
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => self.threshold,
+        };
+        *state = if failures >= self.threshold {
+            BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            BreakerState::Closed { failures }
+        };
+    }
+
+    /// A call was dropped before it finished. Only a trial call counts this
+    /// as a failure, since an abandoned trial says nothing about recovery.
+    fn record_abandoned(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let BreakerState::HalfOpen { .. } = *state {
+            *state = BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            };
+        }
+    }
+}
+
+/// Reports a call to its breaker if it is dropped before finishing, e.g.
+/// when the turn making it is cancelled.
+struct PendingCall<'a> {
+    breaker: &'a CircuitBreaker,
+    finished: bool,
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.breaker.record_abandoned();
+        }
+    }
+}
+
+/// One provider and model to try, with the breaker of its provider.
+pub struct Route {
+    pub provider: String,
+    pub model: String,
+    pub client: Arc<dyn LLMClient>,
+    pub breaker: Arc<CircuitBreaker>,
+}
+
+/// An `LLMClient` that falls back through its routes in order.
+pub struct ResilientClient {
+    routes: Vec<Route>,
+    settings: ResilienceSettings,
+}
+
+impl ResilientClient {
+    /// Creates a client trying `routes` in order, the first being the preferred one.
+    pub fn new(routes: Vec<Route>, settings: ResilienceSettings) -> Self {
+        Self { routes, settings }
+    }
+
+    /// Runs `call` against each route until one succeeds, returning the last
+    /// error if none does.
+    async fn run<T, F, Fut>(&self, operation: &'static str, call: F) -> Result<T>
+    where
+        F: Fn(&Route) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut last_error = None;
+        for route in &self.routes {
+            for attempt in 0..=self.settings.max_retries {
+                if attempt > 0 {
+                    tokio::time::sleep(self.settings.backoff(attempt)).await;
+                }
+                if !route.breaker.allow() {
+                    tracing::debug!(provider = %route.provider, "Circuit open, skipping provider");
+                    last_error.get_or_insert_with(|| {
+                        anyhow!("The circuit for provider '{}' is open", route.provider)
+                    });
+                    break;
+                }
+
+                let span = tracing::info_span!(
+                    "llm_attempt",
+                    operation,
+                    provider = %route.provider,
+                    model = %route.model,
+                    attempt = attempt + 1,
+                    outcome = tracing::field::Empty,
+                );
+                let mut pending = PendingCall {
+                    breaker: &route.breaker,
+                    finished: false,
+                };
+                let result = tokio::time::timeout(self.settings.timeout, call(route))
+                    .instrument(span.clone())
+                    .await;
+                pending.finished = true;
+                let timed_out = result.is_err();
+                let error = match result {
+                    Ok(Ok(value)) => {
+                        span.record("outcome", "ok");
+                        route.breaker.record_success();
+                        return Ok(value);
+                    }
+                    Ok(Err(e)) => e,
+                    Err(_) => anyhow!(
+                        "{} did not respond within {} seconds",
+                        route.provider,
+                        self.settings.timeout.as_secs_f32()
+                    ),
+                };
+
+                // A timeout or a passing error counts against the provider. A
+                // rejected request does not, and isn't worth repeating.
+                let retryable = timed_out || is_retryable(&error);
+                span.record("outcome", if retryable { "retryable" } else { "failed" });
+                span.in_scope(|| tracing::warn!(error = %error, "LLM call failed"));
+                if retryable {
+                    route.breaker.record_failure();
+                } else {
+                    route.breaker.record_success();
+                }
+                last_error = Some(error);
+                if !retryable {
+                    break;
+                }
+            }
+        }
+        let error = last_error.unwrap_or_else(|| anyhow!("No LLM provider is configured"));
+        Err(error.context(format!("All {} LLM routes failed", self.routes.len())))
+    }
+}
+
+#[async_trait]
+impl LLMClient for ResilientClient {
+    async fn decide_action(
+        &self,
+        history_with_user_message: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMDecision> {
+        self.run("decide_action", |route| {
+            let client = route.client.clone();
+            let history = history_with_user_message.clone();
+            let tools = tools.clone();
+            async move { client.decide_action(history, tools).await }
+        })
+        .await
+    }
+
+    /// Falls back only until the first chunk arrives, so a reply that fails
+    /// partway is never repeated from the start. After that, a stream that
+    /// stalls ends with an error counted against its provider.
+    async fn stream_after_tools(
+        &self,
+        history_with_tool_results: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<LLMStream> {
+        let timeout = self.settings.timeout;
+        self.run("stream_after_tools", |route| {
+            let client = route.client.clone();
+            let provider = route.provider.clone();
+            let breaker = route.breaker.clone();
+            let history = history_with_tool_results.clone();
+            let tools = tools.clone();
+            async move {
+                let mut stream = client.stream_after_tools(history, tools).await?;
+                let stream: LLMStream = match stream.next().await {
+                    Some(Err(e)) => return Err(e),
+                    Some(Ok(first)) => Box::pin(
+                        futures::stream::once(async { Ok(first) })
+                            .chain(with_chunk_timeout(stream, timeout, provider, breaker)),
+                    ),
+                    None => Box::pin(futures::stream::empty()),
+                };
+                Ok(stream)
+            }
+        })
+        .await
+    }
+}
+
+/// Ends `stream` with an error, counted as a failure of its provider, when
+/// the next chunk takes longer than `timeout` to arrive.
+fn with_chunk_timeout(
+    stream: LLMStream,
+    timeout: Duration,
+    provider: String,
+    breaker: Arc<CircuitBreaker>,
+) -> LLMStream {
+    Box::pin(futures::stream::unfold(Some(stream), move |stream| {
+        let provider = provider.clone();
+        let breaker = breaker.clone();
+        async move {
+            let mut stream = stream?;
+            match tokio::time::timeout(timeout, stream.next()).await {
+                Ok(Some(chunk)) => Some((chunk, Some(stream))),
+                Ok(None) => None,
+                Err(_) => {
+                    breaker.record_failure();
+                    let error = anyhow!(
+                        "{} stalled for {} seconds mid-stream",
+                        provider,
+                        timeout.as_secs_f32()
+                    );
+                    Some((Err(error), None))
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_client::{LLMAction, LLMStreamEvent, StatusError};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers each call with the next scripted outcome, or hangs on `None`.
+    struct Scripted {
+        outcomes: Mutex<Vec<Option<Result<String>>>>,
+        calls: AtomicUsize,
+    }
+
+    impl Scripted {
+        fn new(outcomes: Vec<Option<Result<String>>>) -> Arc<Self> {
+            Arc::new(Self {
+                outcomes: Mutex::new(outcomes.into_iter().rev().collect()),
+                calls: AtomicUsize::new(0),
+            })
+        }
+
+        async fn next(&self) -> Result<String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let outcome = self
+                .outcomes
+                .lock()
+                .unwrap()
+                .pop()
+                .expect("unscripted call");
+            match outcome {
+                Some(result) => result,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl LLMClient for Scripted {
+        async fn decide_action(
+            &self,
+            _history: Vec<ChatMessage>,
+            _tools: Vec<ToolDefinition>,
+        ) -> Result<LLMDecision> {
+            self.next().await.map(|text| LLMDecision {
+                action: LLMAction::TextResponse(text),
+                usage: None,
+            })
+        }
+
+        async fn stream_after_tools(
+            &self,
+            _history: Vec<ChatMessage>,
+            _tools: Vec<ToolDefinition>,
+        ) -> Result<LLMStream> {
+            let text = self.next().await;
+            Ok(Box::pin(futures::stream::iter([
+                text.map(LLMStreamEvent::TextChunk)
+            ])))
+        }
+    }
+
+    fn status(code: u16) -> Option<Result<String>> {
+        Some(Err(StatusError {
+            status: reqwest::StatusCode::from_u16(code).unwrap(),
+            body: String::new(),
+        }
+        .into()))
+    }
+
+    fn ok(text: &str) -> Option<Result<String>> {
+        Some(Ok(text.to_string()))
+    }
+
+    fn route(provider: &str, client: Arc<Scripted>, breaker: Arc<CircuitBreaker>) -> Route {
+        Route {
+            provider: provider.to_string(),
+            model: "model".to_string(),
+            client,
+            breaker,
+        }
+    }
+
+    fn settings() -> ResilienceSettings {
+        ResilienceSettings {
+            timeout: Duration::from_millis(200),
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+            breaker_threshold: 3,
+            breaker_cooldown: Duration::from_secs(60),
+        }
+    }
+
+    fn reply(decision: LLMDecision) -> String {
+        match decision.action {
+            LLMAction::TextResponse(text) => text,
+            other => panic!("unexpected action: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_then_falls_back() {
+        let settings = settings();
+        let breaker = || Arc::new(CircuitBreaker::new(10, settings.breaker_cooldown));
+
+        // A passing error is retried on the same route.
+        let primary = Scripted::new(vec![status(503), status(429), ok("recovered")]);
+        let client = ResilientClient::new(vec![route("a", primary.clone(), breaker())], settings);
+        let action = client.decide_action(vec![], vec![]).await.unwrap();
+        assert_eq!(reply(action), "recovered");
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 3);
+
+        // A rejected request moves straight on to the next route, and a
+        // timeout counts as a retryable failure.
+        let primary = Scripted::new(vec![status(400)]);
+        let secondary = Scripted::new(vec![None, ok("fallback")]);
+        let client = ResilientClient::new(
+            vec![
+                route("a", primary.clone(), breaker()),
+                route("b", secondary.clone(), breaker()),
+            ],
+            settings,
+        );
+        let action = client.decide_action(vec![], vec![]).await.unwrap();
+        assert_eq!(reply(action), "fallback");
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(secondary.calls.load(Ordering::SeqCst), 2);
+
+        // When every route fails, the last error is returned.
+        let primary = Scripted::new(vec![status(500), status(500), status(500)]);
+        let client = ResilientClient::new(vec![route("a", primary, breaker())], settings);
+        let error = client.decide_action(vec![], vec![]).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("500"), "{:#}", error);
+
+        // A stream that fails before its first chunk falls back too.
+        let primary = Scripted::new(vec![status(401)]);
+        let secondary = Scripted::new(vec![ok("streamed")]);
+        let client = ResilientClient::new(
+            vec![
+                route("a", primary, breaker()),
+                route("b", secondary, breaker()),
+            ],
+            settings,
+        );
+        let mut stream = client.stream_after_tools(vec![], vec![]).await.unwrap();
+        let Some(Ok(LLMStreamEvent::TextChunk(text))) = stream.next().await else {
+            panic!("expected a text chunk");
+        };
+        assert_eq!(text, "streamed");
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_skips_failing_provider() {
+        let settings = ResilienceSettings {
+            max_retries: 0,
+            ..settings()
+        };
+        let breaker = Arc::new(CircuitBreaker::new(2, Duration::from_millis(50)));
+        let primary = Scripted::new(vec![status(502), status(502), ok("back")]);
+        let secondary = Scripted::new(vec![ok("b1"), ok("b2"), ok("b3")]);
+        let client = ResilientClient::new(
+            vec![
+                route("a", primary.clone(), breaker.clone()),
+                route(
+                    "b",
+                    secondary,
+                    Arc::new(CircuitBreaker::new(2, Duration::ZERO)),
+                ),
+            ],
+            settings,
+        );
+
+        for _ in 0..3 {
+            let action = client.decide_action(vec![], vec![]).await.unwrap();
+            assert!(reply(action).starts_with('b'));
+        }
+        // The circuit opened after two failures, so the third call skipped it.
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 2);
+        assert!(!breaker.allow());
+
+        // After the cooldown, a trial call goes through and closes it again.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let action = client.decide_action(vec![], vec![]).await.unwrap();
+        assert_eq!(reply(action), "back");
+        assert!(breaker.allow());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_trial_call_reopens_circuit() {
+        let settings = ResilienceSettings {
+            max_retries: 0,
+            ..settings()
+        };
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::from_millis(50)));
+        let primary = Scripted::new(vec![status(502), None, ok("back")]);
+        let client =
+            ResilientClient::new(vec![route("a", primary.clone(), breaker.clone())], settings);
+        assert!(client.decide_action(vec![], vec![]).await.is_err());
+
+        // The trial call hangs and is cancelled by its caller, which counts
+        // as a failed trial rather than leaving the circuit half-open.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let trial = tokio::time::timeout(
+            Duration::from_millis(20),
+            client.decide_action(vec![], vec![]),
+        )
+        .await;
+        assert!(trial.is_err());
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 2);
+        assert!(!breaker.allow());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let action = client.decide_action(vec![], vec![]).await.unwrap();
+        assert_eq!(reply(action), "back");
+
+        // A trial that never reports back is given up on after a cooldown.
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow());
+    }
+
+    /// Sends one chunk and then never another.
+    struct Stalling;
+
+    #[async_trait]
+    impl LLMClient for Stalling {
+        async fn decide_action(
+            &self,
+            _history: Vec<ChatMessage>,
+            _tools: Vec<ToolDefinition>,
+        ) -> Result<LLMDecision> {
+            unreachable!("Stalling is only used to stream")
+        }
+
+        async fn stream_after_tools(
+            &self,
+            _history: Vec<ChatMessage>,
+            _tools: Vec<ToolDefinition>,
+        ) -> Result<LLMStream> {
+            let first = futures::stream::once(async {
+                Ok(LLMStreamEvent::TextChunk("Half a".to_string()))
+            });
+            Ok(Box::pin(first.chain(futures::stream::pending())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream_times_out() {
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::from_secs(60)));
+        let client = ResilientClient::new(
+            vec![Route {
+                provider: "a".to_string(),
+                model: "model".to_string(),
+                client: Arc::new(Stalling),
+                breaker: breaker.clone(),
+            }],
+            settings(),
+        );
+        let mut stream = client.stream_after_tools(vec![], vec![]).await.unwrap();
+        assert!(matches!(
+            stream.next().await,
+            Some(Ok(LLMStreamEvent::TextChunk(_)))
+        ));
+        let stalled = tokio::time::timeout(Duration::from_secs(1), stream.next())
+            .await
+            .expect("the stall should time out");
+        assert!(matches!(stalled, Some(Err(_))));
+        assert!(stream.next().await.is_none());
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn test_backoff_is_jittered_and_capped() {
+        let settings = ResilienceSettings {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..ResilienceSettings::default()
+        };
+        for _ in 0..20 {
+            let first = settings.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let capped = settings.backoff(10);
+            assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+        }
+    }
+}
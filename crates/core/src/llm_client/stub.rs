@@ -0,0 +1,61 @@
+//! A local stand-in for the LLM HTTP APIs, answering requests with canned
+//! responses and handing their headers and bodies to the test.
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, header},
+    response::IntoResponse,
+    routing::post,
+};
+use serde_json::Value;
+use std::{collections::VecDeque, sync::Arc};
+use tokio::sync::{Mutex, mpsc};
+
+type Responses = Arc<Mutex<VecDeque<(&'static str, String)>>>;
+
+pub struct StubServer {
+    base_url: String,
+    requests: mpsc::UnboundedReceiver<(HeaderMap, Value)>,
+}
+
+impl StubServer {
+    /// Serves `responses`, as content type and body, to successive POSTs to `path`.
+    pub async fn start(path: &str, responses: Vec<(&'static str, String)>) -> Self {
+        let (tx, requests) = mpsc::unbounded_channel();
+        let responses: Responses = Arc::new(Mutex::new(responses.into()));
+        let app = Router::new().route(
+            path,
+            post(
+                move |State(responses): State<Responses>,
+                      headers: HeaderMap,
+                      Json(body): Json<Value>| async move {
+                    let _ = tx.send((headers, body));
+                    let (content_type, body) = responses
+                        .lock()
+                        .await
+                        .pop_front()
+                        .expect("no response left for request");
+                    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app.with_state(responses))
+                .await
+                .unwrap()
+        });
+        Self { base_url, requests }
+    }
+
+    pub fn base_url(&self) -> String {
+        self.base_url.clone()
+    }
+
+    /// The headers and JSON body of the next request the stand-in received.
+    pub async fn next_request(&mut self) -> (HeaderMap, Value) {
+        self.requests.recv().await.expect("no request received")
+    }
+}
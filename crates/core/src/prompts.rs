@@ -0,0 +1,161 @@
+//! Prompt Templates
+//!
+//! Prompts are Markdown templates with `{name}` placeholders, where `{{` and
+//! `}}` stand for literal braces. This module parses them so they can be
+//! offered as MCP prompts, with each placeholder as an argument.
+
+use crate::agent::FeynmanAgent;
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, HashMap};
+
+/// A named prompt template.
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub text: String,
+}
+
+/// A piece of a parsed template.
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+impl PromptTemplate {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            text: text.into(),
+        }
+    }
+
+    /// The first line of the template, which states what the prompt is for.
+    pub fn description(&self) -> &str {
+        self.text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// The distinct placeholder names, in order of first appearance.
+    pub fn arguments(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for segment in self.segments() {
+            if let Segment::Placeholder(name) = segment
+                && !names.contains(&name)
+            {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Fills in every placeholder, failing if an argument is missing.
+    pub fn render(&self, arguments: &HashMap<String, String>) -> Result<String> {
+        let mut rendered = String::with_capacity(self.text.len());
+        for segment in self.segments() {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Placeholder(name) => match arguments.get(name) {
+                    Some(value) => rendered.push_str(value),
+                    None => bail!("Missing argument '{}' for prompt '{}'", name, self.name),
+                },
+            }
+        }
+        Ok(rendered)
+    }
+
+    fn segments(&self) -> Vec<Segment<'_>> {
+        let text = self.text.as_str();
+        let mut segments = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        while i < text.len() {
+            let rest = &text[i..];
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                // Keep one brace of an escaped pair.
+                segments.push(Segment::Text(&text[start..i + 1]));
+                i += 2;
+                start = i;
+            } else if rest.starts_with('{')
+                && let Some(end) = rest.find('}')
+                && is_placeholder(&rest[1..end])
+            {
+                segments.push(Segment::Text(&text[start..i]));
+                segments.push(Segment::Placeholder(&rest[1..end]));
+                i += end + 1;
+                start = i;
+            } else {
+                i += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        segments.push(Segment::Text(&text[start..]));
+        segments
+    }
+}
+
+fn is_placeholder(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Values for placeholders that describe the session, filled in from the
+/// agent's state when a client leaves them out.
+pub fn session_arguments(agent: &FeynmanAgent) -> HashMap<String, String> {
+    let subtopics = agent
+        .incomplete_subtopics
+        .keys()
+        .chain(agent.covered_subtopics.keys())
+        .map(String::as_str)
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>()
+        .join(", ");
+    HashMap::from([
+        (
+            "status_json".to_string(),
+            serde_json::to_string_pretty(agent).unwrap_or_default(),
+        ),
+        ("main_topic".to_string(), agent.main_topic.clone()),
+        ("topic".to_string(), agent.main_topic.clone()),
+        ("subtopics".to_string(), subtopics.clone()),
+        ("subtopic_names".to_string(), subtopics),
+    ])
+}
+
+/// Builds templates from prompt texts keyed by name, sorted by name.
+pub fn templates(prompts: &HashMap<String, String>) -> Vec<PromptTemplate> {
+    prompts
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(name, text)| PromptTemplate::new(name.clone(), text.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_template_arguments_and_render() {
+        let template = PromptTemplate::new(
+            "analyze_answer",
+            "Grade the answer.\n\nQ: \"{question}\" A: \"{answer}\" ({question})\n{{\"correct\": true}}",
+        );
+        assert_eq!(template.description(), "Grade the answer.");
+        assert_eq!(template.arguments(), vec!["question", "answer"]);
+
+        let arguments = HashMap::from([
+            ("question".to_string(), "Why?".to_string()),
+            ("answer".to_string(), "Because.".to_string()),
+        ]);
+        assert_eq!(
+            template.render(&arguments).unwrap(),
+            "Grade the answer.\n\nQ: \"Why?\" A: \"Because.\" (Why?)\n{\"correct\": true}"
+        );
+
+        let missing = HashMap::from([("question".to_string(), "Why?".to_string())]);
+        assert!(template.render(&missing).is_err());
+    }
+}
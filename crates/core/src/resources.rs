@@ -0,0 +1,299 @@
+//! Session Resources
+//!
+//! Describes a session as MCP resources, so clients can read the curriculum,
+//! each subtopic's status and the transcript instead of polling tools:
+//!
+//! - `feynman://session/{id}/curriculum`: the whole agent state, as JSON.
+//! - `feynman://session/{id}/subtopics/{name}`: one subtopic's status, as JSON.
+//!   The name is percent-encoded.
+//! - `feynman://session/{id}/transcript`: the conversation so far, as Markdown.
+//!
+//! Clients subscribed to these are tracked in `ResourceSubscribers`, shared by
+//! every connection serving the session, and notified by whatever watches the
+//! session for changes.
+
+use crate::agent::FeynmanAgent;
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use rmcp::{
+    RoleServer,
+    model::{
+        AnnotateAble, RawResource, RawResourceTemplate, Resource, ResourceContents,
+        ResourceTemplate, ResourceUpdatedNotificationParam,
+    },
+    service::Peer,
+};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tracing::warn;
+
+const JSON: &str = "application/json";
+const MARKDOWN: &str = "text/markdown";
+
+/// Provides the transcript of a session, e.g. from the database.
+#[async_trait]
+pub trait TranscriptSource: Send + Sync {
+    /// The conversation so far, rendered as Markdown.
+    async fn transcript(&self) -> Result<String>;
+}
+
+/// The resources of one session.
+#[derive(Clone)]
+pub struct SessionResources {
+    session_id: String,
+    transcript: Option<Arc<dyn TranscriptSource>>,
+}
+
+impl SessionResources {
+    pub fn new(
+        session_id: impl Into<String>,
+        transcript: Option<Arc<dyn TranscriptSource>>,
+    ) -> Self {
+        Self {
+            session_id: session_id.into(),
+            transcript,
+        }
+    }
+
+    fn base_uri(&self) -> String {
+        format!("feynman://session/{}", self.session_id)
+    }
+
+    /// The URI of the whole curriculum.
+    pub fn curriculum_uri(&self) -> String {
+        format!("{}/curriculum", self.base_uri())
+    }
+
+    /// The URI of one subtopic's status.
+    pub fn subtopic_uri(&self, name: &str) -> String {
+        format!(
+            "{}/subtopics/{}",
+            self.base_uri(),
+            utf8_percent_encode(name, NON_ALPHANUMERIC)
+        )
+    }
+
+    /// The URI of the transcript.
+    pub fn transcript_uri(&self) -> String {
+        format!("{}/transcript", self.base_uri())
+    }
+
+    /// Every resource of the session in its current state.
+    pub fn list(&self, agent: &FeynmanAgent) -> Vec<Resource> {
+        let mut resources = vec![resource(
+            self.curriculum_uri(),
+            "curriculum",
+            format!(
+                "All subtopics of '{}' and which criteria are covered.",
+                agent.main_topic
+            ),
+            JSON,
+        )];
+        let mut names: Vec<_> = agent
+            .incomplete_subtopics
+            .keys()
+            .chain(agent.covered_subtopics.keys())
+            .collect();
+        names.sort();
+        resources.extend(names.into_iter().map(|name| {
+            resource(
+                self.subtopic_uri(name),
+                name,
+                format!("Which criteria of '{}' are covered.", name),
+                JSON,
+            )
+        }));
+        if self.transcript.is_some() {
+            resources.push(resource(
+                self.transcript_uri(),
+                "transcript",
+                "The conversation so far.".to_string(),
+                MARKDOWN,
+            ));
+        }
+        resources
+    }
+
+    /// The template for subtopic URIs.
+    pub fn templates(&self) -> Vec<ResourceTemplate> {
+        vec![
+            RawResourceTemplate {
+                uri_template: format!("{}/subtopics/{{name}}", self.base_uri()),
+                name: "subtopic".to_string(),
+                description: Some("Which criteria of a subtopic are covered.".to_string()),
+                mime_type: Some(JSON.to_string()),
+            }
+            .no_annotation(),
+        ]
+    }
+
+    /// Reads the resource at `uri`.
+    pub async fn read(&self, uri: &str, agent: &FeynmanAgent) -> Result<ResourceContents> {
+        let path = uri
+            .strip_prefix(&self.base_uri())
+            .and_then(|path| path.strip_prefix('/'))
+            .with_context(|| format!("Unknown resource: '{}'", uri))?;
+        let (text, mime_type) = match path {
+            "curriculum" => (serde_json::to_string_pretty(agent)?, JSON),
+            "transcript" => match &self.transcript {
+                Some(source) => (source.transcript().await?, MARKDOWN),
+                None => bail!("This session has no transcript"),
+            },
+            _ => {
+                let encoded = path
+                    .strip_prefix("subtopics/")
+                    .with_context(|| format!("Unknown resource: '{}'", uri))?;
+                let name = percent_decode_str(encoded).decode_utf8()?;
+                let subtopic = agent
+                    .incomplete_subtopics
+                    .get(name.as_ref())
+                    .or_else(|| agent.covered_subtopics.get(name.as_ref()))
+                    .with_context(|| format!("Subtopic '{}' not found", name))?;
+                (serde_json::to_string_pretty(subtopic)?, JSON)
+            }
+        };
+        Ok(ResourceContents::TextResourceContents {
+            uri: uri.to_string(),
+            mime_type: Some(mime_type.to_string()),
+            text,
+        })
+    }
+
+    /// The resources that differ between two versions of the agent state.
+    pub fn changed_uris(&self, before: &FeynmanAgent, after: &FeynmanAgent) -> Vec<String> {
+        let subtopic = |agent: &FeynmanAgent, name: &str| {
+            agent
+                .incomplete_subtopics
+                .get(name)
+                .or_else(|| agent.covered_subtopics.get(name))
+                .cloned()
+        };
+        let changed: BTreeSet<_> = [before, after]
+            .into_iter()
+            .flat_map(|agent| {
+                agent
+                    .incomplete_subtopics
+                    .keys()
+                    .chain(agent.covered_subtopics.keys())
+            })
+            .filter(|name| subtopic(before, name) != subtopic(after, name))
+            .collect();
+        if changed.is_empty() && before.main_topic == after.main_topic {
+            return Vec::new();
+        }
+        std::iter::once(self.curriculum_uri())
+            .chain(changed.into_iter().map(|name| self.subtopic_uri(name)))
+            .collect()
+    }
+}
+
+/// The MCP clients subscribed to a session's resources, by connection.
+///
+/// Clones share the same subscribers, so every connection serving a session
+/// can register its client and any of them can notify all of them.
+#[derive(Clone, Default)]
+pub struct ResourceSubscribers {
+    connections: Arc<Mutex<HashMap<u64, Subscriber>>>,
+}
+
+struct Subscriber {
+    peer: Peer<RoleServer>,
+    uris: HashSet<String>,
+}
+
+impl ResourceSubscribers {
+    /// Subscribes the client on `connection` to the resource at `uri`.
+    pub fn subscribe(&self, connection: u64, peer: Peer<RoleServer>, uri: String) {
+        self.connections
+            .lock()
+            .unwrap()
+            .entry(connection)
+            .or_insert_with(|| Subscriber {
+                peer,
+                uris: HashSet::new(),
+            })
+            .uris
+            .insert(uri);
+    }
+
+    /// Unsubscribes the client on `connection` from the resource at `uri`.
+    pub fn unsubscribe(&self, connection: u64, uri: &str) {
+        if let Some(subscriber) = self.connections.lock().unwrap().get_mut(&connection) {
+            subscriber.uris.remove(uri);
+        }
+    }
+
+    /// Forgets the client on `connection`, e.g. once it disconnected.
+    pub fn remove(&self, connection: u64) {
+        self.connections.lock().unwrap().remove(&connection);
+    }
+
+    /// Tells every client subscribed to one of `uris` that it changed.
+    pub async fn notify(&self, uris: &[String]) {
+        let notifications: Vec<_> = {
+            let mut connections = self.connections.lock().unwrap();
+            connections.retain(|_, subscriber| !subscriber.peer.is_transport_closed());
+            connections
+                .values()
+                .flat_map(|subscriber| {
+                    uris.iter()
+                        .filter(|uri| subscriber.uris.contains(*uri))
+                        .map(|uri| (subscriber.peer.clone(), uri.clone()))
+                })
+                .collect()
+        };
+        for (peer, uri) in notifications {
+            if let Err(e) = peer
+                .notify_resource_updated(ResourceUpdatedNotificationParam { uri })
+                .await
+            {
+                warn!(error = ?e, "Failed to notify MCP client of a resource update.");
+            }
+        }
+    }
+}
+
+fn resource(uri: String, name: &str, description: String, mime_type: &str) -> Resource {
+    RawResource {
+        description: Some(description),
+        mime_type: Some(mime_type.to_string()),
+        ..RawResource::new(uri, name)
+    }
+    .no_annotation()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topic::SubTopic;
+
+    #[test]
+    fn test_changed_uris_lists_changed_subtopics() {
+        let resources = SessionResources::new("s1", None);
+        let before = FeynmanAgent::new(
+            "Graphs".to_string(),
+            vec![
+                SubTopic::new("Nodes".to_string()),
+                SubTopic::new("Edge lists".to_string()),
+            ],
+        );
+        assert!(resources.changed_uris(&before, &before.clone()).is_empty());
+
+        let mut after = before.clone();
+        let mut nodes = after.incomplete_subtopics.remove("Nodes").unwrap();
+        nodes.has_definition = true;
+        nodes.has_mechanism = true;
+        nodes.has_example = true;
+        after.covered_subtopics.insert(nodes.name.clone(), nodes);
+        assert_eq!(
+            resources.changed_uris(&before, &after),
+            [
+                "feynman://session/s1/curriculum",
+                "feynman://session/s1/subtopics/Nodes"
+            ]
+        );
+    }
+}
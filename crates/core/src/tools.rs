@@ -0,0 +1,322 @@
+//! Tool Registry
+//!
+//! This module abstracts how the agent's tools are listed and called. A
+//! `FeynmanService` can be called directly in-process, skipping the JSON-RPC
+//! round trip, while `McpTools` reaches any tools served over the Model
+//! Context Protocol. Both present the same declarations to the LLM.
+//!
+//! A `ToolSet` adds the tools of external MCP servers to the agent's own,
+//! namespaced by server so their names cannot clash.
+
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use rmcp::{
+    ServerHandler, ServiceExt,
+    model::{CallToolRequestParam, JsonObject, RawContent, Tool},
+    service::{RoleClient, RunningService},
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tracing::warn;
+
+/// Separates a server's name from the name of one of its tools, as in `docs__search`.
+pub const NAMESPACE_SEPARATOR: &str = "__";
+
+/// A set of tools an LLM can call by name.
+///
+/// `call` returns the text the tool produced, including errors the tool itself
+/// reports, so they can be handed back to the LLM. It fails only when the call
+/// cannot be made at all, e.g. for an unknown tool or malformed arguments.
+#[async_trait]
+pub trait ToolRegistry: Send + Sync {
+    /// The declarations of every available tool, listed once and cached.
+    fn tools(&self) -> &[Tool];
+
+    /// Calls the named tool with a JSON object of arguments.
+    async fn call(&self, name: &str, arguments: Option<JsonObject>) -> Result<String>;
+}
+
+/// Tools served by an MCP server, reached through a running client connection.
+///
+/// The connection is closed when this is dropped.
+pub struct McpTools {
+    client: RunningService<RoleClient, ()>,
+    tools: Vec<Tool>,
+}
+
+impl McpTools {
+    /// Lists the server's tools once, keeping the client for later calls.
+    pub async fn list(client: RunningService<RoleClient, ()>) -> Result<Self> {
+        let tools = client.list_all_tools().await?;
+        Ok(Self { client, tools })
+    }
+
+    /// Serves `handler` over an in-process pipe and connects to it.
+    ///
+    /// The server task ends when the returned client is dropped.
+    pub async fn serve_in_process<S: ServerHandler>(handler: S) -> Result<Self> {
+        let (server_transport, client_transport) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            if let Ok(service) = handler.serve(server_transport).await {
+                let _ = service.waiting().await;
+            }
+        });
+        Self::list(().serve(client_transport).await?).await
+    }
+}
+
+#[async_trait]
+impl ToolRegistry for McpTools {
+    fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    async fn call(&self, name: &str, arguments: Option<JsonObject>) -> Result<String> {
+        let result = self
+            .client
+            .call_tool(CallToolRequestParam {
+                name: name.to_string().into(),
+                arguments,
+            })
+            .await?;
+        let content = result
+            .content
+            .context("Tool call returned no content")?
+            .pop()
+            .context("Content list was empty")?;
+        match content.raw {
+            RawContent::Text(text_content) => Ok(text_content.text),
+            _ => bail!("Unexpected content type from tool"),
+        }
+    }
+}
+
+/// The tools of an external MCP server, called under the server's name.
+pub struct ExternalTools {
+    name: String,
+    registry: Arc<dyn ToolRegistry>,
+    timeout: Duration,
+}
+
+impl ExternalTools {
+    /// Wraps a server's tools. Each call is given up after `timeout`.
+    pub fn new(
+        name: impl Into<String>,
+        registry: Arc<dyn ToolRegistry>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            registry,
+            timeout,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name the LLM calls one of this server's tools by.
+    pub fn namespaced(&self, tool: &str) -> String {
+        format!("{}{}{}", self.name, NAMESPACE_SEPARATOR, tool)
+    }
+}
+
+/// The agent's built-in tools together with the external tools a session allows.
+///
+/// External tools are declared as `<server>__<tool>`. Their failures and
+/// timeouts are returned as text, so an unreliable server cannot end the
+/// turn that called it.
+pub struct ToolSet {
+    builtin: Arc<dyn ToolRegistry>,
+    /// The external tools by namespaced name, with the server and the tool's own name.
+    external: HashMap<String, (Arc<ExternalTools>, String)>,
+    tools: Vec<Tool>,
+}
+
+impl ToolSet {
+    /// Combines the built-in tools with the allowed tools of each server.
+    ///
+    /// `allowed` names whole servers (`docs`) or single tools (`docs__search`).
+    pub fn new(
+        builtin: Arc<dyn ToolRegistry>,
+        servers: &[Arc<ExternalTools>],
+        allowed: &[String],
+    ) -> Self {
+        let mut tools = builtin.tools().to_vec();
+        let mut external = HashMap::new();
+        for server in servers {
+            let server_allowed = allowed.contains(&server.name);
+            for tool in server.registry.tools() {
+                let name = server.namespaced(&tool.name);
+                if !server_allowed && !allowed.contains(&name) {
+                    continue;
+                }
+                tools.push(Tool {
+                    name: name.clone().into(),
+                    ..tool.clone()
+                });
+                external.insert(name, (server.clone(), tool.name.to_string()));
+            }
+        }
+        Self {
+            builtin,
+            external,
+            tools,
+        }
+    }
+}
+
+#[async_trait]
+impl ToolRegistry for ToolSet {
+    fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    async fn call(&self, name: &str, arguments: Option<JsonObject>) -> Result<String> {
+        let Some((server, tool)) = self.external.get(name) else {
+            return self.builtin.call(name, arguments).await;
+        };
+        match tokio::time::timeout(server.timeout, server.registry.call(tool, arguments)).await {
+            Ok(Ok(text)) => Ok(text),
+            Ok(Err(e)) => {
+                warn!(tool = %name, error = ?e, "External tool call failed.");
+                Ok(format!("Tool '{}' failed: {}", name, e))
+            }
+            Err(_) => {
+                warn!(tool = %name, timeout = ?server.timeout, "External tool call timed out.");
+                Ok(format!(
+                    "Tool '{}' did not respond within {} seconds.",
+                    name,
+                    server.timeout.as_secs_f32()
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{FeynmanAgent, FeynmanService},
+        topic::SubTopic,
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn service() -> FeynmanService {
+        let agent = FeynmanAgent::new(
+            "Linked Lists".to_string(),
+            vec![SubTopic::new("Nodes".to_string())],
+        );
+        FeynmanService::new(Arc::new(Mutex::new(agent)), None)
+    }
+
+    #[tokio::test]
+    async fn test_direct_dispatch_matches_mcp() {
+        let direct = service();
+        let mcp = McpTools::serve_in_process(service()).await.unwrap();
+
+        let names = |tools: &[Tool]| {
+            let mut names: Vec<_> = tools.iter().map(|t| t.name.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(direct.tools()), names(mcp.tools()));
+
+        let update = serde_json::json!({
+            "subtopic_name": "Nodes",
+            "criterion": "definition",
+            "is_covered": true,
+        });
+        let calls = [
+            ("update_subtopic_status", update.as_object().cloned()),
+            ("update_subtopic_status", update.as_object().cloned()),
+            ("get_session_status", None),
+            ("conclude_session", None),
+        ];
+        for (name, arguments) in calls {
+            assert_eq!(
+                direct.call(name, arguments.clone()).await.unwrap(),
+                mcp.call(name, arguments).await.unwrap(),
+                "tool '{}' differs between transports",
+                name
+            );
+        }
+
+        // Tool errors come back as text; unknown tools fail outright.
+        let missing = serde_json::json!({
+            "subtopic_name": "Heaps",
+            "criterion": "definition",
+            "is_covered": true,
+        });
+        assert_eq!(
+            direct
+                .call("update_subtopic_status", missing.as_object().cloned())
+                .await
+                .unwrap(),
+            "Subtopic 'Heaps' not found."
+        );
+        assert!(direct.call("delete_everything", None).await.is_err());
+        assert!(mcp.call("delete_everything", None).await.is_err());
+    }
+
+    /// A server whose only tool never answers.
+    struct Stalled(Vec<Tool>);
+
+    #[async_trait]
+    impl ToolRegistry for Stalled {
+        fn tools(&self) -> &[Tool] {
+            &self.0
+        }
+
+        async fn call(&self, _name: &str, _arguments: Option<JsonObject>) -> Result<String> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_set_namespaces_filters_and_times_out() {
+        let docs = Arc::new(ExternalTools::new(
+            "docs",
+            Arc::new(McpTools::serve_in_process(service()).await.unwrap()),
+            Duration::from_secs(5),
+        ));
+        let slow = Arc::new(ExternalTools::new(
+            "slow",
+            Arc::new(Stalled(vec![Tool::new(
+                "wait",
+                "Waits.",
+                JsonObject::new(),
+            )])),
+            Duration::from_millis(10),
+        ));
+        let servers = [docs, slow];
+        let allowed = ["docs__get_session_status".to_string(), "slow".to_string()];
+        let tools = ToolSet::new(Arc::new(service()), &servers, &allowed);
+
+        let mut names: Vec<_> = tools.tools().iter().map(|t| t.name.to_string()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "conclude_session",
+                "docs__get_session_status",
+                "get_session_status",
+                "slow__wait",
+                "update_subtopic_status",
+            ]
+        );
+
+        assert_eq!(
+            tools.call("docs__get_session_status", None).await.unwrap(),
+            tools.call("get_session_status", None).await.unwrap()
+        );
+        assert_eq!(
+            tools.call("slow__wait", None).await.unwrap(),
+            "Tool 'slow__wait' did not respond within 0.01 seconds."
+        );
+        // Tools left out of the allow-list cannot be called.
+        assert!(tools.call("docs__conclude_session", None).await.is_err());
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 ///
 /// The learning state for each criterion (e.g., `has_definition`) is managed
 /// by the LLM and updated via tool calls to the `FeynmanAgent`.
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct SubTopic {
     pub name: String,
     pub has_definition: bool,
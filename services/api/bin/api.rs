@@ -8,21 +8,21 @@
 //! 5. Starting the web server and handling graceful shutdown.
 
 use anyhow::Context;
-use async_openai::config::OpenAIConfig;
 use feynman_api::{
-    config::{Config, Provider},
+    config::Config,
     db::Db,
+    external_tools, llm,
+    prompts::load_prompts,
+    quota::Quotas,
+    recordings::{self, RecordingStore},
     router::create_router,
     state::AppState,
 };
-use feynman_core::{
-    curriculum::{CurriculumService, LLMCurriculumService},
-    llm_client::{LLMClient, OpenAICompatibleClient},
-};
+use feynman_core::curriculum::{CurriculumService, LLMCurriculumService};
 use sqlx::PgPool;
-use std::{collections::HashMap, fs, net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
+use tracing::{info, warn};
 
 /// Listens for the `Ctrl+C` signal to gracefully shut down the server.
 async fn shutdown_signal() {
@@ -32,25 +32,6 @@ async fn shutdown_signal() {
     info!("Received shutdown signal. Shutting down gracefully...");
 }
 
-/// A helper function to load prompts from a directory.
-fn load_prompts(prompts_path: &std::path::Path) -> anyhow::Result<HashMap<String, String>> {
-    let mut prompts = HashMap::new();
-    for entry in std::fs::read_dir(prompts_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("md") {
-            let prompt_key = path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .context("Could not get file stem")?
-                .to_string();
-            let content = fs::read_to_string(&path)?;
-            prompts.insert(prompt_key, content);
-        }
-    }
-    Ok(prompts)
-}
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // --- 1. Load Configuration ---
@@ -61,6 +42,9 @@ async fn main() -> anyhow::Result<()> {
         .with_max_level(config.log_level)
         .with_timer(tracing_subscriber::fmt::time::ChronoLocal::rfc_3339())
         .init();
+    for warning in &config.warnings {
+        warn!("{}", warning);
+    }
     info!("Configuration loaded. Initializing application state...");
 
     // --- 3. Initialize Database ---
@@ -80,46 +64,26 @@ async fn main() -> anyhow::Result<()> {
             .clone(),
     );
 
-    let (curriculum_service, llm_client): (Arc<dyn CurriculumService>, Arc<dyn LLMClient>) =
-        match &config.provider {
-            Provider::OpenAI => {
-                info!("Using OpenAI provider.");
-                let api_key = config.openai_api_key.as_ref().unwrap();
-                let openai_config = OpenAIConfig::new()
-                    .with_api_key(api_key)
-                    .with_api_base("https://api.openai.com/v1/");
-                (
-                    Arc::new(LLMCurriculumService::new(
-                        openai_config.clone(),
-                        config.chat_model.clone(),
-                        prompts,
-                    )),
-                    Arc::new(OpenAICompatibleClient::new(
-                        openai_config,
-                        config.chat_model.clone(),
-                    )),
-                )
-            }
-            Provider::Gemini => {
-                info!("Using Gemini provider.");
-                let api_key = config.gemini_api_key.as_ref().unwrap();
-                let openai_config = OpenAIConfig::new()
-                    .with_api_key(api_key)
-                    .with_api_base("https://generativelanguage.googleapis.com/v1beta/openai");
+    // The chat and curriculum roles may each use a different provider, and
+    // each falls back through its own list when that provider fails.
+    let llm_clients = llm::LlmClients::new(config.llm_resilience);
+    let llm_client = llm_clients.client_for(&config.chat);
+    let curriculum_service: Arc<dyn CurriculumService> = Arc::new(LLMCurriculumService::new(
+        llm_clients.client_for(&config.curriculum),
+        prompts,
+    ));
+
+    let recording_store = RecordingStore::from_config(&config)
+        .context("Failed to open the recording store")?
+        .map(Arc::new);
+    if let Some(store) = &recording_store {
+        info!(format = ?store.format(), "Recording voice sessions.");
+        if let Some(days) = config.recording_retention_days {
+            recordings::spawn_retention(db.clone(), store.clone(), days);
+        }
+    }
 
-                (
-                    Arc::new(LLMCurriculumService::new(
-                        openai_config.clone(),
-                        config.chat_model.clone(),
-                        prompts,
-                    )),
-                    Arc::new(OpenAICompatibleClient::new(
-                        openai_config,
-                        config.chat_model.clone(),
-                    )),
-                )
-            }
-        };
+    let external_tools = external_tools::connect_all(&config.mcp_servers).await;
 
     let app_state = Arc::new(AppState {
         db,
@@ -127,6 +91,9 @@ async fn main() -> anyhow::Result<()> {
         llm_client,
         system_prompt,
         config: Arc::new(config.clone()),
+        recordings: recording_store,
+        external_tools,
+        quotas: Arc::new(Quotas::new(config.quotas)),
     });
 
     // --- 5. Create Router and Apply Middleware ---
@@ -139,8 +106,11 @@ async fn main() -> anyhow::Result<()> {
 
     // --- 6. Start Server ---
     info!(
-        provider = ?config.provider,
-        model = %config.chat_model,
+        realtime_provider = ?config.realtime_provider,
+        chat_provider = ?config.chat.provider,
+        chat_model = %config.chat.model,
+        curriculum_provider = ?config.curriculum.provider,
+        curriculum_model = %config.curriculum.model,
         bind_address = %config.bind_address,
         "Service configured. Starting server..."
     );
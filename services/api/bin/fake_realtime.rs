@@ -0,0 +1,33 @@
+//! Local Stand-in for the OpenAI Realtime and Gemini Live APIs
+//!
+//! Runs the fake realtime server so the voice path can be exercised without live
+//! API keys. Point the API service at it with:
+//!
+//! - `OPENAI_REALTIME_URL=ws://127.0.0.1:9090/v1/realtime`
+//! - `GEMINI_LIVE_URL=ws://127.0.0.1:9090/ws/BidiGenerateContent`
+//!
+//! The bind address is read from `FAKE_REALTIME_BIND_ADDRESS`, and the canned
+//! behaviour from the `FAKE_REALTIME_*` variables described in `fake_realtime`.
+
+use anyhow::Context;
+use feynman_api::fake_realtime::{self, FakeRealtimeConfig};
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenvy::dotenv().ok();
+    tracing_subscriber::fmt()
+        .with_timer(tracing_subscriber::fmt::time::ChronoLocal::rfc_3339())
+        .init();
+
+    let bind_address = std::env::var("FAKE_REALTIME_BIND_ADDRESS")
+        .unwrap_or_else(|_| "127.0.0.1:9090".to_string());
+    let config = FakeRealtimeConfig::from_env();
+
+    let listener = tokio::net::TcpListener::bind(&bind_address)
+        .await
+        .with_context(|| format!("Failed to bind {}", bind_address))?;
+    info!(%bind_address, ?config, "Fake realtime server listening.");
+
+    fake_realtime::serve(listener, config).await
+}
@@ -0,0 +1,99 @@
+//! Standalone MCP Server for the Feynman Agent
+//!
+//! Serves the tools of a stored session to an external MCP-capable assistant,
+//! over stdio or the streamable HTTP transport. Create the session through the
+//! API first, then run for example:
+//!
+//! - `mcp-server --session-id <id>` to be launched by the assistant over stdio
+//! - `mcp-server --session-id <id> --transport http` to serve `http://127.0.0.1:8090/mcp`
+//!
+//! The database is read from `DATABASE_URL` and the prompt templates from
+//! `PROMPTS_PATH`. With stdio, logs go to stderr.
+
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use feynman_api::{
+    db::Db,
+    mcp::{MCP_HTTP_PATH, SessionTools},
+    prompts::load_prompts,
+};
+use rmcp::{ServiceExt, transport::stdio};
+use sqlx::PgPool;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tracing::{Level, info, warn};
+use uuid::Uuid;
+
+/// How the MCP server talks to its client.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Transport {
+    /// JSON-RPC over stdin and stdout, for assistants that launch the server.
+    Stdio,
+    /// The streamable HTTP transport, with server-sent events.
+    Http,
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Serves a Feynman session's tools over MCP")]
+struct Args {
+    /// The stored session whose tools to serve.
+    #[arg(long, env = "MCP_SESSION_ID")]
+    session_id: Uuid,
+    #[arg(long, value_enum, env = "MCP_TRANSPORT", default_value = "stdio")]
+    transport: Transport,
+    /// The address to serve the HTTP transport on.
+    #[arg(long, env = "MCP_BIND_ADDRESS", default_value = "127.0.0.1:8090")]
+    bind_address: SocketAddr,
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: String,
+    /// The directory of prompt templates to offer as MCP prompts.
+    #[arg(long, env = "PROMPTS_PATH", default_value = "./prompts")]
+    prompts_path: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenvy::dotenv().ok();
+    let args = Args::parse();
+
+    // Stdout carries the protocol on stdio, so logs always go to stderr.
+    tracing_subscriber::fmt()
+        .with_max_level(Level::INFO)
+        .with_writer(std::io::stderr)
+        .with_timer(tracing_subscriber::fmt::time::ChronoLocal::rfc_3339())
+        .init();
+
+    let pool = PgPool::connect(&args.database_url)
+        .await
+        .context("Failed to connect to database")?;
+    let db = Arc::new(Db::new(pool));
+    let prompts = load_prompts(&args.prompts_path).unwrap_or_else(|e| {
+        warn!(error = ?e, path = ?args.prompts_path, "Serving without prompts.");
+        Default::default()
+    });
+    let tools = SessionTools::load(db, args.session_id, &prompts).await?;
+
+    match args.transport {
+        Transport::Stdio => {
+            info!("Serving MCP over stdio.");
+            let service = tools.service().serve(stdio()).await?;
+            service.waiting().await?;
+        }
+        Transport::Http => {
+            let listener = tokio::net::TcpListener::bind(args.bind_address)
+                .await
+                .with_context(|| format!("Failed to bind {}", args.bind_address))?;
+            info!(
+                "Serving MCP over HTTP at http://{}{}",
+                args.bind_address, MCP_HTTP_PATH
+            );
+            axum::serve(listener, tools.http_router())
+                .with_graceful_shutdown(async {
+                    let _ = tokio::signal::ctrl_c().await;
+                })
+                .await?;
+        }
+    }
+
+    info!("MCP server has shut down.");
+    Ok(())
+}
@@ -0,0 +1,18 @@
+-- Audio recordings of voice sessions, one row per stored clip.
+CREATE TYPE recording_format AS ENUM ('wav', 'opus');
+
+CREATE TABLE recordings (
+    id BIGSERIAL PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    -- The message the clip was spoken as; kept if the message goes away.
+    message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    role message_role NOT NULL,
+    format recording_format NOT NULL,
+    -- The key of the audio file in the recording store.
+    object_key TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_recordings_session_id ON recordings(session_id);
+CREATE INDEX idx_recordings_created_at ON recordings(created_at);
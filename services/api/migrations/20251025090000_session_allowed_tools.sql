@@ -0,0 +1,3 @@
+-- The external MCP tools each session may use, by server name or as
+-- `<server>__<tool>`. Sessions created before this allow none.
+ALTER TABLE sessions ADD COLUMN allowed_tools TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,22 @@
+-- What each LLM call and stretch of realtime audio consumed, one row per
+-- call or stretch, priced when it was recorded.
+CREATE TYPE usage_kind AS ENUM ('chat', 'curriculum', 'realtime');
+
+CREATE TABLE usage_records (
+    id BIGSERIAL PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    -- Copied from the session, so a user's usage can be totalled directly.
+    user_id TEXT NOT NULL,
+    kind usage_kind NOT NULL,
+    model TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL DEFAULT 0,
+    completion_tokens INTEGER NOT NULL DEFAULT 0,
+    audio_input_seconds DOUBLE PRECISION NOT NULL DEFAULT 0,
+    audio_output_seconds DOUBLE PRECISION NOT NULL DEFAULT 0,
+    -- In US dollars, from the price table configured at the time.
+    cost_usd DOUBLE PRECISION NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_usage_records_session_id ON usage_records(session_id);
+CREATE INDEX idx_usage_records_user_id_created_at ON usage_records(user_id, created_at);
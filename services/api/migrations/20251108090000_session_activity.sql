@@ -0,0 +1,14 @@
+-- A session counts as updated whenever a message is added to it, so listing
+-- sessions by `updated_at` orders them by recent activity.
+CREATE OR REPLACE FUNCTION touch_session()
+RETURNS TRIGGER AS $$
+BEGIN
+  UPDATE sessions SET updated_at = NOW() WHERE id = NEW.session_id;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER touch_session_on_message
+AFTER INSERT ON messages
+FOR EACH ROW
+EXECUTE PROCEDURE touch_session();
@@ -0,0 +1,3 @@
+-- Whether a session's voice audio is recorded. Sessions opt in when they are
+-- created; those created before this are not recorded.
+ALTER TABLE sessions ADD COLUMN record_audio BOOLEAN NOT NULL DEFAULT FALSE;
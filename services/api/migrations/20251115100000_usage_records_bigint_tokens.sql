@@ -0,0 +1,4 @@
+-- A single call's token counts can exceed what INTEGER holds.
+ALTER TABLE usage_records
+    ALTER COLUMN prompt_tokens TYPE BIGINT,
+    ALTER COLUMN completion_tokens TYPE BIGINT;
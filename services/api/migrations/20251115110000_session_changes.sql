@@ -0,0 +1,20 @@
+-- Announces changes to a session's messages and agent state on the
+-- `session_changes` channel, as `<session id>:<table>`, so other processes
+-- serving the session, like the MCP server, can tell their clients.
+CREATE OR REPLACE FUNCTION notify_session_change()
+RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM pg_notify('session_changes', NEW.session_id::text || ':' || TG_TABLE_NAME);
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_session_change_on_message
+AFTER INSERT OR UPDATE ON messages
+FOR EACH ROW
+EXECUTE PROCEDURE notify_session_change();
+
+CREATE TRIGGER notify_session_change_on_agent_state
+AFTER INSERT ON agent_states
+FOR EACH ROW
+EXECUTE PROCEDURE notify_session_change();
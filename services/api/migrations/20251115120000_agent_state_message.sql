@@ -0,0 +1,10 @@
+-- Link each agent state to the user message whose turn saved it.
+ALTER TABLE agent_states ADD COLUMN message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL;
+
+-- Earlier states are linked to the latest user message saved before them.
+UPDATE agent_states a SET message_id = (
+    SELECT m.id FROM messages m
+    WHERE m.session_id = a.session_id AND m.role = 'user' AND m.created_at <= a.created_at
+    ORDER BY m.id DESC
+    LIMIT 1
+);
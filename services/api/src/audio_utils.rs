@@ -1,5 +1,5 @@
 use base64::Engine;
-use rubato::{FastFixedIn, PolynomialDegree};
+use rubato::{FastFixedIn, PolynomialDegree, Resampler};
 
 // Define standard sample rates for clarity and consistency
 pub const OPENAI_REALTIME_API_PCM16_SAMPLE_RATE: f64 = 24000.0;
@@ -22,6 +22,105 @@ pub fn create_resampler(
     Ok(resampler)
 }
 
+/// Resamples a mono stream that arrives in frames of any length.
+///
+/// The underlying resampler only accepts fixed-size chunks, so samples that do
+/// not fill a chunk are kept until the next call. The resampler's latency is
+/// trimmed from the start of the output and [`StreamingResampler::flush`] pushes
+/// out the tail, so a whole stream yields `input * out_rate / in_rate` samples.
+pub struct StreamingResampler {
+    resampler: FastFixedIn<f32>,
+    ratio: f64,
+    pending: Vec<f32>,
+    delay: usize,
+    samples_in: usize,
+    samples_out: usize,
+}
+
+impl StreamingResampler {
+    /// Creates a streaming resampler that processes `chunk_size` input samples at a time.
+    pub fn new(
+        in_sampling_rate: f64,
+        out_sampling_rate: f64,
+        chunk_size: usize,
+    ) -> anyhow::Result<Self> {
+        let resampler = create_resampler(in_sampling_rate, out_sampling_rate, chunk_size)?;
+        Ok(Self {
+            delay: resampler.output_delay(),
+            resampler,
+            ratio: out_sampling_rate / in_sampling_rate,
+            pending: Vec::new(),
+            samples_in: 0,
+            samples_out: 0,
+        })
+    }
+
+    /// Resamples as many whole chunks as the buffered and new samples allow.
+    pub fn process(&mut self, pcm32: &[f32]) -> anyhow::Result<Vec<f32>> {
+        self.pending.extend_from_slice(pcm32);
+        self.samples_in += pcm32.len();
+        let mut output = Vec::new();
+        loop {
+            let chunk_size = self.resampler.input_frames_next();
+            if self.pending.len() < chunk_size {
+                break;
+            }
+            let resampled = self
+                .resampler
+                .process(&[&self.pending[..chunk_size]], None)?;
+            self.pending.drain(..chunk_size);
+            self.emit(&resampled[0], &mut output);
+        }
+        Ok(output)
+    }
+
+    /// Resamples the buffered remainder and the resampler's delayed samples at the
+    /// end of a stream, then resets for the next one.
+    pub fn flush(&mut self) -> anyhow::Result<Vec<f32>> {
+        let mut output = Vec::new();
+        if !self.pending.is_empty() {
+            let resampled = self
+                .resampler
+                .process_partial(Some(&[&self.pending]), None)?;
+            self.emit(&resampled[0], &mut output);
+        }
+        while self.samples_out < self.expected_samples() {
+            let resampled = self.resampler.process_partial(None::<&[&[f32]]>, None)?;
+            if resampled[0].is_empty() {
+                break;
+            }
+            self.emit(&resampled[0], &mut output);
+        }
+        self.reset();
+        Ok(output)
+    }
+
+    /// Drops buffered samples and starts a new stream.
+    pub fn reset(&mut self) {
+        self.resampler.reset();
+        self.pending.clear();
+        self.delay = self.resampler.output_delay();
+        self.samples_in = 0;
+        self.samples_out = 0;
+    }
+
+    /// The number of output samples the input so far corresponds to.
+    fn expected_samples(&self) -> usize {
+        (self.samples_in as f64 * self.ratio).round() as usize
+    }
+
+    /// Appends resampled samples to `output`, skipping the resampler's latency and
+    /// the zero padding beyond the end of the input.
+    fn emit(&mut self, resampled: &[f32], output: &mut Vec<f32>) {
+        let skip = self.delay.min(resampled.len());
+        self.delay -= skip;
+        let take =
+            (resampled.len() - skip).min(self.expected_samples().saturating_sub(self.samples_out));
+        output.extend_from_slice(&resampled[skip..skip + take]);
+        self.samples_out += take;
+    }
+}
+
 /// Decodes a base64 string representing PCM16 audio into a vector of f32 samples.
 /// The function converts the string to a binary vector of u8, interprets chunks as i16 values,
 /// and then normalizes them to f32 values between -1.0 and 1.0.
@@ -89,6 +188,324 @@ pub fn decode_i16(base64_fragment: &str) -> Vec<i16> {
     }
 }
 
+/// Serializes i16 samples as little-endian PCM16 bytes.
+pub fn i16_to_le_bytes(pcm16: &[i16]) -> Vec<u8> {
+    pcm16
+        .iter()
+        .flat_map(|&sample| sample.to_le_bytes())
+        .collect()
+}
+
+/// Converts little-endian PCM16 bytes back into samples, ignoring a trailing odd byte.
+pub fn le_bytes_to_i16(bytes: &[u8]) -> Vec<i16> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+/// The lowest and highest sample rates accepted from clients, in Hz.
+pub const SUPPORTED_SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8_000..=192_000;
+/// The most interleaved channels accepted from clients.
+pub const MAX_CHANNELS: u16 = 8;
+
+/// The encoding of each sample in client audio, always little-endian.
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleFormat {
+    /// Signed 16-bit integers.
+    #[default]
+    Pcm16,
+    /// 32-bit floats between -1.0 and 1.0.
+    F32,
+}
+
+impl SampleFormat {
+    fn bytes(self) -> usize {
+        match self {
+            Self::Pcm16 => 2,
+            Self::F32 => 4,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> f32 {
+        match self {
+            Self::Pcm16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            Self::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Converts interleaved client audio into mono PCM16 at another sample rate.
+///
+/// Frames may be split anywhere, even mid-sample; bytes that do not make up a
+/// whole frame of all channels are kept until the next call.
+pub struct PcmConverter {
+    sample_format: SampleFormat,
+    channels: usize,
+    remainder: Vec<u8>,
+    resampler: Option<StreamingResampler>,
+}
+
+impl PcmConverter {
+    /// Creates a converter from `channels` interleaved channels of `sample_format`
+    /// at `in_sampling_rate` to mono PCM16 at `out_sampling_rate`.
+    pub fn new(
+        sample_format: SampleFormat,
+        channels: u16,
+        in_sampling_rate: u32,
+        out_sampling_rate: f64,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            SUPPORTED_SAMPLE_RATES.contains(&in_sampling_rate),
+            "Unsupported sample rate {in_sampling_rate} Hz"
+        );
+        anyhow::ensure!(
+            (1..=MAX_CHANNELS).contains(&channels),
+            "Unsupported channel count {channels}"
+        );
+        let resampler = if in_sampling_rate as f64 == out_sampling_rate {
+            None
+        } else {
+            Some(StreamingResampler::new(
+                in_sampling_rate as f64,
+                out_sampling_rate,
+                1024,
+            )?)
+        };
+        Ok(Self {
+            sample_format,
+            channels: channels as usize,
+            remainder: Vec::new(),
+            resampler,
+        })
+    }
+
+    /// Converts the whole frames in the buffered and new bytes, downmixing all
+    /// channels to mono by averaging them.
+    pub fn convert(&mut self, bytes: &[u8]) -> anyhow::Result<Vec<i16>> {
+        self.remainder.extend_from_slice(bytes);
+        let sample_bytes = self.sample_format.bytes();
+        let frame_bytes = sample_bytes * self.channels;
+        let whole = self.remainder.len() / frame_bytes * frame_bytes;
+        let mono: Vec<f32> = self.remainder[..whole]
+            .chunks_exact(frame_bytes)
+            .map(|frame| {
+                let sum: f32 = frame
+                    .chunks_exact(sample_bytes)
+                    .map(|sample| self.sample_format.read(sample))
+                    .sum();
+                sum / self.channels as f32
+            })
+            .collect();
+        self.remainder.drain(..whole);
+        let mono = match &mut self.resampler {
+            Some(resampler) => resampler.process(&mono)?,
+            None => mono,
+        };
+        Ok(convert_f32_to_i16(&mono))
+    }
+}
+
+/// The length of the frames the voice activity detector classifies, in milliseconds.
+const VAD_FRAME_MS: usize = 20;
+/// Voiced audio needed before it counts as the start of speech, in milliseconds.
+const VAD_MIN_SPEECH_MS: usize = 60;
+/// The frame level, in dBFS, that a VAD threshold of 0.0 and 1.0 correspond to.
+const VAD_LEVEL_RANGE_DB: (f32, f32) = (-60.0, -10.0);
+
+/// A change in whether the user is speaking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VadEvent {
+    SpeechStarted,
+    SpeechStopped,
+}
+
+/// An energy-based voice activity detector for mono PCM16.
+///
+/// Audio is classified in 20 ms frames by RMS level. Speech starts after 60 ms of
+/// voiced frames and stops after the configured silence, like the providers'
+/// server VAD. Input may arrive in chunks of any length.
+pub struct VoiceActivityDetector {
+    frame_samples: usize,
+    threshold_db: f32,
+    start_frames: usize,
+    stop_frames: usize,
+    pending: Vec<i16>,
+    speaking: bool,
+    run: usize,
+}
+
+impl VoiceActivityDetector {
+    /// Creates a detector for audio at `sample_rate`.
+    ///
+    /// `threshold` runs from 0.0 (most sensitive) to 1.0 (least sensitive), and
+    /// `silence_duration_ms` is the silence that ends speech.
+    pub fn new(sample_rate: f64, threshold: f32, silence_duration_ms: u32) -> Self {
+        let mut vad = Self {
+            frame_samples: sample_rate as usize * VAD_FRAME_MS / 1000,
+            threshold_db: 0.0,
+            start_frames: VAD_MIN_SPEECH_MS / VAD_FRAME_MS,
+            stop_frames: 0,
+            pending: Vec::new(),
+            speaking: false,
+            run: 0,
+        };
+        vad.configure(threshold, silence_duration_ms);
+        vad
+    }
+
+    /// Changes the threshold and silence duration, keeping the current state.
+    pub fn configure(&mut self, threshold: f32, silence_duration_ms: u32) {
+        let (quietest, loudest) = VAD_LEVEL_RANGE_DB;
+        self.threshold_db = quietest + (loudest - quietest) * threshold.clamp(0.0, 1.0);
+        self.stop_frames = (silence_duration_ms as usize).div_ceil(VAD_FRAME_MS).max(1);
+    }
+
+    /// Whether speech is in progress.
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    /// Classifies the whole frames in the buffered and new samples, returning any
+    /// changes in speech.
+    pub fn process(&mut self, pcm16: &[i16]) -> Vec<VadEvent> {
+        self.pending.extend_from_slice(pcm16);
+        let whole = self.pending.len() / self.frame_samples * self.frame_samples;
+        let frames: Vec<i16> = self.pending.drain(..whole).collect();
+        let mut events = Vec::new();
+        for frame in frames.chunks_exact(self.frame_samples) {
+            let voiced = frame_level_db(frame) >= self.threshold_db;
+            if voiced == self.speaking {
+                self.run = 0;
+                continue;
+            }
+            self.run += 1;
+            let needed = if self.speaking {
+                self.stop_frames
+            } else {
+                self.start_frames
+            };
+            if self.run >= needed {
+                self.speaking = voiced;
+                self.run = 0;
+                events.push(if voiced {
+                    VadEvent::SpeechStarted
+                } else {
+                    VadEvent::SpeechStopped
+                });
+            }
+        }
+        events
+    }
+}
+
+/// The RMS level of a frame in dBFS.
+fn frame_level_db(frame: &[i16]) -> f32 {
+    let energy: f64 = frame.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / frame.len() as f64;
+    let rms = energy.sqrt() / 32768.0;
+    (20.0 * rms.max(1e-6).log10()) as f32
+}
+
+/// The duration of each Opus packet produced by [`OpusPcmEncoder`].
+pub const OPUS_FRAME_MS: usize = 20;
+/// The largest Opus packet allowed by RFC 6716.
+const OPUS_MAX_PACKET_BYTES: usize = 1276;
+/// The longest audio a single Opus packet can hold, in milliseconds.
+const OPUS_MAX_PACKET_MS: usize = 120;
+
+/// Encodes mono PCM16 into 20 ms Opus packets.
+///
+/// Input may arrive in chunks of any length; samples that do not fill a whole
+/// packet are kept until the next call or [`OpusPcmEncoder::flush`].
+pub struct OpusPcmEncoder {
+    encoder: opus_rs::OpusEncoder,
+    frame_samples: usize,
+    pending: Vec<i16>,
+}
+
+impl OpusPcmEncoder {
+    /// Creates an encoder for speech at the given sample rate (8, 12, 16, 24 or 48 kHz).
+    pub fn new(sample_rate: f64) -> anyhow::Result<Self> {
+        let encoder = opus_rs::OpusEncoder::new(sample_rate as i32, 1, opus_rs::Application::Voip)
+            .map_err(|e| anyhow::anyhow!("Failed to create Opus encoder: {}", e))?;
+        Ok(Self {
+            encoder,
+            frame_samples: sample_rate as usize * OPUS_FRAME_MS / 1000,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Encodes as many whole packets as the buffered and new samples allow.
+    pub fn encode(&mut self, pcm16: &[i16]) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.pending.extend_from_slice(pcm16);
+        let whole = self.pending.len() / self.frame_samples * self.frame_samples;
+        let frames: Vec<i16> = self.pending.drain(..whole).collect();
+        frames
+            .chunks_exact(self.frame_samples)
+            .map(|frame| self.encode_frame(frame))
+            .collect()
+    }
+
+    /// Encodes the buffered remainder, padded with silence to a whole packet.
+    pub fn flush(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let mut frame = std::mem::take(&mut self.pending);
+        frame.resize(self.frame_samples, 0);
+        self.encode_frame(&frame).map(Some)
+    }
+
+    /// Drops buffered samples that have not been encoded yet.
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+
+    fn encode_frame(&mut self, frame: &[i16]) -> anyhow::Result<Vec<u8>> {
+        let mut packet = vec![0u8; OPUS_MAX_PACKET_BYTES];
+        let len = self
+            .encoder
+            .encode_i16(frame, self.frame_samples, &mut packet)
+            .map_err(|e| anyhow::anyhow!("Opus encoding failed: {}", e))?;
+        packet.truncate(len);
+        Ok(packet)
+    }
+}
+
+/// Decodes Opus packets into mono PCM16 at a fixed output sample rate.
+pub struct OpusPcmDecoder {
+    decoder: opus_rs::OpusDecoder,
+    max_samples: usize,
+}
+
+impl OpusPcmDecoder {
+    /// Creates a decoder producing audio at the given sample rate, whatever rate
+    /// the packets were encoded at.
+    pub fn new(sample_rate: f64) -> anyhow::Result<Self> {
+        let decoder = opus_rs::OpusDecoder::new(sample_rate as i32, 1)
+            .map_err(|e| anyhow::anyhow!("Failed to create Opus decoder: {}", e))?;
+        Ok(Self {
+            decoder,
+            max_samples: sample_rate as usize * OPUS_MAX_PACKET_MS / 1000,
+        })
+    }
+
+    /// Decodes one packet.
+    pub fn decode(&mut self, packet: &[u8]) -> anyhow::Result<Vec<i16>> {
+        let mut pcm32 = vec![0f32; self.max_samples];
+        let samples = self
+            .decoder
+            .decode(packet, self.max_samples, &mut pcm32)
+            .map_err(|e| anyhow::anyhow!("Opus decoding failed: {}", e))?;
+        pcm32.truncate(samples);
+        Ok(pcm32
+            .iter()
+            .map(|&sample| (sample * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+            .collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,6 +526,134 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_streaming_resampler_conserves_sample_count() {
+        let rates = [(24000.0, 16000.0), (16000.0, 24000.0), (48000.0, 24000.0)];
+        for (in_rate, out_rate) in rates {
+            for frame_size in [1, 7, 160, 511, 512, 513, 2000] {
+                let mut resampler = StreamingResampler::new(in_rate, out_rate, 512).unwrap();
+                let input: Vec<f32> = (0..9_973).map(|i| (i as f32 * 0.01).sin()).collect();
+                let mut output = Vec::new();
+                for frame in input.chunks(frame_size) {
+                    output.extend(resampler.process(frame).unwrap());
+                }
+                output.extend(resampler.flush().unwrap());
+                let expected = (input.len() as f64 * out_rate / in_rate).round() as usize;
+                assert_eq!(
+                    output.len(),
+                    expected,
+                    "{in_rate} -> {out_rate}, frames of {frame_size}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_streaming_resampler_keeps_partial_chunks() {
+        let mut resampler = StreamingResampler::new(24000.0, 16000.0, 512).unwrap();
+        // Less than a chunk is held back rather than rejected.
+        assert!(resampler.process(&[0.5; 100]).unwrap().is_empty());
+        let output = resampler.process(&[0.5; 1000]).unwrap();
+        assert!(!output.is_empty());
+        assert!(output.len() <= 1100 * 2 / 3);
+
+        // After a flush the next stream starts from scratch.
+        resampler.flush().unwrap();
+        assert!(resampler.process(&[0.5; 100]).unwrap().is_empty());
+        assert_eq!(resampler.flush().unwrap().len(), 67);
+
+        // A reset drops what was buffered.
+        resampler.process(&[0.5; 100]).unwrap();
+        resampler.reset();
+        assert!(resampler.flush().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pcm_converter_keeps_partial_frames() {
+        let mut converter = PcmConverter::new(SampleFormat::Pcm16, 1, 24000, 24000.0).unwrap();
+        let bytes = i16_to_le_bytes(&[1000, -2000, 3000]);
+        // An odd byte is held until the rest of its sample arrives.
+        assert_eq!(converter.convert(&bytes[..3]).unwrap().len(), 1);
+        assert_eq!(converter.convert(&bytes[3..]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_pcm_converter_downmixes_channels() {
+        let mut converter = PcmConverter::new(SampleFormat::F32, 2, 16000, 16000.0).unwrap();
+        let bytes: Vec<u8> = [0.5f32, -0.5, 0.25, 0.75]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let mono = converter.convert(&bytes).unwrap();
+        assert_eq!(mono.len(), 2);
+        assert_eq!(mono[0], 0);
+        assert_abs_diff_eq!(mono[1] as f32 / i16::MAX as f32, 0.5, epsilon = 0.001);
+    }
+
+    #[test]
+    fn test_pcm_converter_resamples() {
+        let mut converter = PcmConverter::new(SampleFormat::Pcm16, 1, 48000, 16000.0).unwrap();
+        let bytes = i16_to_le_bytes(&[0; 4800]);
+        let converted = converter.convert(&bytes).unwrap().len();
+        assert!((1600 - 1024..=1600).contains(&converted));
+    }
+
+    #[test]
+    fn test_pcm_converter_rejects_bad_formats() {
+        assert!(PcmConverter::new(SampleFormat::Pcm16, 0, 24000, 24000.0).is_err());
+        assert!(PcmConverter::new(SampleFormat::Pcm16, 1, 0, 24000.0).is_err());
+        assert!(PcmConverter::new(SampleFormat::Pcm16, 9, 24000, 24000.0).is_err());
+    }
+
+    /// `ms` of a 440 Hz tone at `amplitude` (silence at 0.0), at 16 kHz.
+    fn vad_audio(amplitude: f32, ms: usize) -> Vec<i16> {
+        (0..16 * ms)
+            .map(|i| {
+                let t = i as f32 / 16_000.0;
+                (amplitude * i16::MAX as f32 * (t * 440.0 * std::f32::consts::TAU).sin()) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_vad_detects_speech_start_and_stop() {
+        let mut vad = VoiceActivityDetector::new(16_000.0, 0.5, 300);
+        assert!(vad.process(&vad_audio(0.0, 500)).is_empty());
+
+        // A 20 ms click is too short to be speech.
+        assert!(vad.process(&vad_audio(0.5, 20)).is_empty());
+        assert!(vad.process(&vad_audio(0.0, 100)).is_empty());
+
+        // Speech arriving in uneven chunks starts once.
+        let speech = vad_audio(0.5, 400);
+        let mut events = Vec::new();
+        for chunk in speech.chunks(333) {
+            events.extend(vad.process(chunk));
+        }
+        assert_eq!(events, vec![VadEvent::SpeechStarted]);
+        assert!(vad.is_speaking());
+
+        // A short pause does not end it; the configured silence does.
+        assert!(vad.process(&vad_audio(0.0, 200)).is_empty());
+        assert!(vad.process(&vad_audio(0.5, 100)).is_empty());
+        assert_eq!(
+            vad.process(&vad_audio(0.0, 300)),
+            vec![VadEvent::SpeechStopped]
+        );
+        assert!(!vad.is_speaking());
+    }
+
+    #[test]
+    fn test_vad_threshold_sets_sensitivity() {
+        // A tone at about -46 dBFS.
+        let quiet = vad_audio(0.007, 200);
+        let mut vad = VoiceActivityDetector::new(16_000.0, 0.5, 300);
+        assert!(vad.process(&quiet).is_empty());
+
+        vad.configure(0.1, 300);
+        assert_eq!(vad.process(&quiet), vec![VadEvent::SpeechStarted]);
+    }
+
     #[test]
     fn test_decode_f32_from_base64_i16() {
         // Test with known values
@@ -254,6 +799,12 @@ mod tests {
         assert!(result.is_empty());
     }
 
+    #[test]
+    fn test_i16_to_le_bytes() {
+        assert_eq!(i16_to_le_bytes(&[1, -2, 256]), vec![1, 0, 0xFE, 0xFF, 0, 1]);
+        assert!(i16_to_le_bytes(&[]).is_empty());
+    }
+
     #[test]
     fn test_round_trip_conversions() {
         // Test f32 -> base64 -> f32
@@ -283,6 +834,60 @@ mod tests {
         }
     }
 
+    /// A 440 Hz tone at half amplitude.
+    fn tone(sample_rate: f64, samples: usize) -> Vec<i16> {
+        (0..samples)
+            .map(|i| {
+                let t = i as f64 / sample_rate;
+                ((2.0 * std::f64::consts::PI * 440.0 * t).sin() * 16384.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_opus_encoder_buffers_partial_frames() {
+        let mut encoder = OpusPcmEncoder::new(FRONTEND_AUDIO_PLAYER_SAMPLE_RATE).unwrap();
+        // 20 ms at 24 kHz is 480 samples.
+        assert!(encoder.encode(&tone(24000.0, 300)).unwrap().is_empty());
+        assert_eq!(encoder.encode(&tone(24000.0, 700)).unwrap().len(), 2);
+        assert!(encoder.flush().unwrap().is_some());
+        assert!(encoder.flush().unwrap().is_none());
+
+        encoder.encode(&tone(24000.0, 100)).unwrap();
+        encoder.discard();
+        assert!(encoder.flush().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_opus_round_trip() {
+        let original = tone(24000.0, 480 * 10);
+        let mut encoder = OpusPcmEncoder::new(FRONTEND_AUDIO_PLAYER_SAMPLE_RATE).unwrap();
+        let mut decoder = OpusPcmDecoder::new(FRONTEND_AUDIO_PLAYER_SAMPLE_RATE).unwrap();
+        let packets = encoder.encode(&original).unwrap();
+        assert_eq!(packets.len(), 10);
+
+        let mut decoded = Vec::new();
+        for packet in &packets {
+            assert!(packet.len() < 480 * 2, "packet not compressed");
+            decoded.extend(decoder.decode(packet).unwrap());
+        }
+        assert_eq!(decoded.len(), original.len());
+
+        // Lossy, but the tone's energy survives once the codec has settled.
+        let energy =
+            |pcm: &[i16]| pcm.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / pcm.len() as f64;
+        let settled = 480 * 5..;
+        let ratio = energy(&decoded[settled.clone()]) / energy(&original[settled]);
+        assert!((0.5..2.0).contains(&ratio), "energy ratio {}", ratio);
+
+        // Packets encoded at another rate decode to the decoder's rate.
+        let mut encoder = OpusPcmEncoder::new(48000.0).unwrap();
+        let packet = encoder.encode(&tone(48000.0, 960)).unwrap().remove(0);
+        assert_eq!(decoder.decode(&packet).unwrap().len(), 480);
+
+        assert!(decoder.decode(&[0xFF; 3]).is_err());
+    }
+
     #[test]
     fn test_sample_rate_constants() {
         // Verify the constants are reasonable values
@@ -314,7 +919,7 @@ mod tests {
 
         // All should be clamped to valid range
         for value in decoded {
-            assert!(value >= -1.0 && value <= 1.0);
+            assert!((-1.0..=1.0).contains(&value));
         }
 
         // Test with very large chunk size for resampler
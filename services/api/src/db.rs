@@ -5,10 +5,40 @@
 
 use anyhow::Result;
 use feynman_core::agent::FeynmanAgent;
-use sqlx::PgPool;
+use sqlx::{PgPool, postgres::PgListener};
 use uuid::Uuid;
 
-use crate::models::{Message, MessageRole, Session, SessionStatus};
+use crate::models::{
+    AgentStateSnapshot, DailyUsage, ListSessionsQuery, MESSAGE_PREVIEW_CHARS, Message,
+    MessagePreview, MessageRole, ModelUsage, Recording, RecordingFormat, Session, SessionCursor,
+    SessionSort, SessionStatus, SessionSummary,
+};
+use crate::usage::UsageEntry;
+
+/// The channel changes to sessions' messages and agent states are announced on.
+const SESSION_CHANGES_CHANNEL: &str = "session_changes";
+
+/// A change to a session, as announced on the session changes channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionChange {
+    /// A message was added or edited.
+    Messages,
+    /// A new agent state was stored.
+    AgentState,
+}
+
+impl SessionChange {
+    /// Parses a notification payload, `<session id>:<table>`.
+    pub fn parse(payload: &str) -> Option<(Uuid, Self)> {
+        let (session_id, table) = payload.split_once(':')?;
+        let change = match table {
+            "messages" => Self::Messages,
+            "agent_states" => Self::AgentState,
+            _ => return None,
+        };
+        Some((session_id.parse().ok()?, change))
+    }
+}
 
 /// A wrapper around the `PgPool` to provide a clear data access interface.
 #[derive(Clone)]
@@ -22,6 +52,14 @@ impl Db {
         Self { pool }
     }
 
+    /// Listens for changes to any session, which `SessionChange::parse` reads
+    /// from the notification payloads.
+    pub async fn listen_session_changes(&self) -> Result<PgListener> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(SESSION_CHANGES_CHANNEL).await?;
+        Ok(listener)
+    }
+
     /// Runs all pending `sqlx` migrations.
     pub async fn run_migrations(&self) -> Result<()> {
         sqlx::migrate!("./migrations").run(&self.pool).await?;
@@ -33,6 +71,8 @@ impl Db {
         &self,
         user_id: &str,
         topic: &str,
+        allowed_tools: &[String],
+        record_audio: bool,
         initial_state: &FeynmanAgent,
     ) -> Result<Session> {
         let mut tx = self.pool.begin().await?;
@@ -40,12 +80,15 @@ impl Db {
         let session = sqlx::query_as!(
             Session,
             r#"
-            INSERT INTO sessions (user_id, topic)
-            VALUES ($1, $2)
-            RETURNING id, user_id, topic, status as "status: _", created_at, updated_at
+            INSERT INTO sessions (user_id, topic, allowed_tools, record_audio)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, topic, status as "status: _", allowed_tools, record_audio,
+                      created_at, updated_at
             "#,
             user_id,
-            topic
+            topic,
+            allowed_tools,
+            record_audio
         )
         .fetch_one(&mut *tx)
         .await?;
@@ -69,7 +112,8 @@ impl Db {
         let session = sqlx::query_as!(
             Session,
             r#"
-            SELECT id, user_id, topic, status as "status: _", created_at, updated_at
+            SELECT id, user_id, topic, status as "status: _", allowed_tools, record_audio,
+                      created_at, updated_at
             FROM sessions
             WHERE id = $1 AND user_id = $2
             "#,
@@ -81,23 +125,126 @@ impl Db {
         Ok(session)
     }
 
-    /// Lists all sessions for a given user, ordered by most recent.
-    pub async fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>> {
-        let sessions = sqlx::query_as!(
-            Session,
+    /// Lists a page of a user's sessions matching `query`, newest first by
+    /// its sort, continuing after `after` and summarizing each.
+    pub async fn list_sessions(
+        &self,
+        user_id: &str,
+        query: &ListSessionsQuery,
+        after: Option<SessionCursor>,
+        limit: i64,
+    ) -> Result<Vec<SessionSummary>> {
+        let by_updated = query.sort.unwrap_or_default() == SessionSort::UpdatedAt;
+        // Match the topic text literally, not as a LIKE pattern.
+        let topic = query.topic.as_deref().map(|topic| {
+            topic
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        });
+        let rows = sqlx::query!(
             r#"
-            SELECT id, user_id, topic, status as "status: _", created_at, updated_at
-            FROM sessions
-            WHERE user_id = $1
-            ORDER BY created_at DESC
+            SELECT s.id, s.user_id, s.topic, s.status as "status: SessionStatus",
+                   s.allowed_tools, s.record_audio, s.created_at, s.updated_at,
+                   (SELECT COUNT(*) FROM jsonb_object_keys(a.state_json->'covered_subtopics'))
+                       as "covered_subtopics!",
+                   (SELECT COUNT(*) FROM jsonb_object_keys(a.state_json->'incomplete_subtopics'))
+                       as "incomplete_subtopics!",
+                   m.role as "last_role?: MessageRole",
+                   LEFT(m.content, $10) as "last_content?",
+                   m.created_at as "last_created_at?"
+            FROM sessions s
+            LEFT JOIN LATERAL (
+                SELECT state_json FROM agent_states
+                WHERE session_id = s.id
+                ORDER BY created_at DESC
+                LIMIT 1
+            ) a ON TRUE
+            LEFT JOIN LATERAL (
+                SELECT role, content, created_at FROM messages
+                WHERE session_id = s.id
+                ORDER BY id DESC
+                LIMIT 1
+            ) m ON TRUE
+            WHERE s.user_id = $1
+              AND ($2::session_status IS NULL OR s.status = $2)
+              AND ($3::TEXT IS NULL OR s.topic ILIKE '%' || $3 || '%')
+              AND ($4::TIMESTAMPTZ IS NULL OR s.created_at >= $4)
+              AND ($5::TIMESTAMPTZ IS NULL OR s.created_at < $5)
+              AND ($7::TIMESTAMPTZ IS NULL
+                   OR (CASE WHEN $6 THEN s.updated_at ELSE s.created_at END, s.id) < ($7, $8))
+            ORDER BY CASE WHEN $6 THEN s.updated_at ELSE s.created_at END DESC, s.id DESC
+            LIMIT $9
             "#,
-            user_id
+            user_id,
+            query.status as Option<SessionStatus>,
+            topic,
+            query.created_after,
+            query.created_before,
+            by_updated,
+            after.map(|cursor| cursor.at),
+            after.map(|cursor| cursor.id),
+            limit,
+            MESSAGE_PREVIEW_CHARS
         )
         .fetch_all(&self.pool)
         .await?;
+
+        let sessions = rows
+            .into_iter()
+            .map(|row| {
+                let last_message = match (row.last_role, row.last_content, row.last_created_at) {
+                    (Some(role), Some(content), Some(created_at)) => Some(MessagePreview {
+                        role,
+                        content,
+                        created_at,
+                    }),
+                    _ => None,
+                };
+                SessionSummary {
+                    session: Session {
+                        id: row.id,
+                        user_id: row.user_id,
+                        topic: row.topic,
+                        status: row.status,
+                        allowed_tools: row.allowed_tools,
+                        record_audio: row.record_audio,
+                        created_at: row.created_at,
+                        updated_at: row.updated_at,
+                    },
+                    covered_subtopics: row.covered_subtopics,
+                    total_subtopics: row.covered_subtopics + row.incomplete_subtopics,
+                    last_message,
+                }
+            })
+            .collect();
         Ok(sessions)
     }
 
+    /// Finds the user a session belongs to.
+    pub async fn get_session_user(&self, session_id: Uuid) -> Result<Option<String>> {
+        let user_id = sqlx::query_scalar!("SELECT user_id FROM sessions WHERE id = $1", session_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(user_id)
+    }
+
+    /// Counts the sessions a user created since `since`.
+    pub async fn count_sessions_since(
+        &self,
+        user_id: &str,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "count!" FROM sessions WHERE user_id = $1 AND created_at >= $2"#,
+            user_id,
+            since
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+
     /// Adds a new message to a session's conversation history.
     pub async fn add_message(
         &self,
@@ -121,6 +268,25 @@ impl Db {
         Ok(message)
     }
 
+    /// Replaces the content of an existing message, e.g. to keep only the part of
+    /// an AI reply that was heard before the user interrupted it.
+    pub async fn update_message_content(&self, message_id: i64, content: &str) -> Result<Message> {
+        let message = sqlx::query_as!(
+            Message,
+            r#"
+            UPDATE messages
+            SET content = $1
+            WHERE id = $2
+            RETURNING id, session_id, role as "role: _", content, created_at
+            "#,
+            content,
+            message_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(message)
+    }
+
     /// Retrieves the full message history for a session, ordered chronologically.
     pub async fn get_session_messages(&self, session_id: Uuid) -> Result<Vec<Message>> {
         let messages = sqlx::query_as!(
@@ -138,6 +304,84 @@ impl Db {
         Ok(messages)
     }
 
+    /// Lists a page of a session's messages, oldest first, after the message `after`.
+    pub async fn list_messages(
+        &self,
+        session_id: Uuid,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Message>> {
+        let messages = sqlx::query_as!(
+            Message,
+            r#"
+            SELECT id, session_id, role as "role: _", content, created_at
+            FROM messages
+            WHERE session_id = $1 AND ($2::BIGINT IS NULL OR id > $2)
+            ORDER BY id ASC
+            LIMIT $3
+            "#,
+            session_id,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    /// Lists a page of a session's saved agent states, oldest first, after the
+    /// state `after`, each with the user message whose turn saved it.
+    pub async fn list_agent_states(
+        &self,
+        session_id: Uuid,
+        after: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<AgentStateSnapshot>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT a.id, a.state_json, a.created_at,
+                   m.id as "message_id?", m.role as "message_role?: MessageRole",
+                   m.content as "message_content?", m.created_at as "message_created_at?"
+            FROM agent_states a
+            LEFT JOIN messages m ON m.id = a.message_id
+            WHERE a.session_id = $1 AND ($2::BIGINT IS NULL OR a.id > $2)
+            ORDER BY a.id ASC
+            LIMIT $3
+            "#,
+            session_id,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let message = match (
+                    row.message_id,
+                    row.message_role,
+                    row.message_content,
+                    row.message_created_at,
+                ) {
+                    (Some(id), Some(role), Some(content), Some(created_at)) => Some(Message {
+                        id,
+                        session_id,
+                        role,
+                        content,
+                        created_at,
+                    }),
+                    _ => None,
+                };
+                Ok(AgentStateSnapshot {
+                    id: row.id,
+                    state: serde_json::from_value(row.state_json)?,
+                    message,
+                    created_at: row.created_at,
+                })
+            })
+            .collect()
+    }
+
     /// Retrieves the most recent agent state for a session.
     pub async fn get_latest_agent_state(&self, session_id: Uuid) -> Result<Option<FeynmanAgent>> {
         let record = sqlx::query!(
@@ -156,13 +400,44 @@ impl Db {
         }
     }
 
-    /// Persists a new version of the agent's state.
-    pub async fn update_agent_state(&self, session_id: Uuid, state: &FeynmanAgent) -> Result<()> {
+    /// Retrieves the external tools a session may use.
+    pub async fn get_allowed_tools(&self, session_id: Uuid) -> Result<Vec<String>> {
+        let allowed_tools = sqlx::query_scalar!(
+            "SELECT allowed_tools FROM sessions WHERE id = $1",
+            session_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .unwrap_or_default();
+        Ok(allowed_tools)
+    }
+
+    /// Whether a session's voice audio is recorded.
+    pub async fn get_record_audio(&self, session_id: Uuid) -> Result<bool> {
+        let record_audio = sqlx::query_scalar!(
+            "SELECT record_audio FROM sessions WHERE id = $1",
+            session_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .unwrap_or_default();
+        Ok(record_audio)
+    }
+
+    /// Persists a new version of the agent's state, saved during the turn
+    /// answering `message_id` if any.
+    pub async fn update_agent_state(
+        &self,
+        session_id: Uuid,
+        state: &FeynmanAgent,
+        message_id: Option<i64>,
+    ) -> Result<()> {
         let state_json = serde_json::to_value(state)?;
         sqlx::query!(
-            "INSERT INTO agent_states (session_id, state_json) VALUES ($1, $2)",
+            "INSERT INTO agent_states (session_id, state_json, message_id) VALUES ($1, $2, $3)",
             session_id,
-            state_json
+            state_json,
+            message_id
         )
         .execute(&self.pool)
         .await?;
@@ -181,7 +456,8 @@ impl Db {
             UPDATE sessions
             SET status = $1
             WHERE id = $2
-            RETURNING id, user_id, topic, status as "status: _", created_at, updated_at
+            RETURNING id, user_id, topic, status as "status: _", allowed_tools, record_audio,
+                      created_at, updated_at
             "#,
             status as _,
             session_id
@@ -190,4 +466,252 @@ impl Db {
         .await?;
         Ok(session)
     }
+
+    /// Records a stored audio clip, optionally linked to the message it was spoken as.
+    pub async fn add_recording(
+        &self,
+        session_id: Uuid,
+        message_id: Option<i64>,
+        role: MessageRole,
+        format: RecordingFormat,
+        object_key: &str,
+        duration_ms: i32,
+    ) -> Result<Recording> {
+        let recording = sqlx::query_as!(
+            Recording,
+            r#"
+            INSERT INTO recordings (session_id, message_id, role, format, object_key, duration_ms)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, session_id, message_id, role as "role: _", format as "format: _",
+                      object_key, duration_ms, created_at
+            "#,
+            session_id,
+            message_id,
+            role as _,
+            format as _,
+            object_key,
+            duration_ms
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(recording)
+    }
+
+    /// Lists the recordings of a session, ordered chronologically.
+    pub async fn list_recordings(&self, session_id: Uuid) -> Result<Vec<Recording>> {
+        let recordings = sqlx::query_as!(
+            Recording,
+            r#"
+            SELECT id, session_id, message_id, role as "role: _", format as "format: _",
+                   object_key, duration_ms, created_at
+            FROM recordings
+            WHERE session_id = $1
+            ORDER BY created_at ASC, id ASC
+            "#,
+            session_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(recordings)
+    }
+
+    /// Retrieves a single recording of a session.
+    pub async fn get_recording(
+        &self,
+        session_id: Uuid,
+        recording_id: i64,
+    ) -> Result<Option<Recording>> {
+        let recording = sqlx::query_as!(
+            Recording,
+            r#"
+            SELECT id, session_id, message_id, role as "role: _", format as "format: _",
+                   object_key, duration_ms, created_at
+            FROM recordings
+            WHERE session_id = $1 AND id = $2
+            "#,
+            session_id,
+            recording_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(recording)
+    }
+
+    /// Lists the recordings created before `cutoff`.
+    pub async fn list_recordings_before(
+        &self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<Recording>> {
+        let recordings = sqlx::query_as!(
+            Recording,
+            r#"
+            SELECT id, session_id, message_id, role as "role: _", format as "format: _",
+                   object_key, duration_ms, created_at
+            FROM recordings
+            WHERE created_at < $1
+            ORDER BY id
+            "#,
+            cutoff
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(recordings)
+    }
+
+    /// Deletes the given recordings, returning how many were deleted.
+    pub async fn delete_recordings(&self, recording_ids: &[i64]) -> Result<u64> {
+        let result = sqlx::query!("DELETE FROM recordings WHERE id = ANY($1)", recording_ids)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Stores the usage of one LLM call or stretch of audio, under the
+    /// session's user.
+    pub async fn add_usage(
+        &self,
+        session_id: Uuid,
+        entry: &UsageEntry,
+        cost_usd: f64,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO usage_records (session_id, user_id, kind, model, prompt_tokens,
+                                       completion_tokens, audio_input_seconds,
+                                       audio_output_seconds, cost_usd)
+            SELECT id, user_id, $2, $3, $4, $5, $6, $7, $8
+            FROM sessions
+            WHERE id = $1
+            "#,
+            session_id,
+            entry.kind as _,
+            entry.model,
+            i64::from(entry.prompt_tokens),
+            i64::from(entry.completion_tokens),
+            entry.audio_input_seconds,
+            entry.audio_output_seconds,
+            cost_usd
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Totals a session's usage by kind and model.
+    pub async fn session_usage(&self, session_id: Uuid) -> Result<Vec<ModelUsage>> {
+        let usage = sqlx::query_as!(
+            ModelUsage,
+            r#"
+            SELECT kind as "kind: _", model,
+                   SUM(prompt_tokens)::BIGINT as "prompt_tokens!",
+                   SUM(completion_tokens)::BIGINT as "completion_tokens!",
+                   SUM(audio_input_seconds) as "audio_input_seconds!",
+                   SUM(audio_output_seconds) as "audio_output_seconds!",
+                   SUM(cost_usd) as "cost_usd!"
+            FROM usage_records
+            WHERE session_id = $1
+            GROUP BY kind, model
+            ORDER BY kind, model
+            "#,
+            session_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(usage)
+    }
+
+    /// Totals a user's usage across all their sessions by kind and model.
+    pub async fn user_usage(&self, user_id: &str) -> Result<Vec<ModelUsage>> {
+        let usage = sqlx::query_as!(
+            ModelUsage,
+            r#"
+            SELECT kind as "kind: _", model,
+                   SUM(prompt_tokens)::BIGINT as "prompt_tokens!",
+                   SUM(completion_tokens)::BIGINT as "completion_tokens!",
+                   SUM(audio_input_seconds) as "audio_input_seconds!",
+                   SUM(audio_output_seconds) as "audio_output_seconds!",
+                   SUM(cost_usd) as "cost_usd!"
+            FROM usage_records
+            WHERE user_id = $1
+            GROUP BY kind, model
+            ORDER BY kind, model
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(usage)
+    }
+
+    /// Totals the tokens and the seconds of voice audio a user consumed since `since`.
+    pub async fn usage_since(
+        &self,
+        user_id: &str,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(i64, f64)> {
+        let usage = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(prompt_tokens + completion_tokens), 0)::BIGINT as "tokens!",
+                   COALESCE(SUM(audio_input_seconds + audio_output_seconds), 0) as "audio_seconds!"
+            FROM usage_records
+            WHERE user_id = $1 AND created_at >= $2
+            "#,
+            user_id,
+            since
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok((usage.tokens, usage.audio_seconds))
+    }
+
+    /// Totals a user's usage for each UTC day since `since`, leaving out days without any.
+    pub async fn daily_usage(
+        &self,
+        user_id: &str,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<DailyUsage>> {
+        let usage = sqlx::query_as!(
+            DailyUsage,
+            r#"
+            SELECT (created_at AT TIME ZONE 'UTC')::DATE as "day!",
+                   SUM(prompt_tokens)::BIGINT as "prompt_tokens!",
+                   SUM(completion_tokens)::BIGINT as "completion_tokens!",
+                   SUM(audio_input_seconds) as "audio_input_seconds!",
+                   SUM(audio_output_seconds) as "audio_output_seconds!",
+                   SUM(cost_usd) as "cost_usd!"
+            FROM usage_records
+            WHERE user_id = $1 AND created_at >= $2
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+            user_id,
+            since
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_session_change() {
+        let session_id = Uuid::new_v4();
+        assert_eq!(
+            SessionChange::parse(&format!("{}:messages", session_id)),
+            Some((session_id, SessionChange::Messages))
+        );
+        assert_eq!(
+            SessionChange::parse(&format!("{}:agent_states", session_id)),
+            Some((session_id, SessionChange::AgentState))
+        );
+        assert_eq!(
+            SessionChange::parse(&format!("{}:recordings", session_id)),
+            None
+        );
+        assert_eq!(SessionChange::parse("not-a-uuid:messages"), None);
+    }
 }
@@ -0,0 +1,64 @@
+//! External MCP Tool Servers
+//!
+//! Connects to the MCP servers configured in `MCP_SERVERS` once at startup, so
+//! sessions can use their tools alongside the agent's own. A server that
+//! cannot be reached is left out with a warning rather than stopping the API.
+
+use crate::config::{McpServerConfig, McpServerTransport};
+use anyhow::{Context, Result};
+use feynman_core::tools::{ExternalTools, McpTools, ToolRegistry};
+use rmcp::{
+    ServiceExt,
+    transport::{ConfigureCommandExt, StreamableHttpClientTransport, TokioChildProcess},
+};
+use std::{sync::Arc, time::Duration};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Connects to every configured server, skipping those that fail.
+pub async fn connect_all(servers: &[McpServerConfig]) -> Vec<Arc<ExternalTools>> {
+    let mut connected = Vec::with_capacity(servers.len());
+    for server in servers {
+        let timeout = Duration::from_secs(server.timeout_secs);
+        match tokio::time::timeout(timeout, connect(server)).await {
+            Ok(Ok(tools)) => {
+                info!(
+                    server = %server.name,
+                    tools = tools.tools().len(),
+                    "Connected to external MCP server."
+                );
+                connected.push(Arc::new(ExternalTools::new(
+                    server.name.clone(),
+                    Arc::new(tools),
+                    timeout,
+                )));
+            }
+            Ok(Err(e)) => {
+                warn!(server = %server.name, error = ?e, "Skipping external MCP server.")
+            }
+            Err(_) => warn!(
+                server = %server.name,
+                "Skipping external MCP server: it did not connect in time."
+            ),
+        }
+    }
+    connected
+}
+
+/// Starts or reaches one server and lists its tools.
+async fn connect(server: &McpServerConfig) -> Result<McpTools> {
+    let client = match &server.transport {
+        McpServerTransport::Stdio { command, args, env } => {
+            let process = TokioChildProcess::new(Command::new(command).configure(|cmd| {
+                cmd.args(args).envs(env);
+            }))
+            .with_context(|| format!("Failed to launch '{}'", command))?;
+            ().serve(process).await?
+        }
+        McpServerTransport::Http { url } => {
+            ().serve(StreamableHttpClientTransport::from_uri(url.as_str()))
+                .await?
+        }
+    };
+    McpTools::list(client).await
+}
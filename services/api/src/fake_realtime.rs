@@ -0,0 +1,1003 @@
+//! Local Stand-in for the Realtime Voice Providers
+//!
+//! This module implements a small WebSocket server that speaks just enough of the
+//! OpenAI Realtime and Gemini Live protocols to drive the voice path end to end
+//! without live API keys. It is served by the `fake-realtime` binary and can also
+//! be embedded directly in tests via [`serve`].
+//!
+//! - `/v1/realtime` behaves like the OpenAI Realtime API: it acknowledges
+//!   `session.update`, turns appended input audio into a transcribed utterance, and
+//!   answers `response.create` with synthetic audio deltas. Once a session update
+//!   sets `turn_detection` to `null`, utterances end only on `input_audio_buffer.commit`.
+//! - `/ws/{method}` behaves like the Gemini Live API: it answers `setup` with
+//!   `setupComplete`, transcribes `realtimeInput` audio and answers each utterance
+//!   on its own (as the Live API does), and answers completed `clientContent`
+//!   turns with inline audio followed by `turnComplete`. When the setup disables
+//!   automatic activity detection, utterances end only on an `activityEnd` marker.
+//!
+//! When [`FakeRealtimeConfig::tool_call`] names a function the client declared, the
+//! automatic reply to each utterance is that function call; the audio reply follows
+//! once the client returns the call's output.
+//!
+//! When [`FakeRealtimeConfig::late_turn_complete`] is set, the Gemini endpoint
+//! holds back the `turnComplete` of its automatic reply to an utterance until the
+//! client next sends `clientContent`, as the Live API may finish its own turn
+//! after a reply was requested.
+//!
+//! When [`FakeRealtimeConfig::session_ms`] is set, every connection is closed after
+//! that long, like the providers' session limits, to exercise reconnects.
+
+use crate::audio_utils;
+use async_openai::types::realtime::{
+    self as oai_realtime, ClientEvent as OAIClientEvent, ServerEvent as OAIServerEvent,
+};
+use axum::{
+    Router,
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+    routing::get,
+};
+use base64::Engine;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+
+/// Controls the canned behaviour of the stand-in server.
+#[derive(Clone, Debug)]
+pub struct FakeRealtimeConfig {
+    /// The transcript reported for every completed user utterance.
+    pub transcript: String,
+    /// How many milliseconds of appended user audio make up one utterance.
+    pub utterance_ms: u32,
+    /// The length of the synthetic audio returned for each response, in milliseconds.
+    pub response_ms: u32,
+    /// The length of each audio delta sent back to the client, in milliseconds.
+    pub chunk_ms: u32,
+    /// A function call made in reply to each utterance, if the client declared it.
+    pub tool_call: Option<FakeToolCall>,
+    /// Closes each connection after this many milliseconds, if set.
+    pub session_ms: Option<u32>,
+    /// Gemini only: sends the automatic reply's `turnComplete` after the next `clientContent`.
+    pub late_turn_complete: bool,
+}
+
+/// A canned function call made by the stand-in model.
+#[derive(Clone, Debug)]
+pub struct FakeToolCall {
+    /// The name of the function to call.
+    pub name: String,
+    /// The JSON arguments to call it with.
+    pub arguments: Value,
+}
+
+impl Default for FakeRealtimeConfig {
+    fn default() -> Self {
+        Self {
+            transcript: "A linked list is a chain of nodes.".to_string(),
+            utterance_ms: 500,
+            response_ms: 300,
+            chunk_ms: 100,
+            tool_call: None,
+            session_ms: None,
+            late_turn_complete: false,
+        }
+    }
+}
+
+impl FakeRealtimeConfig {
+    /// Loads the configuration from `FAKE_REALTIME_*` environment variables,
+    /// falling back to the defaults for anything unset or unparsable.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let ms = |name: &str, default: u32| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            transcript: std::env::var("FAKE_REALTIME_TRANSCRIPT").unwrap_or(defaults.transcript),
+            utterance_ms: ms("FAKE_REALTIME_UTTERANCE_MS", defaults.utterance_ms),
+            response_ms: ms("FAKE_REALTIME_RESPONSE_MS", defaults.response_ms),
+            chunk_ms: ms("FAKE_REALTIME_CHUNK_MS", defaults.chunk_ms).max(1),
+            tool_call: std::env::var("FAKE_REALTIME_TOOL_CALL")
+                .ok()
+                .map(|name| FakeToolCall {
+                    name,
+                    arguments: std::env::var("FAKE_REALTIME_TOOL_ARGS")
+                        .ok()
+                        .and_then(|args| serde_json::from_str(&args).ok())
+                        .unwrap_or_else(|| json!({})),
+                }),
+            session_ms: std::env::var("FAKE_REALTIME_SESSION_MS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            late_turn_complete: std::env::var("FAKE_REALTIME_LATE_TURN_COMPLETE")
+                .is_ok_and(|v| v == "1" || v == "true"),
+        }
+    }
+}
+
+/// Creates the router exposing both provider endpoints.
+pub fn router(config: FakeRealtimeConfig) -> Router {
+    Router::new()
+        .route("/v1/realtime", get(openai_handler))
+        .route("/ws/{method}", get(gemini_handler))
+        .with_state(Arc::new(config))
+}
+
+/// Serves the stand-in on an already bound listener until the task is dropped.
+pub async fn serve(listener: TcpListener, config: FakeRealtimeConfig) -> anyhow::Result<()> {
+    axum::serve(listener, router(config)).await?;
+    Ok(())
+}
+
+async fn openai_handler(
+    ws: WebSocketUpgrade,
+    State(config): State<Arc<FakeRealtimeConfig>>,
+) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = run_openai(socket, &config).await {
+            warn!(error = ?e, "Fake OpenAI session ended with error.");
+        }
+    })
+}
+
+async fn gemini_handler(
+    ws: WebSocketUpgrade,
+    State(config): State<Arc<FakeRealtimeConfig>>,
+) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = run_gemini(socket, &config).await {
+            warn!(error = ?e, "Fake Gemini session ended with error.");
+        }
+    })
+}
+
+/// The instant at which a connection accepted now hits the configured session limit.
+fn session_deadline(config: &FakeRealtimeConfig) -> Option<tokio::time::Instant> {
+    config
+        .session_ms
+        .map(|ms| tokio::time::Instant::now() + std::time::Duration::from_millis(ms.into()))
+}
+
+/// Receives the next client message, or `None` once the session limit is reached.
+async fn recv_before(
+    socket: &mut WebSocket,
+    deadline: Option<tokio::time::Instant>,
+) -> Option<Result<Message, axum::Error>> {
+    match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, socket.recv()).await {
+            Ok(msg) => msg,
+            Err(_) => {
+                info!("Fake session limit reached; closing the connection.");
+                let _ = socket.send(Message::Close(None)).await;
+                None
+            }
+        },
+        None => socket.recv().await,
+    }
+}
+
+/// Generates `ms` milliseconds of a quiet 440 Hz tone, continuing from `offset` samples.
+fn synth_tone(sample_rate: f64, ms: u32, offset: usize) -> Vec<i16> {
+    let samples = (sample_rate * ms as f64 / 1000.0) as usize;
+    (offset..offset + samples)
+        .map(|n| {
+            let t = n as f64 / sample_rate;
+            ((t * 440.0 * std::f64::consts::TAU).sin() * 0.2 * i16::MAX as f64) as i16
+        })
+        .collect()
+}
+
+/// Returns the number of PCM16 bytes in a base64 payload.
+fn decoded_len(base64_audio: &str) -> usize {
+    base64::engine::general_purpose::STANDARD
+        .decode(base64_audio)
+        .map(|bytes| bytes.len())
+        .unwrap_or(0)
+}
+
+/// Splits a transcript into word-sized deltas that concatenate back to the original.
+fn transcript_deltas(transcript: &str) -> Vec<String> {
+    transcript
+        .split_inclusive(' ')
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the configured tool call if the client declared a function by that name.
+fn declared_tool_call<'a>(
+    config: &'a FakeRealtimeConfig,
+    declared: &[String],
+) -> Option<&'a FakeToolCall> {
+    config
+        .tool_call
+        .as_ref()
+        .filter(|call| declared.contains(&call.name))
+}
+
+/// Converts a duration into a PCM16 mono byte count at the given rate.
+fn bytes_for_ms(sample_rate: f64, ms: u32) -> usize {
+    (sample_rate * ms as f64 / 1000.0) as usize * 2
+}
+
+// --- OpenAI Realtime ---
+
+struct OpenAIState {
+    counter: u64,
+    buffered_bytes: usize,
+    speaking: bool,
+    vad: bool,
+    create_response: bool,
+    previous_item_id: String,
+    tools: Vec<String>,
+}
+
+impl OpenAIState {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.counter += 1;
+        format!("{}_{}", prefix, self.counter)
+    }
+}
+
+async fn send_openai(socket: &mut WebSocket, event: OAIServerEvent) -> anyhow::Result<()> {
+    socket
+        .send(Message::Text(serde_json::to_string(&event)?.into()))
+        .await?;
+    Ok(())
+}
+
+async fn run_openai(mut socket: WebSocket, config: &FakeRealtimeConfig) -> anyhow::Result<()> {
+    info!("Fake OpenAI Realtime client connected.");
+    let mut state = OpenAIState {
+        counter: 0,
+        buffered_bytes: 0,
+        speaking: false,
+        vad: true,
+        create_response: true,
+        previous_item_id: String::new(),
+        tools: Vec::new(),
+    };
+    let utterance_bytes = bytes_for_ms(
+        audio_utils::OPENAI_REALTIME_API_PCM16_SAMPLE_RATE,
+        config.utterance_ms,
+    );
+
+    let event_id = state.next_id("event");
+    send_openai(
+        &mut socket,
+        OAIServerEvent::SessionCreated(oai_realtime::SessionCreatedEvent {
+            event_id,
+            session: oai_realtime::SessionResource::default(),
+        }),
+    )
+    .await?;
+
+    let deadline = session_deadline(config);
+    while let Some(msg) = recv_before(&mut socket, deadline).await {
+        let text = match msg? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let event = match serde_json::from_str::<OAIClientEvent>(&text) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!(error = %e, "Fake OpenAI received an unparsable client event.");
+                continue;
+            }
+        };
+
+        match event {
+            OAIClientEvent::SessionUpdate(e) => {
+                // Only an explicit `null` turns detection off; omitting it keeps the mode.
+                if serde_json::from_str::<Value>(&text)
+                    .is_ok_and(|raw| raw.pointer("/session/turn_detection") == Some(&Value::Null))
+                {
+                    state.vad = false;
+                } else if e.session.turn_detection.is_some() {
+                    state.vad = true;
+                }
+                if let Some(oai_realtime::TurnDetection::ServerVAD {
+                    create_response, ..
+                }) = &e.session.turn_detection
+                {
+                    state.create_response = create_response.unwrap_or(true);
+                }
+                if let Some(tools) = &e.session.tools {
+                    state.tools = tools
+                        .iter()
+                        .map(|tool| match tool {
+                            oai_realtime::ToolDefinition::Function { name, .. } => name.clone(),
+                        })
+                        .collect();
+                }
+                let event_id = state.next_id("event");
+                send_openai(
+                    &mut socket,
+                    OAIServerEvent::SessionUpdated(oai_realtime::SessionUpdatedEvent {
+                        event_id,
+                        session: e.session,
+                    }),
+                )
+                .await?;
+            }
+            OAIClientEvent::InputAudioBufferAppend(e) if !state.vad => {
+                state.buffered_bytes += decoded_len(&e.audio);
+            }
+            OAIClientEvent::InputAudioBufferAppend(e) => {
+                if !state.speaking {
+                    state.speaking = true;
+                    let event_id = state.next_id("event");
+                    let item_id = state.next_id("item");
+                    send_openai(
+                        &mut socket,
+                        OAIServerEvent::InputAudioBufferSpeechStarted(
+                            oai_realtime::InputAudioBufferSpeechStartedEvent {
+                                event_id,
+                                audio_start_ms: 0,
+                                item_id,
+                            },
+                        ),
+                    )
+                    .await?;
+                }
+                state.buffered_bytes += decoded_len(&e.audio);
+                if state.buffered_bytes >= utterance_bytes {
+                    finish_openai_utterance(&mut socket, &mut state, config, true).await?;
+                }
+            }
+            OAIClientEvent::InputAudioBufferCommit(_) => {
+                if state.buffered_bytes > 0 {
+                    finish_openai_utterance(&mut socket, &mut state, config, false).await?;
+                }
+            }
+            OAIClientEvent::InputAudioBufferClear(_) => {
+                state.buffered_bytes = 0;
+                state.speaking = false;
+                let event_id = state.next_id("event");
+                send_openai(
+                    &mut socket,
+                    OAIServerEvent::InputAudioBufferCleared(
+                        oai_realtime::InputAudioBufferClearedEvent { event_id },
+                    ),
+                )
+                .await?;
+            }
+            OAIClientEvent::ConversationItemCreate(e) => {
+                let mut item = e.item;
+                let item_id = state.next_id("item");
+                item.id = Some(item_id.clone());
+                let event_id = state.next_id("event");
+                let previous_item_id = std::mem::replace(&mut state.previous_item_id, item_id);
+                send_openai(
+                    &mut socket,
+                    OAIServerEvent::ConversationItemCreated(
+                        oai_realtime::ConversationItemCreatedEvent {
+                            event_id,
+                            previous_item_id: Some(previous_item_id),
+                            item,
+                        },
+                    ),
+                )
+                .await?;
+            }
+            OAIClientEvent::ResponseCreate(_) => {
+                send_openai_response(&mut socket, &mut state, config).await?;
+            }
+            OAIClientEvent::ConversationItemTruncate(e) => {
+                let event_id = state.next_id("event");
+                send_openai(
+                    &mut socket,
+                    OAIServerEvent::ConversationItemTruncated(
+                        oai_realtime::ConversationItemTruncatedEvent {
+                            event_id,
+                            item_id: e.item_id,
+                            content_index: e.content_index,
+                            audio_end_ms: e.audio_end_ms,
+                        },
+                    ),
+                )
+                .await?;
+            }
+            other => debug!(event = ?other, "Fake OpenAI ignoring client event."),
+        }
+    }
+
+    info!("Fake OpenAI Realtime client disconnected.");
+    Ok(())
+}
+
+/// Ends the buffered utterance: stops speech (when VAD-driven), commits it, and
+/// reports its transcription, optionally followed by an automatic response.
+async fn finish_openai_utterance(
+    socket: &mut WebSocket,
+    state: &mut OpenAIState,
+    config: &FakeRealtimeConfig,
+    vad_driven: bool,
+) -> anyhow::Result<()> {
+    let audio_end_ms = (state.buffered_bytes / 2) as f64 * 1000.0
+        / audio_utils::OPENAI_REALTIME_API_PCM16_SAMPLE_RATE;
+    state.buffered_bytes = 0;
+    state.speaking = false;
+    let item_id = state.next_id("item");
+
+    if vad_driven {
+        let event_id = state.next_id("event");
+        send_openai(
+            socket,
+            OAIServerEvent::InputAudioBufferSpeechStopped(
+                oai_realtime::InputAudioBufferSpeechStoppedEvent {
+                    event_id,
+                    audio_end_ms: audio_end_ms as u32,
+                    item_id: item_id.clone(),
+                },
+            ),
+        )
+        .await?;
+    }
+
+    let event_id = state.next_id("event");
+    let previous_item_id = std::mem::replace(&mut state.previous_item_id, item_id.clone());
+    send_openai(
+        socket,
+        OAIServerEvent::InputAudioBufferCommited(oai_realtime::InputAudioBufferCommitedEvent {
+            event_id,
+            previous_item_id,
+            item_id: item_id.clone(),
+        }),
+    )
+    .await?;
+
+    for delta in transcript_deltas(&config.transcript) {
+        let event_id = state.next_id("event");
+        send_openai(
+            socket,
+            OAIServerEvent::ConversationItemInputAudioTranscriptionDelta(
+                oai_realtime::ConversationItemInputAudioTranscriptionDeltaEvent {
+                    event_id,
+                    item_id: item_id.clone(),
+                    content_index: 0,
+                    delta,
+                    logprobs: None,
+                },
+            ),
+        )
+        .await?;
+    }
+    let event_id = state.next_id("event");
+    send_openai(
+        socket,
+        OAIServerEvent::ConversationItemInputAudioTranscriptionCompleted(
+            oai_realtime::ConversationItemInputAudioTranscriptionCompletedEvent {
+                event_id,
+                item_id,
+                content_index: 0,
+                transcript: config.transcript.clone(),
+                logprobs: None,
+            },
+        ),
+    )
+    .await?;
+
+    if vad_driven && state.create_response {
+        match declared_tool_call(config, &state.tools) {
+            Some(call) => send_openai_function_call(socket, state, call).await?,
+            None => send_openai_response(socket, state, config).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Sends a response consisting of a single completed function call item.
+async fn send_openai_function_call(
+    socket: &mut WebSocket,
+    state: &mut OpenAIState,
+    call: &FakeToolCall,
+) -> anyhow::Result<()> {
+    let response_id = state.next_id("resp");
+    let item = oai_realtime::Item {
+        id: Some(state.next_id("item")),
+        r#type: Some(oai_realtime::ItemType::FunctionCall),
+        status: Some(oai_realtime::ItemStatus::Completed),
+        role: None,
+        content: None,
+        call_id: Some(state.next_id("call")),
+        name: Some(call.name.clone()),
+        arguments: Some(call.arguments.to_string()),
+        output: None,
+    };
+    let resource = |status, output| oai_realtime::ResponseResource {
+        id: response_id.clone(),
+        object: "realtime.response".to_string(),
+        status,
+        status_details: None,
+        output,
+        usage: None,
+    };
+
+    let event_id = state.next_id("event");
+    send_openai(
+        socket,
+        OAIServerEvent::ResponseCreated(oai_realtime::ResponseCreatedEvent {
+            event_id,
+            response: resource(oai_realtime::ResponseStatus::InProgress, vec![]),
+        }),
+    )
+    .await?;
+    let event_id = state.next_id("event");
+    send_openai(
+        socket,
+        OAIServerEvent::ResponseOutputItemDone(oai_realtime::ResponseOutputItemDoneEvent {
+            event_id,
+            response_id: response_id.clone(),
+            output_index: 0,
+            item: item.clone(),
+        }),
+    )
+    .await?;
+    let event_id = state.next_id("event");
+    send_openai(
+        socket,
+        OAIServerEvent::ResponseDone(oai_realtime::ResponseDoneEvent {
+            event_id,
+            response: resource(oai_realtime::ResponseStatus::Completed, vec![item]),
+        }),
+    )
+    .await
+}
+
+/// Streams a synthetic audio response, bracketed by `response.created` and `response.done`.
+async fn send_openai_response(
+    socket: &mut WebSocket,
+    state: &mut OpenAIState,
+    config: &FakeRealtimeConfig,
+) -> anyhow::Result<()> {
+    let response_id = state.next_id("resp");
+    let item_id = state.next_id("item");
+    let resource = |status| oai_realtime::ResponseResource {
+        id: response_id.clone(),
+        object: "realtime.response".to_string(),
+        status,
+        status_details: None,
+        output: vec![],
+        usage: None,
+    };
+
+    let event_id = state.next_id("event");
+    send_openai(
+        socket,
+        OAIServerEvent::ResponseCreated(oai_realtime::ResponseCreatedEvent {
+            event_id,
+            response: resource(oai_realtime::ResponseStatus::InProgress),
+        }),
+    )
+    .await?;
+
+    let mut offset = 0;
+    let mut remaining = config.response_ms;
+    while remaining > 0 {
+        let ms = remaining.min(config.chunk_ms);
+        remaining -= ms;
+        let samples = synth_tone(
+            audio_utils::OPENAI_REALTIME_API_PCM16_SAMPLE_RATE,
+            ms,
+            offset,
+        );
+        offset += samples.len();
+        let event_id = state.next_id("event");
+        send_openai(
+            socket,
+            OAIServerEvent::ResponseAudioDelta(oai_realtime::ResponseAudioDeltaEvent {
+                event_id,
+                response_id: response_id.clone(),
+                item_id: item_id.clone(),
+                output_index: 0,
+                content_index: 0,
+                delta: audio_utils::encode_i16(&samples),
+            }),
+        )
+        .await?;
+    }
+
+    let event_id = state.next_id("event");
+    send_openai(
+        socket,
+        OAIServerEvent::ResponseAudioDone(oai_realtime::ResponseAudioDoneEvent {
+            event_id,
+            response_id: response_id.clone(),
+            item_id,
+            output_index: 0,
+            content_index: 0,
+        }),
+    )
+    .await?;
+
+    let event_id = state.next_id("event");
+    send_openai(
+        socket,
+        OAIServerEvent::ResponseDone(oai_realtime::ResponseDoneEvent {
+            event_id,
+            response: resource(oai_realtime::ResponseStatus::Completed),
+        }),
+    )
+    .await
+}
+
+// --- Gemini Live ---
+
+async fn send_gemini(socket: &mut WebSocket, value: Value) -> anyhow::Result<()> {
+    socket.send(Message::Text(value.to_string().into())).await?;
+    Ok(())
+}
+
+async fn run_gemini(mut socket: WebSocket, config: &FakeRealtimeConfig) -> anyhow::Result<()> {
+    info!("Fake Gemini Live client connected.");
+    let utterance_bytes = bytes_for_ms(
+        audio_utils::GEMINI_LIVE_API_PCM16_SAMPLE_RATE,
+        config.utterance_ms,
+    );
+    let mut buffered_bytes = 0;
+    let mut tools = Vec::new();
+    let mut call_counter = 0;
+    let mut activity_detection = true;
+    // Whether the automatic reply's `turnComplete` is still to be sent.
+    let mut turn_complete_held = false;
+
+    let deadline = session_deadline(config);
+    while let Some(msg) = recv_before(&mut socket, deadline).await {
+        let text = match msg? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let value: Value = match serde_json::from_str(&text) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!(error = %e, "Fake Gemini received unparsable JSON.");
+                continue;
+            }
+        };
+
+        if let Some(setup) = value.get("setup") {
+            tools = setup
+                .get("tools")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|tool| tool.get("functionDeclarations").and_then(Value::as_array))
+                .flatten()
+                .filter_map(|declaration| declaration.get("name").and_then(Value::as_str))
+                .map(str::to_string)
+                .collect();
+            activity_detection = setup
+                .pointer("/realtimeInputConfig/automaticActivityDetection/disabled")
+                .and_then(Value::as_bool)
+                != Some(true);
+            send_gemini(&mut socket, json!({ "setupComplete": {} })).await?;
+        } else if let Some(input) = value.get("realtimeInput") {
+            let data = input
+                .pointer("/audio/data")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            buffered_bytes += decoded_len(data);
+            let activity_ended = input.get("activityEnd").is_some();
+            if (activity_detection && buffered_bytes >= utterance_bytes)
+                || (activity_ended && buffered_bytes > 0)
+            {
+                buffered_bytes = 0;
+                turn_complete_held =
+                    answer_gemini_utterance(&mut socket, config, &tools, &mut call_counter).await?;
+            }
+        } else if value.get("toolResponse").is_some() {
+            send_gemini_response(&mut socket, config).await?;
+        } else if let Some(content) = value.get("clientContent") {
+            if std::mem::take(&mut turn_complete_held) {
+                send_gemini_turn_complete(&mut socket).await?;
+            }
+            let turn_complete = content
+                .get("turnComplete")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            let has_turns = content
+                .get("turns")
+                .and_then(Value::as_array)
+                .is_some_and(|turns| !turns.is_empty());
+            if turn_complete && has_turns {
+                send_gemini_response(&mut socket, config).await?;
+            }
+        } else {
+            debug!(message = %text, "Fake Gemini ignoring client message.");
+        }
+    }
+
+    info!("Fake Gemini Live client disconnected.");
+    Ok(())
+}
+
+/// Reports the transcription of a finished utterance and answers it, either with the
+/// configured tool call or with a spoken turn. Returns whether the spoken turn's
+/// `turnComplete` was held back.
+async fn answer_gemini_utterance(
+    socket: &mut WebSocket,
+    config: &FakeRealtimeConfig,
+    tools: &[String],
+    call_counter: &mut u64,
+) -> anyhow::Result<bool> {
+    send_gemini(
+        socket,
+        json!({ "serverContent": { "inputTranscription": { "text": config.transcript } } }),
+    )
+    .await?;
+    match declared_tool_call(config, tools) {
+        Some(call) => {
+            *call_counter += 1;
+            send_gemini(
+                socket,
+                json!({
+                    "toolCall": {
+                        "functionCalls": [{
+                            "id": format!("call_{}", call_counter),
+                            "name": call.name,
+                            "args": call.arguments,
+                        }]
+                    }
+                }),
+            )
+            .await?;
+            Ok(false)
+        }
+        None if config.late_turn_complete => {
+            send_gemini_audio(socket, config).await?;
+            Ok(true)
+        }
+        None => {
+            send_gemini_response(socket, config).await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Streams a synthetic model turn as inline audio, followed by `turnComplete`.
+async fn send_gemini_response(
+    socket: &mut WebSocket,
+    config: &FakeRealtimeConfig,
+) -> anyhow::Result<()> {
+    send_gemini_audio(socket, config).await?;
+    send_gemini_turn_complete(socket).await
+}
+
+/// Streams a synthetic model turn as inline audio.
+async fn send_gemini_audio(
+    socket: &mut WebSocket,
+    config: &FakeRealtimeConfig,
+) -> anyhow::Result<()> {
+    let sample_rate = audio_utils::GEMINI_LIVE_API_PCM16_SAMPLE_RATE;
+    let mut offset = 0;
+    let mut remaining = config.response_ms;
+    while remaining > 0 {
+        let ms = remaining.min(config.chunk_ms);
+        remaining -= ms;
+        let samples = synth_tone(sample_rate, ms, offset);
+        offset += samples.len();
+        send_gemini(
+            socket,
+            json!({
+                "serverContent": {
+                    "modelTurn": {
+                        "parts": [{
+                            "inlineData": {
+                                "mimeType": format!("audio/pcm;rate={}", sample_rate),
+                                "data": audio_utils::encode_i16(&samples),
+                            }
+                        }]
+                    }
+                }
+            }),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn send_gemini_turn_complete(socket: &mut WebSocket) -> anyhow::Result<()> {
+    send_gemini(socket, json!({ "serverContent": { "turnComplete": true } })).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::{
+        MaybeTlsStream, WebSocketStream, connect_async, tungstenite::protocol::Message as WsMessage,
+    };
+
+    type Client = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+    async fn spawn_server(config: FakeRealtimeConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, config));
+        format!("ws://{}", addr)
+    }
+
+    async fn next_json(client: &mut Client) -> Value {
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                WsMessage::Text(text) => return serde_json::from_str(&text).unwrap(),
+                _ => continue,
+            }
+        }
+    }
+
+    async fn send_json(client: &mut Client, value: Value) {
+        client
+            .send(WsMessage::Text(value.to_string().into()))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transcript_deltas_concatenate() {
+        let transcript = "a stack is LIFO";
+        let deltas = transcript_deltas(transcript);
+        assert_eq!(deltas.len(), 4);
+        assert_eq!(deltas.concat(), transcript);
+    }
+
+    #[test]
+    fn test_synth_tone_length() {
+        assert_eq!(synth_tone(24000.0, 100, 0).len(), 2400);
+        assert_eq!(synth_tone(16000.0, 100, 1600).len(), 1600);
+        assert_eq!(bytes_for_ms(16000.0, 500), 16000);
+    }
+
+    #[tokio::test]
+    async fn test_openai_session_and_utterance() {
+        let base = spawn_server(FakeRealtimeConfig::default()).await;
+        let (mut client, _) = connect_async(format!("{}/v1/realtime?model=test", base))
+            .await
+            .unwrap();
+
+        assert_eq!(next_json(&mut client).await["type"], "session.created");
+
+        send_json(
+            &mut client,
+            json!({ "type": "session.update", "session": { "voice": "alloy" } }),
+        )
+        .await;
+        let updated = next_json(&mut client).await;
+        assert_eq!(updated["type"], "session.updated");
+        assert_eq!(updated["session"]["voice"], "alloy");
+
+        // 600ms of 24kHz audio exceeds the default 500ms utterance length.
+        let audio = audio_utils::encode_i16(&vec![0i16; 14_400]);
+        send_json(
+            &mut client,
+            json!({ "type": "input_audio_buffer.append", "audio": audio }),
+        )
+        .await;
+
+        let mut types = vec![];
+        let mut transcript = String::new();
+        loop {
+            let event = next_json(&mut client).await;
+            let ty = event["type"].as_str().unwrap().to_string();
+            if ty == "conversation.item.input_audio_transcription.completed" {
+                transcript = event["transcript"].as_str().unwrap().to_string();
+            }
+            types.push(ty.clone());
+            if ty == "response.done" {
+                break;
+            }
+        }
+
+        assert_eq!(transcript, FakeRealtimeConfig::default().transcript);
+        assert_eq!(types[0], "input_audio_buffer.speech_started");
+        assert!(types.contains(&"input_audio_buffer.committed".to_string()));
+        assert!(types.contains(&"conversation.item.input_audio_transcription.delta".to_string()));
+        assert_eq!(
+            types
+                .iter()
+                .filter(|t| *t == "response.audio.delta")
+                .count(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openai_respects_disabled_auto_response() {
+        let base = spawn_server(FakeRealtimeConfig::default()).await;
+        let (mut client, _) = connect_async(format!("{}/v1/realtime", base))
+            .await
+            .unwrap();
+        next_json(&mut client).await;
+
+        send_json(
+            &mut client,
+            json!({
+                "type": "session.update",
+                "session": { "turn_detection": {
+                    "type": "server_vad", "threshold": 0.5, "prefix_padding_ms": 200,
+                    "silence_duration_ms": 700, "create_response": false
+                } }
+            }),
+        )
+        .await;
+        next_json(&mut client).await;
+
+        let audio = audio_utils::encode_i16(&vec![0i16; 14_400]);
+        send_json(
+            &mut client,
+            json!({ "type": "input_audio_buffer.append", "audio": audio }),
+        )
+        .await;
+        loop {
+            let event = next_json(&mut client).await;
+            if event["type"] == "conversation.item.input_audio_transcription.completed" {
+                break;
+            }
+        }
+
+        // Without an automatic response, the next event is the one we ask for.
+        send_json(&mut client, json!({ "type": "response.create" })).await;
+        assert_eq!(next_json(&mut client).await["type"], "response.created");
+    }
+
+    #[tokio::test]
+    async fn test_gemini_setup_transcription_and_turn() {
+        let base = spawn_server(FakeRealtimeConfig::default()).await;
+        let (mut client, _) = connect_async(format!(
+            "{}/ws/google.ai.generativelanguage.v1beta.GenerativeService.BidiGenerateContent?key=test",
+            base
+        ))
+        .await
+        .unwrap();
+
+        send_json(&mut client, json!({ "setup": { "model": "models/test" } })).await;
+        assert!(next_json(&mut client).await.get("setupComplete").is_some());
+
+        // 500ms of 16kHz audio completes one utterance.
+        let audio = audio_utils::encode_i16(&vec![0i16; 8_000]);
+        send_json(
+            &mut client,
+            json!({ "realtimeInput": { "audio": { "mimeType": "audio/pcm;rate=16000", "data": audio } } }),
+        )
+        .await;
+        let transcription = next_json(&mut client).await;
+        assert_eq!(
+            transcription["serverContent"]["inputTranscription"]["text"],
+            FakeRealtimeConfig::default().transcript
+        );
+        // The utterance is answered without being asked.
+        assert_eq!(count_gemini_audio_parts(&mut client).await, 3);
+
+        send_json(
+            &mut client,
+            json!({ "clientContent": { "turns": [{ "role": "user", "parts": [{ "text": "Hi" }] }], "turnComplete": true } }),
+        )
+        .await;
+        assert_eq!(count_gemini_audio_parts(&mut client).await, 3);
+    }
+
+    /// Counts inline audio parts until the model turn completes.
+    async fn count_gemini_audio_parts(client: &mut Client) -> usize {
+        let mut audio_parts = 0;
+        loop {
+            let msg = next_json(client).await;
+            if msg["serverContent"]["turnComplete"] == true {
+                return audio_parts;
+            }
+            assert!(
+                msg.pointer("/serverContent/modelTurn/parts/0/inlineData/data")
+                    .is_some()
+            );
+            audio_parts += 1;
+        }
+    }
+}
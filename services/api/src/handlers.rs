@@ -4,25 +4,34 @@
 //! It uses `utoipa` doc comments to generate OpenAPI documentation.
 
 use axum::{
-    extract::{Path, State},
-    http::{HeaderMap, StatusCode},
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Json, Response},
 };
-use feynman_core::topic::SubTopic;
+use feynman_core::{tools::NAMESPACE_SEPARATOR, topic::SubTopic};
 use std::sync::Arc;
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
     models::{
-        CreateSessionPayload, ErrorResponse, MessageRole, Session, UpdateSessionStatusPayload,
+        AgentStateSnapshot, AudioFile, CreateSessionPayload, DailyUsage, DailyUsageQuery,
+        ErrorResponse, HistoryQuery, ListSessionsQuery, Message, MessageRole, Recording,
+        RecordingFormat, Session, SessionCursor, SessionSummary, UpdateSessionStatusPayload,
+        UsageKind, UsageSummary,
     },
+    quota::QuotaExceeded,
+    recordings::{RecordingStore, RecordingTooLarge},
     state::AppState,
+    usage::{self, UsageEntry},
 };
 
 pub enum ApiError {
     BadRequest(String),
     NotFound(String),
+    PayloadTooLarge(String),
+    TooManyRequests(QuotaExceeded),
     InternalServerError(anyhow::Error),
 }
 
@@ -35,6 +44,22 @@ impl IntoResponse for ApiError {
             ApiError::NotFound(message) => {
                 (StatusCode::NOT_FOUND, Json(ErrorResponse { message })).into_response()
             }
+            ApiError::PayloadTooLarge(message) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(ErrorResponse { message }),
+            )
+                .into_response(),
+            ApiError::TooManyRequests(exceeded) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    header::RETRY_AFTER,
+                    exceeded.retry_after.as_secs().max(1).to_string(),
+                )],
+                Json(ErrorResponse {
+                    message: exceeded.to_string(),
+                }),
+            )
+                .into_response(),
             ApiError::InternalServerError(err) => {
                 error!("Internal Server Error: {:?}", err);
                 let message = "An internal server error occurred.".to_string();
@@ -53,7 +78,14 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self::InternalServerError(err.into())
+        let err = match err.into().downcast::<QuotaExceeded>() {
+            Ok(exceeded) => return Self::TooManyRequests(exceeded),
+            Err(err) => err,
+        };
+        match err.downcast::<RecordingTooLarge>() {
+            Ok(too_large) => Self::PayloadTooLarge(too_large.to_string()),
+            Err(err) => Self::InternalServerError(err),
+        }
     }
 }
 
@@ -65,6 +97,7 @@ where
     responses(
         (status = 201, description = "Session created successfully", body = Session),
         (status = 400, description = "Bad request", body = ErrorResponse),
+        (status = 429, description = "Session or token quota exceeded; see the Retry-After header", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     params(
@@ -81,19 +114,56 @@ pub async fn create_session(
         .and_then(|v| v.to_str().ok())
         .ok_or_else(|| ApiError::BadRequest("x-user-id header is required".to_string()))?;
 
-    let subtopic_names = state
+    // Only configured servers can be allowed; their tools may change over time.
+    for allowed in &payload.allowed_tools {
+        let server = allowed
+            .split(NAMESPACE_SEPARATOR)
+            .next()
+            .unwrap_or_default();
+        if !state.config.mcp_servers.iter().any(|s| s.name == server) {
+            return Err(ApiError::BadRequest(format!(
+                "'{}' does not name a configured tool server",
+                allowed
+            )));
+        }
+    }
+
+    if payload.record_audio && state.recordings.is_none() {
+        return Err(ApiError::BadRequest(
+            "Recording is not enabled on this server".to_string(),
+        ));
+    }
+
+    state.quotas.check_new_session(&state.db, user_id).await?;
+
+    let curriculum = state
         .curriculum_service
         .generate_subtopics(&payload.topic)
         .await?;
 
-    let subtopics: Vec<SubTopic> = subtopic_names.into_iter().map(SubTopic::new).collect();
+    let subtopics: Vec<SubTopic> = curriculum
+        .subtopics
+        .into_iter()
+        .map(SubTopic::new)
+        .collect();
 
     let initial_state = feynman_core::agent::FeynmanAgent::new(payload.topic.clone(), subtopics);
 
     let session = state
         .db
-        .create_session(user_id, &payload.topic, &initial_state)
+        .create_session(
+            user_id,
+            &payload.topic,
+            &payload.allowed_tools,
+            payload.record_audio,
+            &initial_state,
+        )
         .await?;
+    // The curriculum was generated before the session existed to charge it to.
+    if let Some(used) = &curriculum.usage {
+        let entry = UsageEntry::tokens(UsageKind::Curriculum, used);
+        usage::record(&state, session.id, entry).await;
+    }
 
     let first_subtopic = initial_state
         .incomplete_subtopics
@@ -115,28 +185,67 @@ pub async fn create_session(
     Ok((StatusCode::CREATED, Json(session)))
 }
 
-/// List all sessions for a user.
+/// List a user's sessions, a page at a time.
+///
+/// When more sessions match, the `x-next-cursor` header holds the cursor
+/// for the next page. Paging by `created_at` is stable; paging by
+/// `updated_at` is not, as sessions active meanwhile move to the front.
 #[utoipa::path(
     get,
     path = "/sessions",
     responses(
-        (status = 200, description = "List of sessions", body = [Session]),
+        (status = 200, description = "A page of sessions, newest first", body = [SessionSummary],
+            headers(("x-next-cursor" = String, description = "Pass as `cursor` to get the next page; absent on the last page"))),
+        (status = 400, description = "Bad request", body = ErrorResponse),
         (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     params(
+        ListSessionsQuery,
         ("x-user-id" = String, Header, description = "The ID of the user")
     )
 )]
 pub async fn list_sessions(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-) -> Result<Json<Vec<Session>>, ApiError> {
+    Query(query): Query<ListSessionsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
     let user_id = headers
         .get("x-user-id")
         .and_then(|v| v.to_str().ok())
         .ok_or_else(|| ApiError::BadRequest("x-user-id header is required".to_string()))?;
-    let sessions = state.db.list_sessions(user_id).await?;
-    Ok(Json(sessions))
+    let limit = query.limit.unwrap_or(50);
+    if !(1..=100).contains(&limit) {
+        return Err(ApiError::BadRequest(
+            "limit must be between 1 and 100".to_string(),
+        ));
+    }
+    let sort = query.sort.unwrap_or_default();
+    let after = match &query.cursor {
+        Some(cursor) => match SessionCursor::decode(cursor) {
+            Some(cursor) if cursor.sort == sort => Some(cursor),
+            _ => {
+                return Err(ApiError::BadRequest(
+                    "cursor is invalid or from a listing with a different sort".to_string(),
+                ));
+            }
+        },
+        None => None,
+    };
+
+    // One extra session tells whether there is another page.
+    let mut sessions = state
+        .db
+        .list_sessions(user_id, &query, after, i64::from(limit) + 1)
+        .await?;
+    let mut response_headers = HeaderMap::new();
+    if sessions.len() > limit as usize {
+        sessions.truncate(limit as usize);
+        if let Some(last) = sessions.last() {
+            let cursor = SessionCursor::after(sort, &last.session).encode();
+            response_headers.insert("x-next-cursor", cursor.parse()?);
+        }
+    }
+    Ok((response_headers, Json(sessions)))
 }
 
 /// Get a specific session by its ID.
@@ -210,3 +319,350 @@ pub async fn update_session_status(
 
     Ok((StatusCode::OK, Json(updated_session)))
 }
+
+/// List a session's messages, a page at a time, oldest first.
+///
+/// When more messages follow, the `x-next-cursor` header holds the `after`
+/// value for the next page.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/messages",
+    responses(
+        (status = 200, description = "A page of the session's messages", body = [Message],
+            headers(("x-next-cursor" = String, description = "Pass as `after` to get the next page; absent on the last page"))),
+        (status = 400, description = "Bad request", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Session ID"),
+        HistoryQuery,
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn list_messages(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    owned_session(&state, &headers, id).await?;
+    let limit = history_limit(&query)?;
+    let messages = state
+        .db
+        .list_messages(id, query.after, limit as i64 + 1)
+        .await?;
+    Ok(history_page(messages, limit, |message| message.id))
+}
+
+/// List the saved versions of a session's agent state, a page at a time,
+/// oldest first, to follow its progress over time.
+///
+/// Each state comes with the user message whose turn saved it. When more
+/// states follow, the `x-next-cursor` header holds the `after` value for the
+/// next page.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/states",
+    responses(
+        (status = 200, description = "A page of the session's agent states", body = [AgentStateSnapshot],
+            headers(("x-next-cursor" = String, description = "Pass as `after` to get the next page; absent on the last page"))),
+        (status = 400, description = "Bad request", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Session ID"),
+        HistoryQuery,
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn list_agent_states(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    owned_session(&state, &headers, id).await?;
+    let limit = history_limit(&query)?;
+    let states = state
+        .db
+        .list_agent_states(id, query.after, limit as i64 + 1)
+        .await?;
+    Ok(history_page(states, limit, |snapshot| snapshot.id))
+}
+
+/// Validates the page size of a history listing.
+fn history_limit(query: &HistoryQuery) -> Result<usize, ApiError> {
+    let limit = query.limit.unwrap_or(50);
+    if !(1..=200).contains(&limit) {
+        return Err(ApiError::BadRequest(
+            "limit must be between 1 and 200".to_string(),
+        ));
+    }
+    Ok(limit as usize)
+}
+
+/// Responds with up to `limit` entries, fetched with one extra to tell whether
+/// another page follows, and the cursor for that page.
+fn history_page<T: serde::Serialize>(
+    mut entries: Vec<T>,
+    limit: usize,
+    id: impl Fn(&T) -> i64,
+) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    if entries.len() > limit {
+        entries.truncate(limit);
+        if let Some(last) = entries.last() {
+            headers.insert("x-next-cursor", id(last).into());
+        }
+    }
+    (headers, Json(entries))
+}
+
+/// Looks up a session owned by the user, failing with `NotFound` otherwise.
+async fn owned_session(state: &AppState, headers: &HeaderMap, id: Uuid) -> Result<(), ApiError> {
+    let user_id = headers
+        .get("x-user-id")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| ApiError::BadRequest("x-user-id header is required".to_string()))?;
+    state
+        .db
+        .get_session(id, user_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Session with id '{}' not found", id)))?;
+    Ok(())
+}
+
+/// Returns the recording store, failing with `NotFound` when recording is disabled.
+fn recording_store(state: &AppState) -> Result<&RecordingStore, ApiError> {
+    state
+        .recordings
+        .as_deref()
+        .ok_or_else(|| ApiError::NotFound("Recordings are not enabled".to_string()))
+}
+
+/// Builds a download response for an audio file.
+fn audio_response(format: RecordingFormat, filename: String, body: Body) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// List the audio recordings of a session.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/recordings",
+    responses(
+        (status = 200, description = "The session's recordings in chronological order", body = [Recording]),
+        (status = 404, description = "Session not found"),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Session ID"),
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn list_recordings(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<Recording>>, ApiError> {
+    owned_session(&state, &headers, id).await?;
+    let recordings = state.db.list_recordings(id).await?;
+    Ok(Json(recordings))
+}
+
+/// Download a session's whole recording: its clips joined in order.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/recording",
+    responses(
+        (status = 200, description = "The recording as WAV or Ogg Opus, in the configured format", content(
+            (AudioFile = "audio/wav"),
+            (AudioFile = "audio/ogg")
+        )),
+        (status = 404, description = "Session or recording not found", body = ErrorResponse),
+        (status = 413, description = "The recording is too long to join into one WAV file", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Session ID"),
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn get_session_recording(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Response, ApiError> {
+    owned_session(&state, &headers, id).await?;
+    let store = recording_store(&state)?;
+    let format = store.format();
+
+    // Clips stored in another format before a configuration change are left out.
+    let keys: Vec<String> = state
+        .db
+        .list_recordings(id)
+        .await?
+        .into_iter()
+        .filter(|recording| recording.format == format)
+        .map(|recording| recording.object_key)
+        .collect();
+    if keys.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "Session with id '{}' has no recording",
+            id
+        )));
+    }
+    // The clips are read one at a time while the response is sent.
+    let joined = store.join(keys).await?;
+    let mut response = audio_response(
+        format,
+        format!("session-{}.{}", id, format.extension()),
+        Body::from_stream(joined.body),
+    );
+    response
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, joined.len.into());
+    Ok(response)
+}
+
+/// Download a single recorded utterance.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/recordings/{recording_id}",
+    responses(
+        (status = 200, description = "The utterance as WAV or Ogg Opus", content(
+            (AudioFile = "audio/wav"),
+            (AudioFile = "audio/ogg")
+        )),
+        (status = 404, description = "Session or recording not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Session ID"),
+        ("recording_id" = i64, Path, description = "Recording ID"),
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn get_recording(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((id, recording_id)): Path<(Uuid, i64)>,
+) -> Result<Response, ApiError> {
+    owned_session(&state, &headers, id).await?;
+    let store = recording_store(&state)?;
+    let recording = state
+        .db
+        .get_recording(id, recording_id)
+        .await?
+        .ok_or_else(|| {
+            ApiError::NotFound(format!("Recording with id '{}' not found", recording_id))
+        })?;
+    let data = store.get(&recording.object_key).await?;
+    Ok(audio_response(
+        recording.format,
+        format!(
+            "recording-{}.{}",
+            recording.id,
+            recording.format.extension()
+        ),
+        Body::from(data),
+    ))
+}
+
+/// Report the tokens, audio and cost a session has used.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/usage",
+    responses(
+        (status = 200, description = "The session's usage, in total and by model", body = UsageSummary),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Session ID"),
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn get_session_usage(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+) -> Result<Json<UsageSummary>, ApiError> {
+    owned_session(&state, &headers, id).await?;
+    let by_model = state.db.session_usage(id).await?;
+    Ok(Json(UsageSummary::from_models(by_model)))
+}
+
+/// Report the tokens, audio and cost a user has used across all sessions.
+#[utoipa::path(
+    get,
+    path = "/usage",
+    responses(
+        (status = 200, description = "The user's usage, in total and by model", body = UsageSummary),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn get_user_usage(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<UsageSummary>, ApiError> {
+    let user_id = headers
+        .get("x-user-id")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| ApiError::BadRequest("x-user-id header is required".to_string()))?;
+    let by_model = state.db.user_usage(user_id).await?;
+    Ok(Json(UsageSummary::from_models(by_model)))
+}
+
+/// Report a user's usage for each recent day.
+#[utoipa::path(
+    get,
+    path = "/usage/daily",
+    responses(
+        (status = 200, description = "The user's usage per UTC day, oldest first; days without usage are left out", body = [DailyUsage]),
+        (status = 400, description = "Bad request", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    params(
+        DailyUsageQuery,
+        ("x-user-id" = String, Header, description = "The ID of the user")
+    )
+)]
+pub async fn get_daily_usage(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<DailyUsageQuery>,
+) -> Result<Json<Vec<DailyUsage>>, ApiError> {
+    let user_id = headers
+        .get("x-user-id")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| ApiError::BadRequest("x-user-id header is required".to_string()))?;
+    let days = query.days.unwrap_or(30);
+    if !(1..=366).contains(&days) {
+        return Err(ApiError::BadRequest(
+            "days must be between 1 and 366".to_string(),
+        ));
+    }
+    let today = chrono::Utc::now().date_naive();
+    let since = (today - chrono::Days::new(days as u64 - 1))
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time")
+        .and_utc();
+    let usage = state.db.daily_usage(user_id, since).await?;
+    Ok(Json(usage))
+}
@@ -7,8 +7,16 @@
 pub mod audio_utils;
 pub mod config;
 pub mod db;
+pub mod external_tools;
+pub mod fake_realtime;
 pub mod handlers;
+pub mod llm;
+pub mod mcp;
 pub mod models;
+pub mod prompts;
+pub mod quota;
+pub mod recordings;
 pub mod router;
 pub mod state;
+pub mod usage;
 pub mod ws;
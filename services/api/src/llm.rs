@@ -0,0 +1,79 @@
+//! LLM Clients for Each Role
+//!
+//! Builds the `LLMClient` a role's settings describe: its provider followed by
+//! any fallbacks, wrapped in a `ResilientClient`. Settings are validated when
+//! the configuration loads, so a client can always be built.
+
+use crate::config::{ChatProvider, LlmSettings};
+use async_openai::config::OpenAIConfig;
+use feynman_core::llm_client::{
+    AnthropicClient, CircuitBreaker, LLMClient, OllamaClient, OpenAICompatibleClient,
+    ResilienceSettings, ResilientClient, Route,
+};
+use std::{collections::HashMap, sync::Arc};
+
+/// Builds LLM clients whose routes share one circuit breaker per provider,
+/// so a provider that fails for one role is skipped by the other too.
+pub struct LlmClients {
+    resilience: ResilienceSettings,
+    breakers: HashMap<ChatProvider, Arc<CircuitBreaker>>,
+}
+
+impl LlmClients {
+    pub fn new(resilience: ResilienceSettings) -> Self {
+        let breakers = [
+            ChatProvider::OpenAI,
+            ChatProvider::Gemini,
+            ChatProvider::Anthropic,
+            ChatProvider::Ollama,
+        ]
+        .into_iter()
+        .map(|provider| {
+            let breaker =
+                CircuitBreaker::new(resilience.breaker_threshold, resilience.breaker_cooldown);
+            (provider, Arc::new(breaker))
+        })
+        .collect();
+        Self {
+            resilience,
+            breakers,
+        }
+    }
+
+    /// Creates a client for `settings`, falling back through its fallbacks in order.
+    pub fn client_for(&self, settings: &LlmSettings) -> Arc<dyn LLMClient> {
+        let routes = std::iter::once(settings)
+            .chain(&settings.fallbacks)
+            .map(|settings| Route {
+                provider: format!("{:?}", settings.provider),
+                model: settings.model.clone(),
+                client: provider_client(settings),
+                breaker: self.breakers[&settings.provider].clone(),
+            })
+            .collect();
+        Arc::new(ResilientClient::new(routes, self.resilience))
+    }
+}
+
+/// Creates a client for the provider, model and endpoint in `settings`.
+fn provider_client(settings: &LlmSettings) -> Arc<dyn LLMClient> {
+    let api_key = settings.api_key.clone().unwrap_or_default();
+    match settings.provider {
+        // Gemini is reached through its OpenAI-compatible endpoint.
+        ChatProvider::OpenAI | ChatProvider::Gemini => Arc::new(OpenAICompatibleClient::new(
+            OpenAIConfig::new()
+                .with_api_key(api_key)
+                .with_api_base(&settings.base_url),
+            settings.model.clone(),
+        )),
+        ChatProvider::Anthropic => Arc::new(AnthropicClient::new(
+            settings.base_url.clone(),
+            api_key,
+            settings.model.clone(),
+        )),
+        ChatProvider::Ollama => Arc::new(OllamaClient::new(
+            settings.base_url.clone(),
+            settings.model.clone(),
+        )),
+    }
+}
@@ -0,0 +1,216 @@
+//! Standalone MCP Server for Stored Sessions
+//!
+//! Serves the agent tools of one stored session over MCP, so external
+//! MCP-capable assistants can run a Feynman session against a curriculum the
+//! API generated. Each tool call starts from the latest state in the database
+//! and saves its change right away, so it doesn't undo changes a WebSocket
+//! session or another MCP connection made meanwhile. The session's curriculum
+//! and transcript are offered as resources, and the prompt templates as
+//! prompts.
+//!
+//! Subscribed clients are notified whenever the session changes in the
+//! database, whether through these tools, another MCP connection or a
+//! WebSocket session in the API.
+
+use crate::{
+    db::{Db, SessionChange},
+    models::MessageRole,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use axum::Router;
+use feynman_core::{
+    agent::{AgentStateStore, FeynmanAgent, FeynmanService},
+    prompts::{self, PromptTemplate},
+    resources::{ResourceSubscribers, SessionResources, TranscriptSource},
+};
+use rmcp::transport::streamable_http_server::{
+    StreamableHttpServerConfig, StreamableHttpService, session::local::LocalSessionManager,
+};
+use sqlx::postgres::PgListener;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// The path the streamable HTTP transport is served under.
+pub const MCP_HTTP_PATH: &str = "/mcp";
+
+/// The tools of one stored session, shared by every MCP connection.
+#[derive(Clone)]
+pub struct SessionTools {
+    agent_state: Arc<Mutex<FeynmanAgent>>,
+    store: Arc<DbStateStore>,
+    resources: SessionResources,
+    prompts: Vec<PromptTemplate>,
+    subscribers: ResourceSubscribers,
+    /// Stops watching the session once the last clone is dropped.
+    _watcher: Arc<AbortOnDrop>,
+}
+
+/// Aborts a task when dropped.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl SessionTools {
+    /// Loads the session's latest agent state and starts watching the session
+    /// for changes, until these tools are dropped.
+    pub async fn load(
+        db: Arc<Db>,
+        session_id: Uuid,
+        prompts: &HashMap<String, String>,
+    ) -> Result<Self> {
+        let agent = db
+            .get_latest_agent_state(session_id)
+            .await?
+            .with_context(|| format!("Session {} not found", session_id))?;
+        info!(%session_id, topic = %agent.main_topic, "Loaded session for MCP.");
+
+        let transcript = Arc::new(DbTranscript {
+            db: db.clone(),
+            session_id,
+        });
+        let resources = SessionResources::new(session_id.to_string(), Some(transcript));
+        let agent_state = Arc::new(Mutex::new(agent.clone()));
+
+        let subscribers = ResourceSubscribers::default();
+        let changes = db.listen_session_changes().await?;
+        let watcher = tokio::spawn(watch_session(
+            db.clone(),
+            session_id,
+            agent_state.clone(),
+            agent,
+            changes,
+            resources.clone(),
+            subscribers.clone(),
+        ));
+
+        Ok(Self {
+            agent_state,
+            store: Arc::new(DbStateStore { db, session_id }),
+            resources,
+            prompts: prompts::templates(prompts),
+            subscribers,
+            _watcher: Arc::new(AbortOnDrop(watcher)),
+        })
+    }
+
+    /// A handler for one MCP connection, acting on the stored agent state.
+    pub fn service(&self) -> FeynmanService {
+        FeynmanService::new(self.agent_state.clone(), None)
+            .with_store(self.store.clone())
+            .with_resources(self.resources.clone())
+            .with_prompts(self.prompts.clone())
+            .with_subscribers(self.subscribers.clone())
+    }
+
+    /// A router serving the tools over the streamable HTTP transport at `MCP_HTTP_PATH`.
+    pub fn http_router(&self) -> Router {
+        let tools = self.clone();
+        let service = StreamableHttpService::new(
+            move || Ok(tools.service()),
+            Arc::new(LocalSessionManager::default()),
+            StreamableHttpServerConfig::default(),
+        );
+        Router::new().nest_service(MCP_HTTP_PATH, service)
+    }
+}
+
+/// Tells subscribed clients which of the session's resources changed, as the
+/// database announces its new messages and agent states, and keeps the state
+/// resources are read from up to date. `seen` is the state subscribers were
+/// last told about.
+async fn watch_session(
+    db: Arc<Db>,
+    session_id: Uuid,
+    agent_state: Arc<Mutex<FeynmanAgent>>,
+    mut seen: FeynmanAgent,
+    mut changes: PgListener,
+    resources: SessionResources,
+    subscribers: ResourceSubscribers,
+) {
+    loop {
+        let notification = match changes.recv().await {
+            Ok(notification) => notification,
+            Err(e) => {
+                warn!(error = ?e, "Lost the session change feed; retrying.");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let change = match SessionChange::parse(notification.payload()) {
+            Some((id, change)) if id == session_id => change,
+            _ => continue,
+        };
+        match change {
+            SessionChange::Messages => subscribers.notify(&[resources.transcript_uri()]).await,
+            SessionChange::AgentState => {
+                // Load under the lock, so a tool saving meanwhile isn't undone.
+                let mut agent = agent_state.lock().await;
+                match db.get_latest_agent_state(session_id).await {
+                    Ok(Some(latest)) => {
+                        *agent = latest.clone();
+                        drop(agent);
+                        let uris = resources.changed_uris(&seen, &latest);
+                        seen = latest;
+                        subscribers.notify(&uris).await;
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!(error = ?e, "Failed to load the changed agent state."),
+                }
+            }
+        }
+    }
+}
+
+/// Keeps a session's agent state in the database, where WebSocket sessions
+/// and other MCP connections may change it too.
+struct DbStateStore {
+    db: Arc<Db>,
+    session_id: Uuid,
+}
+
+#[async_trait]
+impl AgentStateStore for DbStateStore {
+    async fn load(&self) -> Result<FeynmanAgent> {
+        self.db
+            .get_latest_agent_state(self.session_id)
+            .await?
+            .with_context(|| format!("Session {} not found", self.session_id))
+    }
+
+    async fn save(&self, state: &FeynmanAgent) -> Result<()> {
+        self.db
+            .update_agent_state(self.session_id, state, None)
+            .await
+    }
+}
+
+/// Reads a session's transcript from the database.
+struct DbTranscript {
+    db: Arc<Db>,
+    session_id: Uuid,
+}
+
+#[async_trait]
+impl TranscriptSource for DbTranscript {
+    async fn transcript(&self) -> Result<String> {
+        let messages = self.db.get_session_messages(self.session_id).await?;
+        Ok(messages
+            .iter()
+            .map(|message| {
+                let speaker = match message.role {
+                    MessageRole::User => "User",
+                    MessageRole::Ai => "AI",
+                };
+                format!("**{}:** {}", speaker, message.content)
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"))
+    }
+}
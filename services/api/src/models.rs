@@ -3,17 +3,21 @@
 //! This module defines the core data structures used for both database mapping
 //! with `sqlx` and for generating OpenAPI documentation with `utoipa`.
 
-use chrono::{DateTime, Utc};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, NaiveDate, Utc};
+use feynman_core::agent::FeynmanAgent;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::fmt;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 #[derive(sqlx::Type, Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq)]
 #[sqlx(type_name = "session_status", rename_all = "lowercase")]
 pub enum SessionStatus {
+    #[serde(alias = "active")]
     Active,
+    #[serde(alias = "ended")]
     Ended,
 }
 
@@ -34,6 +38,50 @@ impl fmt::Display for MessageRole {
     }
 }
 
+/// The file format of a stored audio recording.
+#[derive(sqlx::Type, Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "recording_format", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    /// 16-bit PCM in a RIFF/WAVE container.
+    Wav,
+    /// Opus in an Ogg container.
+    Opus,
+}
+
+impl RecordingFormat {
+    /// The MIME type of files in this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            RecordingFormat::Wav => "audio/wav",
+            RecordingFormat::Opus => "audio/ogg",
+        }
+    }
+
+    /// The file extension of files in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingFormat::Wav => "wav",
+            RecordingFormat::Opus => "opus",
+        }
+    }
+}
+
+impl std::str::FromStr for RecordingFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "wav" => Ok(Self::Wav),
+            "opus" => Ok(Self::Opus),
+            _ => Err(format!(
+                "'{}' is not a recording format (expected 'wav' or 'opus')",
+                value
+            )),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, ToSchema, FromRow, Debug, Clone)]
 pub struct Session {
     #[schema(value_type = String, format = Uuid)]
@@ -42,10 +90,127 @@ pub struct Session {
     pub topic: String,
     #[schema(value_type = String, example = "active")]
     pub status: SessionStatus,
+    /// The external tools the session may use, by server name or as `<server>__<tool>`.
+    #[schema(example = json!(["docs"]))]
+    pub allowed_tools: Vec<String>,
+    /// Whether the session's voice audio is recorded.
+    pub record_audio: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// A session as listed, with a summary of its progress.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct SessionSummary {
+    #[serde(flatten)]
+    pub session: Session,
+    /// The subtopics mastered so far.
+    pub covered_subtopics: i64,
+    /// All subtopics in the session's curriculum.
+    pub total_subtopics: i64,
+    /// The latest message in the conversation, if there is one.
+    pub last_message: Option<MessagePreview>,
+}
+
+/// The start of a message, for showing in a list.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct MessagePreview {
+    #[schema(value_type = String, example = "ai")]
+    pub role: MessageRole,
+    /// Up to the first `MESSAGE_PREVIEW_CHARS` characters of the message.
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// How many characters of the latest message a session summary includes.
+pub const MESSAGE_PREVIEW_CHARS: i32 = 200;
+
+/// The timestamp sessions are listed by, newest first.
+#[derive(Deserialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionSort {
+    /// When the session was created. Pages are stable: a session stays on
+    /// the page it was first listed on.
+    #[default]
+    CreatedAt,
+    /// When the session last changed, including when a message was added.
+    ///
+    /// A session that changes while it is being paged through moves to the
+    /// front of the listing, so it may be skipped on later pages or listed
+    /// twice. Sessions sharing a timestamp are ordered by ID, so a listing
+    /// never repeats or skips sessions that don't change.
+    UpdatedAt,
+}
+
+/// Which sessions to list, and where to continue from.
+#[derive(Deserialize, IntoParams, Debug, Default)]
+pub struct ListSessionsQuery {
+    /// Only sessions with this status.
+    #[param(value_type = Option<String>, example = "Active")]
+    pub status: Option<SessionStatus>,
+    /// Only sessions whose topic contains this text, ignoring case.
+    pub topic: Option<String>,
+    /// Only sessions created at or after this time.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only sessions created before this time.
+    pub created_before: Option<DateTime<Utc>>,
+    /// What to order sessions by, newest first. Defaults to `created_at`.
+    #[param(inline)]
+    pub sort: Option<SessionSort>,
+    /// How many sessions to return. Defaults to 50, at most 100.
+    pub limit: Option<u32>,
+    /// Continues a listing from the previous page's `x-next-cursor` header.
+    pub cursor: Option<String>,
+}
+
+/// Where a page of listed sessions ends: the sort timestamp and ID of its last
+/// session. Encoded as an opaque string for clients. The next page continues
+/// after this position, wherever the session itself has moved since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionCursor {
+    pub sort: SessionSort,
+    pub at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl SessionCursor {
+    /// The cursor continuing after `session` in a listing ordered by `sort`.
+    pub fn after(sort: SessionSort, session: &Session) -> Self {
+        let at = match sort {
+            SessionSort::CreatedAt => session.created_at,
+            SessionSort::UpdatedAt => session.updated_at,
+        };
+        Self {
+            sort,
+            at,
+            id: session.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let sort = match self.sort {
+            SessionSort::CreatedAt => 'c',
+            SessionSort::UpdatedAt => 'u',
+        };
+        let raw = format!("{}:{}:{}", sort, self.at.timestamp_micros(), self.id);
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    /// Parses an encoded cursor, or `None` if it is malformed.
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let mut parts = raw.splitn(3, ':');
+        let sort = match parts.next()? {
+            "c" => SessionSort::CreatedAt,
+            "u" => SessionSort::UpdatedAt,
+            _ => return None,
+        };
+        let at = DateTime::from_timestamp_micros(parts.next()?.parse().ok()?)?;
+        let id = parts.next()?.parse().ok()?;
+        Some(Self { sort, at, id })
+    }
+}
+
 #[derive(Serialize, Deserialize, ToSchema, FromRow, Debug, Clone)]
 pub struct Message {
     pub id: i64,
@@ -57,10 +222,137 @@ pub struct Message {
     pub created_at: DateTime<Utc>,
 }
 
+/// A saved version of a session's agent state.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct AgentStateSnapshot {
+    pub id: i64,
+    #[schema(value_type = Object)]
+    pub state: FeynmanAgent,
+    /// The user message whose turn saved this state. `None` for the state the
+    /// session started with and for changes made outside a turn, e.g. over MCP.
+    pub message: Option<Message>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Which page of a session's messages or agent states to return, oldest first.
+#[derive(Deserialize, IntoParams, Debug, Default)]
+pub struct HistoryQuery {
+    /// Only entries with a greater ID, to continue from the previous page's
+    /// `x-next-cursor` header.
+    pub after: Option<i64>,
+    /// How many entries to return. Defaults to 50, at most 200.
+    pub limit: Option<u32>,
+}
+
+/// A stored clip of one side of a voice conversation.
+#[derive(Serialize, Deserialize, ToSchema, FromRow, Debug, Clone)]
+pub struct Recording {
+    pub id: i64,
+    #[schema(value_type = String, format = Uuid)]
+    pub session_id: Uuid,
+    /// The message the clip was spoken as, if it was stored as one.
+    pub message_id: Option<i64>,
+    #[schema(value_type = String, example = "user")]
+    pub role: MessageRole,
+    #[schema(value_type = String, example = "wav")]
+    pub format: RecordingFormat,
+    #[serde(skip)]
+    pub object_key: String,
+    pub duration_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What consumed tokens or audio.
+#[derive(sqlx::Type, Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "usage_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UsageKind {
+    /// The ReAct cycle's LLM calls.
+    Chat,
+    /// Generating a session's subtopics.
+    Curriculum,
+    /// Voice audio sent to and received from the realtime provider.
+    Realtime,
+}
+
+/// Usage by one model for one kind of work.
+#[derive(Serialize, Deserialize, ToSchema, FromRow, Debug, Clone, PartialEq)]
+pub struct ModelUsage {
+    #[schema(value_type = String, example = "chat")]
+    pub kind: UsageKind,
+    #[schema(example = "gpt-4o")]
+    pub model: String,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub audio_input_seconds: f64,
+    pub audio_output_seconds: f64,
+    /// In US dollars, at the prices configured when the usage was recorded.
+    pub cost_usd: f64,
+}
+
+/// Total usage of a session or a user, with a breakdown by model.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq, Default)]
+pub struct UsageSummary {
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub audio_input_seconds: f64,
+    pub audio_output_seconds: f64,
+    pub cost_usd: f64,
+    pub by_model: Vec<ModelUsage>,
+}
+
+impl UsageSummary {
+    /// Totals the usage of each model.
+    pub fn from_models(by_model: Vec<ModelUsage>) -> Self {
+        let mut summary = Self::default();
+        for usage in &by_model {
+            summary.prompt_tokens += usage.prompt_tokens;
+            summary.completion_tokens += usage.completion_tokens;
+            summary.audio_input_seconds += usage.audio_input_seconds;
+            summary.audio_output_seconds += usage.audio_output_seconds;
+            summary.cost_usd += usage.cost_usd;
+        }
+        summary.by_model = by_model;
+        summary
+    }
+}
+
+/// A user's usage on one day, in UTC.
+#[derive(Serialize, Deserialize, ToSchema, FromRow, Debug, Clone, PartialEq)]
+pub struct DailyUsage {
+    pub day: NaiveDate,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub audio_input_seconds: f64,
+    pub audio_output_seconds: f64,
+    pub cost_usd: f64,
+}
+
+/// Which days to report usage for.
+#[derive(Deserialize, IntoParams, Debug)]
+pub struct DailyUsageQuery {
+    /// How many days to report, ending today (UTC). Defaults to 30, at most 366.
+    pub days: Option<u32>,
+}
+
+/// A downloadable audio file, documented as binary content.
+#[derive(ToSchema)]
+#[schema(value_type = String, format = Binary)]
+pub struct AudioFile(pub Vec<u8>);
+
 #[derive(Deserialize, ToSchema)]
 pub struct CreateSessionPayload {
     #[schema(example = "Quantum Mechanics")]
     pub topic: String,
+    /// The external tools to allow, by server name or as `<server>__<tool>`.
+    /// None are allowed by default.
+    #[serde(default)]
+    #[schema(example = json!(["docs", "calculator__add"]))]
+    pub allowed_tools: Vec<String>,
+    /// Whether to record the session's voice audio. Requires recording to be
+    /// enabled on the server; off by default.
+    #[serde(default)]
+    pub record_audio: bool,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -101,6 +393,10 @@ mod tests {
 
         assert_eq!(active, SessionStatus::Active);
         assert_eq!(ended, SessionStatus::Ended);
+
+        // Lowercase names are accepted too, as in query strings.
+        let ended: SessionStatus = serde_json::from_str("\"ended\"").unwrap();
+        assert_eq!(ended, SessionStatus::Ended);
     }
 
     #[test]
@@ -147,6 +443,8 @@ mod tests {
             user_id: "test_user_123".to_string(),
             topic: "Quantum Physics".to_string(),
             status: SessionStatus::Active,
+            allowed_tools: vec![],
+            record_audio: false,
             created_at: now,
             updated_at: now,
         };
@@ -196,6 +494,14 @@ mod tests {
         let payload: CreateSessionPayload = serde_json::from_str(json).unwrap();
 
         assert_eq!(payload.topic, "Machine Learning Basics");
+        assert!(payload.allowed_tools.is_empty());
+        assert!(!payload.record_audio);
+
+        let json =
+            r#"{"topic": "Calculus", "allowed_tools": ["calculator"], "record_audio": true}"#;
+        let payload: CreateSessionPayload = serde_json::from_str(json).unwrap();
+        assert_eq!(payload.allowed_tools, ["calculator"]);
+        assert!(payload.record_audio);
     }
 
     #[test]
@@ -241,6 +547,8 @@ mod tests {
             user_id: "test_user".to_string(),
             topic: "Test Topic".to_string(),
             status: SessionStatus::Active,
+            allowed_tools: vec![],
+            record_audio: false,
             created_at: now,
             updated_at: now,
         };
@@ -276,6 +584,8 @@ mod tests {
             user_id: "debug_test".to_string(),
             topic: "Debug Test".to_string(),
             status: SessionStatus::Active,
+            allowed_tools: vec![],
+            record_audio: false,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         };
@@ -299,6 +609,32 @@ mod tests {
         assert_eq!(role1, role2);
     }
 
+    #[test]
+    fn test_usage_summary_totals_models() {
+        let model = |kind, model: &str, tokens: i64, seconds: f64, cost_usd| ModelUsage {
+            kind,
+            model: model.to_string(),
+            prompt_tokens: tokens,
+            completion_tokens: tokens / 2,
+            audio_input_seconds: seconds,
+            audio_output_seconds: seconds * 2.0,
+            cost_usd,
+        };
+        let summary = UsageSummary::from_models(vec![
+            model(UsageKind::Chat, "gpt-4o", 1000, 0.0, 0.25),
+            model(UsageKind::Realtime, "gpt-4o-realtime", 0, 30.0, 0.5),
+        ]);
+        assert_eq!(summary.prompt_tokens, 1000);
+        assert_eq!(summary.completion_tokens, 500);
+        assert_eq!(summary.audio_input_seconds, 30.0);
+        assert_eq!(summary.audio_output_seconds, 60.0);
+        assert_eq!(summary.cost_usd, 0.75);
+        assert_eq!(summary.by_model.len(), 2);
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["by_model"][1]["kind"], "realtime");
+    }
+
     #[test]
     fn test_invalid_enum_deserialization() {
         // Test invalid SessionStatus
@@ -322,6 +658,8 @@ mod tests {
             user_id: "time_test".to_string(),
             topic: "Time Test".to_string(),
             status: SessionStatus::Active,
+            allowed_tools: vec![],
+            record_audio: false,
             created_at: specific_time,
             updated_at: specific_time,
         };
@@ -343,6 +681,8 @@ mod tests {
             user_id: "uuid_test".to_string(),
             topic: "UUID Test".to_string(),
             status: SessionStatus::Ended,
+            allowed_tools: vec![],
+            record_audio: false,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         };
@@ -352,4 +692,77 @@ mod tests {
 
         assert_eq!(deserialized.id, specific_uuid);
     }
+
+    #[test]
+    fn test_session_cursor_round_trip() {
+        let created_at = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap()
+            + chrono::Duration::microseconds(123_456);
+        let session = Session {
+            id: Uuid::parse_str("550e8400-e29b-41d4-a716-446655440000").unwrap(),
+            user_id: "cursor_test".to_string(),
+            topic: "Cursors".to_string(),
+            status: SessionStatus::Active,
+            allowed_tools: vec![],
+            record_audio: false,
+            created_at,
+            updated_at: created_at + chrono::Duration::hours(1),
+        };
+
+        let cursor = SessionCursor::after(SessionSort::UpdatedAt, &session);
+        assert_eq!(cursor.at, session.updated_at);
+        let encoded = cursor.encode();
+        assert!(
+            encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        );
+        assert_eq!(SessionCursor::decode(&encoded), Some(cursor));
+
+        assert_eq!(SessionCursor::decode("not a cursor"), None);
+        assert_eq!(
+            SessionCursor::decode(&URL_SAFE_NO_PAD.encode("x:1:2")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_session_summary_flattens_session() {
+        let now = Utc::now();
+        let summary = SessionSummary {
+            session: Session {
+                id: Uuid::new_v4(),
+                user_id: "summary_test".to_string(),
+                topic: "Graphs".to_string(),
+                status: SessionStatus::Active,
+                allowed_tools: vec![],
+                record_audio: false,
+                created_at: now,
+                updated_at: now,
+            },
+            covered_subtopics: 1,
+            total_subtopics: 3,
+            last_message: Some(MessagePreview {
+                role: MessageRole::Ai,
+                content: "What is a graph?".to_string(),
+                created_at: now,
+            }),
+        };
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["topic"], "Graphs");
+        assert_eq!(json["total_subtopics"], 3);
+        assert_eq!(json["last_message"]["role"], "Ai");
+    }
+
+    #[test]
+    fn test_agent_state_snapshot_serialization() {
+        let snapshot = AgentStateSnapshot {
+            id: 7,
+            state: FeynmanAgent::new("Graphs".to_string(), vec![]),
+            message: None,
+            created_at: Utc::now(),
+        };
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["state"]["main_topic"], "Graphs");
+        assert!(json["message"].is_null());
+    }
 }
@@ -0,0 +1,23 @@
+//! Loads the Markdown prompt templates from the prompts directory.
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Reads every `.md` file in the directory, keyed by file stem.
+pub fn load_prompts(prompts_path: &Path) -> Result<HashMap<String, String>> {
+    let mut prompts = HashMap::new();
+    for entry in fs::read_dir(prompts_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("md") {
+            let prompt_key = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .context("Could not get file stem")?
+                .to_string();
+            let content = fs::read_to_string(&path)?;
+            prompts.insert(prompt_key, content);
+        }
+    }
+    Ok(prompts)
+}
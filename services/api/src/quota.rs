@@ -0,0 +1,277 @@
+//! Per-User Quotas and Rate Limits
+//!
+//! Daily and monthly quotas are checked against the sessions and usage records
+//! in the database, so they hold across restarts and replicas. The turn rate
+//! is limited in memory, per process.
+
+use crate::{config::QuotaLimits, db::Db, ws::protocol::ErrorCode};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveTime, TimeZone, Utc};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// The window the turn rate is measured over.
+const TURN_WINDOW: Duration = Duration::from_secs(60);
+
+/// A limit on what a user may do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quota {
+    SessionsPerDay,
+    TurnsPerMinute,
+    TokensPerMonth,
+    VoiceMinutesPerMonth,
+}
+
+/// A user reached one of their limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub quota: Quota,
+    /// How long until the limit frees up again.
+    pub retry_after: Duration,
+}
+
+impl QuotaExceeded {
+    /// The code reporting this over the WebSocket.
+    pub fn code(&self) -> ErrorCode {
+        match self.quota {
+            Quota::SessionsPerDay => ErrorCode::SessionQuotaExceeded,
+            Quota::TurnsPerMinute => ErrorCode::RateLimited,
+            Quota::TokensPerMonth => ErrorCode::TokenQuotaExceeded,
+            Quota::VoiceMinutesPerMonth => ErrorCode::VoiceQuotaExceeded,
+        }
+    }
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reached = match self.quota {
+            Quota::SessionsPerDay => "You have created as many sessions as you may today.",
+            Quota::TurnsPerMinute => "You are sending messages too quickly.",
+            Quota::TokensPerMonth => "You have used up your tokens for this month.",
+            Quota::VoiceMinutesPerMonth => "You have used up your voice minutes for this month.",
+        };
+        write!(
+            f,
+            "{} Try again in {}s.",
+            reached,
+            self.retry_after.as_secs().max(1)
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Enforces the configured `QuotaLimits`.
+pub struct Quotas {
+    limits: QuotaLimits,
+    /// When each user's recent turns started, oldest first.
+    turns: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl Quotas {
+    pub fn new(limits: QuotaLimits) -> Self {
+        Self {
+            limits,
+            turns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks that the user may create a session, which also spends tokens on
+    /// its curriculum. Fails with `QuotaExceeded` if not.
+    pub async fn check_new_session(&self, db: &Db, user_id: &str) -> Result<()> {
+        if let Some(limit) = self.limits.sessions_per_day {
+            let now = Utc::now();
+            let today = start_of_day(now);
+            if db.count_sessions_since(user_id, today).await? >= i64::from(limit) {
+                return Err(QuotaExceeded {
+                    quota: Quota::SessionsPerDay,
+                    retry_after: until(now, today + ChronoDuration::days(1)),
+                }
+                .into());
+            }
+        }
+        self.check_tokens(db, user_id).await
+    }
+
+    /// Checks that the user may start a turn, and counts it if so. Fails with
+    /// `QuotaExceeded` if not.
+    pub async fn check_turn(&self, db: &Db, user_id: &str) -> Result<()> {
+        self.check_tokens(db, user_id).await?;
+        if let Some(limit) = self.limits.turns_per_minute {
+            let now = Instant::now();
+            let mut turns = self.turns.lock().unwrap();
+            forget_idle(&mut turns, now);
+            let started = turns.entry(user_id.to_string()).or_default();
+            admit_turn(started, now, limit).map_err(|retry_after| QuotaExceeded {
+                quota: Quota::TurnsPerMinute,
+                retry_after,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The seconds of voice audio the user has left this month, or `None` if
+    /// voice is unlimited. Fails with `QuotaExceeded` if none are left.
+    pub async fn voice_seconds_left(&self, db: &Db, user_id: &str) -> Result<Option<f64>> {
+        let Some(minutes) = self.limits.voice_minutes_per_month else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        let month = start_of_month(now);
+        let (_, used) = db.usage_since(user_id, month).await?;
+        let left = minutes * 60.0 - used;
+        if left <= 0.0 {
+            return Err(QuotaExceeded {
+                quota: Quota::VoiceMinutesPerMonth,
+                retry_after: until(now, next_month(month)),
+            }
+            .into());
+        }
+        Ok(Some(left))
+    }
+
+    /// The exceeded voice quota, for when a session runs out of its voice minutes.
+    pub fn voice_exceeded(&self) -> QuotaExceeded {
+        let now = Utc::now();
+        QuotaExceeded {
+            quota: Quota::VoiceMinutesPerMonth,
+            retry_after: until(now, next_month(start_of_month(now))),
+        }
+    }
+
+    async fn check_tokens(&self, db: &Db, user_id: &str) -> Result<()> {
+        let Some(limit) = self.limits.tokens_per_month else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        let month = start_of_month(now);
+        let (used, _) = db.usage_since(user_id, month).await?;
+        if used >= i64::try_from(limit).unwrap_or(i64::MAX) {
+            return Err(QuotaExceeded {
+                quota: Quota::TokensPerMonth,
+                retry_after: until(now, next_month(month)),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Counts a turn starting at `now` if fewer than `limit` started within the
+/// last `TURN_WINDOW`, or returns how long until one more may start.
+fn admit_turn(started: &mut VecDeque<Instant>, now: Instant, limit: u32) -> Result<(), Duration> {
+    while started
+        .front()
+        .is_some_and(|&at| now.duration_since(at) >= TURN_WINDOW)
+    {
+        started.pop_front();
+    }
+    if started.len() >= limit as usize {
+        let retry_after = started
+            .front()
+            .map(|&oldest| TURN_WINDOW - now.duration_since(oldest))
+            .unwrap_or(TURN_WINDOW);
+        return Err(retry_after);
+    }
+    started.push_back(now);
+    Ok(())
+}
+
+/// Drops the users with no turn started within the last `TURN_WINDOW`, so
+/// the map only holds recently active users.
+fn forget_idle(turns: &mut HashMap<String, VecDeque<Instant>>, now: Instant) {
+    turns.retain(|_, started| {
+        started
+            .back()
+            .is_some_and(|&at| now.duration_since(at) < TURN_WINDOW)
+    });
+}
+
+fn start_of_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.with_time(NaiveTime::MIN).unwrap()
+}
+
+fn start_of_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .unwrap()
+}
+
+fn next_month(month: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = match month.month() {
+        12 => (month.year() + 1, 1),
+        m => (month.year(), m + 1),
+    };
+    Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+}
+
+fn until(now: DateTime<Utc>, then: DateTime<Utc>) -> Duration {
+    (then - now).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admit_turn_sliding_window() {
+        let mut started = VecDeque::new();
+        let start = Instant::now();
+        assert!(admit_turn(&mut started, start, 2).is_ok());
+        assert!(admit_turn(&mut started, start + Duration::from_secs(20), 2).is_ok());
+        assert_eq!(
+            admit_turn(&mut started, start + Duration::from_secs(30), 2),
+            Err(Duration::from_secs(30))
+        );
+        // The first turn leaves the window after a minute.
+        assert!(admit_turn(&mut started, start + Duration::from_secs(60), 2).is_ok());
+        assert_eq!(started.len(), 2);
+    }
+
+    #[test]
+    fn test_forget_idle_users() {
+        let start = Instant::now();
+        let mut turns = HashMap::from([
+            ("idle".to_string(), VecDeque::from([start])),
+            (
+                "active".to_string(),
+                VecDeque::from([start, start + Duration::from_secs(30)]),
+            ),
+            ("empty".to_string(), VecDeque::new()),
+        ]);
+        forget_idle(&mut turns, start + Duration::from_secs(60));
+        assert_eq!(turns.keys().collect::<Vec<_>>(), ["active"]);
+    }
+
+    #[test]
+    fn test_quota_periods() {
+        let now = Utc.with_ymd_and_hms(2025, 12, 31, 18, 30, 0).unwrap();
+        assert_eq!(
+            start_of_day(now),
+            Utc.with_ymd_and_hms(2025, 12, 31, 0, 0, 0).unwrap()
+        );
+        let month = start_of_month(now);
+        assert_eq!(month, Utc.with_ymd_and_hms(2025, 12, 1, 0, 0, 0).unwrap());
+        assert_eq!(
+            next_month(month),
+            Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            until(now, next_month(month)),
+            Duration::from_secs(5 * 3600 + 30 * 60)
+        );
+
+        let exceeded = QuotaExceeded {
+            quota: Quota::TurnsPerMinute,
+            retry_after: Duration::from_millis(200),
+        };
+        assert_eq!(exceeded.code(), ErrorCode::RateLimited);
+        assert_eq!(
+            exceeded.to_string(),
+            "You are sending messages too quickly. Try again in 1s."
+        );
+    }
+}
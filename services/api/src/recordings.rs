@@ -0,0 +1,667 @@
+//! Session Audio Recordings
+//!
+//! When a recording store is configured, the user's and the AI's voice audio is
+//! kept as one clip per utterance, linked to the message it was spoken as. Clips
+//! are written as WAV or Ogg Opus files to a local directory or an S3-compatible
+//! bucket, and a whole session can be downloaded as the clips joined in order.
+
+use crate::{
+    audio_utils,
+    config::{Config, RecordingStorage},
+    db::Db,
+    models::{MessageRole, Recording, RecordingFormat},
+    state::AppState,
+};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::{StreamExt, stream::BoxStream};
+use object_store::{
+    ObjectStore, aws::AmazonS3Builder, local::LocalFileSystem, path::Path as ObjectPath,
+};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// The sample rate every clip is stored at, in Hz.
+pub const RECORDING_SAMPLE_RATE: u32 = 24_000;
+/// The longest clip kept for one utterance; older audio is dropped first.
+const MAX_CLIP_MS: usize = 120_000;
+/// How often recordings past the retention period are deleted.
+const RETENTION_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// The size of the header written by [`encode_wav`].
+const WAV_HEADER_BYTES: usize = 44;
+/// The Opus pre-skip, in 48 kHz samples: the encoder lookahead of libopus.
+const OPUS_PRE_SKIP: u16 = 312;
+/// Ogg Opus granule positions always count 48 kHz samples.
+const OPUS_GRANULE_RATE: u64 = 48_000;
+
+/// Wraps mono PCM16 in a RIFF/WAVE container.
+pub fn encode_wav(pcm16: &[i16], sample_rate: u32) -> Vec<u8> {
+    let mut wav = wav_header((pcm16.len() * 2) as u32, sample_rate);
+    wav.extend_from_slice(&audio_utils::i16_to_le_bytes(pcm16));
+    wav
+}
+
+/// The header of a mono PCM16 WAV file holding `data_len` bytes of samples.
+fn wav_header(data_len: u32, sample_rate: u32) -> Vec<u8> {
+    let mut wav = Vec::with_capacity(WAV_HEADER_BYTES);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(WAV_HEADER_BYTES as u32 - 8 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav
+}
+
+/// Returns the sample rate and PCM data of a file written by [`encode_wav`].
+fn wav_data(wav: &[u8]) -> Result<(u32, &[u8])> {
+    anyhow::ensure!(
+        wav.len() >= WAV_HEADER_BYTES
+            && wav.starts_with(b"RIFF")
+            && &wav[8..16] == b"WAVEfmt "
+            && &wav[36..40] == b"data",
+        "Not a recording WAV file"
+    );
+    let sample_rate = u32::from_le_bytes(wav[24..28].try_into()?);
+    Ok((sample_rate, &wav[WAV_HEADER_BYTES..]))
+}
+
+/// Encodes mono PCM16 as a single Ogg Opus stream with the given serial number.
+pub fn encode_ogg_opus(pcm16: &[i16], sample_rate: u32, serial: u32) -> Result<Vec<u8>> {
+    let mut encoder = audio_utils::OpusPcmEncoder::new(sample_rate as f64)?;
+    let mut packets = encoder.encode(pcm16)?;
+    packets.extend(encoder.flush()?);
+
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(1); // channels
+    head.extend_from_slice(&OPUS_PRE_SKIP.to_le_bytes());
+    head.extend_from_slice(&sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // mono/stereo channel mapping
+    let vendor = env!("CARGO_PKG_NAME").as_bytes();
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes()); // no comments
+
+    let mut writer = ogg::PacketWriter::new(Vec::new());
+    writer.write_packet(head, serial, ogg::PacketWriteEndInfo::EndPage, 0)?;
+    let end = if packets.is_empty() {
+        ogg::PacketWriteEndInfo::EndStream
+    } else {
+        ogg::PacketWriteEndInfo::EndPage
+    };
+    writer.write_packet(tags, serial, end, 0)?;
+
+    // The last granule position marks the true end, trimming the padded final packet.
+    let packet_granules = OPUS_GRANULE_RATE * audio_utils::OPUS_FRAME_MS as u64 / 1000;
+    let total_granules = pcm16.len() as u64 * OPUS_GRANULE_RATE / sample_rate as u64;
+    let count = packets.len();
+    for (index, packet) in packets.into_iter().enumerate() {
+        let last = index + 1 == count;
+        let granule = OPUS_PRE_SKIP as u64
+            + if last {
+                total_granules
+            } else {
+                (index as u64 + 1) * packet_granules
+            };
+        let end = if last {
+            ogg::PacketWriteEndInfo::EndStream
+        } else {
+            ogg::PacketWriteEndInfo::NormalPacket
+        };
+        writer.write_packet(packet, serial, end, granule)?;
+    }
+    Ok(writer.into_inner())
+}
+
+/// Encodes one clip in the given format at [`RECORDING_SAMPLE_RATE`].
+pub fn encode(format: RecordingFormat, pcm16: &[i16]) -> Result<Vec<u8>> {
+    match format {
+        RecordingFormat::Wav => Ok(encode_wav(pcm16, RECORDING_SAMPLE_RATE)),
+        RecordingFormat::Opus => encode_ogg_opus(pcm16, RECORDING_SAMPLE_RATE, rand::random()),
+    }
+}
+
+/// Clips joined into one file, read one clip at a time as the file is sent.
+pub struct JoinedRecording {
+    /// The size of the whole file in bytes.
+    pub len: u64,
+    pub body: BoxStream<'static, Result<Bytes>>,
+}
+
+/// The joined clips would be larger than a WAV file can hold.
+#[derive(Debug, thiserror::Error)]
+#[error("The recording is too long to download as one WAV file")]
+pub struct RecordingTooLarge;
+
+/// The configured place recordings are kept, and the format new ones are written in.
+pub struct RecordingStore {
+    store: Arc<dyn ObjectStore>,
+    format: RecordingFormat,
+}
+
+impl RecordingStore {
+    pub fn new(store: Arc<dyn ObjectStore>, format: RecordingFormat) -> Self {
+        Self { store, format }
+    }
+
+    /// Opens the store chosen in the configuration, or returns `None` when
+    /// recording is disabled.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let store: Arc<dyn ObjectStore> = match &config.recording_storage {
+            None => return Ok(None),
+            Some(RecordingStorage::Local(dir)) => {
+                std::fs::create_dir_all(dir).with_context(|| {
+                    format!("Failed to create recording directory {}", dir.display())
+                })?;
+                Arc::new(LocalFileSystem::new_with_prefix(dir)?)
+            }
+            Some(RecordingStorage::S3 { bucket, endpoint }) => {
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+                if let Some(endpoint) = endpoint {
+                    builder = builder
+                        .with_endpoint(endpoint)
+                        .with_allow_http(endpoint.starts_with("http://"));
+                }
+                Arc::new(builder.build()?)
+            }
+        };
+        Ok(Some(Self::new(store, config.recording_format)))
+    }
+
+    /// The format new recordings are written in.
+    pub fn format(&self) -> RecordingFormat {
+        self.format
+    }
+
+    /// Encodes a clip and stores it under the session, returning its key.
+    pub async fn put_clip(&self, session_id: Uuid, pcm16: &[i16]) -> Result<String> {
+        let data = encode(self.format, pcm16)?;
+        let key = format!(
+            "{}/{}.{}",
+            session_id,
+            Uuid::new_v4(),
+            self.format.extension()
+        );
+        self.store
+            .put(&ObjectPath::from(key.as_str()), data.into())
+            .await?;
+        Ok(key)
+    }
+
+    /// Reads a stored file.
+    pub async fn get(&self, key: &str) -> Result<Bytes> {
+        read(self.store.as_ref(), key).await
+    }
+
+    /// Joins stored clips of the store's format into one file, in order.
+    ///
+    /// WAV clips are merged under a single header sized from the stored files;
+    /// Ogg Opus clips are chained, which players handle as consecutive streams.
+    /// Only one clip is held in memory at a time while the body is read.
+    pub async fn join(&self, keys: Vec<String>) -> Result<JoinedRecording> {
+        let mut sizes = Vec::with_capacity(keys.len());
+        for key in &keys {
+            sizes.push(self.store.head(&ObjectPath::from(key.as_str())).await?.size);
+        }
+        let format = self.format;
+        let (header, data_len) = match format {
+            RecordingFormat::Wav => {
+                let data_len: u64 = sizes
+                    .iter()
+                    .map(|size| size.saturating_sub(WAV_HEADER_BYTES as u64))
+                    .sum();
+                // The RIFF size field counts the data and the rest of the header in 32 bits.
+                if data_len + (WAV_HEADER_BYTES as u64 - 8) > u64::from(u32::MAX) {
+                    return Err(RecordingTooLarge.into());
+                }
+                (wav_header(data_len as u32, RECORDING_SAMPLE_RATE), data_len)
+            }
+            RecordingFormat::Opus => (Vec::new(), sizes.iter().sum()),
+        };
+        let len = header.len() as u64 + data_len;
+
+        let store = self.store.clone();
+        let clips =
+            futures_util::stream::iter(keys.into_iter().zip(sizes)).then(move |(key, size)| {
+                let store = store.clone();
+                async move {
+                    let clip = read(store.as_ref(), &key).await?;
+                    // The header was sized before reading, so the clip must not have changed.
+                    anyhow::ensure!(clip.len() as u64 == size, "Recording {} changed", key);
+                    match format {
+                        RecordingFormat::Wav => {
+                            wav_data(&clip)?;
+                            Ok(clip.slice(WAV_HEADER_BYTES..))
+                        }
+                        RecordingFormat::Opus => Ok(clip),
+                    }
+                }
+            });
+        let body = futures_util::stream::once(async move { Ok(Bytes::from(header)) })
+            .chain(clips)
+            .boxed();
+        Ok(JoinedRecording { len, body })
+    }
+
+    /// Deletes a stored file; one that is already gone is not an error.
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        match self.store.delete(&ObjectPath::from(key)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+async fn read(store: &dyn ObjectStore, key: &str) -> Result<Bytes> {
+    Ok(store.get(&ObjectPath::from(key)).await?.bytes().await?)
+}
+
+/// Audio collected for one side of the conversation, at [`RECORDING_SAMPLE_RATE`].
+#[derive(Default)]
+struct Track {
+    samples: Vec<i16>,
+    resampler: Option<(f64, audio_utils::StreamingResampler)>,
+}
+
+impl Track {
+    fn push(&mut self, samples: &[i16], sample_rate: f64) -> Result<()> {
+        if sample_rate == RECORDING_SAMPLE_RATE as f64 {
+            self.samples.extend_from_slice(samples);
+        } else {
+            let resampler = match &mut self.resampler {
+                Some((rate, resampler)) if *rate == sample_rate => resampler,
+                resampler => {
+                    let created = audio_utils::StreamingResampler::new(
+                        sample_rate,
+                        RECORDING_SAMPLE_RATE as f64,
+                        512,
+                    )?;
+                    &mut resampler.insert((sample_rate, created)).1
+                }
+            };
+            let resampled = resampler.process(&audio_utils::convert_i16_to_f32(samples))?;
+            self.samples
+                .extend(audio_utils::convert_f32_to_i16(&resampled));
+        }
+        let max_samples = RECORDING_SAMPLE_RATE as usize * MAX_CLIP_MS / 1000;
+        if self.samples.len() > max_samples {
+            self.samples.drain(..self.samples.len() - max_samples);
+        }
+        Ok(())
+    }
+
+    fn take(&mut self) -> Vec<i16> {
+        if let Some((_, resampler)) = &mut self.resampler {
+            match resampler.flush() {
+                Ok(tail) => self.samples.extend(audio_utils::convert_f32_to_i16(&tail)),
+                Err(e) => warn!(error = ?e, "Dropping the resampled tail of a recording."),
+            }
+        }
+        std::mem::take(&mut self.samples)
+    }
+}
+
+#[derive(Default)]
+struct Tracks {
+    user: Track,
+    ai: Track,
+    /// The stored AI reply the AI audio being collected voices.
+    ai_message_id: Option<i64>,
+}
+
+impl Tracks {
+    fn track(&mut self, role: MessageRole) -> &mut Track {
+        match role {
+            MessageRole::User => &mut self.user,
+            MessageRole::Ai => &mut self.ai,
+        }
+    }
+}
+
+/// Collects a voice session's audio and stores it one utterance at a time.
+///
+/// Clones share the same tracks, so the session can record the user while the
+/// realtime provider task records the AI.
+#[derive(Clone)]
+pub struct Recorder {
+    db: Arc<Db>,
+    store: Arc<RecordingStore>,
+    session_id: Uuid,
+    tracks: Arc<Mutex<Tracks>>,
+}
+
+impl Recorder {
+    /// Creates a recorder for the session, or returns `None` unless the session
+    /// opted in with `record_audio` and recording is enabled.
+    pub fn for_session(state: &AppState, session_id: Uuid, record_audio: bool) -> Option<Self> {
+        Self::new(
+            state.db.clone(),
+            state.recordings.as_ref(),
+            session_id,
+            record_audio,
+        )
+    }
+
+    fn new(
+        db: Arc<Db>,
+        store: Option<&Arc<RecordingStore>>,
+        session_id: Uuid,
+        record_audio: bool,
+    ) -> Option<Self> {
+        if !record_audio {
+            return None;
+        }
+        Some(Self {
+            db,
+            store: store?.clone(),
+            session_id,
+            tracks: Arc::default(),
+        })
+    }
+
+    /// Appends audio spoken by one side, produced at `sample_rate`.
+    pub fn record(&self, role: MessageRole, samples: &[i16], sample_rate: f64) {
+        if let Err(e) = self
+            .tracks
+            .lock()
+            .unwrap()
+            .track(role)
+            .push(samples, sample_rate)
+        {
+            warn!(error = ?e, %role, "Failed to record audio.");
+        }
+    }
+
+    /// Takes the audio collected for one side so far.
+    pub fn take(&self, role: MessageRole) -> Vec<i16> {
+        self.tracks.lock().unwrap().track(role).take()
+    }
+
+    /// Links the AI audio collected next to the stored reply it voices.
+    pub fn set_ai_message(&self, message_id: Option<i64>) {
+        self.tracks.lock().unwrap().ai_message_id = message_id;
+    }
+
+    /// Stores the AI audio collected so far, at the end of a spoken reply.
+    pub fn finish_ai(&self) {
+        let (samples, message_id) = {
+            let mut tracks = self.tracks.lock().unwrap();
+            (tracks.ai.take(), tracks.ai_message_id.take())
+        };
+        self.save(MessageRole::Ai, samples, message_id);
+    }
+
+    /// Stores a clip in the background, linked to a message if there is one.
+    pub fn save(&self, role: MessageRole, samples: Vec<i16>, message_id: Option<i64>) {
+        if samples.is_empty() {
+            return;
+        }
+        let recorder = self.clone();
+        tokio::spawn(async move {
+            let duration_ms = (samples.len() as u64 * 1000 / RECORDING_SAMPLE_RATE as u64) as i32;
+            let stored = async {
+                let key = recorder
+                    .store
+                    .put_clip(recorder.session_id, &samples)
+                    .await?;
+                recorder
+                    .db
+                    .add_recording(
+                        recorder.session_id,
+                        message_id,
+                        role,
+                        recorder.store.format(),
+                        &key,
+                        duration_ms,
+                    )
+                    .await
+            };
+            match stored.await {
+                Ok(recording) => {
+                    info!(recording_id = recording.id, %role, duration_ms, "Stored recording.")
+                }
+                Err(e) => error!(error = ?e, %role, "Failed to store recording."),
+            }
+        });
+    }
+}
+
+/// Deletes the recordings created before `cutoff`, with their files, returning how many.
+///
+/// Files are deleted first, and only the recordings whose files are gone are
+/// deleted, so a file that fails to delete keeps its row and is retried on the
+/// next sweep rather than being orphaned.
+pub async fn delete_expired(
+    db: &Db,
+    store: &RecordingStore,
+    cutoff: chrono::DateTime<chrono::Utc>,
+) -> Result<u64> {
+    let expired = db.list_recordings_before(cutoff).await?;
+    let removed = delete_files(store, &expired).await;
+    if removed.is_empty() {
+        return Ok(0);
+    }
+    db.delete_recordings(&removed).await
+}
+
+/// Deletes the recordings' files, returning the IDs of those whose files are gone.
+async fn delete_files(store: &RecordingStore, recordings: &[Recording]) -> Vec<i64> {
+    let mut removed = Vec::with_capacity(recordings.len());
+    for recording in recordings {
+        match store.delete(&recording.object_key).await {
+            Ok(()) => removed.push(recording.id),
+            Err(e) => {
+                warn!(error = ?e, key = %recording.object_key, "Failed to delete recording file.")
+            }
+        }
+    }
+    removed
+}
+
+/// Periodically deletes recordings older than the retention period.
+pub fn spawn_retention(
+    db: Arc<Db>,
+    store: Arc<RecordingStore>,
+    retention_days: u32,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cutoff = chrono::Utc::now() - chrono::Duration::days(retention_days.into());
+            match delete_expired(&db, &store, cutoff).await {
+                Ok(0) => {}
+                Ok(deleted) => info!(deleted, retention_days, "Deleted expired recordings."),
+                Err(e) => error!(error = ?e, "Failed to delete expired recordings."),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(samples: usize) -> Vec<i16> {
+        (0..samples)
+            .map(|n| ((n as f64 * 0.05).sin() * 8_000.0) as i16)
+            .collect()
+    }
+
+    /// Reads the whole body of a joined recording.
+    async fn collect(joined: JoinedRecording) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut body = joined.body;
+        while let Some(chunk) = body.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn test_wav_clips_join_under_one_header() {
+        let store = RecordingStore::new(
+            Arc::new(object_store::memory::InMemory::new()),
+            RecordingFormat::Wav,
+        );
+        let session_id = Uuid::new_v4();
+        let first = store.put_clip(session_id, &tone(2_400)).await.unwrap();
+        let second = store.put_clip(session_id, &tone(1_200)).await.unwrap();
+
+        let joined = store.join(vec![first, second]).await.unwrap();
+        let len = joined.len;
+        let joined = collect(joined).await.unwrap();
+        assert_eq!(joined.len() as u64, len);
+        let (sample_rate, data) = wav_data(&joined).unwrap();
+        assert_eq!(sample_rate, RECORDING_SAMPLE_RATE);
+        assert_eq!(data.len(), 7_200);
+        assert_eq!(
+            u32::from_le_bytes(joined[4..8].try_into().unwrap()) as usize,
+            joined.len() - 8
+        );
+        assert_eq!(
+            u32::from_le_bytes(joined[40..44].try_into().unwrap()),
+            7_200
+        );
+
+        // A clip that is not WAV fails the body rather than corrupting it.
+        store
+            .store
+            .put(&ObjectPath::from("ogg"), Bytes::from_static(b"OggS").into())
+            .await
+            .unwrap();
+        let joined = store.join(vec!["ogg".to_string()]).await.unwrap();
+        assert!(collect(joined).await.is_err());
+    }
+
+    #[test]
+    fn test_ogg_opus_clip_round_trip() {
+        // 110 ms: five whole packets and a padded sixth.
+        let pcm = tone(2_640);
+        let ogg_bytes = encode_ogg_opus(&pcm, RECORDING_SAMPLE_RATE, 7).unwrap();
+
+        let mut reader = ogg::PacketReader::new(std::io::Cursor::new(ogg_bytes));
+        let head = reader.read_packet_expected().unwrap();
+        assert!(head.data.starts_with(b"OpusHead"));
+        assert_eq!(head.stream_serial(), 7);
+        let tags = reader.read_packet_expected().unwrap();
+        assert!(tags.data.starts_with(b"OpusTags"));
+
+        let mut decoder = audio_utils::OpusPcmDecoder::new(RECORDING_SAMPLE_RATE as f64).unwrap();
+        let mut decoded = 0;
+        let mut last = None;
+        while let Some(packet) = reader.read_packet().unwrap() {
+            decoded += decoder.decode(&packet.data).unwrap().len();
+            last = Some(packet);
+        }
+        assert_eq!(decoded, 6 * 480);
+        let last = last.unwrap();
+        assert!(last.last_in_stream());
+        assert_eq!(last.absgp_page(), OPUS_PRE_SKIP as u64 + 2_640 * 2);
+    }
+
+    #[test]
+    fn test_track_resamples_and_caps_clips() {
+        let mut track = Track::default();
+        track.push(&tone(1_600), 16_000.0).unwrap();
+        track.push(&tone(1_600), 16_000.0).unwrap();
+        let clip = track.take();
+        assert!(clip.len().abs_diff(4_800) <= 1, "got {}", clip.len());
+        assert!(track.take().is_empty());
+
+        let max_samples = RECORDING_SAMPLE_RATE as usize * MAX_CLIP_MS / 1000;
+        track
+            .push(&vec![1; max_samples], RECORDING_SAMPLE_RATE as f64)
+            .unwrap();
+        track.push(&[2; 10], RECORDING_SAMPLE_RATE as f64).unwrap();
+        let clip = track.take();
+        assert_eq!(clip.len(), max_samples);
+        assert_eq!(clip[clip.len() - 1], 2);
+    }
+
+    #[tokio::test]
+    async fn test_local_store_put_get_delete() {
+        let dir = std::env::temp_dir().join(format!("feynman-recordings-{}", Uuid::new_v4()));
+        let config = Config {
+            recording_storage: Some(RecordingStorage::Local(dir.clone())),
+            ..Config::for_tests()
+        };
+        let store = RecordingStore::from_config(&config).unwrap().unwrap();
+        let session_id = Uuid::new_v4();
+
+        let key = store.put_clip(session_id, &tone(480)).await.unwrap();
+        assert!(key.starts_with(&session_id.to_string()));
+        assert!(key.ends_with(".wav"));
+        let stored = store.get(&key).await.unwrap();
+        assert_eq!(wav_data(&stored).unwrap().1.len(), 960);
+
+        store.delete(&key).await.unwrap();
+        assert!(store.get(&key).await.is_err());
+        store.delete(&key).await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recorder_requires_opt_in() {
+        let db = Arc::new(Db::new(
+            sqlx::PgPool::connect_lazy(&Config::for_tests().database_url).unwrap(),
+        ));
+        let store = Arc::new(RecordingStore::new(
+            Arc::new(object_store::memory::InMemory::new()),
+            RecordingFormat::Wav,
+        ));
+        let session_id = Uuid::new_v4();
+
+        assert!(Recorder::new(db.clone(), Some(&store), session_id, true).is_some());
+        assert!(Recorder::new(db.clone(), Some(&store), session_id, false).is_none());
+        assert!(Recorder::new(db, None, session_id, true).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_files_keeps_failed_recordings() {
+        let dir = std::env::temp_dir().join(format!("feynman-recordings-{}", Uuid::new_v4()));
+        let config = Config {
+            recording_storage: Some(RecordingStorage::Local(dir.clone())),
+            ..Config::for_tests()
+        };
+        let store = RecordingStore::from_config(&config).unwrap().unwrap();
+        let session_id = Uuid::new_v4();
+        let stored = store.put_clip(session_id, &tone(480)).await.unwrap();
+        // A directory in place of a file cannot be deleted as one.
+        std::fs::create_dir_all(dir.join("undeletable/clip.wav/inner")).unwrap();
+        let recording = |id, object_key: &str| Recording {
+            id,
+            session_id,
+            message_id: None,
+            role: MessageRole::User,
+            format: RecordingFormat::Wav,
+            object_key: object_key.to_string(),
+            duration_ms: 10,
+            created_at: chrono::Utc::now(),
+        };
+
+        let removed = delete_files(
+            &store,
+            &[
+                recording(1, &stored),
+                recording(2, "gone/clip.wav"),
+                recording(3, "undeletable/clip.wav"),
+            ],
+        )
+        .await;
+        assert_eq!(removed, [1, 2]);
+        assert!(store.get(&stored).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -6,8 +6,9 @@
 use crate::{
     handlers,
     models::{
-        CreateSessionPayload, ErrorResponse, Message, MessageRole, Session, SessionStatus,
-        UpdateSessionStatusPayload,
+        AgentStateSnapshot, AudioFile, CreateSessionPayload, DailyUsage, ErrorResponse, Message,
+        MessagePreview, MessageRole, ModelUsage, Recording, RecordingFormat, Session, SessionSort,
+        SessionStatus, SessionSummary, UpdateSessionStatusPayload, UsageKind, UsageSummary,
     },
     state::AppState,
     ws::ws_handler,
@@ -28,9 +29,17 @@ use utoipa_swagger_ui::SwaggerUi;
         handlers::list_sessions,
         handlers::get_session,
         handlers::update_session_status,
+        handlers::list_messages,
+        handlers::list_agent_states,
+        handlers::list_recordings,
+        handlers::get_session_recording,
+        handlers::get_recording,
+        handlers::get_session_usage,
+        handlers::get_user_usage,
+        handlers::get_daily_usage,
     ),
     components(
-        schemas(Session, Message, CreateSessionPayload, UpdateSessionStatusPayload, ErrorResponse, SessionStatus, MessageRole)
+        schemas(Session, Message, CreateSessionPayload, UpdateSessionStatusPayload, ErrorResponse, SessionStatus, MessageRole, Recording, RecordingFormat, AudioFile, UsageKind, ModelUsage, UsageSummary, DailyUsage, SessionSummary, MessagePreview, SessionSort, AgentStateSnapshot)
     ),
     tags(
         (name = "Feynman API", description = "Session management for the Feynman teaching agent")
@@ -51,6 +60,20 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
             "/sessions/{id}/status",
             patch(handlers::update_session_status),
         )
+        .route("/sessions/{id}/messages", get(handlers::list_messages))
+        .route("/sessions/{id}/states", get(handlers::list_agent_states))
+        .route("/sessions/{id}/recordings", get(handlers::list_recordings))
+        .route(
+            "/sessions/{id}/recordings/{recording_id}",
+            get(handlers::get_recording),
+        )
+        .route(
+            "/sessions/{id}/recording",
+            get(handlers::get_session_recording),
+        )
+        .route("/sessions/{id}/usage", get(handlers::get_session_usage))
+        .route("/usage", get(handlers::get_user_usage))
+        .route("/usage/daily", get(handlers::get_daily_usage))
         .route("/ws", get(ws_handler))
         // Apply the state ONLY to this group of routes.
         .with_state(app_state);
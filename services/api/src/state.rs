@@ -4,7 +4,7 @@
 //! clonable resources like database pools and service clients.
 
 use crate::config::Config;
-use feynman_core::{curriculum::CurriculumService, llm_client::LLMClient};
+use feynman_core::{curriculum::CurriculumService, llm_client::LLMClient, tools::ExternalTools};
 use std::sync::Arc;
 
 /// The shared application state, created once at startup and passed to all handlers.
@@ -16,4 +16,10 @@ pub struct AppState {
     pub llm_client: Arc<dyn LLMClient>,
     pub system_prompt: Arc<String>,
     pub config: Arc<Config>,
+    /// Where voice audio is recorded, if recording is enabled.
+    pub recordings: Option<Arc<crate::recordings::RecordingStore>>,
+    /// The connected external MCP servers, whose tools sessions may allow.
+    pub external_tools: Vec<Arc<ExternalTools>>,
+    /// Enforces per-user limits on sessions, turns, tokens and voice minutes.
+    pub quotas: Arc<crate::quota::Quotas>,
 }
@@ -0,0 +1,237 @@
+//! Usage and Cost Accounting
+//!
+//! Each LLM call and each stretch of realtime audio is stored as a usage record
+//! against its session and user. Records are priced from the configured price
+//! table when they are stored, so a later price change doesn't rewrite what
+//! past usage cost.
+
+use crate::{
+    config::{Config, ModelPrice, RealtimeProvider},
+    models::UsageKind,
+    state::AppState,
+};
+use feynman_core::llm_client::Usage;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// What one LLM call or stretch of realtime audio consumed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageEntry {
+    pub kind: UsageKind,
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub audio_input_seconds: f64,
+    pub audio_output_seconds: f64,
+}
+
+impl UsageEntry {
+    /// The tokens an LLM call used.
+    pub fn tokens(kind: UsageKind, usage: &Usage) -> Self {
+        Self {
+            kind,
+            model: usage.model.clone(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            audio_input_seconds: 0.0,
+            audio_output_seconds: 0.0,
+        }
+    }
+
+    /// Voice audio exchanged with the realtime provider.
+    pub fn audio(model: String, input_seconds: f64, output_seconds: f64) -> Self {
+        Self {
+            kind: UsageKind::Realtime,
+            model,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            audio_input_seconds: input_seconds,
+            audio_output_seconds: output_seconds,
+        }
+    }
+
+    /// What the usage costs in US dollars, or zero if the model has no price.
+    pub fn cost(&self, prices: &HashMap<String, ModelPrice>) -> f64 {
+        let Some(price) = price_for(prices, &self.model) else {
+            debug!(model = %self.model, "No price configured for model.");
+            return 0.0;
+        };
+        self.prompt_tokens as f64 * price.prompt_per_million_tokens / 1e6
+            + self.completion_tokens as f64 * price.completion_per_million_tokens / 1e6
+            + self.audio_input_seconds * price.audio_input_per_minute / 60.0
+            + self.audio_output_seconds * price.audio_output_per_minute / 60.0
+    }
+}
+
+/// The price of `model`: its own, or else that of the longest model name it
+/// starts with, so `gpt-4o` also prices `gpt-4o-2024-08-06`.
+pub fn price_for<'a>(
+    prices: &'a HashMap<String, ModelPrice>,
+    model: &str,
+) -> Option<&'a ModelPrice> {
+    prices
+        .iter()
+        .filter(|(name, _)| model.starts_with(name.as_str()))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, price)| price)
+}
+
+/// Stores usage against a session. Failures are logged rather than returned,
+/// so accounting never interrupts a conversation.
+pub async fn record(state: &AppState, session_id: Uuid, entry: UsageEntry) {
+    let cost_usd = entry.cost(&state.config.model_prices);
+    if let Err(e) = state.db.add_usage(session_id, &entry, cost_usd).await {
+        warn!(error = ?e, %session_id, kind = ?entry.kind, "Failed to record usage.");
+    }
+}
+
+/// The realtime model in use, as usage is recorded and priced under.
+pub fn realtime_model(config: &Config) -> String {
+    match config.realtime_provider {
+        RealtimeProvider::OpenAI => config.openai_realtime_model.clone(),
+        RealtimeProvider::Gemini => config.gemini_live_model.clone(),
+    }
+}
+
+/// Counts the seconds of voice audio sent to and received from the realtime
+/// provider, until they are recorded.
+#[derive(Clone, Debug, Default)]
+pub struct AudioMeter {
+    seconds: Arc<Mutex<MeteredAudio>>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct MeteredAudio {
+    input: f64,
+    output: f64,
+    /// The seconds that may still be used, or `None` if unlimited.
+    budget: Option<f64>,
+}
+
+impl AudioMeter {
+    /// Counts audio sent to the provider.
+    pub fn add_input(&self, samples: usize, sample_rate: f64) {
+        self.seconds.lock().unwrap().input += samples as f64 / sample_rate;
+    }
+
+    /// Counts audio received from the provider.
+    pub fn add_output(&self, samples: usize, sample_rate: f64) {
+        self.seconds.lock().unwrap().output += samples as f64 / sample_rate;
+    }
+
+    /// Limits the audio that may be used from now on, or lifts the limit.
+    pub fn set_budget(&self, seconds: Option<f64>) {
+        let mut metered = self.seconds.lock().unwrap();
+        metered.budget = seconds.map(|seconds| seconds + metered.input + metered.output);
+    }
+
+    /// Whether the audio counted has used up the budget.
+    pub fn exhausted(&self) -> bool {
+        let metered = self.seconds.lock().unwrap();
+        metered
+            .budget
+            .is_some_and(|budget| metered.input + metered.output >= budget)
+    }
+
+    /// Records the audio counted so far as realtime usage, and starts over.
+    pub async fn record(&self, state: &AppState, session_id: Uuid) {
+        let (input, output) = {
+            let mut metered = self.seconds.lock().unwrap();
+            let (input, output) = (metered.input, metered.output);
+            metered.input = 0.0;
+            metered.output = 0.0;
+            if let Some(budget) = &mut metered.budget {
+                *budget -= input + output;
+            }
+            (input, output)
+        };
+        if input > 0.0 || output > 0.0 {
+            let entry = UsageEntry::audio(realtime_model(&state.config), input, output);
+            record(state, session_id, entry).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_cost_from_price_table() {
+        let prices = HashMap::from([
+            (
+                "gpt-4o".to_string(),
+                ModelPrice {
+                    prompt_per_million_tokens: 2.5,
+                    completion_per_million_tokens: 10.0,
+                    ..Default::default()
+                },
+            ),
+            (
+                "gpt-4o-mini".to_string(),
+                ModelPrice {
+                    prompt_per_million_tokens: 0.15,
+                    completion_per_million_tokens: 0.6,
+                    ..Default::default()
+                },
+            ),
+            (
+                "gpt-4o-realtime".to_string(),
+                ModelPrice {
+                    audio_input_per_minute: 0.06,
+                    audio_output_per_minute: 0.24,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let tokens = |model: &str| {
+            UsageEntry::tokens(
+                UsageKind::Chat,
+                &Usage {
+                    model: model.to_string(),
+                    prompt_tokens: 1_000_000,
+                    completion_tokens: 500_000,
+                },
+            )
+        };
+
+        // Dated versions take the price of their base model, and the most
+        // specific name wins.
+        assert!((tokens("gpt-4o-2024-08-06").cost(&prices) - 7.5).abs() < 1e-9);
+        assert!((tokens("gpt-4o-mini").cost(&prices) - 0.45).abs() < 1e-9);
+        assert_eq!(tokens("claude-sonnet-4-5").cost(&prices), 0.0);
+
+        let audio = UsageEntry::audio("gpt-4o-realtime-preview".to_string(), 120.0, 30.0);
+        assert!((audio.cost(&prices) - (0.12 + 0.12)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_audio_meter_counts_seconds() {
+        let meter = AudioMeter::default();
+        meter.add_input(24_000, 24_000.0);
+        meter.add_input(8_000, 16_000.0);
+        meter.add_output(48_000, 24_000.0);
+        {
+            let metered = meter.seconds.lock().unwrap();
+            assert_eq!((metered.input, metered.output), (1.5, 2.0));
+        }
+
+        // A budget counts from when it is set.
+        assert!(!meter.exhausted());
+        meter.set_budget(Some(3.0));
+        meter.add_output(48_000, 24_000.0);
+        assert!(!meter.exhausted());
+        meter.add_input(16_000, 16_000.0);
+        assert!(meter.exhausted());
+        meter.set_budget(None);
+        assert!(!meter.exhausted());
+
+        // Nothing left, e.g. after another session spent it, is used up at once.
+        meter.set_budget(Some(0.0));
+        assert!(meter.exhausted());
+    }
+}
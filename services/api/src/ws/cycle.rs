@@ -1,31 +1,32 @@
 //! Contains the logic for the agent's "ReAct" (Reason and Act) cycle.
 
 use crate::{
-    models::MessageRole,
+    models::{MessageRole, UsageKind},
     state::AppState,
+    usage::{self, UsageEntry},
     ws::{protocol::ServerMessage, provider::RealtimeClientEvent, session::send_msg},
 };
-use anyhow::{Context, Result};
-use async_openai::types::{
-    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
-    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestToolMessageArgs,
-    ChatCompletionRequestUserMessageArgs, ChatCompletionToolArgs, FunctionObjectArgs,
-};
+use anyhow::Result;
 use axum::extract::ws::{Message, WebSocket};
 use feynman_core::{
     agent::FeynmanAgent,
-    llm_client::{LLMAction, LLMStreamEvent},
+    llm_client::{ChatMessage, LLMAction, LLMStreamEvent, ToolDefinition},
+    tools::ToolRegistry,
 };
 use futures_util::{StreamExt, stream::SplitSink};
-use rmcp::{
-    model::{CallToolRequestParam, RawContent},
-    service::{RoleClient, RunningService},
-};
 use std::sync::Arc;
 use tokio::sync::{Mutex, mpsc};
 use uuid::Uuid;
 
-/// Handles a single user interaction, driving the agent through a ReAct cycle.
+/// What a completed ReAct cycle stored.
+#[derive(Debug, Clone, Copy)]
+pub struct CycleOutcome {
+    /// The ID of the stored AI reply, if the LLM produced any text.
+    pub reply_id: Option<i64>,
+}
+
+/// Answers the user message last added to `history`, driving the agent
+/// through a ReAct cycle.
 ///
 /// This involves:
 /// 1.  Constructing the prompt with the latest agent state and history.
@@ -37,20 +38,12 @@ use uuid::Uuid;
 pub async fn handle_react_cycle(
     state: &Arc<AppState>,
     session_id: Uuid,
-    history: &mut Vec<crate::models::Message>,
+    history: &Mutex<Vec<crate::models::Message>>,
     agent_state_arc: &Arc<tokio::sync::Mutex<FeynmanAgent>>,
-    mcp_client: &RunningService<RoleClient, ()>,
-    user_text: &str,
+    tools: &dyn ToolRegistry,
     socket_tx: &Arc<Mutex<SplitSink<WebSocket, Message>>>,
     realtime_tx: &Option<mpsc::Sender<RealtimeClientEvent>>,
-) -> Result<()> {
-    // Add the new user message to the database and local history.
-    let new_user_msg = state
-        .db
-        .add_message(session_id, MessageRole::User, user_text)
-        .await?;
-    history.push(new_user_msg);
-
+) -> Result<CycleOutcome> {
     // Construct the system prompt with the current agent state.
     let current_agent_state = agent_state_arc.lock().await.clone();
     let state_json = serde_json::to_string_pretty(&current_agent_state)?;
@@ -60,55 +53,27 @@ pub async fn handle_react_cycle(
     );
 
     // Build the full message history for the LLM.
-    let mut messages: Vec<ChatCompletionRequestMessage> = vec![
-        ChatCompletionRequestSystemMessageArgs::default()
-            .content(system_prompt_with_state)
-            .build()?
-            .into(),
-    ];
-    for msg in history.iter() {
-        match msg.role {
-            MessageRole::User => messages.push(
-                ChatCompletionRequestUserMessageArgs::default()
-                    .content(msg.content.clone())
-                    .build()?
-                    .into(),
-            ),
-            MessageRole::Ai => messages.push(
-                ChatCompletionRequestAssistantMessageArgs::default()
-                    .content(msg.content.clone())
-                    .build()?
-                    .into(),
-            ),
-        };
-    }
+    let mut messages = vec![ChatMessage::System(system_prompt_with_state)];
+    messages.extend(history.lock().await.iter().map(|msg| match msg.role {
+        MessageRole::User => ChatMessage::User(msg.content.clone()),
+        MessageRole::Ai => ChatMessage::assistant(msg.content.clone()),
+    }));
 
-    // Get the list of available tools for the agent.
-    let tools = mcp_client
-        .list_all_tools()
-        .await?
-        .into_iter()
-        .map(|t| {
-            Ok(ChatCompletionToolArgs::default()
-                .function(
-                    FunctionObjectArgs::default()
-                        .name(t.name)
-                        .description(t.description.unwrap_or_default())
-                        .parameters(serde_json::to_value(&*t.input_schema)?)
-                        .build()?,
-                )
-                .build()?)
-        })
-        .collect::<Result<Vec<_>>>()?;
+    // Declare the agent's tools to the LLM.
+    let tool_definitions: Vec<ToolDefinition> =
+        tools.tools().iter().map(ToolDefinition::from).collect();
 
     // Ask the LLM to decide on the next action.
-    let action = state
+    let decision = state
         .llm_client
-        .decide_action("".to_string(), messages.clone(), tools)
+        .decide_action(messages.clone(), tool_definitions.clone())
         .await?;
+    if let Some(used) = &decision.usage {
+        usage::record(state, session_id, UsageEntry::tokens(UsageKind::Chat, used)).await;
+    }
 
     let mut full_response = String::new();
-    match action {
+    match decision.action {
         LLMAction::TextResponse(response_text) => {
             // If the LLM decides to just respond, use the provided text.
             full_response = response_text
@@ -117,71 +82,60 @@ pub async fn handle_react_cycle(
             // If the LLM decides to use tools, execute them.
             let mut tool_results = vec![];
             for call in &tool_calls {
-                let result = mcp_client
-                    .peer()
-                    .call_tool(CallToolRequestParam {
-                        name: call.function.name.clone().into(),
-                        arguments: Some(serde_json::from_str(&call.function.arguments)?),
-                    })
-                    .await?;
-
-                let annotated_content = result
-                    .content
-                    .context("Tool call returned no content")?
-                    .pop()
-                    .context("Content list was empty")?;
-                let result_text = match annotated_content.raw {
-                    RawContent::Text(text_content) => text_content.text,
-                    _ => "{\"error\": \"Unexpected content type from tool\"}".to_string(),
-                };
-                tool_results.push(result_text);
+                let result_text = tools.call(&call.name, Some(call.arguments.clone())).await?;
+                tool_results.push(ChatMessage::Tool {
+                    call_id: call.id.clone(),
+                    name: call.name.clone(),
+                    content: result_text,
+                });
             }
 
             // Append the tool calls and their results to the history.
             let mut history_with_tools = messages;
-            history_with_tools.push(
-                ChatCompletionRequestAssistantMessageArgs::default()
-                    .tool_calls(tool_calls.clone())
-                    .build()?
-                    .into(),
-            );
-            for (i, result) in tool_results.iter().enumerate() {
-                history_with_tools.push(
-                    ChatCompletionRequestToolMessageArgs::default()
-                        .tool_call_id(tool_calls[i].id.clone())
-                        .content(result.clone())
-                        .build()?
-                        .into(),
-                );
-            }
+            history_with_tools.push(ChatMessage::Assistant {
+                content: String::new(),
+                tool_calls,
+            });
+            history_with_tools.extend(tool_results);
 
             // Call the LLM again with the tool results to get the final response.
             let mut final_stream = state
                 .llm_client
-                .stream_after_tools("".to_string(), history_with_tools)
+                .stream_after_tools(history_with_tools, tool_definitions)
                 .await?;
-            while let Some(event_result) = final_stream.next().await {
-                if let Ok(LLMStreamEvent::TextChunk(chunk)) = event_result {
-                    full_response.push_str(&chunk);
+            // A stream that fails midway leaves the reply incomplete, so the
+            // turn fails without storing or sending any of it.
+            while let Some(event) = final_stream.next().await {
+                match event? {
+                    LLMStreamEvent::TextChunk(chunk) => full_response.push_str(&chunk),
+                    LLMStreamEvent::Usage(used) => {
+                        let entry = UsageEntry::tokens(UsageKind::Chat, &used);
+                        usage::record(state, session_id, entry).await;
+                    }
                 }
             }
         }
     }
 
     // Save the final AI response to the database.
+    let mut reply_id = None;
     if !full_response.is_empty() {
         let new_ai_msg = state
             .db
             .add_message(session_id, MessageRole::Ai, &full_response)
             .await?;
-        history.push(new_ai_msg);
+        reply_id = Some(new_ai_msg.id);
+        history.lock().await.push(new_ai_msg);
     }
 
-    // Send the response to the client, either via TTS or as text.
+    // Send the response to the client, either via TTS or as text. An empty
+    // reply is not spoken, since the model would make up something to say.
     if let Some(tx) = realtime_tx {
-        let _ = tx
-            .send(RealtimeClientEvent::TextToSpeak(full_response))
-            .await;
+        if !full_response.is_empty() {
+            let _ = tx
+                .send(RealtimeClientEvent::TextToSpeak(full_response))
+                .await;
+        }
     } else {
         let mut sink = socket_tx.lock().await;
         send_msg(&mut sink, ServerMessage::ResponseStart).await?;
@@ -195,5 +149,189 @@ pub async fn handle_react_cycle(
         send_msg(&mut sink, ServerMessage::ResponseEnd).await?;
     }
 
-    Ok(())
+    Ok(CycleOutcome { reply_id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, db::Db, models, quota::Quotas};
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use axum::{Router, extract::WebSocketUpgrade, routing::get};
+    use chrono::Utc;
+    use feynman_core::{
+        curriculum::MockCurriculumService,
+        llm_client::{LLMDecision, LLMStream, ToolCall},
+    };
+    use rmcp::model::Tool;
+    use tokio::{net::TcpListener, sync::oneshot};
+    use tokio_tungstenite::connect_async;
+
+    /// Calls a tool, then streams `chunks` as the reply.
+    struct ScriptedLlm {
+        chunks: Vec<std::result::Result<&'static str, &'static str>>,
+    }
+
+    #[async_trait]
+    impl feynman_core::llm_client::LLMClient for ScriptedLlm {
+        async fn decide_action(
+            &self,
+            _history: Vec<ChatMessage>,
+            _tools: Vec<ToolDefinition>,
+        ) -> Result<LLMDecision> {
+            Ok(LLMDecision {
+                action: LLMAction::ToolCall(vec![ToolCall {
+                    id: "call_1".to_string(),
+                    name: "noop".to_string(),
+                    arguments: Default::default(),
+                }]),
+                usage: None,
+            })
+        }
+
+        async fn stream_after_tools(
+            &self,
+            _history: Vec<ChatMessage>,
+            _tools: Vec<ToolDefinition>,
+        ) -> Result<LLMStream> {
+            let events: Vec<_> = self
+                .chunks
+                .iter()
+                .map(|chunk| match chunk {
+                    Ok(text) => Ok(LLMStreamEvent::TextChunk(text.to_string())),
+                    Err(message) => Err(anyhow!(*message)),
+                })
+                .collect();
+            Ok(Box::pin(futures_util::stream::iter(events)))
+        }
+    }
+
+    struct NoopTools;
+
+    #[async_trait]
+    impl ToolRegistry for NoopTools {
+        fn tools(&self) -> &[Tool] {
+            &[]
+        }
+
+        async fn call(
+            &self,
+            _name: &str,
+            _arguments: Option<feynman_core::llm_client::JsonObject>,
+        ) -> Result<String> {
+            Ok("done".to_string())
+        }
+    }
+
+    /// What a cycle run over a real socket produced.
+    struct CycleRun {
+        result: Result<CycleOutcome>,
+        history: Vec<models::Message>,
+        /// The text frames the client received.
+        frames: Vec<String>,
+    }
+
+    /// Runs one cycle answering a user message, with the LLM streaming `chunks`
+    /// after its tool call.
+    async fn run_cycle(
+        chunks: Vec<std::result::Result<&'static str, &'static str>>,
+        realtime_tx: Option<mpsc::Sender<RealtimeClientEvent>>,
+    ) -> CycleRun {
+        let config = Config::for_tests();
+        let state = Arc::new(AppState {
+            db: Arc::new(Db::new(
+                sqlx::PgPool::connect_lazy(&config.database_url).unwrap(),
+            )),
+            curriculum_service: Arc::new(MockCurriculumService),
+            llm_client: Arc::new(ScriptedLlm { chunks }),
+            system_prompt: Arc::new("You are a curious student.".to_string()),
+            quotas: Arc::new(Quotas::new(config.quotas)),
+            config: Arc::new(config),
+            recordings: None,
+            external_tools: vec![],
+        });
+        let session_id = Uuid::new_v4();
+        let history = Arc::new(Mutex::new(vec![models::Message {
+            id: 1,
+            session_id,
+            role: MessageRole::User,
+            content: "A node points to the next one.".to_string(),
+            created_at: Utc::now(),
+        }]));
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let done_tx = Arc::new(std::sync::Mutex::new(Some(done_tx)));
+        let cycle_history = history.clone();
+        let app = Router::new().route(
+            "/ws",
+            get(move |ws: WebSocketUpgrade| {
+                let (state, history) = (state.clone(), cycle_history.clone());
+                let (realtime_tx, done_tx) = (realtime_tx.clone(), done_tx.clone());
+                async move {
+                    ws.on_upgrade(move |socket| async move {
+                        let (sink, _stream) = socket.split();
+                        let agent_state = Arc::new(Mutex::new(FeynmanAgent::new(
+                            "Linked Lists".to_string(),
+                            vec![],
+                        )));
+                        let result = handle_react_cycle(
+                            &state,
+                            session_id,
+                            &history,
+                            &agent_state,
+                            &NoopTools,
+                            &Arc::new(Mutex::new(sink)),
+                            &realtime_tx,
+                        )
+                        .await;
+                        if let Some(done_tx) = done_tx.lock().unwrap().take() {
+                            let _ = done_tx.send(result);
+                        }
+                    })
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (mut client, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let result = done_rx.await.unwrap();
+        // The socket closes once the cycle has ended.
+        let mut frames = vec![];
+        while let Some(Ok(frame)) = client.next().await {
+            if let Ok(text) = frame.to_text()
+                && !text.is_empty()
+            {
+                frames.push(text.to_string());
+            }
+        }
+        let history = history.lock().await.clone();
+        CycleRun {
+            result,
+            history,
+            frames,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_stream_fails_the_cycle() {
+        let run = run_cycle(vec![Ok("Half a"), Err("stream stalled")], None).await;
+
+        let error = run.result.unwrap_err();
+        assert_eq!(error.to_string(), "stream stalled");
+        // The partial reply is neither stored nor sent.
+        assert_eq!(run.history.len(), 1);
+        assert!(run.frames.is_empty(), "{:?}", run.frames);
+    }
+
+    #[tokio::test]
+    async fn test_empty_reply_is_not_spoken() {
+        let (realtime_tx, mut realtime_rx) = mpsc::channel(1);
+        let run = run_cycle(vec![], Some(realtime_tx)).await;
+
+        assert!(run.result.unwrap().reply_id.is_none());
+        assert!(realtime_rx.try_recv().is_err());
+    }
 }
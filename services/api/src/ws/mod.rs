@@ -6,11 +6,13 @@
 //! - `protocol`: Defines the JSON-based message format for client-server communication.
 //! - `session`: Manages the WebSocket connection lifecycle, from handshake to termination.
 //! - `cycle`: Implements the agent's "ReAct" (Reason-Act) logic for processing user input.
+//! - `turn`: Runs each ReAct cycle as a cancellable background task, one at a time.
 //! - `provider`: Handles connections to third-party real-time voice APIs (OpenAI, Gemini).
 
 mod cycle;
 pub mod protocol;
 mod provider;
 pub mod session;
+mod turn;
 
 pub use session::ws_handler;
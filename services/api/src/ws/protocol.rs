@@ -1,6 +1,6 @@
 //! Defines the WebSocket message protocol between the browser client and the API server.
 
-use crate::models;
+use crate::{audio_utils, config::TurnDetection, models};
 use feynman_core::agent::FeynmanAgent;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -16,13 +16,183 @@ pub enum ClientMessage {
         topic: String,
         /// The unique identifier of the session to resume.
         session_id: Option<Uuid>,
+        /// How AI audio should be delivered; defaults to JSON `audio_chunk` messages.
+        #[serde(default)]
+        audio_transport: AudioTransport,
+        /// The codec of voice audio in both directions; defaults to raw PCM16.
+        #[serde(default)]
+        audio_codec: AudioCodec,
     },
     /// A text message from the user to the agent.
     #[serde(rename = "user_message")]
     UserMessage { text: String },
     /// Toggles the voice input/output feature.
     #[serde(rename = "set_voice_enabled")]
-    SetVoiceEnabled { enabled: bool },
+    SetVoiceEnabled {
+        enabled: bool,
+        /// The format of the microphone audio the client will send.
+        #[serde(default)]
+        capture: CaptureFormat,
+        /// The sample rate the client plays AI audio at, in Hz.
+        #[serde(default = "default_sample_rate")]
+        playback_sample_rate: u32,
+    },
+    /// Overrides the voice and turn-detection settings for this session.
+    /// Omitted fields keep their current values.
+    #[serde(rename = "update_voice_settings")]
+    UpdateVoiceSettings(VoiceSettingsUpdate),
+    /// Push-to-talk: the user started an utterance. Audio is only forwarded
+    /// between this and `end_utterance` or `cancel_utterance`.
+    #[serde(rename = "begin_utterance")]
+    BeginUtterance,
+    /// Push-to-talk: the user finished the utterance, which becomes their turn.
+    #[serde(rename = "end_utterance")]
+    EndUtterance,
+    /// Push-to-talk: the user abandoned the utterance; its audio is discarded.
+    #[serde(rename = "cancel_utterance")]
+    CancelUtterance,
+    /// Cancels the running ReAct turn. With a `turn_id`, only that turn is
+    /// cancelled, so a late request cannot hit the turn that followed it.
+    #[serde(rename = "cancel_turn")]
+    CancelTurn {
+        #[serde(default)]
+        turn_id: Option<u64>,
+    },
+}
+
+/// How the server delivers AI audio to the client, chosen in `init`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioTransport {
+    /// Base64-encoded PCM16 in `audio_chunk` JSON messages.
+    #[default]
+    Json,
+    /// Binary WebSocket frames, each an `AudioFrameHeader` followed by the audio payload.
+    Binary,
+}
+
+/// The encoding of voice audio, chosen in `init` for both directions.
+///
+/// With Opus, every binary frame from the client carries one Opus packet, and
+/// every audio chunk or frame sent to the client carries one 20 ms packet.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum AudioCodec {
+    /// Signed 16-bit little-endian mono PCM.
+    #[default]
+    Pcm16 = 0,
+    /// Mono Opus packets.
+    Opus = 1,
+}
+
+impl AudioCodec {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Pcm16),
+            1 => Some(Self::Opus),
+            _ => None,
+        }
+    }
+}
+
+/// The format of the raw PCM audio a client captures, declared when enabling voice.
+///
+/// The server downmixes and resamples it to whatever the voice provider needs.
+/// With the Opus codec the packets carry their own format and this is ignored.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct CaptureFormat {
+    /// The sample rate in Hz; defaults to 24 kHz.
+    pub sample_rate: u32,
+    /// The number of interleaved channels; defaults to mono.
+    pub channels: u16,
+    /// The encoding of each sample; defaults to PCM16.
+    pub sample_format: audio_utils::SampleFormat,
+}
+
+impl Default for CaptureFormat {
+    fn default() -> Self {
+        Self {
+            sample_rate: default_sample_rate(),
+            channels: 1,
+            sample_format: audio_utils::SampleFormat::Pcm16,
+        }
+    }
+}
+
+fn default_sample_rate() -> u32 {
+    audio_utils::FRONTEND_AUDIO_PLAYER_SAMPLE_RATE as u32
+}
+
+/// The fixed header at the start of every binary audio frame sent to the client.
+///
+/// All fields are little-endian:
+///
+/// | Offset | Size | Field                              |
+/// |--------|------|------------------------------------|
+/// | 0      | 1    | Format version, currently 1        |
+/// | 1      | 1    | Codec (`AudioCodec`)               |
+/// | 2      | 2    | Reserved, zero                     |
+/// | 4      | 4    | Sequence number, wrapping          |
+/// | 8      | 4    | Sample rate in Hz                  |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFrameHeader {
+    /// Increments by one for each frame sent in a session.
+    pub sequence: u32,
+    /// The sample rate of the payload in Hz.
+    pub sample_rate: u32,
+    /// The encoding of the payload.
+    pub codec: AudioCodec,
+}
+
+impl AudioFrameHeader {
+    /// The size of the encoded header in bytes.
+    pub const LEN: usize = 12;
+    /// The version of the header layout.
+    pub const VERSION: u8 = 1;
+
+    /// Builds a complete frame from this header and the payload.
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(Self::LEN + payload.len());
+        frame.push(Self::VERSION);
+        frame.push(self.codec as u8);
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&self.sequence.to_le_bytes());
+        frame.extend_from_slice(&self.sample_rate.to_le_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Splits a frame into its header and payload, or `None` if it is malformed.
+    pub fn decode(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < Self::LEN || frame[0] != Self::VERSION {
+            return None;
+        }
+        let header = Self {
+            codec: AudioCodec::from_byte(frame[1])?,
+            sequence: u32::from_le_bytes(frame[4..8].try_into().ok()?),
+            sample_rate: u32::from_le_bytes(frame[8..12].try_into().ok()?),
+        };
+        Some((header, &frame[Self::LEN..]))
+    }
+}
+
+/// A partial update to the realtime voice settings, sent by the client.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct VoiceSettingsUpdate {
+    /// The provider-specific voice name (e.g. "alloy" for OpenAI, "Puck" for Gemini).
+    pub voice: Option<String>,
+    /// The speaking rate multiplier, between 0.25 and 1.5.
+    pub speaking_rate: Option<f32>,
+    /// The VAD activation threshold between 0.0 and 1.0; higher is less sensitive.
+    pub vad_threshold: Option<f32>,
+    /// Audio to include before detected speech, in milliseconds.
+    pub vad_prefix_padding_ms: Option<u32>,
+    /// Silence required to end a turn, in milliseconds.
+    pub vad_silence_duration_ms: Option<u32>,
+    /// Whether the provider or the server detects turns.
+    pub turn_detection: Option<TurnDetection>,
 }
 
 /// Messages sent from the server to the client (browser).
@@ -38,7 +208,12 @@ pub enum ServerMessage {
     /// Pushes a complete, updated agent state to the client.
     StateUpdate { state: FeynmanAgent },
     /// Reports a fatal error to the client.
-    Error { message: String },
+    Error {
+        message: String,
+        /// Identifies errors the client can act on, such as an exceeded quota.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<ErrorCode>,
+    },
     /// Signals the beginning of a streamed text response from the AI.
     ResponseStart,
     /// A chunk of a streamed text response.
@@ -47,10 +222,215 @@ pub enum ServerMessage {
     ResponseEnd,
     /// An update on the user's speech-to-text transcription.
     TranscriptionUpdate { text: String, is_final: bool },
-    /// A chunk of audio data (base64 encoded PCM16) for the AI's voice.
+    /// A chunk of audio data for the AI's voice: base64-encoded PCM16, or one Opus
+    /// packet with the Opus codec. Sent only with the JSON audio transport.
     AudioChunk { data: String },
     /// Signals that the AI has started speaking.
     AiSpeakingStart,
     /// Signals that the AI has finished speaking.
     AiSpeakingEnd,
+    /// Signals that the user has started speaking.
+    UserSpeakingStart,
+    /// Signals that the user has stopped speaking.
+    UserSpeakingEnd,
+    /// The user interrupted the AI; the client should discard any queued AI audio.
+    Interrupted,
+    /// Reports the connectivity of the realtime voice provider.
+    VoiceStatus {
+        status: VoiceConnectionStatus,
+        /// The reconnect attempt in progress, or 0 when not reconnecting.
+        attempt: u32,
+    },
+    /// The agent started answering a user message. Turn IDs increase within a session.
+    TurnStarted { turn_id: u64 },
+    /// The agent stopped working on a turn. A cancelled turn may leave a text
+    /// response without its `response_end`, and keeps its user message.
+    TurnFinished { turn_id: u64, outcome: TurnOutcome },
+}
+
+/// Identifies an error that clients may handle specially.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The user started too many turns in the last minute.
+    RateLimited,
+    /// The user created as many sessions as they may today.
+    SessionQuotaExceeded,
+    /// The user used up their tokens for the month.
+    TokenQuotaExceeded,
+    /// The user used up their voice minutes for the month; voice was turned off.
+    VoiceQuotaExceeded,
+    /// Too many messages were waiting behind the running turn; the message was dropped.
+    TurnQueueFull,
+}
+
+/// How a ReAct turn ended.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TurnOutcome {
+    /// The reply was stored and delivered.
+    Completed,
+    /// The client cancelled the turn, or a newer message interrupted it.
+    ///
+    /// The user message stays stored and in the history without a reply, so
+    /// later turns see it as context; a spoken message keeps its recording.
+    Cancelled,
+    /// The turn hit an error, reported in a preceding `error` message.
+    Failed,
+}
+
+/// The state of the connection to the realtime voice provider.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VoiceConnectionStatus {
+    /// The provider session is set up and streaming.
+    Connected,
+    /// The provider connection dropped; user audio is buffered while reconnecting.
+    Reconnecting,
+    /// Reconnecting failed; voice must be re-enabled to try again.
+    Disconnected,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_audio_options_default_to_json_pcm16() {
+        let init: ClientMessage =
+            serde_json::from_str(r#"{"type":"init","topic":"Graphs"}"#).unwrap();
+        assert!(matches!(
+            init,
+            ClientMessage::Init {
+                audio_transport: AudioTransport::Json,
+                audio_codec: AudioCodec::Pcm16,
+                ..
+            }
+        ));
+
+        let init: ClientMessage =
+            serde_json::from_str(r#"{"type":"init","topic":"Graphs","audio_transport":"binary"}"#)
+                .unwrap();
+        assert!(matches!(
+            init,
+            ClientMessage::Init {
+                audio_transport: AudioTransport::Binary,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_set_voice_enabled_audio_format() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type":"set_voice_enabled","enabled":true}"#).unwrap();
+        let ClientMessage::SetVoiceEnabled {
+            capture,
+            playback_sample_rate,
+            ..
+        } = msg
+        else {
+            panic!("expected set_voice_enabled");
+        };
+        assert_eq!(capture, CaptureFormat::default());
+        assert_eq!(playback_sample_rate, 24_000);
+
+        let msg: ClientMessage = serde_json::from_str(
+            r#"{"type":"set_voice_enabled","enabled":true,"playback_sample_rate":48000,
+                "capture":{"sample_rate":44100,"channels":2,"sample_format":"f32"}}"#,
+        )
+        .unwrap();
+        let ClientMessage::SetVoiceEnabled {
+            capture,
+            playback_sample_rate,
+            ..
+        } = msg
+        else {
+            panic!("expected set_voice_enabled");
+        };
+        assert_eq!(
+            capture,
+            CaptureFormat {
+                sample_rate: 44_100,
+                channels: 2,
+                sample_format: audio_utils::SampleFormat::F32,
+            }
+        );
+        assert_eq!(playback_sample_rate, 48_000);
+    }
+
+    #[test]
+    fn test_push_to_talk_messages() {
+        let parse = |json: &str| serde_json::from_str::<ClientMessage>(json).unwrap();
+        assert!(matches!(
+            parse(r#"{"type":"begin_utterance"}"#),
+            ClientMessage::BeginUtterance
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"end_utterance"}"#),
+            ClientMessage::EndUtterance
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"cancel_utterance"}"#),
+            ClientMessage::CancelUtterance
+        ));
+    }
+
+    #[test]
+    fn test_turn_messages() {
+        let parse = |json: &str| serde_json::from_str::<ClientMessage>(json).unwrap();
+        assert!(matches!(
+            parse(r#"{"type":"cancel_turn"}"#),
+            ClientMessage::CancelTurn { turn_id: None }
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"cancel_turn","turn_id":3}"#),
+            ClientMessage::CancelTurn { turn_id: Some(3) }
+        ));
+
+        let finished = ServerMessage::TurnFinished {
+            turn_id: 3,
+            outcome: TurnOutcome::Cancelled,
+        };
+        assert_eq!(
+            serde_json::to_value(&finished).unwrap(),
+            serde_json::json!({"type": "turn_finished", "turn_id": 3, "outcome": "cancelled"})
+        );
+
+        let full = ServerMessage::Error {
+            message: "Too many messages".to_string(),
+            code: Some(ErrorCode::TurnQueueFull),
+        };
+        assert_eq!(
+            serde_json::to_value(&full).unwrap()["code"],
+            "turn_queue_full"
+        );
+    }
+
+    #[test]
+    fn test_audio_frame_header_round_trip() {
+        let header = AudioFrameHeader {
+            sequence: 0x0102_0304,
+            sample_rate: 24_000,
+            codec: AudioCodec::Pcm16,
+        };
+        let frame = header.encode(&[1, 2, 3, 4]);
+        assert_eq!(frame.len(), AudioFrameHeader::LEN + 4);
+        assert_eq!(&frame[..8], &[1, 0, 0, 0, 4, 3, 2, 1]);
+
+        let (decoded, payload) = AudioFrameHeader::decode(&frame).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(payload, &[1, 2, 3, 4]);
+
+        assert!(AudioFrameHeader::decode(&frame[..4]).is_none());
+        let mut opus = frame.clone();
+        opus[1] = 1;
+        assert_eq!(
+            AudioFrameHeader::decode(&opus).unwrap().0.codec,
+            AudioCodec::Opus
+        );
+        let mut unknown_codec = frame.clone();
+        unknown_codec[1] = 9;
+        assert!(AudioFrameHeader::decode(&unknown_codec).is_none());
+    }
 }
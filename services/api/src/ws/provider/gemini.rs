@@ -1,19 +1,28 @@
 //! Handles the real-time WebSocket connection to Google Gemini for voice interaction.
 
-use super::RealtimeClientEvent;
+use super::{
+    CONNECT_TIMEOUT, ContextTurn, RealtimeClientEvent, RealtimeLink, RealtimeServerEvent,
+    RealtimeTools, Speaker, VOICE_INSTRUCTIONS, VoiceSettings, interrupt_playback,
+    playback::PlaybackTracker, send_voice_status, speak_verbatim_prompt,
+};
 use crate::{
     audio_utils,
+    config::TurnDetection,
     state::AppState,
-    ws::{protocol::ServerMessage, session::send_msg},
+    ws::{
+        protocol::{ServerMessage, VoiceConnectionStatus},
+        session::send_msg,
+    },
 };
-use anyhow::Result;
+use anyhow::{Context, Result, anyhow};
 use axum::extract::ws::{Message, WebSocket};
+use base64::Engine;
 use futures_util::{SinkExt, StreamExt, stream::SplitSink};
-use rubato::Resampler;
+use rmcp::model::Tool;
 use std::sync::Arc;
 use tokio::sync::{Mutex, mpsc};
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as WsMessage};
-use tracing::{error, info, warn};
+use tracing::{error, info};
 
 // --- Local Gemini Realtime Types (for encapsulation) ---
 mod gemini_realtime_types {
@@ -24,6 +33,7 @@ mod gemini_realtime_types {
         Setup(BidiGenerateContentSetup),
         RealtimeInput(BidiGenerateContentRealtimeInput),
         ClientContent(BidiGenerateContentClientContent),
+        ToolResponse(BidiGenerateContentToolResponse),
     }
     #[derive(Serialize)]
     #[serde(rename_all = "camelCase")]
@@ -36,13 +46,64 @@ mod gemini_realtime_types {
     pub(super) struct BidiGenerateContentSetup {
         pub model: String,
         pub generation_config: GenerationConfig,
+        pub system_instruction: Content,
+        pub input_audio_transcription: AudioTranscriptionConfig,
+        pub output_audio_transcription: AudioTranscriptionConfig,
+        pub realtime_input_config: RealtimeInputConfig,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub tools: Vec<GeminiTool>,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct RealtimeInputConfig {
+        pub automatic_activity_detection: AutomaticActivityDetection,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct AutomaticActivityDetection {
+        pub disabled: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub start_of_speech_sensitivity: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub end_of_speech_sensitivity: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub prefix_padding_ms: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub silence_duration_ms: Option<u32>,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct GeminiTool {
+        pub function_declarations: Vec<FunctionDeclaration>,
+    }
+    #[derive(Serialize)]
+    pub(super) struct FunctionDeclaration {
+        pub name: String,
+        pub description: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub parameters: Option<serde_json::Value>,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct BidiGenerateContentToolResponse {
+        pub function_responses: Vec<FunctionResponse>,
+    }
+    #[derive(Serialize)]
+    pub(super) struct FunctionResponse {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        pub name: String,
+        pub response: serde_json::Value,
     }
     #[derive(Serialize)]
     pub(super) struct Content {
-        pub role: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub role: Option<String>,
         pub parts: Vec<Part>,
     }
     #[derive(Serialize)]
+    pub(super) struct AudioTranscriptionConfig {}
+    #[derive(Serialize)]
     pub(super) struct Part {
         pub text: String,
     }
@@ -50,18 +111,40 @@ mod gemini_realtime_types {
     #[serde(rename_all = "camelCase")]
     pub(super) struct GenerationConfig {
         pub response_modalities: Vec<ResponseModality>,
+        pub speech_config: SpeechConfig,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct SpeechConfig {
+        pub voice_config: VoiceConfig,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct VoiceConfig {
+        pub prebuilt_voice_config: PrebuiltVoiceConfig,
+    }
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct PrebuiltVoiceConfig {
+        pub voice_name: String,
     }
     #[derive(Serialize)]
     #[serde(rename_all = "UPPERCASE")]
     pub(super) enum ResponseModality {
-        Text,
         Audio,
     }
     #[derive(Serialize)]
     #[serde(rename_all = "camelCase")]
     pub(super) struct BidiGenerateContentRealtimeInput {
-        pub audio: Blob,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub audio: Option<Blob>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub activity_start: Option<ActivityMarker>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub activity_end: Option<ActivityMarker>,
     }
+    #[derive(Serialize, Default)]
+    pub(super) struct ActivityMarker {}
     #[derive(Serialize)]
     #[serde(rename_all = "camelCase")]
     pub(super) struct Blob {
@@ -73,13 +156,28 @@ mod gemini_realtime_types {
     pub(super) struct ServerMessage {
         pub setup_complete: Option<serde_json::Value>,
         pub server_content: Option<LiveServerContent>,
+        pub tool_call: Option<ToolCall>,
+    }
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub(super) struct ToolCall {
+        pub function_calls: Vec<FunctionCall>,
+    }
+    #[derive(Deserialize, Debug)]
+    pub(super) struct FunctionCall {
+        pub id: Option<String>,
+        pub name: String,
+        #[serde(default)]
+        pub args: serde_json::Value,
     }
     #[derive(Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
     pub(super) struct LiveServerContent {
         pub model_turn: Option<ServerContentTurn>,
         pub input_transcription: Option<ServerTranscription>,
+        pub output_transcription: Option<ServerTranscription>,
         pub turn_complete: Option<bool>,
+        pub interrupted: Option<bool>,
     }
     #[derive(Deserialize, Debug)]
     pub(super) struct ServerContentTurn {
@@ -88,7 +186,6 @@ mod gemini_realtime_types {
     #[derive(Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
     pub(super) struct ServerPart {
-        pub text: Option<String>,
         pub inline_data: Option<ServerBlob>,
     }
     #[derive(Deserialize, Debug)]
@@ -102,96 +199,203 @@ mod gemini_realtime_types {
     }
 }
 
-/// Runs the main loop for the Gemini Realtime API connection.
+/// Converts an MCP tool into a Gemini function declaration.
+///
+/// Gemini accepts an OpenAPI subset of JSON Schema, so schema metadata is dropped
+/// and tools without arguments are declared without parameters.
+fn function_declaration(tool: &Tool) -> gemini_realtime_types::FunctionDeclaration {
+    let mut schema = (*tool.input_schema).clone();
+    schema.remove("$schema");
+    schema.remove("title");
+    let has_properties = schema
+        .get("properties")
+        .and_then(serde_json::Value::as_object)
+        .is_some_and(|properties| !properties.is_empty());
+    gemini_realtime_types::FunctionDeclaration {
+        name: tool.name.to_string(),
+        description: tool.description.as_deref().unwrap_or_default().to_string(),
+        parameters: has_properties.then_some(serde_json::Value::Object(schema)),
+    }
+}
+
+/// Maps the voice settings onto the Live API's activity detection.
+///
+/// The Live API has two sensitivity levels, so the threshold only picks between
+/// them. With server-side turn detection, its own detection is turned off and
+/// activity is signalled explicitly.
+fn activity_detection(settings: &VoiceSettings) -> gemini_realtime_types::RealtimeInputConfig {
+    let automatic_activity_detection = match settings.turn_detection {
+        TurnDetection::Provider => {
+            let level = if settings.vad_threshold > 0.5 {
+                "LOW"
+            } else {
+                "HIGH"
+            };
+            gemini_realtime_types::AutomaticActivityDetection {
+                disabled: false,
+                start_of_speech_sensitivity: Some(format!("START_SENSITIVITY_{}", level)),
+                end_of_speech_sensitivity: Some(format!("END_SENSITIVITY_{}", level)),
+                prefix_padding_ms: Some(settings.vad_prefix_padding_ms),
+                silence_duration_ms: Some(settings.vad_silence_duration_ms),
+            }
+        }
+        TurnDetection::Server | TurnDetection::PushToTalk => {
+            gemini_realtime_types::AutomaticActivityDetection {
+                disabled: true,
+                start_of_speech_sensitivity: None,
+                end_of_speech_sensitivity: None,
+                prefix_padding_ms: None,
+                silence_duration_ms: None,
+            }
+        }
+    };
+    gemini_realtime_types::RealtimeInputConfig {
+        automatic_activity_detection,
+    }
+}
+
+/// Builds a `realtimeInput` message that marks the start or end of user speech.
+fn activity_message(start: bool) -> Result<String> {
+    let input = gemini_realtime_types::BidiGenerateContentRealtimeInput {
+        audio: None,
+        activity_start: start.then(gemini_realtime_types::ActivityMarker::default),
+        activity_end: (!start).then(gemini_realtime_types::ActivityMarker::default),
+    };
+    Ok(serde_json::to_string(
+        &gemini_realtime_types::ClientMessage::RealtimeInput(input),
+    )?)
+}
+
+/// Converts a remembered conversation turn into Gemini content.
+fn context_content(turn: &ContextTurn) -> gemini_realtime_types::Content {
+    let role = match turn.speaker {
+        Speaker::User => "user",
+        Speaker::Assistant => "model",
+    };
+    gemini_realtime_types::Content {
+        role: Some(role.to_string()),
+        parts: vec![gemini_realtime_types::Part {
+            text: turn.text.clone(),
+        }],
+    }
+}
+
+/// Runs the main loop for one Gemini Realtime API connection.
 ///
 /// This function connects to the Gemini WebSocket, handles the specific setup
 /// protocol, and then enters a loop to proxy messages, performing audio
 /// resampling as needed.
+///
+/// The Live API has no switch to stop the model from answering on its own, so
+/// without `tools` model audio is only forwarded while a `TextToSpeak` request is
+/// being voiced. Input transcription arrives in fragments; the accumulated text is
+/// reported on `events_tx` as a user turn once Gemini completes the turn.
+///
+/// With `tools`, the model answers on its own and its function calls are executed
+/// against the agent and returned as tool responses.
+///
+/// Returns `Ok` once the session stops sending events, and an error when the
+/// connection to Gemini fails or drops, so that the caller can reconnect. Client
+/// events are held back until setup completes.
 pub async fn run(
     state: &Arc<AppState>,
-    mut rx: mpsc::Receiver<RealtimeClientEvent>,
+    link: &mut RealtimeLink,
     socket_tx: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    events_tx: &mpsc::Sender<RealtimeServerEvent>,
+    tools: Option<&RealtimeTools>,
 ) -> Result<()> {
-    let api_key = state.config.gemini_api_key.clone().unwrap();
-    let url = format!(
-        "wss://generativelanguage.googleapis.com/ws/google.ai.generativelanguage.v1beta.GenerativeService.BidiGenerateContent?key={}",
-        api_key
-    );
+    let api_key = state
+        .config
+        .realtime_api_key
+        .as_ref()
+        .context("Gemini API key not found")?;
+    let url = format!("{}?key={}", state.config.gemini_live_url, api_key);
 
-    let (ws_stream, _) = connect_async(url).await?;
+    let (ws_stream, _) = link
+        .buffer_while(tokio::time::timeout(CONNECT_TIMEOUT, connect_async(url)))
+        .await
+        .context("Timed out connecting to Gemini Realtime WebSocket")?
+        .context("Failed to connect to Gemini Realtime WebSocket")?;
     info!("Connected to Gemini Realtime WebSocket.");
     let (mut gemini_tx, mut gemini_rx) = ws_stream.split();
 
-    // Create resamplers to convert between frontend and Gemini sample rates.
-    let mut input_resampler = audio_utils::create_resampler(
-        audio_utils::FRONTEND_AUDIO_PLAYER_SAMPLE_RATE,
-        audio_utils::GEMINI_LIVE_API_PCM16_SAMPLE_RATE,
-        512,
-    )?;
-    let mut output_resampler = audio_utils::create_resampler(
-        audio_utils::GEMINI_LIVE_API_PCM16_SAMPLE_RATE,
-        audio_utils::FRONTEND_AUDIO_PLAYER_SAMPLE_RATE,
-        512,
-    )?;
-
     // Send initial setup message.
     let setup_msg = gemini_realtime_types::ClientMessage::Setup(
         gemini_realtime_types::BidiGenerateContentSetup {
-            model: "models/gemini-2.0-flash-exp".to_string(),
+            model: state.config.gemini_live_model.clone(),
             generation_config: gemini_realtime_types::GenerationConfig {
                 response_modalities: vec![gemini_realtime_types::ResponseModality::Audio],
+                speech_config: gemini_realtime_types::SpeechConfig {
+                    voice_config: gemini_realtime_types::VoiceConfig {
+                        prebuilt_voice_config: gemini_realtime_types::PrebuiltVoiceConfig {
+                            voice_name: link.settings().voice.clone(),
+                        },
+                    },
+                },
             },
-        },
-    );
-    gemini_tx
-        .send(WsMessage::Text(serde_json::to_string(&setup_msg)?.into()))
-        .await?;
-
-    // Send the system prompt immediately after setup to complete the handshake.
-    info!("Sending system prompt to Gemini to complete setup.");
-    let system_prompt_turn = gemini_realtime_types::ClientMessage::ClientContent(
-        gemini_realtime_types::BidiGenerateContentClientContent {
-            turns: vec![gemini_realtime_types::Content {
-                role: "system".to_string(),
+            system_instruction: gemini_realtime_types::Content {
+                role: None,
                 parts: vec![gemini_realtime_types::Part {
-                    text: state.system_prompt.to_string(),
+                    text: match tools {
+                        Some(_) => state.system_prompt.to_string(),
+                        None => VOICE_INSTRUCTIONS.to_string(),
+                    },
                 }],
-            }],
-            turn_complete: false, // Keep the turn open for the user to speak
+            },
+            input_audio_transcription: gemini_realtime_types::AudioTranscriptionConfig {},
+            output_audio_transcription: gemini_realtime_types::AudioTranscriptionConfig {},
+            realtime_input_config: activity_detection(link.settings()),
+            tools: tools
+                .iter()
+                .map(|tools| gemini_realtime_types::GeminiTool {
+                    function_declarations: tools.tools().iter().map(function_declaration).collect(),
+                })
+                .collect(),
         },
     );
-    let system_prompt_payload = serde_json::to_string(&system_prompt_turn)?;
     gemini_tx
-        .send(WsMessage::Text(system_prompt_payload.into()))
+        .send(WsMessage::Text(serde_json::to_string(&setup_msg)?.into()))
         .await?;
 
     let mut is_ready = false;
-    loop {
+    // Whether speech is signalled by the server's detector or the client rather than detected by Gemini.
+    let server_turns = link.settings().turn_detection != TurnDetection::Provider;
+    // Whether the current turn was cancelled; the Live API has no way to drop it, so it is ignored.
+    let mut discard_turn = false;
+    // Model turns requested via `TextToSpeak` that have not completed yet.
+    let mut requested_turns = 0usize;
+    // Whether model output was played since the oldest pending request was sent.
+    // Gemini's own turn may complete after a request, so a `turnComplete` only
+    // ends a requested turn once such output has arrived.
+    let mut requested_output = false;
+    // Whether model audio has been forwarded during the current turn.
+    let mut ai_speaking = false;
+    let mut user_speaking = false;
+    let mut pending_transcript = String::new();
+    let mut pending_reply = String::new();
+    let mut playback = PlaybackTracker::default();
+    let disconnected = loop {
         tokio::select! {
-            // Handle events from our application.
-            Some(event) = rx.recv() => {
-                if !is_ready {
-                    warn!("Received client event before Gemini setup was complete. Ignoring.");
-                    continue;
-                }
+            // Handle events from our application once the session is set up.
+            event = link.next_event(), if is_ready => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
                 match event {
                     RealtimeClientEvent::Audio(data) => {
-                        let pcm_i16: Vec<i16> = data.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
-                        let pcm_f32 = audio_utils::convert_i16_to_f32(&pcm_i16);
-                        let input_chunk_size = input_resampler.input_frames_next();
-                        let mut resampled_f32 = Vec::new();
-                        for chunk in pcm_f32.chunks(input_chunk_size) {
-                            if let Ok(res) = input_resampler.process(&[chunk.to_vec()], None) {
-                                resampled_f32.extend_from_slice(&res[0]);
-                            }
+                        // The session already converted the audio to Gemini's input rate.
+                        if data.is_empty() {
+                            continue;
                         }
-                        let base64_data = audio_utils::encode_f32_to_base64_i16(&resampled_f32);
+                        let base64_data = base64::engine::general_purpose::STANDARD.encode(&data);
                         let audio_msg = gemini_realtime_types::ClientMessage::RealtimeInput(
                             gemini_realtime_types::BidiGenerateContentRealtimeInput {
-                                audio: gemini_realtime_types::Blob {
+                                audio: Some(gemini_realtime_types::Blob {
                                     mime_type: "audio/pcm;rate=16000".to_string(),
                                     data: base64_data,
-                                }
+                                }),
+                                activity_start: None,
+                                activity_end: None,
                             }
                         );
                         gemini_tx.send(WsMessage::Text(serde_json::to_string(&audio_msg)?.into())).await?;
@@ -200,18 +404,60 @@ pub async fn run(
                         let tts_msg = gemini_realtime_types::ClientMessage::ClientContent(
                             gemini_realtime_types::BidiGenerateContentClientContent {
                                 turns: vec![gemini_realtime_types::Content {
-                                    role: "model".to_string(),
-                                    parts: vec![gemini_realtime_types::Part { text }],
+                                    role: Some("user".to_string()),
+                                    parts: vec![gemini_realtime_types::Part { text: speak_verbatim_prompt(&text) }],
                                 }],
                                 turn_complete: true,
                             }
                         );
                         gemini_tx.send(WsMessage::Text(serde_json::to_string(&tts_msg)?.into())).await?;
+                        playback.expect_text(&text);
+                        requested_turns += 1;
+                    }
+                    RealtimeClientEvent::UpdateSettings(new_settings) => {
+                        // The Live API fixes the voice and activity detection at setup and has no
+                        // speaking-rate control, so updated settings take effect on the next connection.
+                        info!(?new_settings, "Gemini settings updated; they apply on the next connection.");
+                        let turns_changed = (new_settings.turn_detection == TurnDetection::Provider)
+                            != (link.settings().turn_detection == TurnDetection::Provider);
+                        link.set_settings(new_settings);
+                        // Reconnect right away, or nothing would detect turns in the meantime.
+                        if turns_changed {
+                            break anyhow!("Reconnecting to Gemini to change turn detection");
+                        }
+                    }
+                    // Activity markers are only accepted with automatic detection disabled at setup.
+                    RealtimeClientEvent::SpeechStarted if server_turns => {
+                        gemini_tx.send(WsMessage::Text(activity_message(true)?.into())).await?;
+                        let mut sink = socket_tx.lock().await;
+                        if !std::mem::replace(&mut user_speaking, true) {
+                            send_msg(&mut sink, ServerMessage::UserSpeakingStart).await?;
+                        }
+                        if interrupt_playback(&mut sink, link, &mut playback, &mut ai_speaking, tools.is_none().then_some(events_tx)).await?.is_some() {
+                            requested_turns = 0;
+                            requested_output = false;
+                        }
+                    }
+                    RealtimeClientEvent::SpeechStopped if server_turns => {
+                        gemini_tx.send(WsMessage::Text(activity_message(false)?.into())).await?;
+                        if std::mem::take(&mut user_speaking) {
+                            send_msg(&mut *socket_tx.lock().await, ServerMessage::UserSpeakingEnd).await?;
+                        }
                     }
+                    RealtimeClientEvent::SpeechCancelled if server_turns && user_speaking => {
+                        gemini_tx.send(WsMessage::Text(activity_message(false)?.into())).await?;
+                        user_speaking = false;
+                        discard_turn = true;
+                        send_msg(&mut *socket_tx.lock().await, ServerMessage::UserSpeakingEnd).await?;
+                    }
+                    RealtimeClientEvent::SpeechStarted | RealtimeClientEvent::SpeechStopped | RealtimeClientEvent::SpeechCancelled => {}
                  }
             },
             // Handle events from the Gemini server.
-            Some(msg_result) = gemini_rx.next() => {
+            msg = gemini_rx.next() => {
+                let Some(msg_result) = msg else {
+                    break anyhow!("Gemini WebSocket ended");
+                };
                 match msg_result {
                     Ok(WsMessage::Text(text)) => {
                         if !is_ready {
@@ -222,15 +468,17 @@ pub async fn run(
                                         info!("Gemini session setup is complete. Ready for bidirectional streaming.");
                                         is_ready = true;
 
+                                        // Signal the start of the user turn, restoring the conversation so far on a reconnect.
                                         info!("Signaling start of user turn to Gemini.");
                                         let start_turn_msg = gemini_realtime_types::ClientMessage::ClientContent(
                                             gemini_realtime_types::BidiGenerateContentClientContent {
-                                                turns: vec![],
+                                                turns: link.context().map(context_content).collect(),
                                                 turn_complete: false,
                                             },
                                         );
                                         let start_turn_payload = serde_json::to_string(&start_turn_msg)?;
                                         gemini_tx.send(WsMessage::Text(start_turn_payload.into())).await?;
+                                        send_voice_status(&socket_tx, VoiceConnectionStatus::Connected, 0).await?;
                                     } else {
                                         error!("Received unexpected JSON during Gemini setup: {:?}", gemini_msg);
                                     }
@@ -242,48 +490,197 @@ pub async fn run(
                         } else {
                             // Process regular content messages after setup.
                             if let Ok(gemini_msg) = serde_json::from_str::<gemini_realtime_types::ServerMessage>(&text) {
+                                if let Some(tool_call) = gemini_msg.tool_call
+                                    && let Some(tools) = tools
+                                {
+                                    let mut function_responses = Vec::new();
+                                    for call in tool_call.function_calls {
+                                        let output = if discard_turn {
+                                            "The user cancelled this turn.".to_string()
+                                        } else {
+                                            tools.call(&call.name, call.args).await
+                                        };
+                                        function_responses.push(gemini_realtime_types::FunctionResponse {
+                                            id: call.id,
+                                            name: call.name,
+                                            response: serde_json::json!({ "result": output }),
+                                        });
+                                    }
+                                    let response_msg = gemini_realtime_types::ClientMessage::ToolResponse(
+                                        gemini_realtime_types::BidiGenerateContentToolResponse { function_responses },
+                                    );
+                                    gemini_tx.send(WsMessage::Text(serde_json::to_string(&response_msg)?.into())).await?;
+                                }
                                 let mut sink = socket_tx.lock().await;
                                 if let Some(content) = gemini_msg.server_content {
-                                    if let Some(transcription) = content.input_transcription {
-                                        send_msg(&mut sink, ServerMessage::TranscriptionUpdate { text: transcription.text, is_final: true }).await?;
+                                    // Gemini has no speech-start event, so the first transcribed words mark it.
+                                    let mut user_started = false;
+                                    if let Some(transcription) = content.input_transcription
+                                        && !discard_turn
+                                    {
+                                        if !user_speaking && !server_turns {
+                                            user_speaking = true;
+                                            user_started = true;
+                                            send_msg(&mut sink, ServerMessage::UserSpeakingStart).await?;
+                                        }
+                                        pending_transcript.push_str(&transcription.text);
+                                        send_msg(&mut sink, ServerMessage::TranscriptionUpdate { text: transcription.text, is_final: false }).await?;
+                                    }
+                                    // Gemini cancels generation itself on barge-in; audio it already sent may still be playing.
+                                    if (user_started || content.interrupted == Some(true))
+                                        && interrupt_playback(&mut sink, link, &mut playback, &mut ai_speaking, tools.is_none().then_some(events_tx)).await?.is_some()
+                                    {
+                                        requested_turns = 0;
+                                        requested_output = false;
+                                    }
+                                    // Without tools, autonomous model output is dropped; replies come from the ReAct cycle.
+                                    let plays_model_output = (requested_turns > 0 || tools.is_some()) && !discard_turn;
+                                    if plays_model_output && requested_turns > 0
+                                        && (content.output_transcription.is_some() || content.model_turn.is_some())
+                                    {
+                                        requested_output = true;
+                                    }
+                                    if plays_model_output && let Some(transcription) = &content.output_transcription {
+                                        playback.record_transcript(&transcription.text);
+                                        pending_reply.push_str(&transcription.text);
                                     }
-                                    if let Some(ref model_turn) = content.model_turn {
+                                    if plays_model_output && let Some(ref model_turn) = content.model_turn {
+                                        if !ai_speaking {
+                                            ai_speaking = true;
+                                            send_msg(&mut sink, ServerMessage::AiSpeakingStart).await?;
+                                        }
                                         for part in &model_turn.parts {
                                             if let Some(blob) = &part.inline_data {
-                                                let pcm_f32 = audio_utils::decode_f32_from_base64_i16(&blob.data);
-                                                let output_chunk_size = output_resampler.input_frames_next();
-                                                let mut resampled_f32 = Vec::new();
-                                                for chunk in pcm_f32.chunks(output_chunk_size) {
-                                                    if let Ok(res) = output_resampler.process(&[chunk.to_vec()], None) {
-                                                        resampled_f32.extend_from_slice(&res[0]);
-                                                    }
-                                                }
-                                                let resampled_base64 = audio_utils::encode_f32_to_base64_i16(&resampled_f32);
-                                                send_msg(&mut sink, ServerMessage::AudioChunk { data: resampled_base64 }).await?;
+                                                let samples = audio_utils::decode_i16(&blob.data);
+                                                playback.record_audio(samples.len(), audio_utils::GEMINI_LIVE_API_PCM16_SAMPLE_RATE);
+                                                link.audio().send(&mut sink, &samples, audio_utils::GEMINI_LIVE_API_PCM16_SAMPLE_RATE).await?;
                                             }
                                         }
                                     }
-                                     if content.turn_complete == Some(true) {
-                                        send_msg(&mut sink, ServerMessage::AiSpeakingEnd).await?;
-                                     } else if content.model_turn.is_some() {
-                                        send_msg(&mut sink, ServerMessage::AiSpeakingStart).await?;
-                                     }
+                                    if content.turn_complete == Some(true) {
+                                        discard_turn = false;
+                                        let transcript = std::mem::take(&mut pending_transcript);
+                                        link.remember(Speaker::User, &transcript);
+                                        link.remember(Speaker::Assistant, &std::mem::take(&mut pending_reply));
+                                        if !transcript.trim().is_empty() {
+                                            send_msg(&mut sink, ServerMessage::TranscriptionUpdate { text: transcript.clone(), is_final: true }).await?;
+                                            if tools.is_none() {
+                                                events_tx.send(RealtimeServerEvent::UserTranscript(transcript)).await?;
+                                            }
+                                        }
+                                        if std::mem::take(&mut user_speaking) {
+                                            send_msg(&mut sink, ServerMessage::UserSpeakingEnd).await?;
+                                        }
+                                        let requested_turn_ended = std::mem::take(&mut requested_output);
+                                        if requested_turn_ended {
+                                            requested_turns -= 1;
+                                        }
+                                        if requested_turn_ended || ai_speaking {
+                                            ai_speaking = false;
+                                            link.audio().finish(&mut sink).await?;
+                                            send_msg(&mut sink, ServerMessage::AiSpeakingEnd).await?;
+                                        }
+                                    }
                                 }
                             }
                         }
                     },
                     Ok(WsMessage::Close(close_frame)) => {
-                        error!(?close_frame, "Gemini WebSocket connection closed by server.");
-                        break;
+                        break anyhow!("Gemini closed the connection: {:?}", close_frame);
                     }
                     Err(e) => {
-                        error!("Error reading from Gemini WebSocket: {}", e);
-                        break;
+                        break anyhow!(e).context("Error reading from Gemini WebSocket");
                     }
                     _ => {}
                 }
             },
         }
+    };
+
+    // The turn in progress is lost with the connection.
+    link.audio().discard();
+    if ai_speaking {
+        send_msg(&mut *socket_tx.lock().await, ServerMessage::AiSpeakingEnd).await?;
+    }
+    Err(disconnected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(schema: serde_json::Value) -> Tool {
+        Tool::new(
+            "update_subtopic_status",
+            "Updates a subtopic.",
+            Arc::new(schema.as_object().unwrap().clone()),
+        )
+    }
+
+    #[test]
+    fn test_activity_detection_follows_settings() {
+        let mut settings = VoiceSettings::from_config(&crate::config::Config::for_tests());
+        let config = serde_json::to_value(activity_detection(&settings)).unwrap();
+        let detection = &config["automaticActivityDetection"];
+        assert_eq!(detection["disabled"], false);
+        assert_eq!(
+            detection["startOfSpeechSensitivity"],
+            "START_SENSITIVITY_HIGH"
+        );
+        assert_eq!(detection["silenceDurationMs"], 700);
+
+        settings.turn_detection = TurnDetection::Server;
+        let config = serde_json::to_value(activity_detection(&settings)).unwrap();
+        assert_eq!(
+            config["automaticActivityDetection"],
+            serde_json::json!({ "disabled": true })
+        );
+
+        let start: serde_json::Value =
+            serde_json::from_str(&activity_message(true).unwrap()).unwrap();
+        assert_eq!(
+            start,
+            serde_json::json!({ "realtimeInput": { "activityStart": {} } })
+        );
+        let end: serde_json::Value =
+            serde_json::from_str(&activity_message(false).unwrap()).unwrap();
+        assert_eq!(
+            end,
+            serde_json::json!({ "realtimeInput": { "activityEnd": {} } })
+        );
+    }
+
+    #[test]
+    fn test_context_content_roles() {
+        let turn = |speaker| ContextTurn {
+            speaker,
+            text: "Nodes hold values.".to_string(),
+        };
+        let user = serde_json::to_value(context_content(&turn(Speaker::User))).unwrap();
+        assert_eq!(user["role"], "user");
+        assert_eq!(user["parts"][0]["text"], "Nodes hold values.");
+        let model = serde_json::to_value(context_content(&turn(Speaker::Assistant))).unwrap();
+        assert_eq!(model["role"], "model");
+    }
+
+    #[test]
+    fn test_function_declaration_strips_schema_metadata() {
+        let declaration = function_declaration(&tool(serde_json::json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "UpdateSubtopicStatusArgs",
+            "type": "object",
+            "properties": { "subtopic_name": { "type": "string" } },
+        })));
+        let parameters = declaration.parameters.unwrap();
+        assert_eq!(declaration.name, "update_subtopic_status");
+        assert!(parameters.get("$schema").is_none());
+        assert!(parameters.get("title").is_none());
+        assert_eq!(parameters["properties"]["subtopic_name"]["type"], "string");
+
+        let no_args = function_declaration(&tool(serde_json::json!({
+            "type": "object",
+            "properties": {},
+        })));
+        assert!(no_args.parameters.is_none());
     }
-    Ok(())
 }
@@ -2,19 +2,56 @@
 
 pub mod gemini;
 pub mod openai;
+pub mod playback;
 
-use super::{protocol::ServerMessage, session::send_msg};
-use crate::{config::Provider, state::AppState};
+use super::{
+    protocol::{
+        AudioCodec, AudioFrameHeader, AudioTransport, CaptureFormat, ServerMessage,
+        VoiceConnectionStatus, VoiceSettingsUpdate,
+    },
+    session::send_msg,
+};
+use crate::{
+    audio_utils,
+    config::{self, Config, RealtimeProvider, TurnDetection},
+    models::MessageRole,
+    recordings::Recorder,
+    state::AppState,
+    usage::AudioMeter,
+};
 use anyhow::Result;
 use axum::extract::ws::{Message, WebSocket};
+use base64::Engine;
 use bytes::Bytes;
-use futures_util::stream::SplitSink;
-use std::sync::Arc;
+use feynman_core::tools::ToolRegistry;
+use futures_util::{SinkExt, stream::SplitSink};
+use rmcp::model::Tool;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
-    sync::{mpsc, Mutex},
+    sync::{Mutex, mpsc},
     task::JoinHandle,
 };
-use tracing::error;
+use tracing::{error, info, warn};
+
+/// How long to wait for a provider WebSocket handshake.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Reconnect attempts after a provider connection drops, before giving up.
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+/// The delay before the first reconnect attempt, doubled for each further attempt.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+/// The longest delay between reconnect attempts.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(8);
+/// A connection that stayed up this long resets the reconnect attempt count.
+const STABLE_CONNECTION: Duration = Duration::from_secs(30);
+/// User audio kept while reconnecting: 10 seconds of 24kHz PCM16, longer at lower
+/// provider rates. Older audio is dropped.
+const MAX_BUFFERED_AUDIO_BYTES: usize = 480_000;
+/// The number of recent conversation turns replayed to a reconnected provider.
+const CONTEXT_TURNS: usize = 10;
 
 /// An internal event passed to the active real-time provider task.
 #[derive(Debug)]
@@ -23,6 +60,632 @@ pub enum RealtimeClientEvent {
     Audio(Bytes),
     /// Text that the AI should speak.
     TextToSpeak(String),
+    /// New voice settings to apply to the live provider session.
+    UpdateSettings(VoiceSettings),
+    /// The user started speaking, as heard by the server's voice activity detector
+    /// or marked by the client with push-to-talk.
+    SpeechStarted,
+    /// The user stopped speaking, ending the turn.
+    SpeechStopped,
+    /// The user cancelled a push-to-talk utterance; its audio must not become a turn.
+    SpeechCancelled,
+}
+
+/// An internal event passed from the real-time provider task back to the session loop.
+#[derive(Debug)]
+pub enum RealtimeServerEvent {
+    /// The final transcript of a user utterance, to be handled as a user turn.
+    UserTranscript(String),
+    /// The user interrupted the spoken reply; only part of it was heard.
+    Interrupted(playback::Interruption),
+}
+
+/// Session instructions for the realtime model.
+///
+/// Replies come from the ReAct cycle, so the realtime model only voices text it is given.
+pub(crate) const VOICE_INSTRUCTIONS: &str = "You are the voice of a curious student. \
+Never reply to the user on your own initiative. \
+When asked to speak a text, read it aloud exactly as written, without adding, removing or rephrasing anything.";
+
+/// Wraps an agent reply in a request to read it aloud verbatim.
+pub(crate) fn speak_verbatim_prompt(text: &str) -> String {
+    format!(
+        "Read the following text aloud exactly as written:\n\n{}",
+        text
+    )
+}
+
+/// Voice and turn-detection settings for a realtime provider session.
+///
+/// Defaults come from `Config`; clients can override them per session with
+/// `ClientMessage::UpdateVoiceSettings`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoiceSettings {
+    /// The provider-specific voice name.
+    pub voice: String,
+    /// The speaking rate multiplier.
+    pub speaking_rate: f32,
+    /// The VAD activation threshold.
+    pub vad_threshold: f32,
+    /// Audio to include before detected speech, in milliseconds.
+    pub vad_prefix_padding_ms: u32,
+    /// Silence required to end a turn, in milliseconds.
+    pub vad_silence_duration_ms: u32,
+    /// Whether the provider or the server detects turns.
+    pub turn_detection: TurnDetection,
+}
+
+impl VoiceSettings {
+    /// Builds the default settings for the configured provider.
+    pub fn from_config(config: &Config) -> Self {
+        let voice = match config.realtime_provider {
+            RealtimeProvider::OpenAI => config.openai_realtime_voice.clone(),
+            RealtimeProvider::Gemini => config.gemini_live_voice.clone(),
+        };
+        Self {
+            voice,
+            speaking_rate: config.realtime_speaking_rate,
+            vad_threshold: config.vad_threshold,
+            vad_prefix_padding_ms: config.vad_prefix_padding_ms,
+            vad_silence_duration_ms: config.vad_silence_duration_ms,
+            turn_detection: config.turn_detection,
+        }
+    }
+
+    /// Validates and merges a client update. On error, the settings are left unchanged.
+    pub fn apply(
+        &mut self,
+        provider: &RealtimeProvider,
+        update: VoiceSettingsUpdate,
+    ) -> std::result::Result<(), String> {
+        if let (RealtimeProvider::OpenAI, Some(voice)) = (provider, &update.voice) {
+            config::validate_openai_voice(voice)?;
+        }
+        if let Some(rate) = update.speaking_rate {
+            config::validate_speaking_rate(rate)?;
+        }
+        if let Some(threshold) = update.vad_threshold {
+            config::validate_vad_threshold(threshold)?;
+        }
+
+        if let Some(voice) = update.voice {
+            self.voice = voice;
+        }
+        if let Some(rate) = update.speaking_rate {
+            self.speaking_rate = rate;
+        }
+        if let Some(threshold) = update.vad_threshold {
+            self.vad_threshold = threshold;
+        }
+        if let Some(padding) = update.vad_prefix_padding_ms {
+            self.vad_prefix_padding_ms = padding;
+        }
+        if let Some(silence) = update.vad_silence_duration_ms {
+            self.vad_silence_duration_ms = silence;
+        }
+        if let Some(turn_detection) = update.turn_detection {
+            self.turn_detection = turn_detection;
+        }
+        Ok(())
+    }
+}
+
+/// The agent's tools, exposed to the realtime model for direct function calling.
+///
+/// Calls go through the session's tool registry, so they act on the same
+/// `FeynmanAgent` as the ReAct cycle and emit the same state updates.
+#[derive(Clone)]
+pub struct RealtimeTools {
+    registry: Arc<dyn ToolRegistry>,
+}
+
+impl RealtimeTools {
+    pub fn new(registry: Arc<dyn ToolRegistry>) -> Self {
+        Self { registry }
+    }
+
+    /// The tool declarations to advertise to the provider.
+    pub fn tools(&self) -> &[Tool] {
+        self.registry.tools()
+    }
+
+    /// Executes a function call from the realtime model.
+    ///
+    /// Failures are reported back to the model as a JSON error rather than ending
+    /// the voice session.
+    pub async fn call(&self, name: &str, arguments: serde_json::Value) -> String {
+        info!(tool = name, "Executing realtime function call.");
+        match self.try_call(name, arguments).await {
+            Ok(output) => output,
+            Err(e) => {
+                warn!(tool = name, error = ?e, "Realtime function call failed.");
+                serde_json::json!({ "error": e.to_string() }).to_string()
+            }
+        }
+    }
+
+    async fn try_call(&self, name: &str, arguments: serde_json::Value) -> Result<String> {
+        let arguments = match arguments {
+            serde_json::Value::Object(map) => Some(map),
+            serde_json::Value::Null => None,
+            other => anyhow::bail!("Tool arguments must be an object, got {}", other),
+        };
+        self.registry.call(name, arguments).await
+    }
+}
+
+/// The sample rate of the PCM16 audio a provider takes as input, in Hz.
+pub fn input_sample_rate(provider: &RealtimeProvider) -> f64 {
+    match provider {
+        RealtimeProvider::OpenAI => audio_utils::OPENAI_REALTIME_API_PCM16_SAMPLE_RATE,
+        RealtimeProvider::Gemini => audio_utils::GEMINI_LIVE_API_PCM16_SAMPLE_RATE,
+    }
+}
+
+/// Converts the browser's microphone audio into the PCM16 the provider takes,
+/// and detects speech in it when the server handles turn detection.
+///
+/// With push-to-talk, audio outside an utterance marked by the client is dropped.
+pub struct AudioInput {
+    decoder: Option<audio_utils::OpusPcmDecoder>,
+    converter: audio_utils::PcmConverter,
+    sample_rate: f64,
+    vad: Option<audio_utils::VoiceActivityDetector>,
+    push_to_talk: bool,
+    utterance_open: bool,
+}
+
+impl AudioInput {
+    /// Creates a converter for the codec negotiated at `init` and the capture
+    /// format declared when voice was enabled.
+    pub fn new(
+        codec: AudioCodec,
+        capture: CaptureFormat,
+        provider: &RealtimeProvider,
+    ) -> Result<Self> {
+        let output_rate = input_sample_rate(provider);
+        let (decoder, converter) = match codec {
+            AudioCodec::Pcm16 => (
+                None,
+                audio_utils::PcmConverter::new(
+                    capture.sample_format,
+                    capture.channels,
+                    capture.sample_rate,
+                    output_rate,
+                )?,
+            ),
+            // Opus packets are decoded straight to the provider's rate.
+            AudioCodec::Opus => (
+                Some(audio_utils::OpusPcmDecoder::new(output_rate)?),
+                audio_utils::PcmConverter::new(
+                    audio_utils::SampleFormat::Pcm16,
+                    1,
+                    output_rate as u32,
+                    output_rate,
+                )?,
+            ),
+        };
+        Ok(Self {
+            decoder,
+            converter,
+            sample_rate: output_rate,
+            vad: None,
+            push_to_talk: false,
+            utterance_open: false,
+        })
+    }
+
+    /// Starts, updates or stops server-side voice activity detection for the settings.
+    pub fn configure(&mut self, settings: &VoiceSettings) {
+        self.push_to_talk = settings.turn_detection == TurnDetection::PushToTalk;
+        if !self.push_to_talk {
+            self.utterance_open = false;
+        }
+        match (settings.turn_detection, &mut self.vad) {
+            (TurnDetection::Provider | TurnDetection::PushToTalk, _) => self.vad = None,
+            (TurnDetection::Server, Some(vad)) => {
+                vad.configure(settings.vad_threshold, settings.vad_silence_duration_ms)
+            }
+            (TurnDetection::Server, None) => {
+                self.vad = Some(audio_utils::VoiceActivityDetector::new(
+                    self.sample_rate,
+                    settings.vad_threshold,
+                    settings.vad_silence_duration_ms,
+                ))
+            }
+        }
+    }
+
+    /// Converts one binary frame from the browser into little-endian PCM16 bytes,
+    /// along with the speech changes the server detected in it.
+    pub fn convert(&mut self, data: &[u8]) -> Result<(Bytes, Vec<audio_utils::VadEvent>)> {
+        let samples = match &mut self.decoder {
+            Some(decoder) => decoder.decode(data)?,
+            None => self.converter.convert(data)?,
+        };
+        if self.push_to_talk && !self.utterance_open {
+            return Ok((Bytes::new(), Vec::new()));
+        }
+        let events = match &mut self.vad {
+            Some(vad) => vad.process(&samples),
+            None => Vec::new(),
+        };
+        Ok((audio_utils::i16_to_le_bytes(&samples).into(), events))
+    }
+
+    /// Opens or closes a push-to-talk utterance, returning whether this changed it.
+    pub fn mark_utterance(&mut self, open: bool) -> Result<bool> {
+        anyhow::ensure!(
+            self.push_to_talk,
+            "Push-to-talk needs turn_detection set to 'push_to_talk'."
+        );
+        Ok(std::mem::replace(&mut self.utterance_open, open) != open)
+    }
+}
+
+/// Stops forwarding AI audio the user has talked over, if any is still playing,
+/// and tells the browser to drop what it has queued.
+///
+/// Returns what the user heard. Without realtime tools, the session is told too,
+/// so it can truncate the stored reply.
+pub(crate) async fn interrupt_playback(
+    sink: &mut SplitSink<WebSocket, Message>,
+    link: &mut RealtimeLink,
+    playback: &mut playback::PlaybackTracker,
+    ai_speaking: &mut bool,
+    events_tx: Option<&mpsc::Sender<RealtimeServerEvent>>,
+) -> Result<Option<playback::Interruption>> {
+    let Some(interruption) = playback.interrupt() else {
+        return Ok(None);
+    };
+    info!(
+        played_ms = interruption.played_ms,
+        "User interrupted the AI response."
+    );
+    link.audio().discard();
+    send_msg(sink, ServerMessage::Interrupted).await?;
+    if std::mem::take(ai_speaking) {
+        send_msg(sink, ServerMessage::AiSpeakingEnd).await?;
+    }
+    if let Some(events_tx) = events_tx {
+        events_tx
+            .send(RealtimeServerEvent::Interrupted(interruption.clone()))
+            .await?;
+    }
+    Ok(Some(interruption))
+}
+
+/// Delivers AI audio to the browser in the transport and codec negotiated at
+/// `init`, at the playback sample rate declared when voice was enabled.
+pub struct AudioOutput {
+    transport: AudioTransport,
+    codec: AudioCodec,
+    sample_rate: u32,
+    resampler: Option<(f64, audio_utils::StreamingResampler)>,
+    encoder: Option<audio_utils::OpusPcmEncoder>,
+    sequence: u32,
+    recorder: Option<Recorder>,
+    meter: Option<AudioMeter>,
+}
+
+impl AudioOutput {
+    pub fn new(transport: AudioTransport, codec: AudioCodec, sample_rate: u32) -> Result<Self> {
+        anyhow::ensure!(
+            audio_utils::SUPPORTED_SAMPLE_RATES.contains(&sample_rate),
+            "Unsupported playback sample rate {sample_rate} Hz"
+        );
+        let encoder = match codec {
+            AudioCodec::Pcm16 => None,
+            AudioCodec::Opus => Some(audio_utils::OpusPcmEncoder::new(sample_rate as f64)?),
+        };
+        Ok(Self {
+            transport,
+            codec,
+            sample_rate,
+            resampler: None,
+            encoder,
+            sequence: 0,
+            recorder: None,
+            meter: None,
+        })
+    }
+
+    /// Also records the AI audio sent, storing each spoken reply as a clip.
+    pub fn with_recorder(mut self, recorder: Option<Recorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Also counts the AI audio received, for usage accounting.
+    pub fn with_meter(mut self, meter: AudioMeter) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Whether the metered audio has used up its budget of voice minutes.
+    pub fn exhausted(&self) -> bool {
+        self.meter.as_ref().is_some_and(AudioMeter::exhausted)
+    }
+
+    /// Sends a chunk of PCM16 audio produced at `source_rate`, resampled to the
+    /// playback rate.
+    ///
+    /// Audio that does not fill a whole resampler chunk or Opus packet is held
+    /// back until more arrives or [`AudioOutput::finish`] is called.
+    pub async fn send(
+        &mut self,
+        sink: &mut SplitSink<WebSocket, Message>,
+        samples: &[i16],
+        source_rate: f64,
+    ) -> Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(MessageRole::Ai, samples, source_rate);
+        }
+        if let Some(meter) = &self.meter {
+            meter.add_output(samples.len(), source_rate);
+        }
+        let samples = if source_rate == self.sample_rate as f64 {
+            samples.to_vec()
+        } else {
+            let resampler = match &mut self.resampler {
+                Some((rate, resampler)) if *rate == source_rate => resampler,
+                resampler => {
+                    let created = audio_utils::StreamingResampler::new(
+                        source_rate,
+                        self.sample_rate as f64,
+                        512,
+                    )?;
+                    &mut resampler.insert((source_rate, created)).1
+                }
+            };
+            let resampled = resampler.process(&audio_utils::convert_i16_to_f32(samples))?;
+            audio_utils::convert_f32_to_i16(&resampled)
+        };
+        self.send_pcm(sink, &samples).await
+    }
+
+    /// Sends any audio held back by the resampler or encoder, at the end of a
+    /// spoken response.
+    pub async fn finish(&mut self, sink: &mut SplitSink<WebSocket, Message>) -> Result<()> {
+        if let Some((_, resampler)) = &mut self.resampler {
+            let tail = audio_utils::convert_f32_to_i16(&resampler.flush()?);
+            self.send_pcm(sink, &tail).await?;
+        }
+        if let Some(packet) = self
+            .encoder
+            .as_mut()
+            .map(|e| e.flush())
+            .transpose()?
+            .flatten()
+        {
+            self.send_payload(sink, packet).await?;
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.finish_ai();
+        }
+        Ok(())
+    }
+
+    /// Drops audio held back by the resampler or encoder, when a response is cut short.
+    /// The recording keeps what was sent before.
+    pub fn discard(&mut self) {
+        if let Some(recorder) = &self.recorder {
+            recorder.finish_ai();
+        }
+        if let Some((_, resampler)) = &mut self.resampler {
+            resampler.reset();
+        }
+        if let Some(encoder) = &mut self.encoder {
+            encoder.discard();
+        }
+    }
+
+    /// Encodes and sends PCM16 audio that is already at the playback rate.
+    async fn send_pcm(
+        &mut self,
+        sink: &mut SplitSink<WebSocket, Message>,
+        samples: &[i16],
+    ) -> Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        match &mut self.encoder {
+            Some(encoder) => {
+                for packet in encoder.encode(samples)? {
+                    self.send_payload(sink, packet).await?;
+                }
+                Ok(())
+            }
+            None => {
+                self.send_payload(sink, audio_utils::i16_to_le_bytes(samples))
+                    .await
+            }
+        }
+    }
+
+    async fn send_payload(
+        &mut self,
+        sink: &mut SplitSink<WebSocket, Message>,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        match self.transport {
+            AudioTransport::Json => {
+                send_msg(
+                    sink,
+                    ServerMessage::AudioChunk {
+                        data: base64::engine::general_purpose::STANDARD.encode(payload),
+                    },
+                )
+                .await
+            }
+            AudioTransport::Binary => {
+                let header = AudioFrameHeader {
+                    sequence: self.sequence,
+                    sample_rate: self.sample_rate,
+                    codec: self.codec,
+                };
+                self.sequence = self.sequence.wrapping_add(1);
+                sink.send(Message::Binary(header.encode(&payload).into()))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Who spoke a conversation turn replayed to the provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speaker {
+    User,
+    Assistant,
+}
+
+/// A transcribed conversation turn, kept to restore context after a reconnect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextTurn {
+    pub speaker: Speaker,
+    pub text: String,
+}
+
+/// The provider-independent side of a voice connection, which outlives the
+/// provider WebSocket across reconnects.
+///
+/// It owns the receiver for client events, the current voice settings, the
+/// audio output to the browser, the recent conversation, and the events received
+/// while no provider connection was available, which are replayed once one is.
+pub struct RealtimeLink {
+    rx: mpsc::Receiver<RealtimeClientEvent>,
+    settings: VoiceSettings,
+    audio: AudioOutput,
+    pending: VecDeque<RealtimeClientEvent>,
+    pending_audio_bytes: usize,
+    context: VecDeque<ContextTurn>,
+}
+
+impl RealtimeLink {
+    fn new(
+        rx: mpsc::Receiver<RealtimeClientEvent>,
+        settings: VoiceSettings,
+        audio: AudioOutput,
+    ) -> Self {
+        Self {
+            rx,
+            settings,
+            audio,
+            pending: VecDeque::new(),
+            pending_audio_bytes: 0,
+            context: VecDeque::new(),
+        }
+    }
+
+    /// The voice settings to set up the next provider session with.
+    pub fn settings(&self) -> &VoiceSettings {
+        &self.settings
+    }
+
+    /// Records settings applied to the live session, so a reconnect keeps them.
+    pub fn set_settings(&mut self, settings: VoiceSettings) {
+        self.settings = settings;
+    }
+
+    /// The output for AI audio sent to the browser.
+    pub fn audio(&mut self) -> &mut AudioOutput {
+        &mut self.audio
+    }
+
+    /// The recent conversation, oldest turn first.
+    pub fn context(&self) -> impl Iterator<Item = &ContextTurn> {
+        self.context.iter()
+    }
+
+    /// Adds a transcribed turn to the recent conversation.
+    pub fn remember(&mut self, speaker: Speaker, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        if self.context.len() == CONTEXT_TURNS {
+            self.context.pop_front();
+        }
+        self.context.push_back(ContextTurn {
+            speaker,
+            text: text.to_string(),
+        });
+    }
+
+    /// Returns the next client event, replaying buffered events first.
+    ///
+    /// Returns `None` once the session has dropped its sender, or once the
+    /// session's voice minutes are used up.
+    pub async fn next_event(&mut self) -> Option<RealtimeClientEvent> {
+        if self.audio.exhausted() {
+            return None;
+        }
+        if let Some(event) = self.pending.pop_front() {
+            if let RealtimeClientEvent::Audio(data) = &event {
+                self.pending_audio_bytes -= data.len();
+            }
+            return Some(event);
+        }
+        self.rx.recv().await
+    }
+
+    /// Drives `future` to completion, buffering the client events that arrive
+    /// meanwhile so that the session loop never blocks on a full channel.
+    pub async fn buffer_while<F: Future>(&mut self, future: F) -> F::Output {
+        tokio::pin!(future);
+        loop {
+            tokio::select! {
+                output = &mut future => return output,
+                Some(event) = self.rx.recv() => self.buffer(event),
+            }
+        }
+    }
+
+    /// Keeps an event for the next provider connection. Settings updates apply to
+    /// it directly; audio beyond the buffer limit drops the oldest chunks.
+    fn buffer(&mut self, event: RealtimeClientEvent) {
+        match event {
+            RealtimeClientEvent::UpdateSettings(settings) => self.settings = settings,
+            RealtimeClientEvent::Audio(data) => {
+                self.pending_audio_bytes += data.len();
+                self.pending.push_back(RealtimeClientEvent::Audio(data));
+                while self.pending_audio_bytes > MAX_BUFFERED_AUDIO_BYTES {
+                    let Some(oldest) = self
+                        .pending
+                        .iter()
+                        .position(|event| matches!(event, RealtimeClientEvent::Audio(_)))
+                    else {
+                        break;
+                    };
+                    if let Some(RealtimeClientEvent::Audio(data)) = self.pending.remove(oldest) {
+                        self.pending_audio_bytes -= data.len();
+                    }
+                }
+            }
+            event => self.pending.push_back(event),
+        }
+    }
+}
+
+/// The delay before the given reconnect attempt (starting at 1).
+fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RECONNECT_MAX_DELAY)
+}
+
+/// Sends a voice connectivity update to the browser.
+pub(crate) async fn send_voice_status(
+    socket_tx: &Mutex<SplitSink<WebSocket, Message>>,
+    status: VoiceConnectionStatus,
+    attempt: u32,
+) -> Result<()> {
+    send_msg(
+        &mut *socket_tx.lock().await,
+        ServerMessage::VoiceStatus { status, attempt },
+    )
+    .await
 }
 
 /// Starts a new task for the configured real-time provider (OpenAI or Gemini).
@@ -30,6 +693,17 @@ pub enum RealtimeClientEvent {
 /// This function sets up a channel for communication and spawns a Tokio task
 /// that will run the provider-specific logic.
 ///
+/// AI audio reaches the browser through `audio`, in the negotiated transport and codec.
+///
+/// Final user transcripts are reported on `events_tx` so that the session loop can
+/// run them through the ReAct cycle. When `tools` is given, the realtime model
+/// instead answers on its own and calls the agent's tools directly.
+///
+/// When the provider connection drops, the task reconnects with exponential
+/// backoff, re-sends the session setup and recent conversation, and replays the
+/// user audio received in the meantime. Connectivity is reported to the browser
+/// with `ServerMessage::VoiceStatus`.
+///
 /// # Returns
 /// A tuple containing:
 /// 1. A `mpsc::Sender` to send `RealtimeClientEvent`s to the provider task.
@@ -37,27 +711,896 @@ pub enum RealtimeClientEvent {
 pub async fn start_realtime_provider(
     state: Arc<AppState>,
     socket_tx: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    settings: VoiceSettings,
+    audio: AudioOutput,
+    events_tx: mpsc::Sender<RealtimeServerEvent>,
+    tools: Option<RealtimeTools>,
 ) -> Result<(mpsc::Sender<RealtimeClientEvent>, JoinHandle<()>)> {
     let (tx, rx) = mpsc::channel(128);
-    let provider_config = state.config.provider.clone();
+    let provider_config = state.config.realtime_provider.clone();
 
     let handle = tokio::spawn(async move {
-        let result = match provider_config {
-            Provider::OpenAI => openai::run(&state, rx, socket_tx.clone()).await,
-            Provider::Gemini => gemini::run(&state, rx, socket_tx.clone()).await,
-        };
-        if let Err(e) = result {
-            error!(?provider_config, error = ?e, "Realtime provider task failed");
-            let mut sink = socket_tx.lock().await;
-            let _ = send_msg(
-                &mut sink,
-                ServerMessage::Error {
-                    message: format!("Voice connection failed: {}", e),
+        let mut link = RealtimeLink::new(rx, settings, audio);
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let result = match provider_config {
+                RealtimeProvider::OpenAI => {
+                    openai::run(
+                        &state,
+                        &mut link,
+                        socket_tx.clone(),
+                        &events_tx,
+                        tools.as_ref(),
+                    )
+                    .await
+                }
+                RealtimeProvider::Gemini => {
+                    gemini::run(
+                        &state,
+                        &mut link,
+                        socket_tx.clone(),
+                        &events_tx,
+                        tools.as_ref(),
+                    )
+                    .await
+                }
+            };
+            let e = match result {
+                // Voice ran out of minutes rather than being turned off.
+                Ok(()) if link.audio().exhausted() => {
+                    let exceeded = state.quotas.voice_exceeded();
+                    warn!("Voice minutes used up; stopping the realtime provider.");
+                    let _ =
+                        send_voice_status(&socket_tx, VoiceConnectionStatus::Disconnected, 0).await;
+                    let mut sink = socket_tx.lock().await;
+                    let _ = send_msg(
+                        &mut sink,
+                        ServerMessage::Error {
+                            message: exceeded.to_string(),
+                            code: Some(exceeded.code()),
+                        },
+                    )
+                    .await;
+                    break;
+                }
+                // The session stopped sending events, so voice was turned off.
+                Ok(()) => break,
+                Err(e) => e,
+            };
+
+            if started.elapsed() >= STABLE_CONNECTION {
+                attempt = 0;
+            }
+            attempt += 1;
+            if attempt > MAX_RECONNECT_ATTEMPTS {
+                error!(?provider_config, error = ?e, "Realtime provider task failed");
+                let _ = send_voice_status(&socket_tx, VoiceConnectionStatus::Disconnected, 0).await;
+                let mut sink = socket_tx.lock().await;
+                let _ = send_msg(
+                    &mut sink,
+                    ServerMessage::Error {
+                        message: format!("Voice connection failed: {}", e),
+                        code: None,
+                    },
+                )
+                .await;
+                break;
+            }
+
+            let delay = reconnect_delay(attempt);
+            warn!(?provider_config, error = ?e, attempt, ?delay, "Realtime provider connection lost; reconnecting.");
+            if send_voice_status(&socket_tx, VoiceConnectionStatus::Reconnecting, attempt)
+                .await
+                .is_err()
+            {
+                break;
+            }
+            link.buffer_while(tokio::time::sleep(delay)).await;
+        }
+    });
+
+    Ok((tx, handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::Db,
+        fake_realtime::{self, FakeRealtimeConfig, FakeToolCall},
+        quota::Quotas,
+    };
+    use async_openai::config::OpenAIConfig;
+    use axum::{Router, extract::WebSocketUpgrade, routing::get};
+    use feynman_core::{
+        agent::{FeynmanAgent, FeynmanService},
+        curriculum::MockCurriculumService,
+        llm_client::OpenAICompatibleClient,
+        topic::SubTopic,
+    };
+    use futures_util::StreamExt;
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as WsMessage};
+
+    fn test_state(provider: RealtimeProvider, fake_base: &str) -> Arc<AppState> {
+        let config = Config {
+            realtime_provider: provider,
+            openai_realtime_url: format!("{}/v1/realtime", fake_base),
+            gemini_live_url: format!("{}/ws/gemini", fake_base),
+            ..Config::for_tests()
+        };
+        Arc::new(AppState {
+            db: Arc::new(Db::new(
+                sqlx::PgPool::connect_lazy(&config.database_url).unwrap(),
+            )),
+            curriculum_service: Arc::new(MockCurriculumService),
+            llm_client: Arc::new(OpenAICompatibleClient::new(
+                OpenAIConfig::new(),
+                config.chat.model.clone(),
+            )),
+            system_prompt: Arc::new("You are a curious student.".to_string()),
+            quotas: Arc::new(Quotas::new(config.quotas)),
+            config: Arc::new(config),
+            recordings: None,
+            external_tools: vec![],
+        })
+    }
+
+    /// Starts the fake provider plus a browser-facing socket that runs the
+    /// realtime provider task, returning the browser client, the event sender
+    /// and the receiver for events the session loop would handle.
+    async fn connect_voice_path(
+        provider: RealtimeProvider,
+    ) -> (
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        mpsc::Sender<RealtimeClientEvent>,
+        mpsc::Receiver<RealtimeServerEvent>,
+    ) {
+        connect_voice_path_with(provider, VoicePathOptions::default()).await
+    }
+
+    /// How the test voice path is set up.
+    #[derive(Default)]
+    struct VoicePathOptions {
+        fake: FakeRealtimeConfig,
+        tools: Option<RealtimeTools>,
+        audio_transport: AudioTransport,
+        audio_codec: AudioCodec,
+        /// Defaults to 24 kHz.
+        playback_sample_rate: Option<u32>,
+        /// Defaults to the configured turn detection.
+        turn_detection: Option<TurnDetection>,
+    }
+
+    /// Like `connect_voice_path`, with a custom fake, realtime tools or audio transport.
+    async fn connect_voice_path_with(
+        provider: RealtimeProvider,
+        options: VoicePathOptions,
+    ) -> (
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        mpsc::Sender<RealtimeClientEvent>,
+        mpsc::Receiver<RealtimeServerEvent>,
+    ) {
+        let fake_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fake_base = format!("ws://{}", fake_listener.local_addr().unwrap());
+        tokio::spawn(fake_realtime::serve(fake_listener, options.fake));
+
+        let state = test_state(provider, &fake_base);
+        let tools = options.tools;
+        let (audio_transport, audio_codec) = (options.audio_transport, options.audio_codec);
+        let playback_sample_rate = options.playback_sample_rate.unwrap_or(24_000);
+        let turn_detection = options.turn_detection;
+        let (event_tx_tx, mut event_tx_rx) = mpsc::channel(1);
+        let (server_events_tx, server_events_rx) = mpsc::channel(16);
+        let app = Router::new().route(
+            "/ws",
+            get(move |ws: WebSocketUpgrade| {
+                let state = state.clone();
+                let event_tx_tx = event_tx_tx.clone();
+                let server_events_tx = server_events_tx.clone();
+                let tools = tools.clone();
+                async move {
+                    ws.on_upgrade(move |socket| async move {
+                        let (sink, _stream) = futures_util::StreamExt::split(socket);
+                        let mut settings = VoiceSettings::from_config(&state.config);
+                        if let Some(turn_detection) = turn_detection {
+                            settings.turn_detection = turn_detection;
+                        }
+                        let (tx, _handle) = start_realtime_provider(
+                            state,
+                            Arc::new(Mutex::new(sink)),
+                            settings,
+                            AudioOutput::new(audio_transport, audio_codec, playback_sample_rate)
+                                .unwrap(),
+                            server_events_tx,
+                            tools,
+                        )
+                        .await
+                        .unwrap();
+                        event_tx_tx.send(tx).await.unwrap();
+                        std::future::pending::<()>().await;
+                    })
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (client, _) = connect_async(format!("ws://{}/ws", addr)).await.unwrap();
+        let event_tx = event_tx_rx.recv().await.unwrap();
+        (client, event_tx, server_events_rx)
+    }
+
+    /// Builds the agent's tools as a session does, returning them and the state updates.
+    fn agent_tools() -> (RealtimeTools, mpsc::Receiver<FeynmanAgent>) {
+        let agent = FeynmanAgent::new(
+            "Linked Lists".to_string(),
+            vec![SubTopic::new("Nodes".to_string())],
+        );
+        let (state_tx, state_rx) = mpsc::channel(8);
+        let service = FeynmanService::new(Arc::new(Mutex::new(agent)), Some(state_tx));
+        (RealtimeTools::new(Arc::new(service)), state_rx)
+    }
+
+    /// Has the fake model call `update_subtopic_status` after an utterance and checks
+    /// that the call reaches the agent and the model then speaks on its own.
+    async fn assert_realtime_tool_call(provider: RealtimeProvider) {
+        let fake_config = FakeRealtimeConfig {
+            tool_call: Some(FakeToolCall {
+                name: "update_subtopic_status".to_string(),
+                arguments: serde_json::json!({
+                    "subtopic_name": "Nodes",
+                    "criterion": "definition",
+                    "is_covered": true,
+                }),
+            }),
+            ..Default::default()
+        };
+        let (tools, mut state_rx) = agent_tools();
+        assert!(
+            tools
+                .tools()
+                .iter()
+                .any(|tool| tool.name == "update_subtopic_status")
+        );
+        let (mut client, tx, mut events) = connect_voice_path_with(
+            provider,
+            VoicePathOptions {
+                fake: fake_config,
+                tools: Some(tools),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Give the provider a moment to complete the setup handshake.
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let pcm = vec![0u8; 28_800];
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm)))
+            .await
+            .unwrap();
+
+        let updated = tokio::time::timeout(std::time::Duration::from_secs(5), state_rx.recv())
+            .await
+            .expect("timed out waiting for a state update")
+            .unwrap();
+        assert!(updated.incomplete_subtopics["Nodes"].has_definition);
+
+        let chunk = wait_for(&mut client, "audio_chunk").await;
+        assert!(!chunk["data"].as_str().unwrap().is_empty());
+        // The realtime model handled the turn, so it is not run through the ReAct cycle.
+        assert!(events.try_recv().is_err());
+    }
+
+    /// Reads browser messages until one with the given `type` arrives.
+    async fn wait_for(
+        client: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        ty: &str,
+    ) -> Value {
+        loop {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), client.next())
+                .await
+                .expect("timed out waiting for server message")
+                .unwrap()
+                .unwrap();
+            if let WsMessage::Text(text) = msg {
+                let value: Value = serde_json::from_str(&text).unwrap();
+                if value["type"] == ty {
+                    return value;
+                }
+            }
+        }
+    }
+
+    /// Reads browser messages until a final transcription arrives, failing if the
+    /// provider plays any audio first.
+    async fn wait_for_final_transcript(
+        client: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    ) -> Value {
+        loop {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), client.next())
+                .await
+                .expect("timed out waiting for server message")
+                .unwrap()
+                .unwrap();
+            if let WsMessage::Text(text) = msg {
+                let value: Value = serde_json::from_str(&text).unwrap();
+                assert_ne!(value["type"], "audio_chunk", "provider answered on its own");
+                if value["type"] == "transcription_update" && value["is_final"] == true {
+                    return value;
+                }
+            }
+        }
+    }
+
+    /// Asserts that the session loop receives the given transcript as a user turn.
+    async fn expect_user_transcript(events: &mut mpsc::Receiver<RealtimeServerEvent>, text: &str) {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .expect("timed out waiting for user transcript")
+            .unwrap();
+        match event {
+            RealtimeServerEvent::UserTranscript(transcript) => assert_eq!(transcript, text),
+            other => panic!("expected a user transcript, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_voice_settings_apply_update() {
+        let mut settings = VoiceSettings::from_config(&Config::for_tests());
+        assert_eq!(settings.voice, "alloy");
+
+        settings
+            .apply(
+                &RealtimeProvider::OpenAI,
+                VoiceSettingsUpdate {
+                    voice: Some("verse".to_string()),
+                    vad_threshold: Some(0.8),
+                    ..Default::default()
                 },
             )
+            .unwrap();
+        assert_eq!(settings.voice, "verse");
+        assert_eq!(settings.vad_threshold, 0.8);
+        assert_eq!(settings.vad_silence_duration_ms, 700);
+
+        // An invalid field rejects the whole update.
+        let before = settings.clone();
+        let err = settings
+            .apply(
+                &RealtimeProvider::OpenAI,
+                VoiceSettingsUpdate {
+                    voice: Some("robot".to_string()),
+                    speaking_rate: Some(1.2),
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+        assert!(err.contains("robot"));
+        assert_eq!(settings, before);
+
+        // Gemini voice names are passed through unvalidated.
+        settings
+            .apply(
+                &RealtimeProvider::Gemini,
+                VoiceSettingsUpdate {
+                    voice: Some("Kore".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(settings.voice, "Kore");
+    }
+
+    #[tokio::test]
+    async fn test_link_buffers_events_while_disconnected() {
+        let (tx, rx) = mpsc::channel(8);
+        let mut link = RealtimeLink::new(
+            rx,
+            VoiceSettings::from_config(&Config::for_tests()),
+            AudioOutput::new(AudioTransport::Json, AudioCodec::Pcm16, 24_000).unwrap(),
+        );
+
+        // Events arriving while waiting are buffered, not left in the channel.
+        let chunk = Bytes::from(vec![0u8; MAX_BUFFERED_AUDIO_BYTES / 2]);
+        let mut update = link.settings().clone();
+        update.voice = "verse".to_string();
+        for event in [
+            RealtimeClientEvent::Audio(chunk.clone()),
+            RealtimeClientEvent::TextToSpeak("Tell me more.".to_string()),
+            RealtimeClientEvent::Audio(chunk.clone()),
+            RealtimeClientEvent::UpdateSettings(update),
+            RealtimeClientEvent::Audio(chunk.clone()),
+        ] {
+            tx.send(event).await.unwrap();
+        }
+        link.buffer_while(tokio::time::sleep(Duration::from_millis(50)))
             .await;
+
+        // Settings apply to the next connection; the oldest audio is dropped to fit.
+        assert_eq!(link.settings().voice, "verse");
+        assert!(matches!(
+            link.next_event().await,
+            Some(RealtimeClientEvent::TextToSpeak(_))
+        ));
+        for _ in 0..2 {
+            assert!(matches!(
+                link.next_event().await,
+                Some(RealtimeClientEvent::Audio(_))
+            ));
         }
-    });
+        assert_eq!(link.pending_audio_bytes, 0);
 
-    Ok((tx, handle))
-}
\ No newline at end of file
+        drop(tx);
+        assert!(link.next_event().await.is_none());
+    }
+
+    #[test]
+    fn test_link_remembers_recent_turns() {
+        let (_tx, rx) = mpsc::channel(1);
+        let mut link = RealtimeLink::new(
+            rx,
+            VoiceSettings::from_config(&Config::for_tests()),
+            AudioOutput::new(AudioTransport::Json, AudioCodec::Pcm16, 24_000).unwrap(),
+        );
+        link.remember(Speaker::User, "  ");
+        assert_eq!(link.context().count(), 0);
+
+        for i in 0..CONTEXT_TURNS + 2 {
+            link.remember(Speaker::Assistant, &format!("turn {}", i));
+        }
+        let turns: Vec<_> = link.context().map(|turn| turn.text.as_str()).collect();
+        assert_eq!(turns.len(), CONTEXT_TURNS);
+        assert_eq!(turns[0], "turn 2");
+    }
+
+    #[test]
+    fn test_reconnect_delay_backs_off_exponentially() {
+        assert_eq!(reconnect_delay(1), RECONNECT_BASE_DELAY);
+        assert_eq!(reconnect_delay(2), RECONNECT_BASE_DELAY * 2);
+        assert_eq!(reconnect_delay(3), RECONNECT_BASE_DELAY * 4);
+        assert_eq!(reconnect_delay(30), RECONNECT_MAX_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_openai_voice_path_against_fake_server() {
+        let (mut client, tx, mut events) = connect_voice_path(RealtimeProvider::OpenAI).await;
+        let transcript = FakeRealtimeConfig::default().transcript;
+
+        // 600ms of silence at 24kHz, sent as raw PCM16 like the browser does.
+        let pcm = vec![0u8; 28_800];
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm)))
+            .await
+            .unwrap();
+
+        let transcription = wait_for_final_transcript(&mut client).await;
+        assert_eq!(transcription["text"], transcript);
+        expect_user_transcript(&mut events, &transcript).await;
+
+        // Automatic responses are disabled, so nothing is spoken until asked.
+        let idle = tokio::time::timeout(std::time::Duration::from_millis(300), async {
+            wait_for(&mut client, "audio_chunk").await
+        })
+        .await;
+        assert!(idle.is_err(), "provider answered on its own");
+
+        tx.send(RealtimeClientEvent::TextToSpeak(
+            "Tell me more.".to_string(),
+        ))
+        .await
+        .unwrap();
+        let chunk = wait_for(&mut client, "audio_chunk").await;
+        assert!(!chunk["data"].as_str().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_gemini_voice_path_against_fake_server() {
+        let (mut client, tx, mut events) = connect_voice_path(RealtimeProvider::Gemini).await;
+        let transcript = FakeRealtimeConfig::default().transcript;
+
+        // Give the provider a moment to complete the setup handshake.
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        // 600ms of audio; the fake answers it on its own, which must not be played.
+        let pcm = vec![0u8; 28_800];
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm)))
+            .await
+            .unwrap();
+        let transcription = wait_for_final_transcript(&mut client).await;
+        assert_eq!(transcription["text"], transcript);
+        expect_user_transcript(&mut events, &transcript).await;
+
+        tx.send(RealtimeClientEvent::TextToSpeak(
+            "Tell me more.".to_string(),
+        ))
+        .await
+        .unwrap();
+        let chunk = wait_for(&mut client, "audio_chunk").await;
+        assert!(!chunk["data"].as_str().unwrap().is_empty());
+        wait_for(&mut client, "ai_speaking_end").await;
+    }
+
+    #[tokio::test]
+    async fn test_gemini_plays_reply_requested_before_its_own_turn_completes() {
+        let (mut client, tx, mut events) = connect_voice_path_with(
+            RealtimeProvider::Gemini,
+            VoicePathOptions {
+                fake: FakeRealtimeConfig {
+                    late_turn_complete: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        // The fake answers the utterance on its own but completes that turn only
+        // after the reply below has been requested.
+        let pcm = vec![0u8; 28_800];
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm)))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        tx.send(RealtimeClientEvent::TextToSpeak(
+            "Tell me more.".to_string(),
+        ))
+        .await
+        .unwrap();
+
+        wait_for_final_transcript(&mut client).await;
+        expect_user_transcript(&mut events, &FakeRealtimeConfig::default().transcript).await;
+        wait_for(&mut client, "ai_speaking_start").await;
+        let chunk = wait_for(&mut client, "audio_chunk").await;
+        assert!(!chunk["data"].as_str().unwrap().is_empty());
+        wait_for(&mut client, "ai_speaking_end").await;
+    }
+
+    #[tokio::test]
+    async fn test_openai_realtime_tool_call_against_fake_server() {
+        assert_realtime_tool_call(RealtimeProvider::OpenAI).await;
+    }
+
+    #[tokio::test]
+    async fn test_gemini_realtime_tool_call_against_fake_server() {
+        assert_realtime_tool_call(RealtimeProvider::Gemini).await;
+    }
+
+    /// Runs a voice path with server-side turn detection: the provider must not end
+    /// the utterance on its own, only when the session reports that speech stopped.
+    async fn assert_server_turns(provider: RealtimeProvider) {
+        let (mut client, tx, mut events) = connect_voice_path_with(
+            provider,
+            VoicePathOptions {
+                turn_detection: Some(TurnDetection::Server),
+                ..Default::default()
+            },
+        )
+        .await;
+        let transcript = FakeRealtimeConfig::default().transcript;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        tx.send(RealtimeClientEvent::SpeechStarted).await.unwrap();
+        wait_for(&mut client, "user_speaking_start").await;
+        let pcm = vec![0u8; 28_800];
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm)))
+            .await
+            .unwrap();
+
+        // Well past the fake's utterance length, yet the turn is still open.
+        let idle = tokio::time::timeout(std::time::Duration::from_millis(300), async {
+            wait_for(&mut client, "transcription_update").await
+        })
+        .await;
+        assert!(idle.is_err(), "provider ended the turn on its own");
+
+        tx.send(RealtimeClientEvent::SpeechStopped).await.unwrap();
+        wait_for(&mut client, "user_speaking_end").await;
+        let transcription = wait_for_final_transcript(&mut client).await;
+        assert_eq!(transcription["text"], transcript);
+        expect_user_transcript(&mut events, &transcript).await;
+    }
+
+    #[tokio::test]
+    async fn test_openai_server_turns_against_fake_server() {
+        assert_server_turns(RealtimeProvider::OpenAI).await;
+    }
+
+    #[tokio::test]
+    async fn test_gemini_server_turns_against_fake_server() {
+        assert_server_turns(RealtimeProvider::Gemini).await;
+    }
+
+    /// Cancels one push-to-talk utterance and ends the next, checking that only the
+    /// second becomes a user turn.
+    async fn assert_push_to_talk(provider: RealtimeProvider) {
+        let (mut client, tx, mut events) = connect_voice_path_with(
+            provider,
+            VoicePathOptions {
+                turn_detection: Some(TurnDetection::PushToTalk),
+                ..Default::default()
+            },
+        )
+        .await;
+        let transcript = FakeRealtimeConfig::default().transcript;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        tx.send(RealtimeClientEvent::SpeechStarted).await.unwrap();
+        wait_for(&mut client, "user_speaking_start").await;
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(vec![0u8; 28_800])))
+            .await
+            .unwrap();
+        tx.send(RealtimeClientEvent::SpeechCancelled).await.unwrap();
+        wait_for(&mut client, "user_speaking_end").await;
+        let idle = tokio::time::timeout(std::time::Duration::from_millis(300), async {
+            wait_for(&mut client, "transcription_update").await
+        })
+        .await;
+        assert!(idle.is_err(), "cancelled utterance was transcribed");
+
+        tx.send(RealtimeClientEvent::SpeechStarted).await.unwrap();
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(vec![0u8; 9_600])))
+            .await
+            .unwrap();
+        tx.send(RealtimeClientEvent::SpeechStopped).await.unwrap();
+        let transcription = wait_for_final_transcript(&mut client).await;
+        assert_eq!(transcription["text"], transcript);
+        expect_user_transcript(&mut events, &transcript).await;
+    }
+
+    #[tokio::test]
+    async fn test_openai_push_to_talk_against_fake_server() {
+        assert_push_to_talk(RealtimeProvider::OpenAI).await;
+    }
+
+    #[tokio::test]
+    async fn test_gemini_push_to_talk_against_fake_server() {
+        assert_push_to_talk(RealtimeProvider::Gemini).await;
+    }
+
+    /// Starts a long spoken reply, then has the user talk over it.
+    async fn assert_barge_in(provider: RealtimeProvider) {
+        let fake_config = FakeRealtimeConfig {
+            response_ms: 5_000,
+            ..Default::default()
+        };
+        let (mut client, tx, mut events) = connect_voice_path_with(
+            provider,
+            VoicePathOptions {
+                fake: fake_config,
+                ..Default::default()
+            },
+        )
+        .await;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let reply = "Tell me more about how the nodes point to each other.";
+        tx.send(RealtimeClientEvent::TextToSpeak(reply.to_string()))
+            .await
+            .unwrap();
+        wait_for(&mut client, "ai_speaking_start").await;
+        wait_for(&mut client, "audio_chunk").await;
+
+        let pcm = vec![0u8; 28_800];
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm)))
+            .await
+            .unwrap();
+        wait_for(&mut client, "user_speaking_start").await;
+        wait_for(&mut client, "interrupted").await;
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .expect("timed out waiting for the interruption")
+            .unwrap();
+        match event {
+            RealtimeServerEvent::Interrupted(interruption) => {
+                assert!(interruption.played_ms < 5_000);
+                assert!(reply.starts_with(&interruption.spoken_text));
+                assert!(interruption.spoken_text.len() < reply.len());
+            }
+            other => panic!("expected an interruption, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_barge_in_against_fake_server() {
+        assert_barge_in(RealtimeProvider::OpenAI).await;
+    }
+
+    #[tokio::test]
+    async fn test_gemini_barge_in_against_fake_server() {
+        assert_barge_in(RealtimeProvider::Gemini).await;
+    }
+
+    /// Has the fake drop every connection shortly after it opens, and checks that the
+    /// provider reconnects, replays audio sent meanwhile, and keeps transcribing.
+    async fn assert_reconnect(provider: RealtimeProvider) {
+        let fake_config = FakeRealtimeConfig {
+            session_ms: Some(1_000),
+            ..Default::default()
+        };
+        let transcript = fake_config.transcript.clone();
+        let (mut client, tx, mut events) = connect_voice_path_with(
+            provider,
+            VoicePathOptions {
+                fake: fake_config,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let status = wait_for(&mut client, "voice_status").await;
+        assert_eq!(status["status"], "connected");
+        let pcm = vec![0u8; 28_800];
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm.clone())))
+            .await
+            .unwrap();
+        wait_for_final_transcript(&mut client).await;
+        expect_user_transcript(&mut events, &transcript).await;
+
+        let status = wait_for(&mut client, "voice_status").await;
+        assert_eq!(status["status"], "reconnecting");
+        assert_eq!(status["attempt"], 1);
+        // Audio spoken while disconnected is buffered and sent after reconnecting.
+        tx.send(RealtimeClientEvent::Audio(Bytes::from(pcm)))
+            .await
+            .unwrap();
+
+        let status = wait_for(&mut client, "voice_status").await;
+        assert_eq!(status["status"], "connected");
+        let transcription = wait_for_final_transcript(&mut client).await;
+        assert_eq!(transcription["text"], transcript);
+        expect_user_transcript(&mut events, &transcript).await;
+    }
+
+    #[tokio::test]
+    async fn test_openai_reconnects_after_provider_disconnect() {
+        assert_reconnect(RealtimeProvider::OpenAI).await;
+    }
+
+    #[tokio::test]
+    async fn test_gemini_reconnects_after_provider_disconnect() {
+        assert_reconnect(RealtimeProvider::Gemini).await;
+    }
+
+    /// Has the provider speak with the binary audio transport and returns the
+    /// header and payload of the first two frames.
+    async fn binary_audio_frames(
+        provider: RealtimeProvider,
+        audio_codec: AudioCodec,
+        playback_sample_rate: u32,
+    ) -> Vec<(AudioFrameHeader, Vec<u8>)> {
+        let (mut client, tx, _events) = connect_voice_path_with(
+            provider,
+            VoicePathOptions {
+                audio_transport: AudioTransport::Binary,
+                audio_codec,
+                playback_sample_rate: Some(playback_sample_rate),
+                ..Default::default()
+            },
+        )
+        .await;
+        wait_for(&mut client, "voice_status").await;
+        tx.send(RealtimeClientEvent::TextToSpeak(
+            "Tell me more.".to_string(),
+        ))
+        .await
+        .unwrap();
+
+        let mut frames = Vec::new();
+        while frames.len() < 2 {
+            let msg = tokio::time::timeout(std::time::Duration::from_secs(5), client.next())
+                .await
+                .expect("timed out waiting for audio frames")
+                .unwrap()
+                .unwrap();
+            match msg {
+                WsMessage::Binary(frame) => {
+                    let (header, payload) = AudioFrameHeader::decode(&frame).unwrap();
+                    frames.push((header, payload.to_vec()));
+                }
+                WsMessage::Text(text) => {
+                    let value: Value = serde_json::from_str(&text).unwrap();
+                    assert_ne!(value["type"], "audio_chunk", "audio sent as JSON");
+                }
+                _ => {}
+            }
+        }
+        frames
+    }
+
+    /// Checks PCM16 frames: valid headers with consecutive sequence numbers.
+    async fn assert_binary_audio(provider: RealtimeProvider, playback_sample_rate: u32) {
+        let frames = binary_audio_frames(provider, AudioCodec::Pcm16, playback_sample_rate).await;
+        for (i, (header, payload)) in frames.iter().enumerate() {
+            assert_eq!(header.codec, AudioCodec::Pcm16);
+            assert_eq!(header.sample_rate, playback_sample_rate);
+            assert_eq!(header.sequence, i as u32);
+            assert!(!payload.is_empty() && payload.len() % 2 == 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_binary_audio_transport() {
+        assert_binary_audio(RealtimeProvider::OpenAI, 24_000).await;
+    }
+
+    #[tokio::test]
+    async fn test_gemini_binary_audio_transport() {
+        assert_binary_audio(RealtimeProvider::Gemini, 24_000).await;
+    }
+
+    #[tokio::test]
+    async fn test_audio_resampled_to_playback_rate() {
+        assert_binary_audio(RealtimeProvider::OpenAI, 48_000).await;
+        assert_binary_audio(RealtimeProvider::Gemini, 16_000).await;
+    }
+
+    #[test]
+    fn test_audio_input_converts_to_provider_rate() {
+        let capture = CaptureFormat {
+            sample_rate: 16_000,
+            channels: 2,
+            sample_format: audio_utils::SampleFormat::Pcm16,
+        };
+        // Gemini takes 16 kHz, so stereo frames are only downmixed.
+        let mut input =
+            AudioInput::new(AudioCodec::Pcm16, capture, &RealtimeProvider::Gemini).unwrap();
+        let stereo = audio_utils::i16_to_le_bytes(&[100, 300, -100, -300]);
+        assert_eq!(input.convert(&stereo[..5]).unwrap().0.len(), 2);
+        assert_eq!(input.convert(&stereo[5..]).unwrap().0.len(), 2);
+
+        let bad = CaptureFormat {
+            channels: 0,
+            ..capture
+        };
+        assert!(AudioInput::new(AudioCodec::Pcm16, bad, &RealtimeProvider::OpenAI).is_err());
+        assert!(AudioOutput::new(AudioTransport::Binary, AudioCodec::Opus, 44_100).is_err());
+    }
+
+    #[test]
+    fn test_push_to_talk_drops_audio_outside_utterances() {
+        let mut input = AudioInput::new(
+            AudioCodec::Pcm16,
+            CaptureFormat::default(),
+            &RealtimeProvider::OpenAI,
+        )
+        .unwrap();
+        let frame = audio_utils::i16_to_le_bytes(&[1_000; 480]);
+        assert!(input.mark_utterance(true).is_err());
+
+        let mut settings = VoiceSettings::from_config(&Config::for_tests());
+        settings.turn_detection = TurnDetection::PushToTalk;
+        input.configure(&settings);
+        assert!(input.convert(&frame).unwrap().0.is_empty());
+        assert!(input.mark_utterance(true).unwrap());
+        assert!(!input.mark_utterance(true).unwrap());
+        assert_eq!(input.convert(&frame).unwrap().0.len(), frame.len());
+        assert!(input.mark_utterance(false).unwrap());
+        assert!(input.convert(&frame).unwrap().0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_opus_audio_frames_decode_to_20ms() {
+        let mut decoder =
+            audio_utils::OpusPcmDecoder::new(audio_utils::FRONTEND_AUDIO_PLAYER_SAMPLE_RATE)
+                .unwrap();
+        for (header, payload) in
+            binary_audio_frames(RealtimeProvider::OpenAI, AudioCodec::Opus, 24_000).await
+        {
+            assert_eq!(header.codec, AudioCodec::Opus);
+            assert_eq!(decoder.decode(&payload).unwrap().len(), 480);
+        }
+    }
+}
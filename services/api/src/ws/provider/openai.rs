@@ -1,17 +1,27 @@
 //! Handles the real-time WebSocket connection to OpenAI for voice interaction.
 
-use super::RealtimeClientEvent;
+use super::{
+    CONNECT_TIMEOUT, ContextTurn, RealtimeClientEvent, RealtimeLink, RealtimeServerEvent,
+    RealtimeTools, Speaker, VOICE_INSTRUCTIONS, VoiceSettings, interrupt_playback,
+    playback::PlaybackTracker, send_voice_status, speak_verbatim_prompt,
+};
 use crate::{
     audio_utils,
+    config::{Config, TurnDetection},
     state::AppState,
-    ws::{protocol::ServerMessage, session::send_msg},
+    ws::{
+        protocol::{ServerMessage, VoiceConnectionStatus},
+        session::send_msg,
+    },
 };
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use async_openai::types::realtime::{
     self as oai_realtime, ClientEvent as OAIClientEvent, ServerEvent as OAIServerEvent,
 };
 use axum::extract::ws::{Message, WebSocket};
+use base64::Engine;
 use futures_util::{SinkExt, StreamExt, stream::SplitSink};
+use rmcp::model::Tool;
 use std::sync::Arc;
 use tokio::sync::{Mutex, mpsc};
 use tokio_tungstenite::{
@@ -20,19 +30,196 @@ use tokio_tungstenite::{
 };
 use tracing::info;
 
-/// Runs the main loop for the OpenAI Realtime API connection.
+/// Builds the `session.update` payload for the configured model and the given voice settings.
+///
+/// With `tools`, the model answers each turn on its own and may call them;
+/// otherwise it only speaks what it is asked to.
+fn session_update_message(
+    config: &Config,
+    settings: &VoiceSettings,
+    instructions: &str,
+    tools: Option<&[Tool]>,
+) -> Result<String> {
+    let voice: oai_realtime::RealtimeVoice =
+        serde_json::from_value(serde_json::Value::String(settings.voice.clone()))
+            .with_context(|| format!("Unsupported OpenAI voice '{}'", settings.voice))?;
+    let session_config = oai_realtime::SessionResource {
+        model: Some(config.openai_realtime_model.clone()),
+        instructions: Some(instructions.to_string()),
+        modalities: Some(vec!["text".to_string(), "audio".to_string()]),
+        voice: Some(voice),
+        input_audio_format: Some(oai_realtime::AudioFormat::PCM16),
+        output_audio_format: Some(oai_realtime::AudioFormat::PCM16),
+        input_audio_transcription: Some(oai_realtime::AudioTranscription {
+            model: Some(config.openai_transcription_model.clone()),
+            ..Default::default()
+        }),
+        turn_detection: match settings.turn_detection {
+            TurnDetection::Provider => Some(oai_realtime::TurnDetection::ServerVAD {
+                threshold: settings.vad_threshold,
+                prefix_padding_ms: settings.vad_prefix_padding_ms,
+                silence_duration_ms: settings.vad_silence_duration_ms,
+                interrupt_response: Some(true),
+                // Without tools, replies come from the ReAct cycle, not from the realtime model.
+                create_response: Some(tools.is_some()),
+            }),
+            // Turns are committed explicitly when the server or the client ends them.
+            TurnDetection::Server | TurnDetection::PushToTalk => None,
+        },
+        tools: tools
+            .map(|tools| tools.iter().map(tool_definition).collect::<Result<_>>())
+            .transpose()?,
+        tool_choice: tools.map(|_| oai_realtime::ToolChoice::Auto),
+        ..Default::default()
+    };
+    let event = OAIClientEvent::SessionUpdate(oai_realtime::SessionUpdateEvent {
+        session: session_config,
+        event_id: None,
+    });
+
+    // `speed` is newer than the typed session resource, so it is set on the raw payload.
+    let mut payload = serde_json::to_value(&event)?;
+    if settings.speaking_rate != 1.0 {
+        payload["session"]["speed"] = serde_json::json!(settings.speaking_rate);
+    }
+    // An omitted `turn_detection` keeps the current mode; `null` turns it off.
+    if settings.turn_detection != TurnDetection::Provider {
+        payload["session"]["turn_detection"] = serde_json::Value::Null;
+    }
+    Ok(payload.to_string())
+}
+
+/// Converts an MCP tool into a realtime function declaration.
+fn tool_definition(tool: &Tool) -> Result<oai_realtime::ToolDefinition> {
+    Ok(oai_realtime::ToolDefinition::Function {
+        name: tool.name.to_string(),
+        description: tool.description.as_deref().unwrap_or_default().to_string(),
+        parameters: serde_json::to_value(&*tool.input_schema)?,
+    })
+}
+
+/// Builds the `conversation.item.create` payload that restores a turn of the
+/// conversation in a new session.
+fn context_item_message(turn: &ContextTurn) -> Result<String> {
+    let (role, content_type) = match turn.speaker {
+        Speaker::User => (
+            oai_realtime::ItemRole::User,
+            oai_realtime::ItemContentType::InputText,
+        ),
+        Speaker::Assistant => (
+            oai_realtime::ItemRole::Assistant,
+            oai_realtime::ItemContentType::Text,
+        ),
+    };
+    let item = oai_realtime::Item {
+        id: None,
+        r#type: Some(oai_realtime::ItemType::Message),
+        status: None,
+        role: Some(role),
+        content: Some(vec![oai_realtime::ItemContent {
+            r#type: content_type,
+            text: Some(turn.text.clone()),
+            audio: None,
+            transcript: None,
+        }]),
+        call_id: None,
+        name: None,
+        arguments: None,
+        output: None,
+    };
+    let event = OAIClientEvent::ConversationItemCreate(oai_realtime::ConversationItemCreateEvent {
+        event_id: None,
+        previous_item_id: None,
+        item,
+    });
+    Ok(serde_json::to_string(&event)?)
+}
+
+/// Builds the `conversation.item.create` payload that returns a function call's output.
+fn function_output_message(call_id: String, output: String) -> Result<String> {
+    let item = oai_realtime::Item {
+        id: None,
+        r#type: Some(oai_realtime::ItemType::FunctionCallOutput),
+        status: None,
+        role: None,
+        content: None,
+        call_id: Some(call_id),
+        name: None,
+        arguments: None,
+        output: Some(output),
+    };
+    let event = OAIClientEvent::ConversationItemCreate(oai_realtime::ConversationItemCreateEvent {
+        event_id: None,
+        previous_item_id: None,
+        item,
+    });
+    Ok(serde_json::to_string(&event)?)
+}
+
+/// Builds the payloads that cancel the in-flight response and truncate its audio
+/// item to what the user heard, as the Realtime API expects after a barge-in.
+fn interruption_messages(
+    response_active: bool,
+    audio_item_id: Option<String>,
+    played_ms: u32,
+) -> Result<Vec<String>> {
+    let mut messages = Vec::new();
+    if response_active {
+        messages.push(serde_json::to_string(&OAIClientEvent::ResponseCancel(
+            oai_realtime::ResponseCancelEvent { event_id: None },
+        ))?);
+    }
+    if let Some(item_id) = audio_item_id {
+        messages.push(serde_json::to_string(
+            &OAIClientEvent::ConversationItemTruncate(
+                oai_realtime::ConversationItemTruncateEvent {
+                    event_id: None,
+                    item_id,
+                    content_index: 0,
+                    audio_end_ms: played_ms,
+                },
+            ),
+        )?);
+    }
+    Ok(messages)
+}
+
+/// Builds a `response.create` payload that speaks the given text verbatim.
+fn speak_message(text: &str) -> Result<String> {
+    let event = OAIClientEvent::ResponseCreate(oai_realtime::ResponseCreateEvent {
+        event_id: None,
+        response: Some(oai_realtime::SessionResource {
+            modalities: Some(vec!["text".to_string(), "audio".to_string()]),
+            instructions: Some(speak_verbatim_prompt(text)),
+            ..Default::default()
+        }),
+    });
+    Ok(serde_json::to_string(&event)?)
+}
+
+/// Runs the main loop for one OpenAI Realtime API connection.
 ///
 /// This function connects to the OpenAI WebSocket, handles session setup,
 /// and then enters a loop to proxy messages between our client and OpenAI.
+/// Completed input transcriptions are forwarded on `events_tx` as user turns,
+/// unless `tools` are given, in which case the model answers and calls them itself.
+///
+/// Returns `Ok` once the session stops sending events, and an error when the
+/// connection to OpenAI fails or drops, so that the caller can reconnect.
 pub async fn run(
     state: &Arc<AppState>,
-    mut rx: mpsc::Receiver<RealtimeClientEvent>,
+    link: &mut RealtimeLink,
     socket_tx: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    events_tx: &mpsc::Sender<RealtimeServerEvent>,
+    tools: Option<&RealtimeTools>,
 ) -> Result<()> {
-    let url = "wss://api.openai.com/v1/realtime?model=gpt-4o-realtime-preview-2024-10-01";
+    let url = format!(
+        "{}?model={}",
+        state.config.openai_realtime_url, state.config.openai_realtime_model
+    );
     let api_key = state
         .config
-        .openai_api_key
+        .realtime_api_key
         .as_ref()
         .context("OpenAI API key not found")?;
 
@@ -44,89 +231,362 @@ pub async fn run(
         .headers_mut()
         .insert("OpenAI-Beta", "realtime=v1".parse()?);
 
-    let (ws_stream, _) = connect_async(request)
+    let (ws_stream, _) = link
+        .buffer_while(tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            connect_async(request),
+        ))
         .await
+        .context("Timed out connecting to OpenAI Realtime WebSocket")?
         .context("Failed to connect to OpenAI Realtime WebSocket")?;
     let (mut openai_tx, mut openai_rx) = ws_stream.split();
     info!("Connected to OpenAI Realtime API.");
 
     // Configure the real-time session parameters.
-    let session_config = oai_realtime::SessionResource {
-        model: Some("gpt-4o-realtime-preview-2024-10-01".to_string()),
-        modalities: Some(vec!["text".to_string(), "audio".to_string()]),
-        voice: Some(oai_realtime::RealtimeVoice::Alloy),
-        input_audio_format: Some(oai_realtime::AudioFormat::PCM16),
-        output_audio_format: Some(oai_realtime::AudioFormat::PCM16),
-        input_audio_transcription: Some(oai_realtime::AudioTranscription {
-            model: Some("whisper-1".to_string()),
-            ..Default::default()
-        }),
-        turn_detection: Some(oai_realtime::TurnDetection::ServerVAD {
-            threshold: 0.5,
-            prefix_padding_ms: 200,
-            silence_duration_ms: 700,
-            interrupt_response: Some(true),
-            create_response: Some(true),
-        }),
-        ..Default::default()
+    let instructions = match tools {
+        Some(_) => state.system_prompt.as_str(),
+        None => VOICE_INSTRUCTIONS,
     };
-    let event = OAIClientEvent::SessionUpdate(oai_realtime::SessionUpdateEvent {
-        session: session_config,
-        event_id: None,
-    });
+    let tool_declarations = tools.map(RealtimeTools::tools);
     openai_tx
-        .send(WsMessage::Text(serde_json::to_string(&event)?.into()))
+        .send(WsMessage::Text(
+            session_update_message(
+                &state.config,
+                link.settings(),
+                instructions,
+                tool_declarations,
+            )?
+            .into(),
+        ))
         .await?;
+    // Restore the conversation so far when this is a reconnect.
+    for turn in link.context() {
+        openai_tx
+            .send(WsMessage::Text(context_item_message(turn)?.into()))
+            .await?;
+    }
+    send_voice_status(&socket_tx, VoiceConnectionStatus::Connected, 0).await?;
+
+    // Set once function outputs are submitted; the model continues after the response ends.
+    let mut tool_outputs_pending = false;
+    // Barge-in bookkeeping for the response currently being generated or played.
+    let mut playback = PlaybackTracker::default();
+    let mut response_active = false;
+    let mut ai_speaking = false;
+    let mut audio_item_id: Option<String> = None;
 
     // Main event loop for the OpenAI connection.
-    loop {
+    let disconnected = loop {
         tokio::select! {
             biased;
             // Handle events from our application (e.g., audio to send).
-            Some(event) = rx.recv() => {
+            event = link.next_event() => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
                 match event {
                     RealtimeClientEvent::Audio(data) => {
-                        let audio_i16: Vec<i16> = data.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
-                        let encoded_audio = audio_utils::encode_i16(&audio_i16);
+                        if data.is_empty() {
+                            continue;
+                        }
+                        let encoded_audio = base64::engine::general_purpose::STANDARD.encode(&data);
                         let append_event = oai_realtime::InputAudioBufferAppendEvent { audio: encoded_audio, event_id: None };
                         openai_tx.send(WsMessage::Text(serde_json::to_string(&OAIClientEvent::InputAudioBufferAppend(append_event))?.into())).await?;
                     }
                     RealtimeClientEvent::TextToSpeak(text) => {
-                         let item = oai_realtime::Item {
-                             r#type: Some(oai_realtime::ItemType::Message),
-                             role: Some(oai_realtime::ItemRole::System),
-                             content: Some(vec![oai_realtime::ItemContent {
-                                 r#type: oai_realtime::ItemContentType::InputText,
-                                 text: Some(text), audio: None, transcript: None,
-                             }]),
-                             id: None, status: None, call_id: None, name: None, arguments: None, output: None
-                         };
-                         let create_event = oai_realtime::ConversationItemCreateEvent { item, event_id: None, previous_item_id: None };
-                         openai_tx.send(WsMessage::Text(serde_json::to_string(&OAIClientEvent::ConversationItemCreate(create_event))?.into())).await?;
-
-                         let response_event = oai_realtime::ResponseCreateEvent{ response: None, event_id: None };
-                         openai_tx.send(WsMessage::Text(serde_json::to_string(&OAIClientEvent::ResponseCreate(response_event))?.into())).await?;
+                        playback.expect_text(&text);
+                        openai_tx.send(WsMessage::Text(speak_message(&text)?.into())).await?;
+                    }
+                    RealtimeClientEvent::UpdateSettings(new_settings) => {
+                        info!(?new_settings, "Applying updated voice settings to OpenAI session.");
+                        openai_tx.send(WsMessage::Text(session_update_message(&state.config, &new_settings, instructions, tool_declarations)?.into())).await?;
+                        link.set_settings(new_settings);
+                    }
+                    RealtimeClientEvent::SpeechStarted => {
+                        let mut sink = socket_tx.lock().await;
+                        send_msg(&mut sink, ServerMessage::UserSpeakingStart).await?;
+                        if let Some(interruption) = interrupt_playback(&mut sink, link, &mut playback, &mut ai_speaking, tools.is_none().then_some(events_tx)).await? {
+                            for message in interruption_messages(response_active, audio_item_id.take(), interruption.played_ms)? {
+                                openai_tx.send(WsMessage::Text(message.into())).await?;
+                            }
+                        }
+                    }
+                    RealtimeClientEvent::SpeechStopped => {
+                        send_msg(&mut *socket_tx.lock().await, ServerMessage::UserSpeakingEnd).await?;
+                        let commit_event = OAIClientEvent::InputAudioBufferCommit(oai_realtime::InputAudioBufferCommitEvent { event_id: None });
+                        openai_tx.send(WsMessage::Text(serde_json::to_string(&commit_event)?.into())).await?;
+                        // With tools the realtime model answers the turn itself.
+                        if tools.is_some() {
+                            let response_event = oai_realtime::ResponseCreateEvent { event_id: None, response: None };
+                            openai_tx.send(WsMessage::Text(serde_json::to_string(&OAIClientEvent::ResponseCreate(response_event))?.into())).await?;
+                        }
+                    }
+                    RealtimeClientEvent::SpeechCancelled => {
+                        send_msg(&mut *socket_tx.lock().await, ServerMessage::UserSpeakingEnd).await?;
+                        let clear_event = OAIClientEvent::InputAudioBufferClear(oai_realtime::InputAudioBufferClearEvent { event_id: None });
+                        openai_tx.send(WsMessage::Text(serde_json::to_string(&clear_event)?.into())).await?;
                     }
                 }
             },
             // Handle events from the OpenAI server (e.g., audio to play).
-            Some(msg_result) = openai_rx.next() => {
-                if let Ok(WsMessage::Text(text)) = msg_result {
-                    if let Ok(server_event) = serde_json::from_str::<OAIServerEvent>(&text) {
-                        let mut sink = socket_tx.lock().await;
-                        match server_event {
-                            OAIServerEvent::ConversationItemInputAudioTranscriptionDelta(e) => send_msg(&mut sink, ServerMessage::TranscriptionUpdate { text: e.delta, is_final: false }).await?,
-                            OAIServerEvent::ConversationItemInputAudioTranscriptionCompleted(e) => send_msg(&mut sink, ServerMessage::TranscriptionUpdate { text: e.transcript, is_final: true }).await?,
-                            OAIServerEvent::ResponseAudioDelta(e) => send_msg(&mut sink, ServerMessage::AudioChunk { data: e.delta }).await?,
-                            OAIServerEvent::InputAudioBufferSpeechStarted(_) => send_msg(&mut sink, ServerMessage::AiSpeakingStart).await?,
-                            OAIServerEvent::InputAudioBufferSpeechStopped(_) => send_msg(&mut sink, ServerMessage::AiSpeakingEnd).await?,
-                            OAIServerEvent::ResponseDone(_) => send_msg(&mut sink, ServerMessage::AiSpeakingEnd).await?,
-                            OAIServerEvent::Error(e) => send_msg(&mut sink, ServerMessage::Error { message: e.error.message }).await?,
-                            _ => {}
+            msg = openai_rx.next() => {
+                let text = match msg {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(close_frame))) => break anyhow!("OpenAI closed the connection: {:?}", close_frame),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => break anyhow!(e).context("Error reading from OpenAI Realtime WebSocket"),
+                    None => break anyhow!("OpenAI Realtime WebSocket ended"),
+                };
+                let Ok(server_event) = serde_json::from_str::<OAIServerEvent>(&text) else {
+                    continue;
+                };
+                if let OAIServerEvent::ResponseOutputItemDone(e) = &server_event
+                    && let Some(tools) = tools
+                    && matches!(e.item.r#type, Some(oai_realtime::ItemType::FunctionCall))
+                {
+                    let name = e.item.name.clone().unwrap_or_default();
+                    let output = match e.item.arguments.as_deref().filter(|a| !a.trim().is_empty()) {
+                        Some(arguments) => match serde_json::from_str(arguments) {
+                            Ok(arguments) => tools.call(&name, arguments).await,
+                            Err(e) => serde_json::json!({ "error": format!("Invalid arguments: {}", e) }).to_string(),
+                        },
+                        None => tools.call(&name, serde_json::Value::Null).await,
+                    };
+                    let call_id = e.item.call_id.clone().unwrap_or_default();
+                    openai_tx.send(WsMessage::Text(function_output_message(call_id, output)?.into())).await?;
+                    tool_outputs_pending = true;
+                    continue;
+                }
+
+                let mut sink = socket_tx.lock().await;
+                match server_event {
+                    OAIServerEvent::ConversationItemInputAudioTranscriptionDelta(e) => send_msg(&mut sink, ServerMessage::TranscriptionUpdate { text: e.delta, is_final: false }).await?,
+                    OAIServerEvent::ConversationItemInputAudioTranscriptionCompleted(e) => {
+                        send_msg(&mut sink, ServerMessage::TranscriptionUpdate { text: e.transcript.clone(), is_final: true }).await?;
+                        link.remember(Speaker::User, &e.transcript);
+                        if tools.is_none() && !e.transcript.trim().is_empty() {
+                            events_tx.send(RealtimeServerEvent::UserTranscript(e.transcript)).await?;
+                        }
+                    }
+                    OAIServerEvent::ResponseCreated(_) => response_active = true,
+                    OAIServerEvent::ResponseAudioDelta(e) => {
+                        if !ai_speaking {
+                            ai_speaking = true;
+                            send_msg(&mut sink, ServerMessage::AiSpeakingStart).await?;
                         }
+                        let samples = audio_utils::decode_i16(&e.delta);
+                        playback.record_audio(samples.len(), audio_utils::OPENAI_REALTIME_API_PCM16_SAMPLE_RATE);
+                        audio_item_id = Some(e.item_id);
+                        link.audio().send(&mut sink, &samples, audio_utils::OPENAI_REALTIME_API_PCM16_SAMPLE_RATE).await?;
                     }
+                    OAIServerEvent::ResponseAudioTranscriptDelta(e) => playback.record_transcript(&e.delta),
+                    OAIServerEvent::ResponseAudioTranscriptDone(e) => link.remember(Speaker::Assistant, &e.transcript),
+                    OAIServerEvent::InputAudioBufferSpeechStarted(_) => {
+                        send_msg(&mut sink, ServerMessage::UserSpeakingStart).await?;
+                        if let Some(interruption) = interrupt_playback(&mut sink, link, &mut playback, &mut ai_speaking, tools.is_none().then_some(events_tx)).await? {
+                            for message in interruption_messages(response_active, audio_item_id.take(), interruption.played_ms)? {
+                                openai_tx.send(WsMessage::Text(message.into())).await?;
+                            }
+                        }
+                    }
+                    OAIServerEvent::InputAudioBufferSpeechStopped(_) => send_msg(&mut sink, ServerMessage::UserSpeakingEnd).await?,
+                    OAIServerEvent::ResponseDone(_) => {
+                        response_active = false;
+                        if std::mem::take(&mut ai_speaking) {
+                            link.audio().finish(&mut sink).await?;
+                            send_msg(&mut sink, ServerMessage::AiSpeakingEnd).await?;
+                        }
+                        if std::mem::take(&mut tool_outputs_pending) {
+                            let response_event = oai_realtime::ResponseCreateEvent { event_id: None, response: None };
+                            openai_tx.send(WsMessage::Text(serde_json::to_string(&OAIClientEvent::ResponseCreate(response_event))?.into())).await?;
+                        }
+                    }
+                    OAIServerEvent::Error(e) => send_msg(&mut sink, ServerMessage::Error { message: e.error.message, code: None }).await?,
+                    _ => {}
                 }
             },
         }
+    };
+
+    // The response in progress is lost with the connection.
+    link.audio().discard();
+    if ai_speaking {
+        send_msg(&mut *socket_tx.lock().await, ServerMessage::AiSpeakingEnd).await?;
+    }
+    Err(disconnected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn settings() -> VoiceSettings {
+        VoiceSettings {
+            voice: "shimmer".to_string(),
+            speaking_rate: 1.0,
+            vad_threshold: 0.65,
+            vad_prefix_padding_ms: 250,
+            vad_silence_duration_ms: 900,
+            turn_detection: TurnDetection::Provider,
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            openai_realtime_model: "gpt-4o-mini-realtime-preview".to_string(),
+            openai_transcription_model: "gpt-4o-mini-transcribe".to_string(),
+            ..Config::for_tests()
+        }
+    }
+
+    #[test]
+    fn test_session_update_uses_settings() {
+        let payload: Value = serde_json::from_str(
+            &session_update_message(&config(), &settings(), VOICE_INSTRUCTIONS, None).unwrap(),
+        )
+        .unwrap();
+        let session = &payload["session"];
+
+        assert_eq!(payload["type"], "session.update");
+        assert_eq!(session["model"], "gpt-4o-mini-realtime-preview");
+        assert_eq!(session["voice"], "shimmer");
+        assert_eq!(
+            session["input_audio_transcription"]["model"],
+            "gpt-4o-mini-transcribe"
+        );
+        assert_eq!(session["turn_detection"]["type"], "server_vad");
+        assert_eq!(session["turn_detection"]["prefix_padding_ms"], 250);
+        assert_eq!(session["turn_detection"]["silence_duration_ms"], 900);
+        assert_eq!(session["turn_detection"]["create_response"], false);
+        assert_eq!(session["instructions"], VOICE_INSTRUCTIONS);
+        assert!(session.get("tools").is_none());
+        assert!(session.get("speed").is_none());
+    }
+
+    #[test]
+    fn test_session_update_disables_provider_vad_for_server_turns() {
+        let mut settings = settings();
+        settings.turn_detection = TurnDetection::Server;
+        let payload: Value = serde_json::from_str(
+            &session_update_message(&config(), &settings, VOICE_INSTRUCTIONS, None).unwrap(),
+        )
+        .unwrap();
+        assert!(payload["session"]["turn_detection"].is_null());
+        assert!(payload["session"].get("turn_detection").is_some());
+    }
+
+    #[test]
+    fn test_session_update_speaking_rate_and_invalid_voice() {
+        let mut settings = settings();
+        settings.speaking_rate = 1.25;
+        let payload: Value = serde_json::from_str(
+            &session_update_message(&config(), &settings, VOICE_INSTRUCTIONS, None).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(payload["session"]["speed"], 1.25);
+
+        settings.voice = "robot".to_string();
+        assert!(session_update_message(&config(), &settings, VOICE_INSTRUCTIONS, None).is_err());
+    }
+
+    #[test]
+    fn test_session_update_declares_tools() {
+        let tool = Tool::new(
+            "update_subtopic_status",
+            "Updates a subtopic.",
+            Arc::new(
+                serde_json::json!({ "type": "object", "properties": { "subtopic_name": { "type": "string" } } })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ),
+        );
+        let payload: Value = serde_json::from_str(
+            &session_update_message(&config(), &settings(), "Be curious.", Some(&[tool])).unwrap(),
+        )
+        .unwrap();
+        let session = &payload["session"];
+
+        assert_eq!(session["instructions"], "Be curious.");
+        assert_eq!(session["turn_detection"]["create_response"], true);
+        assert_eq!(session["tool_choice"], "auto");
+        assert_eq!(session["tools"][0]["type"], "function");
+        assert_eq!(session["tools"][0]["name"], "update_subtopic_status");
+        assert_eq!(
+            session["tools"][0]["parameters"]["properties"]["subtopic_name"]["type"],
+            "string"
+        );
+    }
+
+    #[test]
+    fn test_function_output_message() {
+        let payload: Value = serde_json::from_str(
+            &function_output_message("call_1".to_string(), "ok".to_string()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(payload["type"], "conversation.item.create");
+        assert_eq!(payload["item"]["type"], "function_call_output");
+        assert_eq!(payload["item"]["call_id"], "call_1");
+        assert_eq!(payload["item"]["output"], "ok");
+    }
+
+    #[test]
+    fn test_context_item_message() {
+        let user: Value = serde_json::from_str(
+            &context_item_message(&ContextTurn {
+                speaker: Speaker::User,
+                text: "What is a node?".to_string(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(user["type"], "conversation.item.create");
+        assert_eq!(user["item"]["type"], "message");
+        assert_eq!(user["item"]["role"], "user");
+        assert_eq!(user["item"]["content"][0]["type"], "input_text");
+        assert_eq!(user["item"]["content"][0]["text"], "What is a node?");
+
+        let assistant: Value = serde_json::from_str(
+            &context_item_message(&ContextTurn {
+                speaker: Speaker::Assistant,
+                text: "It holds a value.".to_string(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(assistant["item"]["role"], "assistant");
+        assert_eq!(assistant["item"]["content"][0]["type"], "text");
+    }
+
+    #[test]
+    fn test_interruption_messages() {
+        let messages: Vec<Value> = interruption_messages(true, Some("item_1".to_string()), 1200)
+            .unwrap()
+            .iter()
+            .map(|m| serde_json::from_str(m).unwrap())
+            .collect();
+        assert_eq!(messages[0]["type"], "response.cancel");
+        assert_eq!(messages[1]["type"], "conversation.item.truncate");
+        assert_eq!(messages[1]["item_id"], "item_1");
+        assert_eq!(messages[1]["audio_end_ms"], 1200);
+
+        // A finished response only needs its audio truncated.
+        let messages = interruption_messages(false, Some("item_1".to_string()), 1200).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(interruption_messages(false, None, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_speak_message_reads_text_verbatim() {
+        let payload: Value =
+            serde_json::from_str(&speak_message("Tell me more.").unwrap()).unwrap();
+        assert_eq!(payload["type"], "response.create");
+        assert!(
+            payload["response"]["instructions"]
+                .as_str()
+                .unwrap()
+                .ends_with("Tell me more.")
+        );
     }
 }
@@ -0,0 +1,140 @@
+//! Estimates how much of the AI's streamed audio the browser has played, so that a
+//! barge-in can cut the response where the user actually stopped hearing it.
+
+use std::time::{Duration, Instant};
+
+/// What the user heard of an interrupted AI response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interruption {
+    /// The approximate playback position when the user interrupted, in milliseconds.
+    pub played_ms: u32,
+    /// The part of the response text that was spoken before the interruption.
+    pub spoken_text: String,
+}
+
+/// Follows the AI audio sent to the browser.
+///
+/// The browser starts playing as soon as the first chunk arrives and plays chunks
+/// back to back, so the playback position is the wall-clock time since the first
+/// chunk, capped at the amount of audio sent.
+#[derive(Debug, Default)]
+pub struct PlaybackTracker {
+    started_at: Option<Instant>,
+    sent: Duration,
+    transcript: String,
+    requested_text: Option<String>,
+}
+
+impl PlaybackTracker {
+    /// Remembers the text the next response was asked to speak, used to work out
+    /// the spoken part when the provider sends no transcript of its audio.
+    pub fn expect_text(&mut self, text: &str) {
+        self.requested_text = Some(text.to_string());
+    }
+
+    /// Records `samples` of audio at `sample_rate` sent to the browser.
+    pub fn record_audio(&mut self, samples: usize, sample_rate: f64) {
+        if !self.is_playing() {
+            self.started_at = Some(Instant::now());
+            self.sent = Duration::ZERO;
+            self.transcript.clear();
+        }
+        self.sent += Duration::from_secs_f64(samples as f64 / sample_rate);
+    }
+
+    /// Records a chunk of the provider's transcript of the audio being sent.
+    pub fn record_transcript(&mut self, delta: &str) {
+        self.transcript.push_str(delta);
+    }
+
+    /// Whether the browser is still playing audio that has been sent.
+    pub fn is_playing(&self) -> bool {
+        self.started_at
+            .is_some_and(|started_at| started_at.elapsed() < self.sent)
+    }
+
+    /// Stops tracking the current audio and, if it was still playing, returns what
+    /// the user heard of it.
+    pub fn interrupt(&mut self) -> Option<Interruption> {
+        let started_at = self.started_at.take()?;
+        let played = started_at.elapsed();
+        if played >= self.sent {
+            return None;
+        }
+
+        let text = if self.transcript.is_empty() {
+            self.requested_text.take().unwrap_or_default()
+        } else {
+            std::mem::take(&mut self.transcript)
+        };
+        let fraction = played.as_secs_f64() / self.sent.as_secs_f64();
+        Some(Interruption {
+            played_ms: played.as_millis() as u32,
+            spoken_text: spoken_prefix(&text, fraction),
+        })
+    }
+}
+
+/// Returns the leading `fraction` of `text`, cut back to the last whole word.
+fn spoken_prefix(text: &str, fraction: f64) -> String {
+    let total = text.chars().count();
+    let cut = (total as f64 * fraction.clamp(0.0, 1.0)).floor() as usize;
+    if cut >= total {
+        return text.to_string();
+    }
+    let (byte_cut, next) = text.char_indices().nth(cut).unwrap();
+    if next.is_whitespace() {
+        return text[..byte_cut].trim_end().to_string();
+    }
+    match text[..byte_cut].rfind(char::is_whitespace) {
+        Some(end) => text[..end].trim_end().to_string(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spoken_prefix_cuts_at_word_boundaries() {
+        let text = "Tell me more about nodes.";
+        assert_eq!(spoken_prefix(text, 0.0), "");
+        assert_eq!(spoken_prefix(text, 0.1), "");
+        assert_eq!(spoken_prefix(text, 0.3), "Tell me");
+        assert_eq!(spoken_prefix(text, 0.5), "Tell me more");
+        assert_eq!(spoken_prefix(text, 1.0), text);
+        assert_eq!(spoken_prefix("Ünïcödé wörds hére", 0.6), "Ünïcödé");
+    }
+
+    #[test]
+    fn test_interrupt_only_while_playing() {
+        let mut tracker = PlaybackTracker::default();
+        assert!(tracker.interrupt().is_none());
+
+        // Ten seconds of audio cannot have finished playing yet.
+        tracker.expect_text("Tell me more about nodes.");
+        tracker.record_audio(240_000, 24_000.0);
+        assert!(tracker.is_playing());
+        let interruption = tracker.interrupt().unwrap();
+        assert!(interruption.played_ms < 10_000);
+        assert!("Tell me more about nodes.".starts_with(&interruption.spoken_text));
+        assert!(!tracker.is_playing());
+
+        // Audio that has already played out is not interrupted.
+        tracker.record_audio(0, 24_000.0);
+        assert!(tracker.interrupt().is_none());
+    }
+
+    #[test]
+    fn test_transcript_takes_precedence_over_requested_text() {
+        let mut tracker = PlaybackTracker::default();
+        tracker.expect_text("Requested text that was never spoken.");
+        tracker.record_audio(240_000, 24_000.0);
+        tracker.record_transcript("Spoken");
+        tracker.record_transcript(" words");
+        std::thread::sleep(Duration::from_millis(20));
+        let interruption = tracker.interrupt().unwrap();
+        assert!("Spoken words".starts_with(&interruption.spoken_text));
+    }
+}
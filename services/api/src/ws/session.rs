@@ -1,11 +1,14 @@
 //! Manages the primary WebSocket connection lifecycle for an agent session.
 
 use super::{
-    cycle::handle_react_cycle,
-    protocol::{ClientMessage, ServerMessage},
+    protocol::{AudioCodec, AudioTransport, ClientMessage, ServerMessage},
     provider,
+    turn::{TurnContext, TurnRequest, TurnRunner},
+};
+use crate::{
+    audio_utils, config::ToolTransport, models, quota::QuotaExceeded, recordings::Recorder,
+    state::AppState, usage::AudioMeter,
 };
-use crate::{models, state::AppState};
 use anyhow::{Context, Result, anyhow};
 use axum::{
     extract::{
@@ -14,13 +17,15 @@ use axum::{
     },
     response::Response,
 };
-use feynman_core::agent::{FeynmanAgent, FeynmanService};
+use feynman_core::{
+    agent::{FeynmanAgent, FeynmanService},
+    tools::{McpTools, ToolRegistry, ToolSet},
+};
 use futures_util::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
-use rmcp::ServiceExt;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::{
     sync::{Mutex, mpsc},
     task::JoinHandle,
@@ -28,6 +33,10 @@ use tokio::{
 use tracing::{Instrument, error, info, instrument, warn};
 use uuid::Uuid;
 
+/// How often a voice session records its audio usage and re-checks the
+/// user's voice minutes.
+const VOICE_BUDGET_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
 /// Axum handler to upgrade an HTTP connection to a WebSocket.
 pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Response {
     ws.on_upgrade(|socket| handle_socket(socket, state))
@@ -41,14 +50,14 @@ pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>
 #[instrument(name = "ws_session", skip_all, fields(session_id))]
 async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     let temp_id: u32 = rand::random();
-    tracing::Span::current().record("session_id", &temp_id.to_string());
+    tracing::Span::current().record("session_id", temp_id.to_string());
     info!("New WebSocket connection. Awaiting initialization...");
 
     let (socket_tx, mut socket_rx) = socket.split();
     let socket_tx_arc = Arc::new(Mutex::new(socket_tx));
 
     // The first message from the client must be an `init` message.
-    let (session_id, topic, agent_state, history) =
+    let (session_id, user_id, topic, agent_state, history, audio_transport, audio_codec) =
         if let Some(Ok(ws_msg)) = socket_rx.next().await {
             match ws_msg {
                 Message::Text(text) => initialize_session_state(&text, &state).await,
@@ -68,6 +77,7 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                     &mut sink,
                     ServerMessage::Error {
                         message: e.to_string(),
+                        code: None,
                     },
                 )
                 .await;
@@ -76,8 +86,11 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
             (
                 Uuid::nil(),
                 String::new(),
+                String::new(),
                 FeynmanAgent::new("".into(), vec![]),
                 vec![],
+                AudioTransport::default(),
+                AudioCodec::default(),
             )
         });
 
@@ -111,8 +124,11 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                 socket_tx_arc,
                 socket_rx,
                 session_id,
+                user_id,
                 agent_state,
                 history,
+                audio_transport,
+                audio_codec,
             )
             .await
             {
@@ -128,57 +144,126 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
 async fn initialize_session_state(
     init_text: &str,
     state: &Arc<AppState>,
-) -> Result<(Uuid, String, FeynmanAgent, Vec<models::Message>)> {
+) -> Result<(
+    Uuid,
+    String,
+    String,
+    FeynmanAgent,
+    Vec<models::Message>,
+    AudioTransport,
+    AudioCodec,
+)> {
     let init_msg: ClientMessage = serde_json::from_str(init_text)?;
-    let (topic, session_id) = if let ClientMessage::Init { topic, session_id } = init_msg {
+    let (topic, session_id, audio_transport, audio_codec) = if let ClientMessage::Init {
+        topic,
+        session_id,
+        audio_transport,
+        audio_codec,
+    } = init_msg
+    {
         (
             topic,
             session_id.context("`session_id` is required for `init`")?,
+            audio_transport,
+            audio_codec,
         )
     } else {
         return Err(anyhow!("First message must be `init`"));
     };
 
     tracing::Span::current().record("topic", &topic);
-    tracing::Span::current().record("session_id", &session_id.to_string());
+    tracing::Span::current().record("session_id", session_id.to_string());
     info!("Resuming existing session");
 
+    let user_id = state
+        .db
+        .get_session_user(session_id)
+        .await?
+        .context("Session not found")?;
     let agent_state = state
         .db
         .get_latest_agent_state(session_id)
         .await?
         .context("Session state not found")?;
     let history = state.db.get_session_messages(session_id).await?;
-    Ok((session_id, topic, agent_state, history))
+    Ok((
+        session_id,
+        user_id,
+        topic,
+        agent_state,
+        history,
+        audio_transport,
+        audio_codec,
+    ))
 }
 
 /// The main event loop for an active WebSocket session.
 ///
 /// This function listens for messages from the client, updates from the agent's
 /// internal state, and orchestrates the interaction between them.
+#[allow(clippy::too_many_arguments)]
 async fn run_agent_session(
     state: Arc<AppState>,
     socket_tx: Arc<Mutex<SplitSink<WebSocket, Message>>>,
     mut socket_rx: SplitStream<WebSocket>,
     session_id: Uuid,
+    user_id: String,
     agent_state: FeynmanAgent,
-    mut history: Vec<models::Message>,
+    history: Vec<models::Message>,
+    audio_transport: AudioTransport,
+    audio_codec: AudioCodec,
 ) -> Result<()> {
     let agent_state_arc = Arc::new(tokio::sync::Mutex::new(agent_state));
-    let (state_update_tx, mut state_update_rx) = mpsc::channel(8);
+    let (state_update_tx, state_update_rx) = mpsc::channel(8);
+    let state_updates = Arc::new(tokio::sync::Mutex::new(state_update_rx));
     let feynman_service = FeynmanService::new(agent_state_arc.clone(), Some(state_update_tx));
-    let (server_transport, client_transport) = tokio::io::duplex(4096);
-
-    // Spawn the agent's tool-handling service.
-    let agent_tool_handle = tokio::spawn(async move {
-        if let Ok(service) = feynman_service.serve(server_transport).await {
-            let _ = service.waiting().await;
-        }
-    });
-    let mcp_client = ().serve(client_transport).await?;
+    // The ReAct cycle and the realtime model share the agent's tools, plus
+    // the external tools this session allows.
+    let builtin_tools: Arc<dyn ToolRegistry> = match state.config.tool_transport {
+        ToolTransport::Direct => Arc::new(feynman_service),
+        ToolTransport::Mcp => Arc::new(McpTools::serve_in_process(feynman_service).await?),
+    };
+    let allowed_tools = state.db.get_allowed_tools(session_id).await?;
+    let tools: Arc<dyn ToolRegistry> = Arc::new(ToolSet::new(
+        builtin_tools,
+        &state.external_tools,
+        &allowed_tools,
+    ));
 
     let mut realtime_tx: Option<mpsc::Sender<provider::RealtimeClientEvent>> = None;
     let mut realtime_task_handle: Option<JoinHandle<()>> = None;
+    let mut voice_settings = provider::VoiceSettings::from_config(&state.config);
+    let (realtime_event_tx, mut realtime_event_rx) = mpsc::channel(16);
+    // Converts microphone audio to the provider's input format while voice is enabled.
+    let mut audio_input: Option<provider::AudioInput> = None;
+    // Records voice audio per utterance when the session opted in and a
+    // recording store is configured.
+    let record_audio = state.db.get_record_audio(session_id).await?;
+    let recorder = Recorder::for_session(&state, session_id, record_audio);
+    // Counts the voice audio exchanged with the realtime provider, for usage accounting.
+    let audio_meter = AudioMeter::default();
+    // While voice is on, usage is recorded and the budget re-checked regularly,
+    // since the user's other sessions draw on the same minutes.
+    let mut voice_budget_check = tokio::time::interval_at(
+        tokio::time::Instant::now() + VOICE_BUDGET_CHECK_INTERVAL,
+        VOICE_BUDGET_CHECK_INTERVAL,
+    );
+    voice_budget_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let history = Arc::new(Mutex::new(history));
+    // ReAct turns run in the background so this loop never waits on the LLM.
+    let mut turns = TurnRunner::new(
+        TurnContext {
+            state: state.clone(),
+            session_id,
+            history: history.clone(),
+            agent_state: agent_state_arc.clone(),
+            tools: tools.clone(),
+            socket_tx: socket_tx.clone(),
+            recorder: recorder.clone(),
+            state_updates: state_updates.clone(),
+        },
+        state.config.turn_policy,
+    );
 
     loop {
         tokio::select! {
@@ -190,31 +275,145 @@ async fn run_agent_session(
                             if let Ok(msg) = serde_json::from_str::<ClientMessage>(&text) {
                                 match msg {
                                     ClientMessage::UserMessage { text } => {
-                                        handle_react_cycle(&state, session_id, &mut history, &agent_state_arc, &mcp_client, &text, &socket_tx, &realtime_tx).await?;
+                                        if admit_turn(&state, &user_id, &socket_tx).await? {
+                                            turns.submit(TurnRequest { text, clip: None }, &realtime_tx).await?;
+                                        }
                                     }
-                                    ClientMessage::SetVoiceEnabled { enabled } => {
+                                    ClientMessage::CancelTurn { turn_id } => {
+                                        if !turns.cancel(turn_id, &realtime_tx).await? {
+                                            info!(?turn_id, "No matching turn to cancel.");
+                                        }
+                                    }
+                                    ClientMessage::SetVoiceEnabled { enabled, capture, playback_sample_rate } => {
                                         if enabled {
                                             if let Some(handle) = realtime_task_handle.take() { handle.abort(); }
-                                            let (tx, handle) = provider::start_realtime_provider(state.clone(), socket_tx.clone()).await?;
+                                            // Voice stops once the user's monthly minutes run out.
+                                            audio_meter.record(&state, session_id).await;
+                                            match quota_check(state.quotas.voice_seconds_left(&state.db, &user_id).await, &socket_tx).await? {
+                                                Some(left) => audio_meter.set_budget(left),
+                                                None => {
+                                                    realtime_tx = None;
+                                                    audio_input = None;
+                                                    continue;
+                                                }
+                                            }
+                                            let audio = provider::AudioInput::new(audio_codec, capture, &state.config.realtime_provider)
+                                                .and_then(|input| Ok((input, provider::AudioOutput::new(audio_transport, audio_codec, playback_sample_rate)?.with_recorder(recorder.clone()).with_meter(audio_meter.clone()))));
+                                            let (mut input, output) = match audio {
+                                                Ok(audio) => audio,
+                                                Err(e) => {
+                                                    warn!(error = ?e, "Rejected voice audio format.");
+                                                    realtime_tx = None;
+                                                    audio_input = None;
+                                                    send_msg(&mut *socket_tx.lock().await, ServerMessage::Error { message: e.to_string(), code: None }).await?;
+                                                    continue;
+                                                }
+                                            };
+                                            // Let the realtime model call the agent's tools directly when configured.
+                                            let realtime_tools = if state.config.realtime_tools {
+                                                Some(provider::RealtimeTools::new(tools.clone()))
+                                            } else {
+                                                None
+                                            };
+                                            let (tx, handle) = provider::start_realtime_provider(state.clone(), socket_tx.clone(), voice_settings.clone(), output, realtime_event_tx.clone(), realtime_tools).await?;
                                             realtime_tx = Some(tx);
                                             realtime_task_handle = Some(handle);
+                                            input.configure(&voice_settings);
+                                            audio_input = Some(input);
                                         } else {
                                             if let Some(handle) = realtime_task_handle.take() {
                                                 handle.abort();
                                                 info!("Aborted realtime provider task.");
                                             }
                                             realtime_tx = None;
+                                            audio_input = None;
+                                            audio_meter.record(&state, session_id).await;
                                             info!("Voice disabled by client.");
                                         }
                                     }
+                                    ClientMessage::UpdateVoiceSettings(update) => {
+                                        match voice_settings.apply(&state.config.realtime_provider, update) {
+                                            Ok(()) => {
+                                                info!(settings = ?voice_settings, "Voice settings updated by client.");
+                                                if let Some(input) = &mut audio_input {
+                                                    input.configure(&voice_settings);
+                                                }
+                                                if let Some(tx) = &realtime_tx
+                                                    && tx.send(provider::RealtimeClientEvent::UpdateSettings(voice_settings.clone())).await.is_err()
+                                                {
+                                                    warn!("Voice provider task is gone; settings apply on next enable.");
+                                                }
+                                            }
+                                            Err(message) => {
+                                                warn!(%message, "Rejected voice settings update.");
+                                                send_msg(&mut *socket_tx.lock().await, ServerMessage::Error { message, code: None }).await?;
+                                            }
+                                        }
+                                    }
+                                    ClientMessage::BeginUtterance | ClientMessage::EndUtterance | ClientMessage::CancelUtterance => {
+                                        let (open, event) = match msg {
+                                            ClientMessage::BeginUtterance => (true, provider::RealtimeClientEvent::SpeechStarted),
+                                            ClientMessage::EndUtterance => (false, provider::RealtimeClientEvent::SpeechStopped),
+                                            _ => (false, provider::RealtimeClientEvent::SpeechCancelled),
+                                        };
+                                        let marked = match (&realtime_tx, &mut audio_input) {
+                                            (Some(tx), Some(input)) => input.mark_utterance(open).map(|changed| changed.then_some(tx)),
+                                            _ => Err(anyhow!("Push-to-talk needs voice to be enabled.")),
+                                        };
+                                        match marked {
+                                            Ok(Some(tx)) => {
+                                                if let Err(e) = tx.send(event).await {
+                                                    error!("Failed to send utterance marker to provider task: {}", e);
+                                                }
+                                            }
+                                            // Repeated markers, e.g. from a bouncing key, change nothing.
+                                            Ok(None) => {}
+                                            Err(e) => {
+                                                warn!(error = ?e, "Rejected utterance marker.");
+                                                send_msg(&mut *socket_tx.lock().await, ServerMessage::Error { message: e.to_string(), code: None }).await?;
+                                            }
+                                        }
+                                    }
                                     _ => warn!("Ignoring unexpected text message post-init."),
                                 }
                             }
                         },
                         Message::Binary(data) => {
-                            if let Some(tx) = &realtime_tx {
-                               if let Err(e) = tx.send(provider::RealtimeClientEvent::Audio(data.into())).await {
-                                   error!("Failed to send audio to provider task: {}", e);
+                            // The provider task stopped on its own, e.g. after running out of voice minutes.
+                            if realtime_tx.as_ref().is_some_and(|tx| tx.is_closed()) {
+                                info!("Realtime provider task has stopped; turning voice off.");
+                                realtime_tx = None;
+                                audio_input = None;
+                                audio_meter.record(&state, session_id).await;
+                            }
+                            if let (Some(tx), Some(input)) = (&realtime_tx, &mut audio_input) {
+                               let (data, speech_events) = match input.convert(&data) {
+                                   Ok(converted) => converted,
+                                   Err(e) => {
+                                       warn!("Dropping undecodable audio frame: {:?}", e);
+                                       continue;
+                                   }
+                               };
+                               // Nothing to forward, e.g. a partial frame or push-to-talk audio outside an utterance.
+                               if data.is_empty() && speech_events.is_empty() {
+                                   continue;
+                               }
+                               let input_rate = provider::input_sample_rate(&state.config.realtime_provider);
+                               if let Some(recorder) = &recorder {
+                                   recorder.record(models::MessageRole::User, &audio_utils::le_bytes_to_i16(&data), input_rate);
+                               }
+                               audio_meter.add_input(data.len() / 2, input_rate);
+                               // Speech changes follow the audio they were detected in, so a commit includes it.
+                               let events = std::iter::once(provider::RealtimeClientEvent::Audio(data)).chain(
+                                   speech_events.into_iter().map(|event| match event {
+                                       audio_utils::VadEvent::SpeechStarted => provider::RealtimeClientEvent::SpeechStarted,
+                                       audio_utils::VadEvent::SpeechStopped => provider::RealtimeClientEvent::SpeechStopped,
+                                   }),
+                               );
+                               for event in events {
+                                   if let Err(e) = tx.send(event).await {
+                                       error!("Failed to send audio to provider task: {}", e);
+                                   }
                                }
                             } else {
                                 warn!("Received audio data from client, but no voice provider is active.");
@@ -232,10 +431,40 @@ async fn run_agent_session(
                     }
                 }
             },
+            // Handle final voice transcripts as user turns.
+            Some(event) = realtime_event_rx.recv() => {
+                match event {
+                    provider::RealtimeServerEvent::UserTranscript(text) => {
+                        info!(%text, "Submitting voice transcript as a turn.");
+                        // The utterance ends here; audio arriving during the turn belongs to the next one.
+                        let clip = recorder.as_ref().map(|recorder| recorder.take(models::MessageRole::User));
+                        if admit_turn(&state, &user_id, &socket_tx).await? {
+                            turns.submit(TurnRequest { text, clip }, &realtime_tx).await?;
+                        }
+                    }
+                    provider::RealtimeServerEvent::Interrupted(interruption) => {
+                        // Keep only what the user heard, so later turns see the real conversation.
+                        if let Some(message_id) = turns.take_spoken_reply() {
+                            info!(message_id, played_ms = interruption.played_ms, "Truncating interrupted AI reply.");
+                            let updated = state.db.update_message_content(message_id, &interruption.spoken_text).await?;
+                            if let Some(message) = history.lock().await.iter_mut().rev().find(|m| m.id == message_id) {
+                                *message = updated;
+                            }
+                        }
+                    }
+                }
+            },
+            // Stop voice once the user's minutes run out across all their sessions.
+            _ = voice_budget_check.tick(), if realtime_tx.is_some() => {
+                refresh_voice_budget(&state, session_id, &user_id, &audio_meter).await;
+            },
+            // Wrap up finished turns and start the next queued message.
+            result = turns.wait(), if turns.is_running() => {
+                turns.complete(result, &realtime_tx).await?;
+            },
             // Handle state updates from the agent's internal logic.
-            Some(new_state) = state_update_rx.recv() => {
-                state.db.update_agent_state(session_id, &new_state).await?;
-                send_msg(&mut *socket_tx.lock().await, ServerMessage::StateUpdate { state: new_state }).await?;
+            Some(new_state) = async { state_updates.lock().await.recv().await } => {
+                turns.save_state(new_state).await?;
             },
             // If all channels close, exit the loop.
             else => break,
@@ -243,14 +472,75 @@ async fn run_agent_session(
     }
 
     // Clean up background tasks on exit.
+    drop(turns);
     if let Some(handle) = realtime_task_handle.take() {
         handle.abort();
     }
-    agent_tool_handle.abort();
+    audio_meter.record(&state, session_id).await;
     info!("WebSocket connection closed and agent session terminated.");
     Ok(())
 }
 
+/// Records the voice audio used so far and re-reads the user's remaining
+/// minutes into the meter. An exceeded quota empties the budget, which stops
+/// the provider task; if the quota cannot be checked the budget is kept.
+async fn refresh_voice_budget(
+    state: &AppState,
+    session_id: Uuid,
+    user_id: &str,
+    audio_meter: &AudioMeter,
+) {
+    audio_meter.record(state, session_id).await;
+    match state.quotas.voice_seconds_left(&state.db, user_id).await {
+        Ok(left) => audio_meter.set_budget(left),
+        Err(e) => match e.downcast::<QuotaExceeded>() {
+            Ok(_) => audio_meter.set_budget(Some(0.0)),
+            Err(e) => warn!(error = ?e, "Failed to re-check the voice quota."),
+        },
+    }
+}
+
+/// Checks the user's quotas before a turn. An exceeded quota is reported to
+/// the client and the turn is dropped.
+async fn admit_turn(
+    state: &AppState,
+    user_id: &str,
+    socket_tx: &Mutex<SplitSink<WebSocket, Message>>,
+) -> Result<bool> {
+    let checked = state.quotas.check_turn(&state.db, user_id).await;
+    Ok(quota_check(checked, socket_tx).await?.is_some())
+}
+
+/// Passes on the result of a quota check, or `None` after telling the client
+/// which quota was exceeded. A check that fails for another reason, e.g. the
+/// database being unreachable, is reported too, rather than ending the session.
+async fn quota_check<T>(
+    checked: Result<T>,
+    socket_tx: &Mutex<SplitSink<WebSocket, Message>>,
+) -> Result<Option<T>> {
+    let error = match checked {
+        Ok(value) => return Ok(Some(value)),
+        Err(e) => match e.downcast::<QuotaExceeded>() {
+            Ok(exceeded) => {
+                info!(quota = ?exceeded.quota, "Quota exceeded.");
+                ServerMessage::Error {
+                    message: exceeded.to_string(),
+                    code: Some(exceeded.code()),
+                }
+            }
+            Err(e) => {
+                error!(error = ?e, "Failed to check quotas.");
+                ServerMessage::Error {
+                    message: "Your usage could not be checked. Please try again.".to_string(),
+                    code: None,
+                }
+            }
+        },
+    };
+    send_msg(&mut *socket_tx.lock().await, error).await?;
+    Ok(None)
+}
+
 /// A helper function to serialize and send a `ServerMessage` to the client.
 pub(crate) async fn send_msg(
     socket_tx: &mut SplitSink<WebSocket, Message>,
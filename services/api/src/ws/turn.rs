@@ -0,0 +1,328 @@
+//! Runs ReAct turns as background tasks, so the session loop keeps forwarding
+//! audio and state updates while the agent is thinking.
+
+use super::{
+    cycle::{CycleOutcome, handle_react_cycle},
+    protocol::{ErrorCode, ServerMessage, TurnOutcome},
+    provider::RealtimeClientEvent,
+    session::send_msg,
+};
+use crate::{config::TurnPolicy, models, recordings::Recorder, state::AppState};
+use anyhow::Result;
+use axum::extract::ws::{Message, WebSocket};
+use feynman_core::{agent::FeynmanAgent, tools::ToolRegistry};
+use futures_util::stream::SplitSink;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, OnceLock},
+};
+use tokio::{
+    sync::{Mutex, mpsc},
+    task::{JoinError, JoinHandle},
+};
+use tracing::{Instrument, error, info};
+use uuid::Uuid;
+
+/// How many messages may wait behind the running turn under `TurnPolicy::Queue`.
+/// Each becomes an LLM call, so further messages are rejected.
+const MAX_QUEUED_TURNS: usize = 8;
+
+/// A user message waiting to be answered.
+pub struct TurnRequest {
+    pub text: String,
+    /// The audio of a spoken message, saved once the message is stored.
+    pub clip: Option<Vec<i16>>,
+}
+
+/// The session resources every turn works with.
+pub struct TurnContext {
+    pub state: Arc<AppState>,
+    pub session_id: Uuid,
+    pub history: Arc<Mutex<Vec<models::Message>>>,
+    pub agent_state: Arc<Mutex<FeynmanAgent>>,
+    pub tools: Arc<dyn ToolRegistry>,
+    pub socket_tx: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    pub recorder: Option<Recorder>,
+    /// States saved by the agent's tools, waiting to be stored.
+    pub state_updates: Arc<Mutex<mpsc::Receiver<FeynmanAgent>>>,
+}
+
+/// A turn that has been started.
+struct Turn {
+    id: u64,
+    /// The ID of the user message, once the turn has stored it.
+    user_message_id: Arc<OnceLock<i64>>,
+    clip: Option<Vec<i16>>,
+    /// Whether the reply was handed to the voice provider.
+    voiced: bool,
+}
+
+/// Runs at most one turn at a time and decides what happens to messages
+/// that arrive in the meantime.
+pub struct TurnRunner {
+    context: Arc<TurnContext>,
+    policy: TurnPolicy,
+    next_id: u64,
+    active: Option<(Turn, JoinHandle<Result<CycleOutcome>>)>,
+    queue: VecDeque<TurnRequest>,
+    /// The stored AI reply last handed to the voice provider.
+    spoken_reply: Option<i64>,
+}
+
+impl TurnRunner {
+    pub fn new(context: TurnContext, policy: TurnPolicy) -> Self {
+        Self {
+            context: Arc::new(context),
+            policy,
+            next_id: 0,
+            active: None,
+            queue: VecDeque::new(),
+            spoken_reply: None,
+        }
+    }
+
+    /// Whether a turn is running.
+    pub fn is_running(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Stores a state saved by the agent's tools, linked to the message of the
+    /// running turn, and sends it to the client.
+    pub async fn save_state(&self, new_state: FeynmanAgent) -> Result<()> {
+        let message_id = self
+            .active
+            .as_ref()
+            .and_then(|(turn, _)| turn.user_message_id.get().copied());
+        self.store_state(new_state, message_id).await
+    }
+
+    /// Takes the ID of the AI reply being voiced, e.g. to truncate it after an interruption.
+    pub fn take_spoken_reply(&mut self) -> Option<i64> {
+        self.spoken_reply.take()
+    }
+
+    /// Starts a turn for the message, applying the policy if one is already running.
+    pub async fn submit(
+        &mut self,
+        request: TurnRequest,
+        realtime_tx: &Option<mpsc::Sender<RealtimeClientEvent>>,
+    ) -> Result<()> {
+        if self.active.is_some() {
+            match self.policy {
+                TurnPolicy::Queue if self.queue.len() >= MAX_QUEUED_TURNS => {
+                    info!(
+                        queued = self.queue.len(),
+                        "Rejected user message; the queue is full."
+                    );
+                    return self
+                        .send(ServerMessage::Error {
+                            message: "Too many messages are waiting for an answer.".to_string(),
+                            code: Some(ErrorCode::TurnQueueFull),
+                        })
+                        .await;
+                }
+                TurnPolicy::Queue => {
+                    self.queue.push_back(request);
+                    info!(
+                        queued = self.queue.len(),
+                        "Queued user message behind the running turn."
+                    );
+                    return Ok(());
+                }
+                TurnPolicy::Interrupt => self.abort().await?,
+            }
+        }
+        self.start(request, realtime_tx).await
+    }
+
+    /// Cancels the running turn, or only turn `turn_id` when given, then starts the
+    /// next queued message. Returns whether a turn was cancelled.
+    pub async fn cancel(
+        &mut self,
+        turn_id: Option<u64>,
+        realtime_tx: &Option<mpsc::Sender<RealtimeClientEvent>>,
+    ) -> Result<bool> {
+        match &self.active {
+            Some((turn, _)) if turn_id.is_none_or(|id| id == turn.id) => {}
+            _ => return Ok(false),
+        }
+        self.abort().await?;
+        self.start_next(realtime_tx).await?;
+        Ok(true)
+    }
+
+    /// Waits for the running turn to end, or forever when idle.
+    ///
+    /// This is cancel safe, so it can be polled in a `select!` loop.
+    pub async fn wait(&mut self) -> Result<Result<CycleOutcome>, JoinError> {
+        match &mut self.active {
+            Some((_, handle)) => handle.await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Wraps up the turn that `wait` returned for and starts the next queued message.
+    pub async fn complete(
+        &mut self,
+        result: Result<Result<CycleOutcome>, JoinError>,
+        realtime_tx: &Option<mpsc::Sender<RealtimeClientEvent>>,
+    ) -> Result<()> {
+        if let Some((turn, _)) = self.active.take() {
+            self.finish(turn, result).await?;
+        }
+        self.start_next(realtime_tx).await
+    }
+
+    /// Aborts the running turn and waits for its task to stop, so it cannot
+    /// touch the history afterwards.
+    async fn abort(&mut self) -> Result<()> {
+        if let Some((turn, handle)) = self.active.take() {
+            handle.abort();
+            // A turn that finished just before the abort still counts as completed.
+            let result = handle.await;
+            self.finish(turn, result).await?;
+        }
+        Ok(())
+    }
+
+    async fn start_next(
+        &mut self,
+        realtime_tx: &Option<mpsc::Sender<RealtimeClientEvent>>,
+    ) -> Result<()> {
+        match self.queue.pop_front() {
+            Some(request) => self.start(request, realtime_tx).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn start(
+        &mut self,
+        request: TurnRequest,
+        realtime_tx: &Option<mpsc::Sender<RealtimeClientEvent>>,
+    ) -> Result<()> {
+        self.next_id += 1;
+        let id = self.next_id;
+        // Announce the turn before it can send any response messages.
+        self.send(ServerMessage::TurnStarted { turn_id: id })
+            .await?;
+
+        let context = self.context.clone();
+        let realtime_tx = realtime_tx.clone();
+        let voiced = realtime_tx.is_some();
+        let text = request.text;
+        let user_message_id = Arc::new(OnceLock::new());
+        let stored_id = user_message_id.clone();
+        let handle = tokio::spawn(
+            async move {
+                // The message is kept even if the turn is cancelled later.
+                let user_message = context
+                    .state
+                    .db
+                    .add_message(context.session_id, models::MessageRole::User, &text)
+                    .await?;
+                let _ = stored_id.set(user_message.id);
+                context.history.lock().await.push(user_message);
+
+                handle_react_cycle(
+                    &context.state,
+                    context.session_id,
+                    &context.history,
+                    &context.agent_state,
+                    context.tools.as_ref(),
+                    &context.socket_tx,
+                    &realtime_tx,
+                )
+                .await
+            }
+            .instrument(tracing::info_span!("turn", turn_id = id)),
+        );
+        let turn = Turn {
+            id,
+            user_message_id,
+            clip: request.clip,
+            voiced,
+        };
+        self.active = Some((turn, handle));
+        Ok(())
+    }
+
+    /// Records the result of an ended turn and reports it to the client.
+    async fn finish(
+        &mut self,
+        turn: Turn,
+        result: Result<Result<CycleOutcome>, JoinError>,
+    ) -> Result<()> {
+        // The turn's task has stopped, so all states it saved are queued; store
+        // them as its own before the next turn starts.
+        let user_message_id = turn.user_message_id.get().copied();
+        let queued: Vec<_> = {
+            let mut state_updates = self.context.state_updates.lock().await;
+            std::iter::from_fn(|| state_updates.try_recv().ok()).collect()
+        };
+        for new_state in queued {
+            self.store_state(new_state, user_message_id).await?;
+        }
+        // The message was spoken whether or not the turn answered it.
+        if let (Some(recorder), Some(clip)) = (&self.context.recorder, turn.clip) {
+            recorder.save(models::MessageRole::User, clip, user_message_id);
+        }
+        let outcome = match result {
+            Ok(Ok(cycle)) => {
+                self.spoken_reply = cycle.reply_id.filter(|_| turn.voiced);
+                if let Some(recorder) = &self.context.recorder {
+                    recorder.set_ai_message(self.spoken_reply);
+                }
+                TurnOutcome::Completed
+            }
+            Err(e) if e.is_cancelled() => {
+                info!(turn_id = turn.id, "Turn cancelled.");
+                TurnOutcome::Cancelled
+            }
+            Ok(Err(e)) => {
+                error!(turn_id = turn.id, error = ?e, "Turn failed.");
+                self.send(ServerMessage::Error {
+                    message: e.to_string(),
+                    code: None,
+                })
+                .await?;
+                TurnOutcome::Failed
+            }
+            Err(e) => {
+                error!(turn_id = turn.id, error = ?e, "Turn task panicked.");
+                self.send(ServerMessage::Error {
+                    message: "The agent failed to answer.".to_string(),
+                    code: None,
+                })
+                .await?;
+                TurnOutcome::Failed
+            }
+        };
+        self.send(ServerMessage::TurnFinished {
+            turn_id: turn.id,
+            outcome,
+        })
+        .await
+    }
+
+    async fn store_state(&self, new_state: FeynmanAgent, message_id: Option<i64>) -> Result<()> {
+        self.context
+            .state
+            .db
+            .update_agent_state(self.context.session_id, &new_state, message_id)
+            .await?;
+        self.send(ServerMessage::StateUpdate { state: new_state })
+            .await
+    }
+
+    async fn send(&self, msg: ServerMessage) -> Result<()> {
+        send_msg(&mut *self.context.socket_tx.lock().await, msg).await
+    }
+}
+
+impl Drop for TurnRunner {
+    fn drop(&mut self) {
+        if let Some((_, handle)) = &self.active {
+            handle.abort();
+        }
+    }
+}